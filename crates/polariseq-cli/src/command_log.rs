@@ -0,0 +1,130 @@
+//! `<output>/commands.jsonl`: the audit trail of external commands.
+//!
+//! [`CommandLogLayer`] turns each `polariseq_core::audit` event into one
+//! JSON line (time, argv, cwd, status, exit code, duration, stderr tail).
+//! The human log filters these events out.
+
+use polariseq_core::audit::AUDIT_TARGET;
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+pub const COMMANDS_LOG: &str = "commands.jsonl";
+
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        // argv arrives JSON-encoded so it stays an array here.
+        let value = match field.name() {
+            "argv" => serde_json::from_str(value).unwrap_or_else(|_| value.into()),
+            _ => value.into(),
+        };
+        self.0.insert(field.name().to_string(), value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            // The program name, already the first argv entry.
+            "message" => {}
+            _ => self.record_str(field, &format!("{:?}", value)),
+        }
+    }
+}
+
+/// Appends audit events to `commands.jsonl`, created on the first one.
+pub struct CommandLogLayer {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl CommandLogLayer {
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            path: output_dir.join(COMMANDS_LOG),
+            file: Mutex::new(None),
+        }
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        file.as_mut()
+            .expect("opened above")
+            .write_all(line.as_bytes())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for CommandLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != AUDIT_TARGET {
+            return;
+        }
+        let mut fields = Fields::default();
+        fields
+            .0
+            .insert("time".into(), chrono::Local::now().to_rfc3339().into());
+        event.record(&mut fields);
+        if let Ok(line) = serde_json::to_string(&fields.0) {
+            // The audit log must not fail the download.
+            let _ = self.append(&format!("{}\n", line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn writes_one_json_line_per_command() {
+        let dir = std::env::temp_dir().join(format!("polariseq-commands-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let subscriber = tracing_subscriber::registry().with(CommandLogLayer::new(&dir));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut command = Command::new("wget");
+            command
+                .args(["-c", "ftp://example.org/SRR1.fastq.gz"])
+                .current_dir(&dir);
+            polariseq_core::audit::record(&command, Instant::now(), Err("cancelled"));
+            tracing::info!("not a command");
+        });
+        let log = std::fs::read_to_string(dir.join(COMMANDS_LOG)).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["argv"][2], "ftp://example.org/SRR1.fastq.gz");
+        assert_eq!(entry["status"], "cancelled");
+        assert!(entry.get("message").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
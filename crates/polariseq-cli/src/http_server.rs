@@ -3,10 +3,12 @@ use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{Json, Router};
 use base64::engine::general_purpose;
 use base64::Engine;
+use polariseq_core::distributed::{RunReport, WorkQueue, WorkerRequest};
+use polariseq_core::metrics::Metrics;
 use polariseq_core::progress_store::ProgressStore;
 use rand::Rng;
 use std::sync::Arc;
@@ -69,3 +71,83 @@ async fn handle_progress(State(state): State<Arc<HttpState>>) -> impl IntoRespon
         })),
     )
 }
+
+/// Serve `queue` to `polariseq worker` instances on `addr` (host:port).
+pub async fn start_queue_server(addr: String, queue: WorkQueue) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/claim", post(handle_claim))
+        .route("/heartbeat", post(handle_heartbeat))
+        .route("/report", post(handle_report))
+        .route("/status", get(handle_status))
+        .with_state(queue);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    tracing::info!("Work queue listening on {}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn handle_claim(
+    State(queue): State<WorkQueue>,
+    Json(request): Json<WorkerRequest>,
+) -> impl IntoResponse {
+    let claim = queue.claim(&request.worker);
+    if let Some(record) = &claim.record {
+        tracing::info!("[{}] Claimed by {}", record.run_accession, request.worker);
+    }
+    Json(claim)
+}
+
+async fn handle_heartbeat(
+    State(queue): State<WorkQueue>,
+    Json(request): Json<WorkerRequest>,
+) -> impl IntoResponse {
+    let run_id = request.run_id.unwrap_or_default();
+    if queue.heartbeat(&request.worker, &run_id) {
+        StatusCode::OK
+    } else {
+        StatusCode::GONE
+    }
+}
+
+async fn handle_report(
+    State(queue): State<WorkQueue>,
+    Json(report): Json<RunReport>,
+) -> impl IntoResponse {
+    match &report.error {
+        None => tracing::info!(
+            "[{}] Done on {} ({}s)",
+            report.run_id,
+            report.worker,
+            report.seconds
+        ),
+        Some(e) => tracing::warn!("[{}] Failed on {}: {}", report.run_id, report.worker, e),
+    }
+    queue.report(report);
+    StatusCode::OK
+}
+
+async fn handle_status(State(queue): State<WorkQueue>) -> impl IntoResponse {
+    Json(queue.status())
+}
+
+pub async fn start_metrics_server(port: u16, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(handle_metrics))
+        .with_state(metrics);
+
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    tracing::info!("Prometheus metrics on 0.0.0.0:{}/metrics", port);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn handle_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        metrics.render(),
+    )
+}
@@ -2,33 +2,44 @@ use anyhow::{anyhow, Context, Result};
 use chrono::Local;
 use clap::Parser;
 use clap::Subcommand;
-use csv::WriterBuilder;
-use indicatif::{HumanBytes, MultiProgress, ProgressBar};
-use regex::Regex;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget};
 
-use nu_ansi_term::Color;
+use nu_ansi_term::{Color, Style};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::{ExitCode, Stdio};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::process::Command;
-use tokio::sync::Semaphore;
-use tracing::{error, info, warn, Event, Subscriber};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, Event, Subscriber};
 use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, Writer};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::fmt::FmtContext;
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{fmt, EnvFilter};
 
-use polariseq_core::progress_store::{
-    new_progress_store, ProgressStore, RunProgress, RunStage, StageProgress,
-};
+use polariseq_core::progress::{IndicatifSink, LogSink, ProgressSink};
+use polariseq_core::progress_store::new_progress_store;
 use polariseq_core::observer::DownloadObserver;
+use polariseq_core::client::{DownloadJob, PolariseqClient};
+use polariseq_core::error::{self, PolariseqError};
+use polariseq_core::irods::{IrodsHook, IrodsTarget};
+use polariseq_core::manifest::ManifestHook;
+use polariseq_core::multiqc::MultiqcHook;
+use polariseq_core::push::{RsyncHook, RsyncTarget};
+use polariseq_core::qc::{QcHook, QcTool};
+use polariseq_core::shell_hook::ShellHook;
+use polariseq_core::sidecar::InfoJsonHook;
+use polariseq_core::tenx::TenxHook;
+use polariseq_core::trim::{FastpHook, Trimmer};
 use polariseq_core::*;
 
+mod command_log;
 mod http_server;
+mod otlp;
+mod plain;
+mod run_logs;
+mod timings;
 mod ui_manager;
 use ui_manager::{Mode, UiManager};
 
@@ -90,10 +101,27 @@ struct Cli {
         long,
         global = true,
         value_name = "FILE",
+        env = "POLARISEQ_YAML",
         help = "YAML config path",
         help_heading = "Global Options"
     )]
     yaml: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "prefetch executable (overrides YAML and POLARISEQ_PREFETCH)",
+        help_heading = "Global Options"
+    )]
+    prefetch_path: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "fasterq-dump executable (overrides YAML and POLARISEQ_FASTERQ_DUMP)",
+        help_heading = "Global Options"
+    )]
+    fasterq_dump_path: Option<PathBuf>,
     #[arg(
         long,
         global = true,
@@ -110,12 +138,55 @@ struct Cli {
         help_heading = "Global Options"
     )]
     log_format: LogFormat,
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        help = "Export tracing spans over OTLP/HTTP to this collector, e.g. http://localhost:4318 (needs the `otlp` cargo feature)",
+        help_heading = "Global Options"
+    )]
+    otlp_endpoint: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Log progress as periodic percent lines instead of bars (automatic when stderr is not a terminal)",
+        help_heading = "Global Options"
+    )]
+    no_progress: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "ASCII-only output: no emoji, box-drawing or other symbols in logs and the banner",
+        help_heading = "Global Options"
+    )]
+    plain: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        conflicts_with = "replay_http",
+        help = "Record ENA/NCBI API responses to this cassette (JSON) for --replay-http",
+        help_heading = "Global Options"
+    )]
+    record_http: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Answer ENA/NCBI API requests from a cassette written by --record-http instead of the network",
+        help_heading = "Global Options"
+    )]
+    replay_http: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Download sequencing data from EBI ENA / NCBI SRA
-    Download(DownloadArgs),
+    Download(Box<DownloadArgs>),
+    /// Fetch metadata and resolve download URLs into a job file for `execute`
+    Plan(Box<PlanArgs>),
+    /// Download the runs of a `plan` job file without metadata API calls
+    Execute(ExecuteArgs),
     /// Download public reference databases configured in YAML from S3
     PublicData(PublicDataArgs),
     /// Validate an existing BLAST database directory with blastdbcmd
@@ -126,19 +197,32 @@ enum Commands {
     Upload(UploadArgs),
     /// Manage external dependencies (sra-tools)
     Deps(DepsArgs),
+    /// Write a commented polariseq.yaml with the tools found on this machine
+    Init(InitArgs),
+    /// Diagnose tools, network access and the output directory
+    Doctor(DoctorArgs),
+    /// Fetch a project's ENA metadata, or compare it with an earlier snapshot
+    Metadata(MetadataArgs),
+    /// Remove stale .meta.json, .part and .lock files and empty run directories
+    Clean(CleanArgs),
+    /// Download runs handed out by a `download --coordinator` instance
+    Worker(WorkerArgs),
+    /// Pause, resume, throttle or cancel runs of a `download --control` job
+    Control(ControlArgs),
 }
 
 // ============================================================
 // Download Subcommand Arguments (unchanged from original Args)
 // ============================================================
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 struct DownloadArgs {
     #[arg(
         short = 'A',
         long,
         value_name = "ID",
-        help = "ENA project accession, e.g. PRJNA1251654",
+        env = "POLARISEQ_ACCESSION",
+        help = "ENA project accession, e.g. PRJNA1251654, or several separated by commas (also GSE series or GCA_/GCF_ assemblies)",
         help_heading = "Input Options"
     )]
     accession: Option<String>,
@@ -150,23 +234,68 @@ struct DownloadArgs {
         help_heading = "Input Options"
     )]
     tsv: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "GCA/GCF",
+        help = "Also download this reference assembly (FASTA + GFF) into <output>/reference",
+        help_heading = "Input Options"
+    )]
+    reference: Option<String>,
+    #[arg(
+        long = "geo-suppl",
+        default_value = "false",
+        help = "For GSE accessions, also fetch GEO supplementary files into <output>/geo_suppl",
+        help_heading = "Input Options"
+    )]
+    geo_suppl: bool,
 
     #[arg(
         short,
         long,
         value_name = "DIR",
+        env = "POLARISEQ_OUTPUT",
         help = "Output directory for downloaded data",
         help_heading = "Input Options"
     )]
     output: PathBuf,
 
-    #[arg(short, long, default_value = "aws", help_heading = "Download Options")]
+    #[arg(
+        short,
+        long,
+        default_value = "aws",
+        env = "POLARISEQ_DOWNLOAD",
+        help_heading = "Download Options"
+    )]
     download: DownloadMethod,
+    #[arg(
+        long,
+        value_name = "RULE",
+        help = "Backends for matching runs, e.g. fastq=ftp, protected=prefetch, 'SRR*=aws,ftp' (repeatable; first match wins)",
+        help_heading = "Download Options"
+    )]
+    route: Vec<String>,
+    #[arg(
+        long = "group-by",
+        value_enum,
+        default_value = "run",
+        help = "Scheduling unit: run, or sample (runs of one sample download one after another in the same worker)",
+        help_heading = "Download Options"
+    )]
+    group_by: polariseq_core::GroupBy,
+    #[arg(
+        long = "sort-by",
+        value_enum,
+        default_value = "run",
+        help = "Order of the runs in ena_metadata.tsv, the MD5 tables and the plan: run, sample, study or none (ENA/TSV order)",
+        help_heading = "Download Options"
+    )]
+    sort_by: polariseq_core::SortBy,
 
     #[arg(
         short = 'p',
         long,
         default_value = "4",
+        env = "POLARISEQ_THREADS",
         help = "File-level concurrency",
         help_heading = "Download Options"
     )]
@@ -175,17 +304,43 @@ struct DownloadArgs {
         short = 't',
         long = "aws-threads",
         default_value = "8",
+        env = "POLARISEQ_AWS_THREADS",
         help = "Threads per file (AWS)",
         help_heading = "Download Options"
     )]
     aws_threads: usize,
+    #[arg(
+        long = "max-connections",
+        default_value = "32",
+        env = "POLARISEQ_MAX_CONNECTIONS",
+        help = "Range requests open at once across all files (AWS); lowered when the server rate-limits, 0 for no cap",
+        help_heading = "Download Options"
+    )]
+    max_connections: usize,
     #[arg(
         long = "chunk-size",
         default_value = "200",
+        env = "POLARISEQ_CHUNK_SIZE",
         help = "Chunk size in MB (AWS only)",
         help_heading = "Download Options"
     )]
     chunk_size: u64,
+    #[arg(
+        long,
+        value_name = "RATE",
+        env = "POLARISEQ_BANDWIDTH",
+        help = "Bandwidth limit of the job, e.g. 50M (AWS ranged downloads; change it later with `polariseq control`)",
+        help_heading = "Download Options"
+    )]
+    bandwidth: Option<String>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "POLARISEQ_PROFILE",
+        help = "Settings from `profiles:` in polariseq.yaml (backend, -p, -t, --chunk-size, --bandwidth); flags and POLARISEQ_* variables win",
+        help_heading = "Download Options"
+    )]
+    profile: Option<String>,
     #[arg(
         long = "pe-only",
         default_value = "false",
@@ -193,6 +348,170 @@ struct DownloadArgs {
         help_heading = "Download Options"
     )]
     pe_only: bool,
+    #[arg(
+        long = "sra-format",
+        value_enum,
+        default_value = "any",
+        help = "SRA quality tier: full (skip SRA Lite-only runs), lite (prefer Lite), any (prefer full)",
+        help_heading = "Download Options"
+    )]
+    sra_format: polariseq_core::aws_s3::SraFormat,
+    #[arg(
+        long = "s3-http",
+        value_enum,
+        default_value = "http1",
+        help = "HTTP version of AWS range requests: http1 (a connection per chunk worker) or http2 (chunks multiplexed on one connection where S3 accepts it)",
+        help_heading = "Download Options"
+    )]
+    s3_http: polariseq_core::aws_s3::S3Http,
+    #[arg(
+        long = "aws-staging",
+        value_enum,
+        default_value = "run",
+        help = "Where AWS mode keeps the .sra and intermediate FASTQ: run (<output>/<run>/, finished files moved up) or flat (the output directory)",
+        help_heading = "Download Options"
+    )]
+    aws_staging: polariseq_core::aws_s3::AwsStaging,
+    #[arg(
+        long = "preallocate",
+        value_enum,
+        default_value = "sparse",
+        help = "How AWS .part files get their size: sparse (length only) or full (reserve the disk space up front with fallocate, else zeros)",
+        help_heading = "Download Options"
+    )]
+    preallocate: polariseq_core::prealloc::Preallocation,
+    #[arg(
+        long = "bench-s3-http",
+        help = "Fetch 64 MiB of the first run from AWS over HTTP/1.1 and HTTP/2, report which is faster, and exit",
+        help_heading = "Download Options"
+    )]
+    bench_s3_http: bool,
+    #[arg(
+        long = "print-urls",
+        help = "Print the URL, MD5, size and local path of every file instead of downloading (tab-separated)",
+        help_heading = "Download Options"
+    )]
+    print_urls: bool,
+    #[arg(
+        long = "export-aria2",
+        value_name = "FILE",
+        help = "Write an aria2 input file (mirrors, dir, out, MD5 checksum) for every file instead of downloading",
+        help_heading = "Download Options"
+    )]
+    export_aria2: Option<PathBuf>,
+    #[arg(
+        long = "output-format",
+        value_enum,
+        default_value = "fastq",
+        help = "Format of runs converted from SRA: fastq (gzipped), or ubam/cram via samtools import; FTP runs stay FASTQ",
+        help_heading = "Download Options"
+    )]
+    output_format: polariseq_core::OutputFormat,
+    #[arg(
+        long = "prefer-bam",
+        help = "Download the bam_ftp alignments of runs that have them (over FTP, MD5-verified) into <output>/alignments instead of FASTQ",
+        help_heading = "Download Options"
+    )]
+    prefer_bam: bool,
+    #[arg(
+        long = "min-speed",
+        value_name = "RATE",
+        help = "Abort a run's transfer that stays below RATE (e.g. 1M) for --min-speed-window and retry it on the next backend",
+        help_heading = "Download Options"
+    )]
+    min_speed: Option<String>,
+    #[arg(
+        long = "min-speed-window",
+        value_name = "DURATION",
+        default_value = "60s",
+        requires = "min_speed",
+        help = "How long a transfer may stay below --min-speed (e.g. 90s, 5m)",
+        help_heading = "Download Options"
+    )]
+    min_speed_window: String,
+    #[arg(
+        long = "auto-tune",
+        help = "Start with few runs and threads and adjust them every minute from throughput and errors; -p/-t given explicitly are the ceilings (default 16 and 32)",
+        help_heading = "Download Options"
+    )]
+    auto_tune: bool,
+    #[arg(
+        long = "prefetch-batch",
+        help = "Fetch runs that use prefetch (protected runs, or routed to prefetch first) with one `prefetch --option-file` call before converting them",
+        help_heading = "Download Options"
+    )]
+    prefetch_batch: bool,
+    #[arg(
+        long = "prefetch-transport",
+        value_enum,
+        help = "prefetch --transport: https (for sites without Aspera access), fasp or both; overrides prefetch.transport in the YAML",
+        help_heading = "Download Options"
+    )]
+    prefetch_transport: Option<polariseq_core::controlled::PrefetchTransport>,
+    #[arg(
+        long = "prefetch-resume",
+        value_name = "BOOL",
+        help = "prefetch --resume: continue partial downloads (true) or restart them (false); overrides prefetch.resume",
+        help_heading = "Download Options"
+    )]
+    prefetch_resume: Option<bool>,
+    #[arg(
+        long = "eliminate-quals",
+        help = "prefetch --eliminate-quals: fetch SRA Lite without quality scores",
+        help_heading = "Download Options"
+    )]
+    eliminate_quals: bool,
+    #[arg(
+        long = "fasterq-mem",
+        value_name = "SIZE",
+        help = "fasterq-dump --mem of each conversion (e.g. 4G); capped by --max-memory",
+        help_heading = "Download Options"
+    )]
+    fasterq_mem: Option<String>,
+    #[arg(
+        long = "max-memory",
+        value_name = "SIZE",
+        help = "Memory for all fasterq-dump conversions together (e.g. 32G), split evenly across the -p runs in flight",
+        help_heading = "Download Options"
+    )]
+    max_memory: Option<String>,
+    #[arg(
+        long,
+        value_name = "N",
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19),
+        help = "Run fasterq-dump and samtools under `nice -n N` (19 = lowest priority); overrides priority.nice in the YAML",
+        help_heading = "Download Options"
+    )]
+    nice: Option<i32>,
+    #[arg(
+        long,
+        value_name = "CLASS",
+        help = "Run conversions under ionice: idle, best-effort or best-effort:0-7; overrides priority.ionice",
+        help_heading = "Download Options"
+    )]
+    ionice: Option<polariseq_core::priority::IoNice>,
+    #[arg(
+        long,
+        value_name = "CPUS",
+        help = "Pin conversions to these CPUs with taskset -c (e.g. 0-7); overrides priority.taskset",
+        help_heading = "Download Options"
+    )]
+    taskset: Option<polariseq_core::priority::CpuList>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "dbGaP repository key (.ngc) or JWT cart for controlled-access runs",
+        help_heading = "Download Options"
+    )]
+    ngc: Option<PathBuf>,
+    #[arg(
+        long = "ega-user",
+        value_name = "EMAIL",
+        help = "EGA account for --download ega (password from POLARISEQ_EGA_PASSWORD, or set POLARISEQ_EGA_TOKEN)",
+        help_heading = "Download Options"
+    )]
+    ega_user: Option<String>,
 
     #[arg(long = "filter-sample", num_args = 1.., help = "Include samples matching regex", help_heading = "Filters")]
     filter_sample: Vec<String>,
@@ -210,6 +529,21 @@ struct DownloadArgs {
         help_heading = "Advanced Options"
     )]
     cleanup_sra: bool,
+    #[arg(
+        long = "clean-stale",
+        value_name = "DAYS",
+        default_value_t = clean::DEFAULT_MAX_AGE_DAYS,
+        help = "At the end of the job, remove .meta.json, .part and .lock files and empty run directories untouched for DAYS days (0 disables)",
+        help_heading = "Advanced Options"
+    )]
+    clean_stale: u64,
+    #[arg(
+        long = "max-files",
+        value_name = "N",
+        help = "File-count limit of the output volume (e.g. a quota); default: free inodes from df -i. Over it, downloads drop .sra files after conversion and --only-scripts writes one combined script",
+        help_heading = "Advanced Options"
+    )]
+    max_files: Option<u64>,
     #[arg(
         long,
         default_value = "false",
@@ -219,129 +553,322 @@ struct DownloadArgs {
     dry_run: bool,
     #[arg(
         long,
-        value_name = "PORT",
-        help = "Enable HTTP progress API on this port (AES-256-GCM encrypted)",
+        default_value = "false",
+        help = "Write one script per run to <output>/scripts instead of downloading",
         help_heading = "Advanced Options"
     )]
-    progress_port: Option<u16>,
+    only_scripts: bool,
     #[arg(
         long,
-        default_value = "false",
-        help = "Write encryption key to progress.key file in output directory (required for external platforms to decrypt progress)",
+        value_enum,
+        default_value = "bash",
+        requires = "only_scripts",
+        help = "Scheduler header for --only-scripts (job name = run, cpus from -t, memory/time from size)",
         help_heading = "Advanced Options"
     )]
-    write_progress_key: bool,
-}
-
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-struct PublicDataArgs {
+    script_flavor: scripts::ScriptFlavor,
     #[arg(
-        short = 'n',
         long,
-        value_name = "NAME",
-        help = "YAML public_data identifier to download, e.g. ncbi_nt",
-        help_heading = "Input Options"
+        value_enum,
+        value_delimiter = ',',
+        requires = "only_scripts",
+        help = "Also write runners for the scripts: parallel (scripts/run_all.sh, -p at a time) and/or make (scripts/Makefile, make -jN); both resume",
+        help_heading = "Advanced Options"
     )]
-    name: String,
+    script_runners: Vec<scripts::ScriptRunner>,
     #[arg(
-        short,
         long,
-        value_name = "DIR",
-        default_value = ".",
-        help = "Directory for downloaded public database files",
-        help_heading = "Input Options"
+        value_enum,
+        value_name = "SCHEDULER",
+        help = "Write a job array (scripts/array.sh + index) and submit it instead of downloading; prints the command when sbatch is not available",
+        help_heading = "Advanced Options"
     )]
-    output: PathBuf,
+    submit: Option<scripts::SubmitTarget>,
     #[arg(
-        short = 'p',
         long,
-        default_value = "8",
-        help = "File-level download concurrency",
-        help_heading = "Download Options"
+        default_value_t = 20,
+        requires = "submit",
+        help = "Maximum number of array tasks running at once",
+        help_heading = "Advanced Options"
     )]
-    multithreads: usize,
+    array_throttle: usize,
     #[arg(
-        short = 't',
-        long = "aws-threads",
-        default_value = "4",
-        help = "HTTP range workers per file",
-        help_heading = "Download Options"
+        long,
+        value_enum,
+        value_name = "ENGINE",
+        help = "Write a Nextflow or Snakemake pipeline with its runs.tsv to <output>/workflow instead of downloading",
+        help_heading = "Advanced Options"
     )]
-    aws_threads: usize,
+    export_workflow: Option<workflow::WorkflowEngine>,
     #[arg(
-        long = "chunk-size",
-        default_value = "200",
-        help = "HTTP range chunk size in MB",
-        help_heading = "Download Options"
+        long,
+        value_name = "USER@HOST:/DIR",
+        help = "Run the download commands on a remote host over SSH, streaming logs back (tools must be on the remote PATH)",
+        help_heading = "Advanced Options"
     )]
-    chunk_size: u64,
+    remote: Option<remote::RemoteTarget>,
     #[arg(
         long,
-        default_value = "false",
-        help = "List matching objects without downloading them",
+        value_name = "HOST:PORT",
+        help = "Serve the planned runs to `polariseq worker` instances on this address instead of downloading, and wait for their reports",
         help_heading = "Advanced Options"
     )]
-    dry_run: bool,
-}
-
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-struct ValidateArgs {
+    coordinator: Option<String>,
     #[arg(
-        short = 'd',
         long,
-        value_name = "DIR",
-        help = "Directory containing the BLAST database volumes"
+        value_name = "TARGET",
+        help = "Notify on failed runs and at the end of the job: slack:URL, webhook:URL or mailto:ADDRESS (repeatable)",
+        help_heading = "Advanced Options"
     )]
-    dir: PathBuf,
+    notify: Vec<notify::NotifyTarget>,
     #[arg(
-        short = 't',
         long,
-        value_name = "TYPE",
-        help = "BLAST database type: nucl or prot"
+        help = "Write manifest.json with source, backend, size, MD5/SHA-256 and timestamps of every file, plus tool versions and this command line",
+        help_heading = "Download Options"
     )]
-    dbtype: String,
+    manifest: bool,
     #[arg(
-        short = 'T',
-        long,
-        value_name = "FILE",
-        help = "Path to blastdbcmd executable (overrides software.blastdbcmd in YAML)"
+        long = "info-json",
+        help = "Write <run>.info.json next to each run with its full ENA record and download provenance (backend, source URLs, time), so copied files keep their metadata",
+        help_heading = "Download Options"
     )]
-    tool: Option<PathBuf>,
-}
-
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-struct Md5Args {
-    #[command(subcommand)]
-    command: Md5Subcommand,
-}
-
-#[derive(Subcommand, Debug)]
-enum Md5Subcommand {
-    /// Generate an md5sum-compatible manifest for a file or directory
-    Generate(Md5GenerateArgs),
-    /// Verify files against an existing md5sum-compatible manifest
-    Verify(Md5VerifyArgs),
-}
-
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-struct Md5GenerateArgs {
+    info_json: bool,
     #[arg(
-        short,
-        long,
-        value_name = "PATH",
-        help = "File or directory to hash"
+        long = "md5-manifest",
+        help = "Write one md5_manifest.tsv (run, read, MD5, size, library layout, sample title, file and final path) instead of the R1/R2 MD5 tables",
+        help_heading = "Download Options"
     )]
-    input: PathBuf,
+    md5_manifest: bool,
     #[arg(
-        short,
         long,
-        value_name = "FILE",
-        default_value = "md5.txt",
-        help = "Output manifest path"
+        conflicts_with_all = ["dest_remove_local", "dest_stream"],
+        help = "Package the output as a BagIt bag once the job finishes: run files under data/, plus manifest-md5.txt and bag-info.txt from the ENA metadata",
+        help_heading = "Download Options"
+    )]
+    bagit: bool,
+    #[arg(
+        long = "ro-crate",
+        conflicts_with_all = ["dest_remove_local", "dest_stream"],
+        help = "Write ro-crate-metadata.json once the job finishes: runs, files with size, MD5 and SHA-256, source accessions and the polariseq version",
+        help_heading = "Download Options"
+    )]
+    ro_crate: bool,
+    #[arg(
+        long,
+        help = "Write polariseq_mqc.json (run counts, sizes, speeds, verification status) for MultiQC",
+        help_heading = "Download Options"
+    )]
+    multiqc: bool,
+    #[arg(
+        long,
+        help = "Accept pause/resume/bandwidth/cancel commands on <output>/polariseq.sock (see `polariseq control`)",
+        help_heading = "Advanced Options"
+    )]
+    control: bool,
+    #[arg(
+        long,
+        help = "Run even if another polariseq process holds the output directory's lock",
+        help_heading = "Advanced Options"
+    )]
+    force: bool,
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Upload each verified run's files to s3://bucket/prefix, gs://bucket/prefix (gs:// uses POLARISEQ_GCS_HMAC_KEY/_SECRET), or push them with rsync to sftp://[user@]host[:port]/path, [user@]host:/path or rsync://host/module; irods:///zone/coll[?register] puts (or registers) them into iRODS",
+        help_heading = "Download Options"
+    )]
+    dest: Option<String>,
+    #[arg(
+        long,
+        requires = "dest",
+        help = "Delete local files once they are uploaded to --dest",
+        help_heading = "Download Options"
+    )]
+    dest_remove_local: bool,
+    #[arg(
+        long,
+        requires = "dest",
+        help = "Stream the published files straight into --dest without local staging (no SRA→FASTQ conversion)",
+        help_heading = "Download Options"
+    )]
+    dest_stream: bool,
+    #[arg(
+        long = "on-start",
+        value_name = "COMMAND",
+        help = "Shell command run before each run downloads, with its ENA metadata in POLARISEQ_* variables; a failure fails the run (repeatable)",
+        help_heading = "Download Options"
+    )]
+    on_start: Vec<String>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Trim each run's FASTQ files after download (reports go next to the reads)",
+        help_heading = "Download Options"
+    )]
+    trim: Option<Trimmer>,
+    #[arg(
+        long = "fastp-args",
+        value_name = "ARGS",
+        requires = "trim",
+        allow_hyphen_values = true,
+        help = "Extra fastp arguments for --trim fastp, e.g. \"--cut_front --length_required 30\"",
+        help_heading = "Download Options"
+    )]
+    fastp_args: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        help = "Run FastQC or falco on each run's final FASTQ files and flag runs with no reads or few Q30 reads",
+        help_heading = "Download Options"
+    )]
+    qc: Option<QcTool>,
+    #[arg(
+        long = "qc-threads",
+        value_name = "N",
+        default_value_t = 2,
+        requires = "qc",
+        help = "Threads per --qc tool call",
+        help_heading = "Download Options"
+    )]
+    qc_threads: usize,
+    #[arg(
+        long = "tenx-names",
+        help = "Hard-link the FASTQ files of 10x Genomics runs under CellRanger names in <output>/cellranger/<sample>",
+        help_heading = "Download Options"
+    )]
+    tenx_names: bool,
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Enable HTTP progress API on this port (AES-256-GCM encrypted)",
+        help_heading = "Advanced Options"
+    )]
+    progress_port: Option<u16>,
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Write encryption key to progress.key file in output directory (required for external platforms to decrypt progress)",
+        help_heading = "Advanced Options"
+    )]
+    write_progress_key: bool,
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Serve Prometheus metrics on this port at /metrics (bytes, active runs, failures per backend, per-run speed)",
+        help_heading = "Advanced Options"
+    )]
+    metrics_port: Option<u16>,
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct PublicDataArgs {
+    #[arg(
+        short = 'n',
+        long,
+        value_name = "NAME",
+        help = "YAML public_data identifier to download, e.g. ncbi_nt",
+        help_heading = "Input Options"
+    )]
+    name: String,
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        default_value = ".",
+        help = "Directory for downloaded public database files",
+        help_heading = "Input Options"
+    )]
+    output: PathBuf,
+    #[arg(
+        short = 'p',
+        long,
+        default_value = "8",
+        help = "File-level download concurrency",
+        help_heading = "Download Options"
+    )]
+    multithreads: usize,
+    #[arg(
+        short = 't',
+        long = "aws-threads",
+        default_value = "4",
+        help = "HTTP range workers per file",
+        help_heading = "Download Options"
+    )]
+    aws_threads: usize,
+    #[arg(
+        long = "chunk-size",
+        default_value = "200",
+        help = "HTTP range chunk size in MB",
+        help_heading = "Download Options"
+    )]
+    chunk_size: u64,
+    #[arg(
+        long,
+        default_value = "false",
+        help = "List matching objects without downloading them",
+        help_heading = "Advanced Options"
+    )]
+    dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct ValidateArgs {
+    #[arg(
+        short = 'd',
+        long,
+        value_name = "DIR",
+        help = "Directory containing the BLAST database volumes"
+    )]
+    dir: PathBuf,
+    #[arg(
+        short = 't',
+        long,
+        value_name = "TYPE",
+        help = "BLAST database type: nucl or prot"
+    )]
+    dbtype: String,
+    #[arg(
+        short = 'T',
+        long,
+        value_name = "FILE",
+        help = "Path to blastdbcmd executable (overrides software.blastdbcmd in YAML)"
+    )]
+    tool: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct Md5Args {
+    #[command(subcommand)]
+    command: Md5Subcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum Md5Subcommand {
+    /// Generate an md5sum-compatible manifest for a file or directory
+    Generate(Md5GenerateArgs),
+    /// Verify files against an existing md5sum-compatible manifest
+    Verify(Md5VerifyArgs),
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+struct Md5GenerateArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        help = "File or directory to hash"
+    )]
+    input: PathBuf,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        default_value = "md5.txt",
+        help = "Output manifest path"
     )]
     output: PathBuf,
     #[arg(
@@ -493,39 +1020,241 @@ enum DepsSubcommand {
     },
 }
 
-// ============================================================
-// Shared Types
-// ============================================================
+#[derive(Parser, Debug)]
+struct InitArgs {
+    #[arg(long, help = "Overwrite an existing config file")]
+    force: bool,
+}
 
-#[derive(Debug, Clone, clap::ValueEnum)]
-enum LogFormat {
-    Text,
-    Json,
+#[derive(Parser, Debug)]
+struct DoctorArgs {
+    #[arg(
+        short,
+        long,
+        default_value = ".",
+        help = "Output directory to check for write access and free space"
+    )]
+    output: PathBuf,
+    #[arg(
+        long,
+        default_value_t = 50,
+        value_name = "GB",
+        help = "Warn when the output volume has less free space"
+    )]
+    min_free_gb: u64,
+    #[arg(long, help = "Print the results as JSON")]
+    json: bool,
 }
 
-// ============================================================
-// Progress-aware logging infrastructure
-// ============================================================
+#[derive(Parser, Debug)]
+struct MetadataArgs {
+    #[arg(
+        short = 'A',
+        long,
+        value_name = "ID",
+        help = "ENA project accession, e.g. PRJNA1251654, or several separated by commas"
+    )]
+    accession: String,
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        help = "Save the current metadata as <DIR>/<ID>_metadata/ena_metadata_<ID>.tsv"
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "OLD_TSV",
+        help = "Report runs added, removed or changed (sizes, MD5s, last_updated) since this ena_metadata.tsv"
+    )]
+    diff: Option<PathBuf>,
+    #[arg(long, requires = "diff", help = "Print the differences as JSON")]
+    json: bool,
+}
 
-/// Global MultiProgress instance shared between logging and progress bars.
-/// When progress bars are active, log messages are rendered above them via
-/// MultiProgress::println(), preventing display corruption.
-static GLOBAL_MP: std::sync::LazyLock<MultiProgress> = std::sync::LazyLock::new(MultiProgress::new);
+#[derive(Parser, Debug)]
+struct PlanArgs {
+    #[command(flatten)]
+    download: DownloadArgs,
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = jobfile::DEFAULT_JOB_FILE,
+        help = "Where to write the job file; -o is the output directory on the compute side"
+    )]
+    job_file: PathBuf,
+}
 
-/// Tracks whether any progress bars are currently active on GLOBAL_MP.
-/// When true, MpWriter routes through MultiProgress::println() (which draws
-/// above active bars). When false, MpWriter writes directly to stderr
-/// (because MultiProgress::println() is a no-op without active bars).
-static BARS_ACTIVE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+#[derive(Parser, Debug)]
+struct ExecuteArgs {
+    #[arg(value_name = "JOB_FILE", help = "Job file written by `polariseq plan`")]
+    job_file: PathBuf,
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        help = "Output directory (default: the -o given to plan)"
+    )]
+    output: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Run even if another polariseq process holds the output directory's lock"
+    )]
+    force: bool,
+}
 
-/// Custom writer that routes tracing output intelligently:
-/// - Progress bars active → MultiProgress::println() (renders above bars)
-/// - No progress bars → direct stderr (MultiProgress::println is a no-op)
-struct MpWriter {
-    buf: Vec<u8>,
+#[derive(Parser, Debug)]
+struct CleanArgs {
+    #[arg(short, long, default_value = ".", help = "Output directory to sweep")]
+    output: PathBuf,
+    #[arg(
+        long,
+        value_name = "DAYS",
+        default_value_t = clean::DEFAULT_MAX_AGE_DAYS,
+        help = "Only remove entries untouched for this many days"
+    )]
+    days: u64,
+    #[arg(long, help = "List what would be removed without removing it")]
+    dry_run: bool,
+    #[arg(
+        long,
+        help = "Sweep even if another polariseq process holds the output directory's lock"
+    )]
+    force: bool,
 }
 
-impl std::io::Write for MpWriter {
+#[derive(Parser, Debug)]
+struct ControlArgs {
+    #[arg(
+        value_name = "TARGET",
+        help = "Output directory of the job, or the path of its control socket"
+    )]
+    target: PathBuf,
+    #[arg(
+        value_name = "COMMAND",
+        num_args = 1..=2,
+        required = true,
+        help = "pause | resume | status | bandwidth <RATE|off> (e.g. 50M) | cancel <RUN>"
+    )]
+    command: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+struct WorkerArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "URL",
+        help = "Coordinator address, e.g. http://node1:7070"
+    )]
+    coordinator: String,
+    #[arg(short, long, help = "Output directory on this node")]
+    output: PathBuf,
+    #[arg(
+        long,
+        help = "Worker name in the coordinator's report [default: hostname]"
+    )]
+    name: Option<String>,
+}
+
+// ============================================================
+// Shared Types
+// ============================================================
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+// ============================================================
+// Progress-aware logging infrastructure
+// ============================================================
+
+/// Global MultiProgress instance shared between logging and progress bars.
+/// When progress bars are active, log messages are rendered above them via
+/// MultiProgress::println(), preventing display corruption.
+static GLOBAL_MP: std::sync::LazyLock<MultiProgress> = std::sync::LazyLock::new(MultiProgress::new);
+
+/// Set for `--no-progress` or when stderr is not a terminal (nohup, SLURM):
+/// progress is logged as percent lines and terminal output carries no ANSI
+/// escapes.
+static LOG_PROGRESS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+fn log_progress() -> bool {
+    LOG_PROGRESS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Matches of the `download` command line, to tell given flags from
+/// defaults when a `--profile` fills them in.
+static DOWNLOAD_MATCHES: std::sync::OnceLock<clap::ArgMatches> = std::sync::OnceLock::new();
+
+/// Whether `id` came from a flag or its `POLARISEQ_*` variable, both of
+/// which take precedence over the YAML profile.
+fn given_explicitly(id: &str) -> bool {
+    use clap::parser::ValueSource;
+    matches!(
+        DOWNLOAD_MATCHES.get().and_then(|m| m.value_source(id)),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// `args` with the settings of `profile` that were not set explicitly.
+fn apply_profile(args: &DownloadArgs, profile: &polariseq_core::profile::Profile) -> DownloadArgs {
+    let mut args = args.clone();
+    if !given_explicitly("download") {
+        args.download = profile.download.unwrap_or(args.download);
+    }
+    if !given_explicitly("multithreads") {
+        args.multithreads = profile.multithreads.unwrap_or(args.multithreads);
+    }
+    if !given_explicitly("aws_threads") {
+        args.aws_threads = profile.aws_threads.unwrap_or(args.aws_threads);
+    }
+    if !given_explicitly("chunk_size") {
+        args.chunk_size = profile.chunk_size.unwrap_or(args.chunk_size);
+    }
+    if args.bandwidth.is_none() {
+        args.bandwidth = profile.bandwidth.clone();
+    }
+    args
+}
+
+/// Set for `--plain`: terminal and file logs pass through `plain::to_ascii`.
+static PLAIN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+fn plain_text(text: &str) -> std::borrow::Cow<'_, str> {
+    if PLAIN.load(std::sync::atomic::Ordering::Relaxed) {
+        plain::to_ascii(text)
+    } else {
+        std::borrow::Cow::Borrowed(text)
+    }
+}
+
+/// Progress sink drawing per-file bars on GLOBAL_MP, or logging percent
+/// lines in non-interactive mode.
+fn progress_sink() -> Arc<dyn ProgressSink> {
+    if log_progress() {
+        Arc::new(LogSink)
+    } else {
+        Arc::new(IndicatifSink::new(GLOBAL_MP.clone()))
+    }
+}
+
+/// Tracks whether any progress bars are currently active on GLOBAL_MP.
+/// When true, MpWriter routes through MultiProgress::println() (which draws
+/// above active bars). When false, MpWriter writes directly to stderr
+/// (because MultiProgress::println() is a no-op without active bars).
+static BARS_ACTIVE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Custom writer that routes tracing output intelligently:
+/// - Progress bars active → MultiProgress::println() (renders above bars)
+/// - No progress bars → direct stderr (MultiProgress::println is a no-op)
+struct MpWriter {
+    buf: Vec<u8>,
+}
+
+impl std::io::Write for MpWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.buf.extend_from_slice(buf);
         Ok(buf.len())
@@ -534,9 +1263,12 @@ impl std::io::Write for MpWriter {
     fn flush(&mut self) -> std::io::Result<()> {
         if !self.buf.is_empty() {
             let s = String::from_utf8_lossy(&self.buf);
-            let s = s.trim_end_matches('\n');
+            let s = plain_text(s.trim_end_matches('\n'));
+            let s = s.as_ref();
             if !s.is_empty() {
-                if BARS_ACTIVE.load(std::sync::atomic::Ordering::Relaxed) {
+                // A hidden MultiProgress drops println() output.
+                if BARS_ACTIVE.load(std::sync::atomic::Ordering::Relaxed) && !GLOBAL_MP.is_hidden()
+                {
                     let _ = GLOBAL_MP.println(s);
                 } else {
                     eprintln!("{}", s);
@@ -575,7 +1307,6 @@ where
         mut writer: Writer<'_>,
         event: &Event<'_>,
     ) -> std::fmt::Result {
-        use nu_ansi_term::Style;
 
         let use_color = writer.has_ansi_escapes();
 
@@ -650,46 +1381,29 @@ where
     }
 }
 
-// Network health check
-async fn check_network_health() {
-    info!("Network connectivity check");
-    let targets = vec![
-        ("https://www.ebi.ac.uk", "EBI API"),
-        ("https://eutils.ncbi.nlm.nih.gov", "NCBI API"),
-        ("https://s3.amazonaws.com", "AWS S3 Endpoint"),
-    ];
-    let client = match reqwest::Client::builder()
-        .timeout(Duration::from_secs(3))
-        .build()
-    {
-        Ok(c) => c,
-        Err(e) => {
-            warn!("Failed to initialize network checker: {}", e);
-            return;
-        }
-    };
-    for (url, name) in targets {
-        match client.head(url).send().await {
-            Ok(_) => {
-                info!("  ✓  {} reachable", name);
-            }
-            Err(e) => {
-                warn!("  ✗  {} NOT reachable", name);
-                if e.is_connect() || e.is_timeout() {
-                    warn!("     → Hint: check DNS (/etc/resolv.conf) or proxy (https_proxy)");
-                }
-            }
-        }
-    }
-    info!("Network check done — proceeding");
-}
-
 #[tokio::main]
 async fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let matches = <Cli as clap::CommandFactory>::command().get_matches();
+    let cli = match <Cli as clap::FromArgMatches>::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
+    };
+    if let Some(("download" | "plan", download)) = matches.subcommand() {
+        let _ = DOWNLOAD_MATCHES.set(download.clone());
+    }
+    PLAIN.store(cli.plain, std::sync::atomic::Ordering::Relaxed);
+    if cli.no_progress || !std::io::stderr().is_terminal() {
+        LOG_PROGRESS.store(true, std::sync::atomic::Ordering::Relaxed);
+        GLOBAL_MP.set_draw_target(ProgressDrawTarget::hidden());
+    }
 
     let output_dir = match &cli.command {
         Commands::Download(args) => args.output.clone(),
+        Commands::Plan(args) => job_file_dir(&args.job_file),
+        Commands::Execute(args) => args
+            .output
+            .clone()
+            .unwrap_or_else(|| job_file_dir(&args.job_file)),
         Commands::PublicData(args) => args.output.clone(),
         Commands::Validate(args) => args.dir.clone(),
         Commands::Md5(args) => match &args.command {
@@ -700,18 +1414,34 @@ async fn main() -> ExitCode {
                 .unwrap_or_else(|| PathBuf::from(".")),
             Md5Subcommand::Verify(v) => v.dir.clone(),
         },
-        Commands::Upload(_) | Commands::Deps(_) => PathBuf::from("."),
+        Commands::Worker(args) => args.output.clone(),
+        Commands::Metadata(args) => args.output.clone().unwrap_or_else(|| PathBuf::from(".")),
+        Commands::Upload(_)
+        | Commands::Deps(_)
+        | Commands::Init(_)
+        | Commands::Doctor(_)
+        | Commands::Clean(_)
+        | Commands::Control(_) => PathBuf::from("."),
     };
 
     let download_output: Option<&Path> = match &cli.command {
         Commands::Download(args) => Some(args.output.as_path()),
+        Commands::Execute(args) => args.output.as_deref(),
         Commands::PublicData(args) => Some(args.output.as_path()),
         Commands::Validate(args) => Some(args.dir.as_path()),
         Commands::Md5(args) => match &args.command {
             Md5Subcommand::Generate(g) => g.output.parent(),
             Md5Subcommand::Verify(v) => Some(v.dir.as_path()),
         },
-        Commands::Upload(_) | Commands::Deps(_) => None,
+        Commands::Worker(args) => Some(args.output.as_path()),
+        Commands::Metadata(args) => args.output.as_deref(),
+        Commands::Plan(_)
+        | Commands::Upload(_)
+        | Commands::Deps(_)
+        | Commands::Init(_)
+        | Commands::Doctor(_)
+        | Commands::Clean(_)
+        | Commands::Control(_) => None,
     };
     if let Some(output) = download_output {
         if let Err(e) = fs::create_dir_all(output) {
@@ -722,75 +1452,128 @@ async fn main() -> ExitCode {
 
     print_banner();
 
-    if let Err(e) = setup_logging(
+    // Held until exit so buffered spans are flushed.
+    let _otlp = match setup_logging(
         &output_dir,
         &cli.log_level,
         &cli.log_format,
+        cli.otlp_endpoint.as_deref(),
         match &cli.command {
             Commands::Download(args) => args.accession.as_deref(),
+            Commands::Plan(args) => args.download.accession.as_deref(),
             // md5 logs land next to the hashed data; the `md5` tag makes their
             // names match md5::MD5_LOG_PREFIX so hashing can skip them.
             Commands::Md5(_) => Some("md5"),
-            Commands::PublicData(_) | Commands::Validate(_) | Commands::Upload(_) | Commands::Deps(_) => None,
+            Commands::Execute(_)
+            | Commands::PublicData(_)
+            | Commands::Validate(_)
+            | Commands::Upload(_)
+            | Commands::Deps(_)
+            | Commands::Init(_)
+            | Commands::Doctor(_)
+            | Commands::Metadata(_)
+            | Commands::Clean(_)
+            | Commands::Worker(_)
+            | Commands::Control(_) => None,
         },
     ) {
-        eprintln!("Failed to setup logging: {}", e);
-        return ExitCode::FAILURE;
-    }
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Failed to setup logging: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    // Pre-validate YAML config for commands that require it — fail fast
-    // before spending time on network connectivity checks.
-    if matches!(&cli.command, Commands::Download(_)) {
+    // An explicit `-y` must exist — fail fast before spending time on
+    // network connectivity checks. Without it, downloads fall back to tools
+    // detected on PATH.
+    if matches!(
+        &cli.command,
+        Commands::Download(_) | Commands::Plan(_) | Commands::Execute(_) | Commands::Worker(_)
+    ) {
         let preflight: Result<()> = (|| {
-            let yp = yaml_path(&cli)?;
+            let Some(yp) = &cli.yaml else {
+                return Ok(());
+            };
             if !yp.exists() {
-                return Err(anyhow!(
+                return Err(PolariseqError::Config(format!(
                     "YAML configuration file not found: {}\n\
-                     Hint: pass the correct path with `-y <FILE>` or place polariseq.yaml next to the executable",
+                     Hint: fix the `-y <FILE>` path, or omit it to use polariseq.yaml next to the executable or tools on PATH",
                     yp.display()
-                ));
+                ))
+                .into());
             }
             Ok(())
         })();
         if let Err(e) = preflight {
-            error!("Application failed: {}", e);
-            eprintln!(
-                "\nAn error occurred. Please check the log file for detailed error information."
-            );
-            return ExitCode::FAILURE;
+            return report_failure(&e);
         }
     }
 
-    if !matches!(
-        &cli.command,
-        Commands::PublicData(_) | Commands::Validate(_) | Commands::Md5(_)
-    ) {
-        check_network_health().await;
+    let cassette = match (&cli.record_http, &cli.replay_http) {
+        (Some(path), _) => cassette::record(path),
+        (_, Some(path)) => cassette::replay(path),
+        (None, None) => Ok(()),
+    };
+    if let Err(e) = cassette {
+        return report_failure(&e);
     }
 
     let result: Result<()> = async {
         match &cli.command {
-            Commands::Download(args) => run_download(args, &cli).await,
+            Commands::Download(args) if !args.notify.is_empty() => {
+                let job = args
+                    .accession
+                    .clone()
+                    .or_else(|| args.tsv.as_ref().map(|t| t.display().to_string()))
+                    .unwrap_or_default();
+                let notifier = Arc::new(notify::Notifier::new(
+                    args.notify.clone(),
+                    &job,
+                    args.output.clone(),
+                )?);
+                let result = run_download(args, &cli, Some(notifier.clone()), None).await;
+                notifier.finish(&result).await;
+                result
+            }
+            Commands::Download(args) => run_download(args, &cli, None, None).await,
+            Commands::Plan(args) => {
+                run_download(&args.download, &cli, None, Some(&args.job_file)).await
+            }
+            Commands::Execute(args) => run_execute(args, &cli).await,
             Commands::PublicData(args) => run_public_data(args, &cli).await,
             Commands::Validate(args) => run_validate(args, &cli).await,
             Commands::Md5(args) => run_md5(args).await,
             Commands::Upload(args) => run_upload(args).await,
             Commands::Deps(args) => run_deps(args, &cli).await,
+            Commands::Init(args) => run_init(args, &cli),
+            Commands::Doctor(args) => run_doctor(args, &cli).await,
+            Commands::Metadata(args) => run_metadata(args).await,
+            Commands::Clean(args) => run_clean(args),
+            Commands::Worker(args) => run_worker(args, &cli).await,
+            Commands::Control(args) => run_control(args).await,
         }
     }
     .await;
 
     if let Err(e) = result {
-        tracing::error!("Application failed: {:?}", e);
-        eprintln!(
-            "\nAn error occurred. Please check the log file for detailed error information."
-        );
-        return ExitCode::FAILURE;
+        return report_failure(&e);
     }
 
     ExitCode::SUCCESS
 }
 
+/// Log the failure with its category and map it to the process exit code
+/// (see `polariseq_core::error::PolariseqError::exit_code`).
+fn report_failure(e: &anyhow::Error) -> ExitCode {
+    match error::classify(e) {
+        Some(kind) => tracing::error!("Application failed [{}]: {:?}", kind.kind(), e),
+        None => tracing::error!("Application failed: {:?}", e),
+    }
+    eprintln!("\nAn error occurred. Please check the log file for detailed error information.");
+    ExitCode::from(error::exit_code(e))
+}
+
 fn default_yaml_path() -> Result<PathBuf> {
     let executable =
         std::env::current_exe().context("Failed to locate the polariseq executable")?;
@@ -804,10 +1587,23 @@ fn yaml_path(cli: &Cli) -> Result<PathBuf> {
     cli.yaml.clone().map(Ok).unwrap_or_else(default_yaml_path)
 }
 
+/// Apply `--prefetch-path` / `--fasterq-dump-path` on top of the YAML and
+/// `POLARISEQ_*` environment values.
+fn apply_tool_overrides(cli: &Cli, config: &mut Config) {
+    if let Some(path) = &cli.prefetch_path {
+        config.software.prefetch = path.clone();
+    }
+    if let Some(path) = &cli.fasterq_dump_path {
+        config.software.fasterq_dump = path.clone();
+    }
+}
+
+#[cfg(feature = "aws")]
 async fn run_public_data(args: &PublicDataArgs, cli: &Cli) -> Result<()> {
     let yaml_path = yaml_path(cli)?;
-    let config = load_config(&yaml_path)
+    let mut config = load_config(&yaml_path)
         .with_context(|| format!("Failed to load public data config {}", yaml_path.display()))?;
+    apply_tool_overrides(cli, &mut config);
 
     // Start the global status bar (pinned at the bottom of GLOBAL_MP). For
     // public-data the total item count is filled in later by the downloader
@@ -822,7 +1618,7 @@ async fn run_public_data(args: &PublicDataArgs, cli: &Cli) -> Result<()> {
         .await?
         .with_workers(args.multithreads, args.aws_threads)
         .with_chunk_size_mb(args.chunk_size)
-        .with_progress(Arc::new(GLOBAL_MP.clone()));
+        .with_progress(progress_sink());
 
     let downloader = if let Some(ui) = &ui {
         downloader.with_observer(ui.clone() as Arc<dyn DownloadObserver>)
@@ -889,20 +1685,20 @@ async fn run_validate(args: &ValidateArgs, cli: &Cli) -> Result<()> {
 }
 
 async fn run_md5(args: &Md5Args) -> Result<()> {
-    // Per-file hashing bars share the global MultiProgress. On a non-TTY the
-    // bars would be hidden anyway, so skip them and keep logs on stderr.
-    let mp = if GLOBAL_MP.is_hidden() {
-        None
+    // Per-file hashing bars share the global MultiProgress; without a
+    // terminal, hashing progress is logged instead.
+    let mp = if log_progress() {
+        Some(progress_sink())
     } else {
         BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
-        Some(Arc::new(GLOBAL_MP.clone()))
+        Some(progress_sink())
     };
     let result = run_md5_command(args, mp).await;
     BARS_ACTIVE.store(false, std::sync::atomic::Ordering::Relaxed);
     result
 }
 
-async fn run_md5_command(args: &Md5Args, mp: Option<Arc<MultiProgress>>) -> Result<()> {
+async fn run_md5_command(args: &Md5Args, mp: Option<Arc<dyn ProgressSink>>) -> Result<()> {
     match &args.command {
         Md5Subcommand::Generate(generate_args) => {
             if !generate_args.input.exists() {
@@ -959,133 +1755,1073 @@ async fn run_md5_command(args: &Md5Args, mp: Option<Arc<MultiProgress>>) -> Resu
 // Download Command Entry Point (original main logic, unchanged)
 // ============================================================
 
-async fn run_download(args: &DownloadArgs, cli: &Cli) -> Result<()> {
-    let filters = RegexFilters {
-        include_sample: args
-            .filter_sample
-            .iter()
-            .map(|s| Regex::new(s))
-            .collect::<Result<Vec<_>, _>>()
-            .context("Invalid regex pattern for --filter-sample")?,
-        include_run: args
-            .filter_run
-            .iter()
-            .map(|s| Regex::new(s))
-            .collect::<Result<Vec<_>, _>>()
-            .context("Invalid regex pattern for --filter-run")?,
-        exclude_sample: args
-            .exclude_sample
-            .iter()
-            .map(|s| Regex::new(s))
-            .collect::<Result<Vec<_>, _>>()
-            .context("Invalid regex pattern for --exclude-sample")?,
-        exclude_run: args
-            .exclude_run
+/// Directory of `job_file`, for the logs of `plan` and `execute`.
+fn job_file_dir(job_file: &Path) -> PathBuf {
+    job_file
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// `download`, or with `job_file` (`plan`) fetch and resolve the job and
+/// write it there instead of downloading.
+async fn run_download(
+    args: &DownloadArgs,
+    cli: &Cli,
+    notifier: Option<Arc<notify::Notifier>>,
+    job_file: Option<&Path>,
+) -> Result<()> {
+    let yaml_path = yaml_path(cli)?;
+    let mut config =
+        load_config_or_detect(&yaml_path).context("Failed to load YAML configuration")?;
+    apply_tool_overrides(cli, &mut config);
+    let profiled;
+    let args = match &args.profile {
+        Some(name) => {
+            profiled = apply_profile(args, config.profile(name)?);
+            info!("Using profile '{}'", name);
+            &profiled
+        }
+        None => args,
+    };
+    if let Some(nice) = args.nice {
+        config.priority.nice = Some(nice);
+    }
+    if let Some(ionice) = args.ionice {
+        config.priority.ionice = Some(ionice);
+    }
+    if let Some(cpus) = &args.taskset {
+        config.priority.taskset = Some(cpus.clone());
+    }
+    if let Some(transport) = args.prefetch_transport {
+        config.prefetch.transport = Some(transport);
+    }
+    if let Some(resume) = args.prefetch_resume {
+        config.prefetch.resume = Some(resume);
+    }
+    config.prefetch.eliminate_quals |= args.eliminate_quals;
+    // Auto-tuning may go beyond the defaults of -p and -t.
+    let ceiling = |id: &str, value: usize, max: usize| {
+        if args.auto_tune && !given_explicitly(id) {
+            value.max(max)
+        } else {
+            value
+        }
+    };
+    let mut job = DownloadJob::from(DownloadOptions {
+        accession: args.accession.clone(),
+        tsv: args.tsv.clone(),
+        output: args.output.clone(),
+        download_method: args.download,
+        multithreads: ceiling(
+            "multithreads",
+            args.multithreads,
+            polariseq_core::autotune::MAX_RUNS,
+        ),
+        aws_threads: ceiling(
+            "aws_threads",
+            args.aws_threads,
+            polariseq_core::autotune::MAX_THREADS,
+        ),
+        chunk_size: args.chunk_size,
+        max_connections: Some(args.max_connections).filter(|cap| *cap > 0),
+        pe_only: args.pe_only,
+        filter_sample: args.filter_sample.clone(),
+        filter_run: args.filter_run.clone(),
+        exclude_sample: args.exclude_sample.clone(),
+        exclude_run: args.exclude_run.clone(),
+        cleanup_sra: args.cleanup_sra,
+        clean_stale_days: Some(args.clean_stale).filter(|days| *days > 0),
+        dry_run: args.dry_run,
+        sra_format: args.sra_format,
+        routes: args.route.clone(),
+        group_by: args.group_by,
+        sort_by: args.sort_by,
+        output_format: args.output_format,
+        prefer_bam: args.prefer_bam,
+        min_speed: args
+            .min_speed
+            .as_deref()
+            .map(|rate| -> Result<_> {
+                Ok(polariseq_core::speed::MinSpeed {
+                    bytes_per_sec: polariseq_core::control::parse_rate(rate)?,
+                    window_secs: polariseq_core::speed::parse_window(&args.min_speed_window)?,
+                })
+            })
+            .transpose()
+            .context("Invalid --min-speed")?,
+        auto_tune: args.auto_tune,
+        s3_http: args.s3_http,
+        aws_staging: args.aws_staging,
+        preallocate: args.preallocate,
+        fasterq_mem: args
+            .fasterq_mem
+            .as_deref()
+            .map(polariseq_core::control::parse_rate)
+            .transpose()
+            .context("Invalid --fasterq-mem")?,
+        max_memory: args
+            .max_memory
+            .as_deref()
+            .map(polariseq_core::control::parse_rate)
+            .transpose()
+            .context("Invalid --max-memory")?,
+        prefetch_batch: args.prefetch_batch,
+        md5_manifest: args.md5_manifest,
+        ..DownloadOptions::default()
+    });
+    RegexFilters::new(&job.options)?;
+    polariseq_core::route::parse_routes(&job.options.routes)?;
+    // EGA takes its own accessions, possibly several.
+    if args.download != DownloadMethod::Ega {
+        if let Some(accession) = &mut job.options.accession {
+            *accession = accession
+                .split(',')
+                .filter(|a| !a.trim().is_empty())
+                .map(|a| Ok(polariseq_core::accession::parse(a)?.0))
+                .collect::<Result<Vec<_>>>()?
+                .join(",");
+        }
+    }
+    if let Some(ngc) = &args.ngc {
+        job = job.credential(controlled::AccessCredential::from_path(ngc)?);
+    }
+    if let Some(reference) = args
+        .reference
+        .as_deref()
+        .filter(|r| !assembly::is_assembly_accession(r))
+    {
+        return Err(PolariseqError::InvalidInput(format!(
+            "--reference expects a GCA_/GCF_ assembly accession, got {}",
+            reference
+        ))
+        .into());
+    }
+
+    let gse = job
+        .options
+        .accession
+        .clone()
+        .filter(|a| geo::is_gse_accession(a));
+    if args.geo_suppl && gse.is_none() {
+        return Err(PolariseqError::InvalidInput(
+            "--geo-suppl requires a GSE accession (-A GSE…)".to_string(),
+        )
+        .into());
+    }
+
+    let needs_internet = args.download == DownloadMethod::Ega
+        || args.reference.is_some()
+        || args.geo_suppl
+        || args
+            .accession
+            .as_deref()
+            .is_some_and(assembly::is_assembly_accession);
+    if job_file.is_some() && needs_internet {
+        return Err(PolariseqError::InvalidInput(
+            "plan covers ENA runs only; EGA, assemblies, --reference and --geo-suppl need internet access when downloading"
+                .to_string(),
+        )
+        .into());
+    }
+
+    info!("Output directory: {}", args.output.display());
+    // With --remote the output directory is on the remote host; a plan's
+    // is on the compute side.
+    let _lock = match &args.remote {
+        None if job_file.is_none() => lock::OutputLock::acquire(&args.output, args.force)?,
+        _ => None,
+    };
+
+    if args.download == DownloadMethod::Ega {
+        return download_with_ega(&config, args).await;
+    }
+
+    if let Some(accession) = job
+        .options
+        .accession
+        .as_deref()
+        .filter(|a| assembly::is_assembly_accession(a))
+    {
+        return download_reference(accession, args).await;
+    }
+
+    let progress_store = new_progress_store();
+    let mut client = PolariseqClient::new(config)
+        .with_progress(progress_sink())
+        .with_progress_store(progress_store.clone());
+    if let Some(rate) = &args.bandwidth {
+        let limit = polariseq_core::control::parse_rate(rate).context("Invalid --bandwidth")?;
+        client.pause_token().set_bandwidth(Some(limit));
+    }
+    if let Some(notifier) = notifier {
+        client = client.with_hook(notifier);
+    }
+    let metrics = args.metrics_port.map(|_| metrics::Metrics::new());
+    if let Some(metrics) = &metrics {
+        client = client.with_metrics(metrics.clone());
+    }
+    // Built now so a bad --dest fails before metadata is fetched; added
+    // to the client after the plan-dependent hooks.
+    let dest_hook: Option<Arc<dyn polariseq_core::backend::RunHook>> =
+        match (&args.dest, args.dest_stream) {
+            (Some(dest), false) if RsyncTarget::matches(dest) => Some(Arc::new(RsyncHook::new(
+                dest.parse()?,
+                args.dest_remove_local,
+            )?)),
+            (Some(dest), false) if IrodsTarget::matches(dest) => Some(Arc::new(IrodsHook::new(
+                dest.parse()?,
+                args.dest_remove_local,
+            )?)),
+            (Some(dest), false) => Some(destination_hook(dest, args.dest_remove_local).await?),
+            _ => None,
+        };
+    // Missing or broken tools fail here, not after metadata was fetched.
+    // Scripts run elsewhere, so their tools need not exist here.
+    let local = job_file.is_none()
+        && !args.only_scripts
+        && args.submit.is_none()
+        && args.export_workflow.is_none()
+        && args.remote.is_none()
+        && args.coordinator.is_none()
+        && !args.dest_stream;
+    if local {
+        client.preflight(&job)?;
+        client.network_preflight(&job).await?;
+    }
+    let trim_hook = match args.trim {
+        Some(Trimmer::Fastp) if local => Some(Arc::new(FastpHook::new(
+            args.fastp_args.as_deref().unwrap_or_default(),
+        )?)),
+        _ => None,
+    };
+    let qc_hook = match args.qc {
+        Some(tool) if local => Some(Arc::new(QcHook::new(tool, args.qc_threads)?)),
+        _ => None,
+    };
+
+    // First Ctrl-C stops the job cleanly; partial downloads stay resumable.
+    let cancel = client.cancellation_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            warn!("Interrupted, stopping downloads (rerun to resume)...");
+            cancel.cancel();
+        }
+    });
+
+    let plan = if let Some(gse) = gse.as_deref() {
+        match geo::resolve_bioproject(gse).await {
+            Ok(project) => {
+                info!("{} is linked to BioProject {}", gse, project);
+                client.plan_records(&job, fetch_ena_data(&project).await?)?
+            }
+            // Series without raw data (e.g. arrays) still have processed files.
+            Err(e) if args.geo_suppl => {
+                warn!("{:#}", e);
+                download_geo_suppl(gse, args).await?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        client.plan(&job).await?
+    };
+    info!("Records after filtering: {}", plan.records.len());
+
+    if plan.records.is_empty() {
+        warn!("No records match the filter criteria. Exiting.");
+        return Ok(());
+    }
+
+    if let Some(path) = job_file {
+        let mut plan = plan;
+        plan.resolved_sra = jobfile::resolve_sra(&job, &plan).await?;
+        jobfile::JobFile::new(&job, &plan).write(path)?;
+        info!(
+            "Wrote {} run(s) to {}; download them with: polariseq execute {}",
+            plan.processed.len(),
+            path.display(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    client.write_metadata(&job, &plan)?;
+    let invocation: Vec<String> = std::env::args().collect();
+    provenance::write(client.config(), &job, &plan, &invocation).await?;
+
+    let protected_runs = &plan.protected_runs;
+    if !protected_runs.is_empty() {
+        match &job.credential {
+            Some(_) => info!(
+                "{} run(s) have no public files; skipping S3/ENA and fetching them with prefetch (controlled access)",
+                protected_runs.len()
+            ),
+            None => warn!(
+                "{} run(s) have no public files on ENA/S3 (e.g. {}). If they are dbGaP controlled-access runs, re-run with --ngc <prj_xxx.ngc>.",
+                protected_runs.len(),
+                protected_runs[0]
+            ),
+        }
+    }
+
+    if plan.is_empty(&job) {
+        warn!("Records were found, but none have downloadable FASTQ/SRA files. The data may not have been synced to SRA/ENA yet. Please try again later.");
+        return Ok(());
+    }
+    let fetch_protected = job.credential.is_some() && !protected_runs.is_empty();
+
+    if let (Some(dest), true) = (&args.dest, args.dest_stream) {
+        if RsyncTarget::matches(dest) || IrodsTarget::matches(dest) {
+            return Err(PolariseqError::InvalidInput(
+                "--dest-stream needs an s3:// or gs:// destination".to_string(),
+            )
+            .into());
+        }
+        return stream_to_destination(dest, &client, &job, &plan).await;
+    }
+
+    if let Some(addr) = &args.coordinator {
+        return serve_queue(addr, &job, &plan).await;
+    }
+
+    if let Some(target) = &args.remote {
+        let cancel = client.cancellation_token();
+        return remote::run_remote(client.config(), &job, &plan, target, cancel).await;
+    }
+
+    if let Some(engine) = args.export_workflow {
+        let pipeline = workflow::export_workflow(client.config(), &job, &plan, engine).await?;
+        info!(
+            "Wrote {} (input: {})",
+            pipeline.display(),
+            workflow::runs_sheet(&pipeline).display()
+        );
+        return Ok(());
+    }
+
+    if let Some(scripts::SubmitTarget::Slurm) = args.submit {
+        let array =
+            scripts::write_slurm_array(client.config(), &job, &plan, args.array_throttle).await?;
+        info!(
+            "Wrote job array of {} task(s), at most {} at once: {}",
+            array.tasks,
+            array.throttle,
+            array.script.display()
+        );
+        if polariseq_core::deps::locate_tool("sbatch").is_some() {
+            info!("Submitted job array {}", array.submit()?);
+        } else {
+            info!("sbatch not found; submit from a cluster node with:");
+            info!("   {}", array.sbatch_command());
+        }
+        return Ok(());
+    }
+
+    if args.only_scripts {
+        let protected = if fetch_protected {
+            protected_runs.len()
+        } else {
+            0
+        };
+        let runs = plan.processed.len() + protected;
+        let limit = inodes::file_limit(job.output(), args.max_files);
+        if let Some(limit) = limit.filter(|l| inodes::script_files(runs, false) > *l) {
+            warn!(
+                "{} per-run scripts would exceed the file limit of {}; writing one combined script",
+                runs, limit
+            );
+            let script =
+                scripts::write_combined_script(client.config(), &job, &plan, args.script_flavor)
+                    .await?;
+            info!("Wrote {} run(s) to {}", runs, script.display());
+            return Ok(());
+        }
+        let written =
+            scripts::write_scripts(client.config(), &job, &plan, args.script_flavor).await?;
+        info!(
+            "Wrote {} script(s) to {}",
+            written.len(),
+            job.output().join(scripts::SCRIPTS_DIR).display()
+        );
+        let runs: Vec<String> = written
             .iter()
-            .map(|s| Regex::new(s))
-            .collect::<Result<Vec<_>, _>>()
-            .context("Invalid regex pattern for --exclude-run")?,
+            .filter_map(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .collect();
+        for runner in &args.script_runners {
+            let path = scripts::write_runner(job.output(), *runner, &runs, args.multithreads)?;
+            info!("Wrote {}", path.display());
+        }
+        return Ok(());
+    }
+
+    if args.bench_s3_http {
+        return bench_s3_http(&job, &plan).await;
+    }
+
+    if args.print_urls || args.export_aria2.is_some() {
+        let files = polariseq_core::urls::planned_files(&job, &plan).await?;
+        if let Some(path) = &args.export_aria2 {
+            fs::write(path, polariseq_core::urls::aria2_input(&files))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            info!(
+                "Wrote {} file(s) to {} (aria2c -i {})",
+                files.len(),
+                path.display(),
+                path.display()
+            );
+        }
+        if args.print_urls {
+            print!("{}", polariseq_core::urls::url_list(&files));
+        }
+        return Ok(());
+    }
+
+    let mut files = inodes::download_files(&job, &plan);
+    if let Some(limit) = inodes::file_limit(job.output(), args.max_files) {
+        if files.total() > limit && !job.options.cleanup_sra {
+            let lean = job.clone().cleanup_sra(true);
+            let lean_files = inodes::download_files(&lean, &plan);
+            if lean_files.total() <= limit {
+                warn!(
+                    "About {} files would exceed the file limit of {}; removing .sra files after conversion (--cleanup-sra)",
+                    files.total(),
+                    limit
+                );
+                job = lean;
+                files = lean_files;
+            }
+        }
+        if files.total() > limit {
+            warn!(
+                "The job needs about {} files ({} kept, {} temporary) but the output allows {}; split the job, lower -p or use another volume",
+                files.total(),
+                files.kept,
+                files.temporary,
+                limit
+            );
+        }
+    }
+
+    if args.dry_run {
+        info!(
+            "Estimated files: {} ({} kept, {} temporary)",
+            files.total(),
+            files.kept,
+            files.temporary
+        );
+        info!("Dry Run Mode: Listing files that would be downloaded:");
+        for record in &plan.processed {
+            info!("   [{}]", record.run_accession);
+            info!(
+                "      - File 1: {} ({})",
+                record.fastq_ftp_1_name,
+                HumanBytes(record.fastq_bytes_1)
+            );
+
+            if let (Some(name), Some(size)) = (&record.fastq_ftp_2_name, record.fastq_bytes_2) {
+                info!("      - File 2: {} ({})", name, HumanBytes(size));
+            }
+        }
+        if fetch_protected {
+            for run_id in protected_runs {
+                info!("   [{}] controlled access (prefetch --ngc)", run_id);
+            }
+        }
+        if let Some(reference) = &args.reference {
+            download_reference(reference, args).await?;
+        }
+        if let (true, Some(gse)) = (args.geo_suppl, gse.as_deref()) {
+            download_geo_suppl(gse, args).await?;
+        }
+        info!("Dry Run completed. No files were downloaded.");
+        return Ok(());
+    }
+
+    if !args.on_start.is_empty() {
+        client = client.with_hook(Arc::new(ShellHook::new(
+            args.on_start.clone(),
+            &plan.records,
+        )));
+    }
+    // Hooks run in order: trimming adds files QC, the manifest and the
+    // report cover, and all read the files before --dest-remove-local
+    // deletes them.
+    if let Some(hook) = trim_hook {
+        client = client.with_hook(hook);
+    }
+    if let Some(hook) = qc_hook {
+        client = client.with_hook(hook);
+    }
+    if args.tenx_names {
+        client = client.with_hook(Arc::new(TenxHook::new(&plan.records)));
+    }
+    if args.manifest {
+        let manifest =
+            ManifestHook::new(client.config(), &job, &plan, std::env::args().collect()).await?;
+        client = client.with_hook(Arc::new(manifest));
+    }
+    if args.info_json {
+        client = client.with_hook(Arc::new(InfoJsonHook::new(&job, &plan)));
+    }
+    if args.multiqc {
+        client = client.with_hook(Arc::new(MultiqcHook::new(&args.output)));
+    }
+    if let Some(hook) = dest_hook {
+        client = client.with_hook(hook);
+    }
+
+    let control_stop = CancellationToken::new();
+    if args.control {
+        spawn_control_socket(&args.output, &client, control_stop.clone())?;
+    }
+
+    if let (Some(port), Some(metrics)) = (args.metrics_port, metrics) {
+        tokio::spawn(async move {
+            if let Err(e) = http_server::start_metrics_server(port, metrics).await {
+                tracing::error!("Metrics server failed: {}", e);
+            }
+        });
+    }
+
+    if let Some(port) = args.progress_port {
+        if args.write_progress_key {
+            let key_hex = http_server::progress_key_hex();
+            let key_path = args.output.join("progress.key");
+            fs::write(&key_path, &key_hex)?;
+            info!("Progress key written to {}", key_path.display());
+        }
+
+        let store = progress_store.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http_server::start_progress_server(port, store).await {
+                tracing::error!("Progress server failed: {}", e);
+            }
+        });
+    }
+
+    // The status bar only tracks the AWS pipeline; FTP draws its own bars.
+    let ui = (args.download == DownloadMethod::Aws && !plan.processed.is_empty()).then(|| {
+        UiManager::start(
+            GLOBAL_MP.clone(),
+            Mode::Sra {
+                store: progress_store.clone(),
+            },
+            plan.processed.len() as u64,
+        )
+    });
+    let client = match &ui {
+        Some(ui) => {
+            BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+            client.with_observer(ui.clone() as Arc<dyn DownloadObserver>)
+        }
+        None => client,
     };
+    let result = client.execute(&job, &plan).await;
+    control_stop.cancel();
+    if let Some(ui) = ui {
+        BARS_ACTIVE.store(false, std::sync::atomic::Ordering::Relaxed);
+        ui.stop();
+    }
+    result?;
+
+    if let Some(reference) = &args.reference {
+        download_reference(reference, args).await?;
+    }
+
+    if let (true, Some(gse)) = (args.geo_suppl, gse.as_deref()) {
+        download_geo_suppl(gse, args).await?;
+    }
+
+    if args.bagit {
+        polariseq_core::bagit::make_bag(&args.output, &plan, args.accession.as_deref())?;
+    }
+    if args.ro_crate {
+        polariseq_core::rocrate::make_crate(&args.output, &plan, args.accession.as_deref())?;
+    }
+
+    info!("{} download completed successfully!", SCRIPT_NAME);
+    Ok(())
+}
+
+// ============================================================
+// Upload Command Entry Point (NEW)
+// ============================================================
+
+#[cfg(feature = "aws")]
+async fn run_upload(args: &UploadArgs) -> Result<()> {
+    warn!("The upload subcommand is still under testing. Use with caution.");
+    polariseq_core::upload::run_upload(
+        &args.bucket,
+        &args.prefix,
+        &args.files,
+        &args.region,
+        args.concurrent,
+        args.apply_policy,
+        &args.metadata_template,
+        args.dry_run,
+        None,
+    )
+    .await
+}
+
+#[cfg(not(feature = "aws"))]
+async fn run_public_data(_args: &PublicDataArgs, _cli: &Cli) -> Result<()> {
+    Err(missing_aws_feature("public-data"))
+}
+
+#[cfg(not(feature = "aws"))]
+async fn run_upload(_args: &UploadArgs) -> Result<()> {
+    Err(missing_aws_feature("upload"))
+}
+
+#[cfg(feature = "aws")]
+async fn destination_hook(
+    url: &str,
+    remove_local: bool,
+) -> Result<Arc<dyn polariseq_core::backend::RunHook>> {
+    let dest: polariseq_core::dest::Destination = url.parse()?;
+    Ok(Arc::new(
+        polariseq_core::dest::ObjectStoreHook::new(dest, remove_local).await?,
+    ))
+}
+
+/// `--bench-s3-http`: time both HTTP versions against the AWS copy of the
+/// first run that has one.
+async fn bench_s3_http(job: &DownloadJob, plan: &polariseq_core::client::JobPlan) -> Result<()> {
+    use polariseq_core::aws_s3::{benchmark_http, S3Http, SraLookup, SraUtils, HTTP_BENCH_BYTES};
+
+    let mut found = None;
+    for record in &plan.records {
+        let lookup =
+            SraUtils::get_metadata_for_format(&record.run_accession, job.options.sra_format)
+                .await?;
+        if let SraLookup::Found(metadata) = lookup {
+            found = Some((record.run_accession.clone(), metadata));
+            break;
+        }
+    }
+    let Some((run_id, metadata)) = found else {
+        let reason = "No run of the job has an AWS copy".to_string();
+        return Err(PolariseqError::Metadata(reason).into());
+    };
+    info!(
+        "Benchmarking {} of {} with {} concurrent range requests",
+        HumanBytes(HTTP_BENCH_BYTES.min(metadata.size)),
+        run_id,
+        job.options.aws_threads
+    );
+    let results = benchmark_http(&metadata, HTTP_BENCH_BYTES, job.options.aws_threads).await?;
+    let flag = |mode| match mode {
+        S3Http::Http1 => "http1",
+        S3Http::Http2 => "http2",
+    };
+    for bench in &results {
+        info!(
+            "   {}: {} in {:.1}s ({}/s), negotiated {}, first byte after {:.0} ms",
+            flag(bench.mode),
+            HumanBytes(bench.bytes),
+            bench.seconds,
+            HumanBytes(bench.bytes_per_sec()),
+            bench.negotiated,
+            bench.first_byte_seconds * 1000.0
+        );
+    }
+    if let Some(best) = results.iter().max_by_key(|b| b.bytes_per_sec()) {
+        info!("Faster on this route: --s3-http {}", flag(best.mode));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "aws")]
+async fn stream_to_destination(
+    url: &str,
+    client: &PolariseqClient,
+    job: &DownloadJob,
+    plan: &polariseq_core::client::JobPlan,
+) -> Result<()> {
+    use polariseq_core::dest::{mirror_plan, ObjectStore};
+
+    let store = ObjectStore::connect(url.parse()?).await?;
+    mirror_plan(store, job, plan, client.cancellation_token()).await
+}
+
+#[cfg(not(feature = "aws"))]
+async fn stream_to_destination(
+    _url: &str,
+    _client: &PolariseqClient,
+    _job: &DownloadJob,
+    _plan: &polariseq_core::client::JobPlan,
+) -> Result<()> {
+    Err(missing_aws_feature("--dest-stream"))
+}
+
+#[cfg(not(feature = "aws"))]
+async fn destination_hook(
+    _url: &str,
+    _remove_local: bool,
+) -> Result<Arc<dyn polariseq_core::backend::RunHook>> {
+    Err(missing_aws_feature("--dest"))
+}
+
+#[cfg(not(feature = "aws"))]
+fn missing_aws_feature(command: &str) -> anyhow::Error {
+    PolariseqError::Config(format!(
+        "`{}` needs polariseq built with the `aws` cargo feature",
+        command
+    ))
+    .into()
+}
+
+// ============================================================
+// Init Command Entry Point
+// ============================================================
+
+fn run_init(args: &InitArgs, cli: &Cli) -> Result<()> {
     let yaml_path = yaml_path(cli)?;
-    let config = load_config(&yaml_path).context("Failed to load YAML configuration")?;
+    if yaml_path.exists() && !args.force {
+        return Err(PolariseqError::InvalidInput(format!(
+            "{} already exists; pass --force to overwrite it",
+            yaml_path.display()
+        ))
+        .into());
+    }
+
+    let mut config = Config::detect();
+    apply_tool_overrides(cli, &mut config);
+    fs::write(
+        &yaml_path,
+        polariseq_core::deps::config_template(&config.software),
+    )
+    .with_context(|| format!("Failed to write {}", yaml_path.display()))?;
+    info!("Wrote {}", yaml_path.display());
+
+    let config = load_config(&yaml_path).context("Generated config does not load")?;
+    let software = &config.software;
+    let mut missing = 0;
+    for (name, path) in [
+        ("prefetch", &software.prefetch),
+        ("fasterq-dump", &software.fasterq_dump),
+    ] {
+        if path.exists() {
+            info!("  ✓  {}: {}", name, path.display());
+        } else {
+            missing += 1;
+            warn!("  ✗  {} not found", name);
+        }
+    }
+    if let Some(path) = software.blastdbcmd.as_ref().filter(|p| p.exists()) {
+        info!("  ✓  blastdbcmd: {}", path.display());
+    }
+    if let Some(path) = software.samtools.as_ref().filter(|p| p.exists()) {
+        info!("  ✓  samtools: {}", path.display());
+    }
+    if missing > 0 {
+        warn!(
+            "FTP downloads work as is. For AWS/prefetch downloads run `{} deps install`, or edit {}",
+            SCRIPT_NAME,
+            yaml_path.display()
+        );
+    }
+    Ok(())
+}
+
+// ============================================================
+// Control Socket
+// ============================================================
+
+#[cfg(unix)]
+fn spawn_control_socket(
+    output: &Path,
+    client: &PolariseqClient,
+    stop: CancellationToken,
+) -> Result<()> {
+    let path = output.join(polariseq_core::control::CONTROL_SOCKET);
+    let controller = polariseq_core::control::Controller::new(client);
+    tokio::spawn(async move {
+        if let Err(e) = polariseq_core::control::serve(&path, controller, stop).await {
+            tracing::error!("Control socket failed: {:#}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_control_socket(
+    _output: &Path,
+    _client: &PolariseqClient,
+    _stop: CancellationToken,
+) -> Result<()> {
+    Err(PolariseqError::InvalidInput("--control needs Unix domain sockets".into()).into())
+}
+
+#[cfg(unix)]
+async fn run_control(args: &ControlArgs) -> Result<()> {
+    let line = args.command.join(" ");
+    // Validate locally for a clear error before connecting.
+    line.parse::<polariseq_core::control::ControlCommand>()?;
+    let path = if args.target.is_dir() {
+        args.target.join(polariseq_core::control::CONTROL_SOCKET)
+    } else {
+        args.target.clone()
+    };
+    let reply = polariseq_core::control::send(&path, &line).await?;
+    match reply.strip_prefix("error ") {
+        Some(message) => Err(anyhow!("{}", message)),
+        None => {
+            info!("{}", reply.trim_start_matches("ok "));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_control(_args: &ControlArgs) -> Result<()> {
+    Err(PolariseqError::InvalidInput("`control` needs Unix domain sockets".into()).into())
+}
+
+// ============================================================
+// Doctor Command Entry Point
+// ============================================================
+
+// Coordinator side of `download --coordinator`: serve the plan, wait for
+// every run to be reported, then write distributed_report.json.
+async fn serve_queue(
+    addr: &str,
+    job: &DownloadJob,
+    plan: &polariseq_core::client::JobPlan,
+) -> Result<()> {
+    use polariseq_core::distributed::WorkQueue;
 
-    info!("Output directory: {}", args.output.display());
+    if !plan.protected_runs.is_empty() {
+        warn!(
+            "{} controlled-access run(s) are not distributed",
+            plan.protected_runs.len()
+        );
+    }
+    let queue = WorkQueue::new(job.options.clone(), plan.processed.clone());
+    let server = tokio::spawn(http_server::start_queue_server(
+        addr.to_string(),
+        queue.clone(),
+    ));
+    info!(
+        "Serving {} run(s); start workers with: polariseq worker -c http://<this-host>:{} -o <dir>",
+        plan.processed.len(),
+        addr.rsplit(':').next().unwrap_or_default()
+    );
 
-    let records = if let Some(accession) = &args.accession {
-        fetch_ena_data(accession).await?
-    } else if let Some(tsv_path) = &args.tsv {
-        read_tsv_data(tsv_path)?
-    } else {
-        return Err(anyhow!("Either --accession or --tsv must be provided"));
+    let status = tokio::select! {
+        status = queue.wait_finished() => status,
+        result = server => {
+            result??;
+            return Err(anyhow!("Work queue server stopped"));
+        }
     };
+    let report_path = job.output().join("distributed_report.json");
+    fs::write(&report_path, serde_json::to_string_pretty(&status)?)?;
+    info!(
+        "All runs reported: {} succeeded, {} failed (report: {})",
+        status.succeeded,
+        status.failed,
+        report_path.display()
+    );
+    if status.failed > 0 {
+        return Err(anyhow!("{} run(s) failed on workers", status.failed));
+    }
+    Ok(())
+}
+
+async fn run_execute(args: &ExecuteArgs, cli: &Cli) -> Result<()> {
+    let mut config = load_config_or_detect(&yaml_path(cli)?)?;
+    apply_tool_overrides(cli, &mut config);
+    let file = jobfile::JobFile::read(&args.job_file)?;
+    info!(
+        "Job file {} written by polariseq {} on {}",
+        args.job_file.display(),
+        file.polariseq,
+        file.created
+    );
+    let (mut job, plan) = file.into_job();
+    if let Some(output) = &args.output {
+        job.options.output = output.clone();
+    }
+    info!("Output directory: {}", job.output().display());
+    fs::create_dir_all(job.output())
+        .with_context(|| format!("Failed to create {}", job.output().display()))?;
+    let _lock = lock::OutputLock::acquire(job.output(), args.force)?;
+    if !plan.protected_runs.is_empty() {
+        warn!(
+            "{} run(s) without public files are not downloaded by execute",
+            plan.protected_runs.len()
+        );
+    }
 
-    info!("Total records fetched: {}", records.len());
-    let filtered_records = apply_filters(records, &filters)?;
-    info!("Records after filtering: {}", filtered_records.len());
+    let client = PolariseqClient::new(config).with_progress(progress_sink());
+    client.preflight(&job)?;
+    let cancel = client.cancellation_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            warn!("Interrupted, stopping downloads (rerun to resume)...");
+            cancel.cancel();
+        }
+    });
 
-    if filtered_records.is_empty() {
-        warn!("No records match the filter criteria. Exiting.");
-        return Ok(());
+    client.write_metadata(&job, &plan)?;
+    let invocation: Vec<String> = std::env::args().collect();
+    provenance::write(client.config(), &job, &plan, &invocation).await?;
+    if !job.options.dry_run && !plan.is_empty(&job) {
+        client.execute(&job, &plan).await?;
     }
+    info!("{} download completed successfully!", SCRIPT_NAME);
+    Ok(())
+}
 
-    save_metadata_tsv(&filtered_records, &args.output, args.accession.as_deref())?;
+async fn run_worker(args: &WorkerArgs, cli: &Cli) -> Result<()> {
+    let mut config = load_config_or_detect(&yaml_path(cli)?)?;
+    apply_tool_overrides(cli, &mut config);
+    let name = args.name.clone().unwrap_or_else(|| {
+        std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| {
+                fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .map(|h| h.trim().to_string())
+            })
+            .unwrap_or_else(|| format!("worker-{}", std::process::id()))
+    });
+
+    let client = PolariseqClient::new(config).with_progress(progress_sink());
+    let cancel = client.cancellation_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            warn!("Interrupted, finishing the current run report...");
+            cancel.cancel();
+        }
+    });
 
-    let processed = process_records(filtered_records, args.pe_only, None)?;
-    save_md5_files(&processed, &args.output, args.accession.as_deref())?;
+    let summary =
+        polariseq_core::distributed::run_worker(&client, &args.coordinator, &name, &args.output)
+            .await?;
+    info!(
+        "Worker {} finished: {} succeeded, {} failed",
+        name, summary.succeeded, summary.failed
+    );
+    Ok(())
+}
 
-    if processed.is_empty() {
-        warn!("Records were found, but none have downloadable FASTQ/SRA files. The data may not have been synced to SRA/ENA yet. Please try again later.");
-        return Ok(());
-    }
+async fn run_doctor(args: &DoctorArgs, cli: &Cli) -> Result<()> {
+    use polariseq_core::doctor::{run_checks, CheckStatus};
 
-    if args.dry_run {
-        info!("Dry Run Mode: Listing files that would be downloaded:");
-        for record in &processed {
-            info!("   [{}]", record.run_accession);
-            info!(
-                "      - File 1: {} ({})",
-                record.fastq_ftp_1_name,
-                HumanBytes(record.fastq_bytes_1)
-            );
+    let mut config = load_config_or_detect(&yaml_path(cli)?)?;
+    apply_tool_overrides(cli, &mut config);
+    let checks = run_checks(&config, &args.output, args.min_free_gb * 1024 * 1024 * 1024).await;
 
-            if let (Some(name), Some(size)) = (&record.fastq_ftp_2_name, record.fastq_bytes_2) {
-                info!("      - File 2: {} ({})", name, HumanBytes(size));
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for check in &checks {
+            match check.status {
+                CheckStatus::Ok => info!("  ✓  {}: {}", check.name, check.detail),
+                CheckStatus::Warn => warn!("  !  {}: {}", check.name, check.detail),
+                CheckStatus::Fail => warn!("  ✗  {}: {}", check.name, check.detail),
+            }
+            if let Some(fix) = &check.fix {
+                warn!("     → {}", fix);
             }
         }
-        info!("Dry Run completed. No files were downloaded.");
-        return Ok(());
     }
 
-    let progress_store = new_progress_store();
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} checks failed (unused backends can be ignored)",
+            failed,
+            checks.len()
+        ));
+    }
+    info!("All required checks passed");
+    Ok(())
+}
 
-    if let Some(port) = args.progress_port {
-        if args.write_progress_key {
-            let key_hex = http_server::progress_key_hex();
-            let key_path = args.output.join("progress.key");
-            fs::write(&key_path, &key_hex)?;
-            info!("Progress key written to {}", key_path.display());
-        }
+// ============================================================
+// Metadata Command Entry Point
+// ============================================================
 
-        let store = progress_store.clone();
-        tokio::spawn(async move {
-            if let Err(e) = http_server::start_progress_server(port, store).await {
-                tracing::error!("Progress server failed: {}", e);
-            }
-        });
+async fn run_metadata(args: &MetadataArgs) -> Result<()> {
+    let mut records = client::fetch_accession(&args.accession).await?;
+    SortBy::Run.sort(&mut records);
+    if let Some(output) = &args.output {
+        save_metadata_tsv(&records, output, Some(&args.accession))?;
     }
+    let Some(old) = &args.diff else {
+        info!("{} runs at ENA for {}", records.len(), args.accession);
+        return Ok(());
+    };
 
-    match args.download {
-        DownloadMethod::Ftp => {
-            download_with_ftp(&processed, &config, args).await?;
+    let previous =
+        read_tsv_data(old).with_context(|| format!("Failed to read {}", old.display()))?;
+    let diff = metadata_diff::diff(&previous, &records);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        for run in &diff.added {
+            println!("added\t{}", run);
         }
-        DownloadMethod::Aws => {
-            validate_config(&config, DownloadMethod::Aws)?;
-            download_with_aws(&processed, &config, args, progress_store.clone()).await?;
+        for run in &diff.removed {
+            println!("removed\t{}", run);
+        }
+        for change in &diff.changed {
+            for field in &change.fields {
+                println!(
+                    "changed\t{}\t{}\t{}\t{}",
+                    change.run_accession, field.field, field.old, field.new
+                );
+            }
         }
     }
-
-    info!("{} download completed successfully!", SCRIPT_NAME);
+    info!(
+        "{} new, {} removed and {} changed runs since {}",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        old.display()
+    );
+    let runs = diff.to_download();
+    if !runs.is_empty() {
+        info!("To download again: {}", runs.join(","));
+    }
     Ok(())
 }
 
 // ============================================================
-// Upload Command Entry Point (NEW)
+// Clean Command Entry Point
 // ============================================================
 
-async fn run_upload(args: &UploadArgs) -> Result<()> {
-    warn!("The upload subcommand is still under testing. Use with caution.");
-    polariseq_core::upload::run_upload(
-        &args.bucket,
-        &args.prefix,
-        &args.files,
-        &args.region,
-        args.concurrent,
-        args.apply_policy,
-        &args.metadata_template,
-        args.dry_run,
-        None,
-    )
-    .await
+fn run_clean(args: &CleanArgs) -> Result<()> {
+    // A running job's resume state is not stale, however old.
+    let _lock = lock::OutputLock::acquire(&args.output, args.force)?;
+    let report = clean::clean(&args.output, clean::days(args.days), args.dry_run)?;
+    for path in &report.removed {
+        info!("  {}", path.display());
+    }
+    let verb = if args.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    info!(
+        "{} {} stale entries older than {} days ({})",
+        verb,
+        report.removed.len(),
+        args.days,
+        HumanBytes(report.bytes)
+    );
+    Ok(())
 }
 
 // ============================================================
@@ -1149,7 +2885,9 @@ async fn run_deps(args: &DepsArgs, cli: &Cli) -> Result<()> {
         DepsSubcommand::Check => {
             let yaml_path = yaml_path(cli)?;
             let config = if yaml_path.exists() {
-                Some(load_config(&yaml_path)?)
+                let mut config = load_config(&yaml_path)?;
+                apply_tool_overrides(cli, &mut config);
+                Some(config)
             } else {
                 None
             };
@@ -1190,6 +2928,10 @@ async fn run_deps(args: &DepsArgs, cli: &Cli) -> Result<()> {
 }
 
 fn print_banner() {
+    if PLAIN.load(std::sync::atomic::Ordering::Relaxed) {
+        println!("\nPOLARISEQ - Sequencing Data Toolkit | v{}\n", VERSION);
+        return;
+    }
     // Full-string lines (not `\`-continued) so leading indent is preserved.
     // Single solid color — clean, not flashy.
     const LINES: &[&str] = &[
@@ -1201,9 +2943,15 @@ fn print_banner() {
         "    ╚═╝      ╚═════╝ ╚══════╝╚═╝  ╚═╝╚═╝  ╚═╝╚═╝╚══════╝╚══════╝ ╚══▀▀═╝",
     ];
 
+    // No escapes in nohup/SLURM output files.
+    let (logo, accent) = if log_progress() {
+        (Style::new(), Style::new())
+    } else {
+        (Color::White.bold(), Color::Cyan.normal())
+    };
     println!();
     for line in LINES {
-        println!("{}", Color::White.bold().paint(*line));
+        println!("{}", logo.paint(*line));
     }
     // Center subtitle + quote under the ASCII logo (width 72).
     const LOGO_WIDTH: usize = 72;
@@ -1213,7 +2961,7 @@ fn print_banner() {
     };
     println!(
         "{}",
-        Color::Cyan.paint(center(&format!(
+        accent.paint(center(&format!(
             "Sequencing Data Toolkit  │  v{}",
             VERSION
         )))
@@ -1223,13 +2971,24 @@ fn print_banner() {
         "We are only borrowing these atoms from the universe, for a brief",
         "experience of this world.",
     ] {
-        println!("{}", Color::Cyan.paint(center(line)));
+        println!("{}", accent.paint(center(line)));
     }
     println!();
 }
 
 /// One-line pass/fail summary for validate / md5 verify (avoids double-emoji clutter).
 fn print_summary_line(label: &str, passed: usize, failed: usize, fail_word: &str) {
+    if log_progress() {
+        let mark = if failed > 0 { "✗" } else { "✓" };
+        eprintln!(
+            "{}",
+            plain_text(&format!(
+                "\n{} {}  ·  {} passed  ·  {} {}",
+                mark, label, passed, failed, fail_word
+            ))
+        );
+        return;
+    }
     let ok = Color::Green.bold().paint(format!("{} passed", passed));
     let bad = if failed > 0 {
         Color::Red.bold().paint(format!("{} {}", failed, fail_word))
@@ -1241,15 +3000,19 @@ fn print_summary_line(label: &str, passed: usize, failed: usize, fail_word: &str
     } else {
         Color::Green.bold().paint(format!("✓ {}", label))
     };
-    eprintln!("\n{}  ·  {}  ·  {}", head, ok, bad);
+    eprintln!(
+        "{}",
+        plain_text(&format!("\n{}  ·  {}  ·  {}", head, ok, bad))
+    );
 }
 
 fn setup_logging(
     output_dir: &Path,
     log_level: &str,
     format: &LogFormat,
+    otlp_endpoint: Option<&str>,
     tag: Option<&str>,
-) -> Result<()> {
+) -> Result<Option<otlp::OtlpGuard>> {
     use tracing_subscriber::{layer::SubscriberExt, Layer};
     let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
     // `tag` marks the log producer: the accession for downloads, the
@@ -1260,23 +3023,50 @@ fn setup_logging(
         format!("{}_{}.log", SCRIPT_NAME, timestamp)
     };
     let log_path = output_dir.join(&log_name);
+    // md5 logs sit next to hashed data; leave them and their neighbours be.
+    if tag != Some("md5") {
+        if let Err(e) = run_logs::rotate(output_dir, &format!("{}_", SCRIPT_NAME), &log_path) {
+            eprintln!("Failed to rotate old logs: {}", e);
+        }
+    }
     let file = File::create(&log_path)?;
 
     // File layer always uses simple text for readability
+    let file_writer = if PLAIN.load(std::sync::atomic::Ordering::Relaxed) {
+        BoxMakeWriter::new(std::sync::Mutex::new(plain::Plain(file)))
+    } else {
+        BoxMakeWriter::new(file)
+    };
     let file_layer = fmt::layer()
-        .with_writer(file)
+        .with_writer(file_writer)
         .with_ansi(false)
         .with_target(true)
         .with_thread_ids(true)
         .with_timer(fmt::time::LocalTime::rfc_3339())
-        .with_filter(EnvFilter::new("debug"));
+        .with_filter(EnvFilter::new("debug,commands=off"));
+    let run_log_layer = run_logs::RunLogLayer::new(output_dir).with_filter(EnvFilter::new(
+        "polariseq_core=debug,polariseq=debug,download_detail=debug",
+    ));
+    let timings_layer =
+        timings::TimingsLayer::new(output_dir).with_filter(EnvFilter::new("polariseq_core=info"));
+    let command_log_layer = command_log::CommandLogLayer::new(output_dir);
 
     let mut stdout_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level));
-    if let Ok(directive) = "download_detail=off".parse() {
-        stdout_filter = stdout_filter.add_directive(directive);
+    for directive in ["download_detail=off", "commands=off"] {
+        if let Ok(directive) = directive.parse() {
+            stdout_filter = stdout_filter.add_directive(directive);
+        }
     }
 
+    let (otlp_layer, otlp_guard) = match otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, guard) = otlp::layer(endpoint)?;
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
     // stdout layer writes through MpWriter so that log messages are rendered
     // above active progress bars via MultiProgress::println(), preventing
     // display corruption when progress bars and logs share the terminal.
@@ -1291,7 +3081,11 @@ fn setup_logging(
                 .with_filter(stdout_filter);
 
             let subscriber = tracing_subscriber::registry()
+                .with(otlp_layer)
                 .with(file_layer)
+                .with(run_log_layer)
+                .with(timings_layer)
+                .with(command_log_layer)
                 .with(json_layer);
             tracing::subscriber::set_global_default(subscriber)
                 .context("Failed to set subscriber")?;
@@ -1300,11 +3094,16 @@ fn setup_logging(
             let stdout_layer = fmt::layer()
                 .compact()
                 .event_format(ColoredFormatter)
+                .with_ansi(!log_progress())
                 .with_writer(|| MpWriter { buf: Vec::new() })
                 .with_filter(stdout_filter);
 
             let subscriber = tracing_subscriber::registry()
+                .with(otlp_layer)
                 .with(file_layer)
+                .with(run_log_layer)
+                .with(timings_layer)
+                .with(command_log_layer)
                 .with(stdout_layer);
             tracing::subscriber::set_global_default(subscriber)
                 .context("Failed to set subscriber")?;
@@ -1312,536 +3111,132 @@ fn setup_logging(
     }
 
     info!("Log file created: {}", log_path.display());
-    Ok(())
+    if let Some(endpoint) = otlp_endpoint {
+        info!("Exporting traces to {}", endpoint);
+    }
+    Ok(otlp_guard)
 }
 
-fn apply_filters(records: Vec<EnaRecord>, filters: &RegexFilters) -> Result<Vec<EnaRecord>> {
-    let mut filtered = Vec::new();
-    let mut filtered_count = 0;
-    for record in records {
-        if filters.should_include(&record) {
-            filtered.push(record);
-        } else {
-            filtered_count += 1;
-        }
+// Reference assembly (GCA_/GCF_) into <output>/reference.
+async fn download_reference(accession: &str, args: &DownloadArgs) -> Result<()> {
+    if !assembly::is_assembly_accession(accession) {
+        return Err(anyhow!(
+            "Not an assembly accession: {} (expected GCA_/GCF_ followed by 9 digits)",
+            accession
+        ));
     }
-    if filtered_count > 0 {
-        info!(
-            "Filtered out {} records based on regex patterns",
-            filtered_count
-        );
+    if args.dry_run {
+        for file in assembly::resolve_assembly(accession).await? {
+            info!(
+                "   [{}] {} ({})",
+                accession,
+                file.name,
+                HumanBytes(file.size)
+            );
+        }
+        return Ok(());
     }
-    Ok(filtered)
-}
 
-fn save_md5_files(
-    records: &[ProcessedRecord],
-    output_dir: &Path,
-    accession: Option<&str>,
-) -> Result<()> {
-    let save_dir = if let Some(acc) = accession {
-        let meta_dir = output_dir.join(format!("{}_metadata", acc));
-        fs::create_dir_all(&meta_dir)?;
-        meta_dir
-    } else {
-        output_dir.to_path_buf()
-    };
-    info!(
-        "Saving MD5 files to {}...",
-        save_dir
-            .file_name()
-            .map(|n| n.to_string_lossy().into_owned())
-            .unwrap_or_else(|| save_dir.display().to_string())
-    );
-    let (r1_path, r2_path) = if let Some(acc) = accession {
-        (
-            save_dir.join(format!("R1_fastq_md5_{}.tsv", acc)),
-            save_dir.join(format!("R2_fastq_md5_{}.tsv", acc)),
-        )
-    } else {
-        (
-            save_dir.join("R1_fastq_md5.tsv"),
-            save_dir.join("R2_fastq_md5.tsv"),
-        )
-    };
+    BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+    let result = assembly::download_assembly(
+        accession,
+        &args.output,
+        args.chunk_size,
+        args.aws_threads,
+        Some(progress_sink()),
+    )
+    .await;
+    BARS_ACTIVE.store(false, std::sync::atomic::Ordering::Relaxed);
+    info!("[{}] Reference saved to {}", accession, result?.display());
+    Ok(())
+}
 
-    let mut r1_file = File::create(&r1_path)?;
-    let mut r2_file = File::create(&r2_path)?;
-
-    for record in records {
-        writeln!(
-            r1_file,
-            "{}\t{}\t{}",
-            record.fastq_md5_1, record.fastq_ftp_1_name, record.sample_title
-        )?;
-        if let (Some(md5), Some(name)) = (&record.fastq_md5_2, &record.fastq_ftp_2_name) {
-            writeln!(r2_file, "{}\t{}\t{}", md5, name, record.sample_title)?;
+// GEO supplementary files into <output>/geo_suppl/<GSE>, with md5.txt.
+async fn download_geo_suppl(gse: &str, args: &DownloadArgs) -> Result<()> {
+    if args.dry_run {
+        for file in geo::list_suppl_files(gse).await? {
+            info!("   [{}] {} ({})", gse, file.name, HumanBytes(file.size));
         }
+        return Ok(());
     }
-    info!("MD5 files saved");
-    Ok(())
-}
 
-fn save_metadata_tsv(
-    records: &[EnaRecord],
-    output_dir: &Path,
-    accession: Option<&str>,
-) -> Result<()> {
-    let save_dir = if let Some(acc) = accession {
-        let meta_dir = output_dir.join(format!("{}_metadata", acc));
-        fs::create_dir_all(&meta_dir)?;
-        meta_dir
-    } else {
-        output_dir.to_path_buf()
-    };
-    let path = if let Some(acc) = accession {
-        save_dir.join(format!("ena_metadata_{}.tsv", acc))
-    } else {
-        save_dir.join("ena_metadata.tsv")
-    };
+    BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+    let result = geo::download_suppl(
+        gse,
+        &args.output,
+        args.chunk_size,
+        args.aws_threads,
+        Some(progress_sink()),
+    )
+    .await;
+    BARS_ACTIVE.store(false, std::sync::atomic::Ordering::Relaxed);
     info!(
-        "Saving ENA metadata to {}...",
-        path.file_name().unwrap_or_default().to_string_lossy()
+        "[{}] Supplementary files saved to {}",
+        gse,
+        result?.display()
     );
-
-    let mut file = File::create(&path)?;
-    if let Some(acc) = accession {
-        writeln!(file, "# Project Accession: {}", acc)?;
-    }
-
-    let mut wtr = WriterBuilder::new().delimiter(b'\t').from_writer(file);
-
-    for record in records {
-        wtr.serialize(record)?;
-    }
-    wtr.flush()?;
-    info!("Metadata saved");
     Ok(())
 }
 
-// Must be pub for submodules
-pub fn create_script(output_path: &Path, fastq_id: &str, command: &str) -> Result<PathBuf> {
-    let scripts_dir = output_path.join("scripts");
-    fs::create_dir_all(&scripts_dir)?;
-    let script_path = scripts_dir.join(format!("{}.sh", fastq_id));
-    let mut file = File::create(&script_path)?;
-    writeln!(file, "#!/usr/bin/env bash")?;
-    writeln!(file, "set -euo pipefail")?;
-    writeln!(file, "mkdir -p {}", output_path.display())?;
-    writeln!(file, "cd {}", output_path.display())?;
-    writeln!(file, "{}", command)?;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&script_path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&script_path, perms)?;
-    }
-    Ok(script_path)
-}
-
-// AWS Entry (Keep original logic)
-async fn download_with_aws(
-    records: &[ProcessedRecord],
-    config: &Config,
-    args: &DownloadArgs,
-    progress_store: ProgressStore,
-) -> Result<()> {
-    info!("Starting AWS S3 downloads...");
+// EGA Entry: EGAD/EGAF accessions bypass ENA metadata entirely.
+async fn download_with_ega(config: &Config, args: &DownloadArgs) -> Result<()> {
+    use polariseq_core::ega::{EgaClient, EgaCredentials, EgaDownloader};
+
+    let accessions: Vec<&str> = args
+        .accession
+        .as_deref()
+        .ok_or_else(|| anyhow!("--download ega requires --accession EGAD…/EGAF…"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
 
-    let file_concurrency = args.multithreads;
-    let chunk_concurrency = args.aws_threads;
-    let process_threads = if args.aws_threads > 4 {
-        args.aws_threads
-    } else {
-        4
-    };
-    let chunk_size_mb = args.chunk_size;
+    let credentials = EgaCredentials::from_env(args.ega_user.as_deref())?;
+    let ega_config = config.ega.clone().unwrap_or_default();
+    let client = EgaClient::connect(&ega_config, &credentials).await?;
 
-    info!(
-        "Config: Parallel Files = {}, Threads/File = {}, Chunk Size = {}MB",
-        file_concurrency, chunk_concurrency, chunk_size_mb
-    );
+    let mut files = Vec::new();
+    for accession in &accessions {
+        let resolved = client.resolve(accession).await?;
+        info!("{}: {} file(s)", accession, resolved.len());
+        files.extend(resolved);
+    }
+    if files.is_empty() {
+        warn!("No EGA files are available to this account for the given accession(s).");
+        return Ok(());
+    }
 
-    {
-        let mut map = progress_store.write().await;
-        for record in records {
-            let sra_size = record.fastq_bytes_1 + record.fastq_bytes_2.unwrap_or(0);
-            let extract_weight = (sra_size as f64) * 3.0;
-            map.insert(
-                record.run_accession.clone(),
-                RunProgress {
-                    run_id: record.run_accession.clone(),
-                    stage: RunStage::Pending,
-                    overall_percent: 0.0,
-                    download: StageProgress::new(sra_size as f64),
-                    extraction: StageProgress::new(extract_weight),
-                    compression: StageProgress::new(extract_weight),
-                },
+    if args.dry_run {
+        info!("Dry Run Mode: Listing files that would be downloaded:");
+        for file in &files {
+            info!(
+                "   [{}] {} ({})",
+                file.file_id,
+                file.local_name(),
+                HumanBytes(file.plain_size())
             );
         }
+        info!("Dry Run completed. No files were downloaded.");
+        return Ok(());
     }
 
-    let semaphore = Arc::new(Semaphore::new(file_concurrency));
-    let mp = Arc::new(GLOBAL_MP.clone());
-    let ui = UiManager::start(
-        GLOBAL_MP.clone(),
-        Mode::Sra {
-            store: progress_store.clone(),
-        },
-        records.len() as u64,
+    info!(
+        "Starting EGA downloads: Parallel Files = {}, Threads/File = {}, Chunk Size = {}MB",
+        args.multithreads, args.aws_threads, args.chunk_size
     );
-    BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
-    let mut handles = Vec::new();
-
-    let fasterq_dump_path = config.software.fasterq_dump.display().to_string();
-
-    for record in records {
-        let run_id = record.run_accession.clone();
-        let output_dir = args.output.clone();
-        let sem = semaphore.clone();
-        let mp = mp.clone();
-        let ui = ui.clone();
-        let max_workers = chunk_concurrency;
-        let chunk_size = chunk_size_mb;
-        let fasterq_dump = fasterq_dump_path.clone();
-        let cleanup_sra = args.cleanup_sra;
-        let progress_store = progress_store.clone();
-
-        let handle = tokio::spawn(async move {
-            let _permit = sem.acquire().await.expect("semaphore closed");
-
-            {
-                let mut map = progress_store.write().await;
-                if let Some(rp) = map.get_mut(&run_id) {
-                    rp.stage = RunStage::Downloading;
-                }
-            }
-
-            let metadata = polariseq_core::aws_s3::SraUtils::get_metadata(&run_id, None).await?;
-            let sra_filename = run_id.clone();
-            let sra_size = metadata.as_ref().map(|m| m.size).unwrap_or(0);
-            info!(target: "download_detail", "[{}] Step 1: Downloading via AWS S3...", run_id);
-
-            if let Some(sra_metadata) = metadata {
-                // Share the per-file byte counter with the status bar so the
-                // global speed aggregates this run while downloading.
-                let counter = ui.register(&run_id, sra_size);
-                let downloader = polariseq_core::aws_s3::ResumableDownloader::new(
-                    run_id.clone(),
-                    sra_metadata,
-                    output_dir.clone(),
-                    chunk_size,
-                    max_workers,
-                    Some(mp),
-                    Some(progress_store.clone()),
-                )
-                .await?
-                .with_progress_bytes(counter);
-
-                let success = downloader.start().await?;
-                // Download phase done — drop it from the live speed set. Counts
-                // (active/completed/failed) come from progress_store in SRA mode.
-                ui.unregister(&run_id);
-                if !success {
-                    let mut map = progress_store.write().await;
-                    if let Some(rp) = map.get_mut(&run_id) {
-                        rp.stage = RunStage::Failed;
-                    }
-                    return Err(anyhow::anyhow!("Download failed for {}", run_id));
-                }
-            } else {
-                warn!("[{}] No AWS S3 URI found", run_id);
-                let mut map = progress_store.write().await;
-                if let Some(rp) = map.get_mut(&run_id) {
-                    rp.stage = RunStage::Failed;
-                }
-                return Err(anyhow::anyhow!("No S3 URI for {}", run_id));
-            }
-
-            {
-                let mut map = progress_store.write().await;
-                if let Some(rp) = map.get_mut(&run_id) {
-                    rp.download.percent = 100.0;
-                    rp.stage = RunStage::Extracting;
-                    rp.recalculate_overall();
-                }
-            }
-
-            let fq_1 = output_dir.join(format!("{}_1.fastq", run_id));
-            let fq_single = output_dir.join(format!("{}.fastq", run_id));
-            let fq_exists = (fq_1.exists()
-                && fq_1.metadata().map(|m| m.len() > 0).unwrap_or(false))
-                || (fq_single.exists()
-                    && fq_single.metadata().map(|m| m.len() > 0).unwrap_or(false));
-
-            // Captures fasterq-dump stderr so the final error branch can
-            // report *why* conversion failed.
-            let mut fqdump_error: Option<String> = None;
-
-            if fq_exists {
-                info!(target: "download_detail", "[{}] FASTQ files already exist, skipping conversion.", run_id);
-            } else {
-                info!(target: "download_detail", "[{}] Step 2: Converting (fasterq-dump)...", run_id);
-
-                let fasterq_tmp_dir = output_dir.join(".fasterq_tmp").join(&run_id);
-                tokio::fs::create_dir_all(&fasterq_tmp_dir)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to create fasterq-dump temporary directory: {}",
-                            fasterq_tmp_dir.display()
-                        )
-                    })?;
-                let fasterq_tmp_dir = tokio::fs::canonicalize(&fasterq_tmp_dir)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to resolve fasterq-dump temporary directory: {}",
-                            fasterq_tmp_dir.display()
-                        )
-                    })?;
-                let fasterq_output_dir = tokio::fs::canonicalize(&output_dir)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to resolve fasterq-dump output directory: {}",
-                            output_dir.display()
-                        )
-                    })?;
-
-                let estimated_fastq_size = sra_size * 3;
-                let child = Command::new(&fasterq_dump)
-                    .arg("--split-3")
-                    .arg("-e")
-                    .arg(process_threads.to_string())
-                    .arg("-O")
-                    .arg(&fasterq_output_dir)
-                    .arg("-t")
-                    .arg(&fasterq_tmp_dir)
-                    .arg("-f")
-                    .arg(&sra_filename)
-                    .current_dir(&output_dir)
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::piped())
-                    .spawn()?;
-
-                let output_dir_mon = output_dir.clone();
-                let run_id_mon = run_id.clone();
-                let store_mon = progress_store.clone();
-                let extract_monitor = tokio::spawn(async move {
-                    let mut interval = tokio::time::interval(Duration::from_millis(500));
-                    loop {
-                        interval.tick().await;
-                        let mut total_size = 0u64;
-                        for name in &[
-                            format!("{}.fastq", run_id_mon),
-                            format!("{}_1.fastq", run_id_mon),
-                            format!("{}_2.fastq", run_id_mon),
-                        ] {
-                            let path = output_dir_mon.join(name);
-                            if let Ok(meta) = tokio::fs::metadata(&path).await {
-                                total_size += meta.len();
-                            }
-                        }
-                        let mut map = store_mon.write().await;
-                        if let Some(rp) = map.get_mut(&run_id_mon) {
-                            rp.extraction.update(total_size, estimated_fastq_size);
-                            rp.extraction.percent = rp.extraction.percent.min(99.0);
-                            rp.recalculate_overall();
-                        }
-                    }
-                });
-
-                let output = child.wait_with_output().await?;
-                extract_monitor.abort();
-                let fqdump_stderr = String::from_utf8_lossy(&output.stderr);
-
-                if !output.status.success() {
-                    let detail = fqdump_stderr.trim().to_string();
-                    error!(
-                        "[{}] fasterq-dump exited with {}: {}",
-                        run_id,
-                        output.status,
-                        detail
-                    );
-                    fqdump_error = Some(detail);
-                }
-            }
-
-            {
-                let mut map = progress_store.write().await;
-                if let Some(rp) = map.get_mut(&run_id) {
-                    rp.extraction.percent = 100.0;
-                    rp.extraction.bytes_done = rp.extraction.bytes_total;
-                    rp.stage = RunStage::Compressing;
-                    rp.recalculate_overall();
-                }
-            }
-
-            let fq_exists_after = (fq_1.exists()
-                && fq_1.metadata().map(|m| m.len() > 0).unwrap_or(false))
-                || (fq_single.exists()
-                    && fq_single.metadata().map(|m| m.len() > 0).unwrap_or(false));
-
-            if fq_exists_after {
-                info!(target: "download_detail", "[{}] Step 3: Compressing...", run_id);
-
-                let mut fastq_total_size = 0u64;
-                for name in &[
-                    format!("{}.fastq", run_id),
-                    format!("{}_1.fastq", run_id),
-                    format!("{}_2.fastq", run_id),
-                ] {
-                    let path = output_dir.join(name);
-                    if let Ok(meta) = tokio::fs::metadata(&path).await {
-                        fastq_total_size += meta.len();
-                    }
-                }
-
-                let compression_bytes = Arc::new(AtomicU64::new(0));
-                let cb_bytes = compression_bytes.clone();
-                let progress_cb: polariseq_core::progress_store::CompressionProgressCallback =
-                    Arc::new(move |bytes_read, _total| {
-                        cb_bytes.store(bytes_read, Ordering::Relaxed);
-                    });
-
-                let comp_store = progress_store.clone();
-                let comp_run_id = run_id.clone();
-                let comp_bytes_mon = compression_bytes.clone();
-                let comp_total = fastq_total_size;
-                let comp_monitor = tokio::spawn(async move {
-                    let mut interval = tokio::time::interval(Duration::from_millis(200));
-                    loop {
-                        interval.tick().await;
-                        let done = comp_bytes_mon.load(Ordering::Relaxed);
-                        let mut map = comp_store.write().await;
-                        if let Some(rp) = map.get_mut(&comp_run_id) {
-                            rp.compression.update(done, comp_total);
-                            rp.compression.percent = rp.compression.percent.min(99.0);
-                            rp.recalculate_overall();
-                        }
-                    }
-                });
-
-                let output_dir_compress = output_dir.clone();
-                let run_id_compress = run_id.clone();
-                tokio::task::spawn_blocking(move || {
-                    polariseq_core::compress_fastq_files(
-                        &output_dir_compress,
-                        &run_id_compress,
-                        process_threads,
-                        Some(progress_cb),
-                    )
-                })
-                .await
-                .context("Compression task panicked")?
-                .context("Compression failed")?;
-
-                comp_monitor.abort();
-
-                {
-                    let mut map = progress_store.write().await;
-                    if let Some(rp) = map.get_mut(&run_id) {
-                        rp.compression.percent = 100.0;
-                        rp.compression.bytes_done = rp.compression.bytes_total;
-                        rp.overall_percent = 100.0;
-                        rp.stage = RunStage::Completed;
-                    }
-                }
-
-                if cleanup_sra {
-                    let sra_path = output_dir.join(&sra_filename);
-                    if sra_path.exists() {
-                        info!(target: "download_detail", "[{}] Cleaning up SRA file: {}", run_id, sra_path.display());
-                        if let Err(e) = tokio::fs::remove_file(&sra_path).await {
-                            warn!("[{}] Failed to remove SRA file: {}", run_id, e);
-                        }
-                    }
-                }
-
-                info!("[{}] Done", run_id);
-                Ok(())
-            } else {
-                let reason = fqdump_error
-                    .as_deref()
-                    .unwrap_or("no FASTQ output found");
-                error!(
-                    "[{}] Conversion failed: {}",
-                    run_id, reason
-                );
-                let mut map = progress_store.write().await;
-                if let Some(rp) = map.get_mut(&run_id) {
-                    rp.stage = RunStage::Failed;
-                }
-                Err(anyhow::anyhow!("Conversion failed for {}: {}", run_id, reason))
-            }
-        });
-
-        handles.push(handle);
-    }
+    let ui = UiManager::start(GLOBAL_MP.clone(), Mode::PublicData, files.len() as u64);
+    let downloader = EgaDownloader::new(client)
+        .with_workers(args.multithreads, args.aws_threads)
+        .with_chunk_size_mb(args.chunk_size)
+        .with_progress(progress_sink())
+        .with_observer(ui.clone() as Arc<dyn DownloadObserver>);
 
-    let total_tasks = handles.len();
-    let mut failed = 0usize;
-    let mut first_err: Option<anyhow::Error> = None;
-    for handle in handles {
-        match handle.await {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => {
-                failed += 1;
-                warn!("Task failed: {:#}", e);
-                if first_err.is_none() {
-                    first_err = Some(e);
-                }
-            }
-            Err(e) => {
-                failed += 1;
-                warn!("Task join error: {}", e);
-                if first_err.is_none() {
-                    first_err = Some(anyhow!("task join error: {}", e));
-                }
-            }
-        }
-    }
+    BARS_ACTIVE.store(true, std::sync::atomic::Ordering::Relaxed);
+    let result = downloader.download_files(&files, &args.output).await;
     BARS_ACTIVE.store(false, std::sync::atomic::Ordering::Relaxed);
     ui.stop();
+    result?;
 
-    let gz_files: Vec<PathBuf> = fs::read_dir(&args.output)?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|p| p.extension().is_some_and(|ext| ext == "gz"))
-        .collect();
-
-    if !gz_files.is_empty() {
-        generate_md5sum_file(&args.output, &gz_files)?;
-    }
-
-    if failed > 0 {
-        let msg = format!(
-            "{} of {} AWS S3 download task(s) failed",
-            failed, total_tasks
-        );
-        error!("{}", msg);
-        return Err(first_err.unwrap_or_else(|| anyhow!("{}", msg)));
-    }
-
-    info!("All AWS S3 tasks completed");
+    info!("{} EGA download completed successfully!", SCRIPT_NAME);
     Ok(())
 }
-
-// FTP Entry
-async fn download_with_ftp(
-    records: &[ProcessedRecord],
-    config: &Config,
-    args: &DownloadArgs,
-) -> Result<()> {
-    // Call ftp.rs, pass file size to enable percentage progress bar
-    polariseq_core::ftp::process_downloads(
-        records,
-        config,
-        &args.output,
-        polariseq_core::ftp::Protocol::Ftp,
-        args.multithreads,
-    )
-    .await
-}
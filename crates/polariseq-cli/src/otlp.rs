@@ -0,0 +1,72 @@
+//! OTLP trace export for `--otlp-endpoint` (cargo feature `otlp`).
+//!
+//! The core's `metadata`, `run`, `download`, `verify`, `convert`,
+//! `compress` and `hook` spans are sent over OTLP/HTTP (protobuf) to a
+//! collector such as Jaeger or Tempo, under the service name `polariseq`.
+
+use anyhow::Result;
+use tracing_subscriber::{Layer, Registry};
+
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Flushes buffered spans when dropped at exit.
+#[cfg(feature = "otlp")]
+pub struct OtlpGuard(opentelemetry_sdk::trace::SdkTracerProvider);
+
+#[cfg(not(feature = "otlp"))]
+pub struct OtlpGuard;
+
+#[cfg(feature = "otlp")]
+impl Drop for OtlpGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            eprintln!("Failed to flush OTLP spans: {}", e);
+        }
+    }
+}
+
+/// `http://host:4318` → `http://host:4318/v1/traces`; full URLs are kept.
+#[cfg(feature = "otlp")]
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+/// Tracing layer exporting spans to `endpoint`.
+#[cfg(feature = "otlp")]
+pub fn layer(endpoint: &str) -> Result<(BoxedLayer, OtlpGuard)> {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing_subscriber::filter::Targets;
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_url(endpoint))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("polariseq").build())
+        .build();
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("polariseq"))
+        .with_filter(
+            Targets::new()
+                .with_target("polariseq_core", tracing::Level::INFO)
+                .with_target("polariseq", tracing::Level::INFO),
+        );
+    Ok((Box::new(layer), OtlpGuard(provider)))
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn layer(_endpoint: &str) -> Result<(BoxedLayer, OtlpGuard)> {
+    Err(polariseq_core::error::PolariseqError::Config(
+        "`--otlp-endpoint` needs polariseq built with the `otlp` cargo feature".to_string(),
+    )
+    .into())
+}
@@ -0,0 +1,98 @@
+//! ASCII-only output for `--plain`.
+//!
+//! [`to_ascii`] rewrites the symbols used in log messages (check marks,
+//! arrows, box drawing, spinner glyphs, emoji) to ASCII; other non-ASCII
+//! text such as sample names is left alone. [`Plain`] applies it to a
+//! log writer.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+fn replacement(c: char) -> Option<&'static str> {
+    Some(match c {
+        '✓' | '✔' => "OK",
+        '✗' | '✘' | '❌' => "FAIL",
+        '→' | '⇒' => "->",
+        '←' => "<-",
+        '↑' => "^",
+        '↓' => "v",
+        '·' | '•' => "-",
+        '…' => "...",
+        '—' | '–' => "-",
+        '│' | '║' | '┃' => "|",
+        '─' | '━' => "-",
+        '═' => "=",
+        // Remaining box drawing: corners and junctions.
+        '\u{2500}'..='\u{257F}' => "+",
+        // Block elements (bars, logo).
+        '\u{2580}'..='\u{259F}' => "#",
+        // Braille spinner frames, emoji, pictographs and their modifiers.
+        '\u{2800}'..='\u{28FF}'
+        | '\u{2600}'..='\u{27BF}'
+        | '\u{1F000}'..='\u{1FAFF}'
+        | '\u{FE0F}'
+        | '\u{200D}' => "",
+        _ => return None,
+    })
+}
+
+/// `text` with emoji and box-drawing characters replaced by ASCII.
+pub fn to_ascii(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() || !text.chars().any(|c| replacement(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut dropped = false;
+    for c in text.chars() {
+        match replacement(c) {
+            Some("") => {
+                dropped = true;
+                continue;
+            }
+            Some(ascii) => out.push_str(ascii),
+            // Emoji are usually followed by a space; don't leave it leading.
+            None if c == ' ' && dropped && (out.is_empty() || out.ends_with(' ')) => {}
+            None => out.push(c),
+        }
+        dropped = false;
+    }
+    Cow::Owned(out)
+}
+
+/// A writer passing formatted log lines through [`to_ascii`].
+pub struct Plain<W>(pub W);
+
+impl<W: Write> Write for Plain<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // fmt layers write whole formatted events, so chunks are valid UTF-8.
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(to_ascii(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_symbols_and_keeps_text() {
+        assert_eq!(to_ascii("  ✓  wget: 1.21"), "  OK  wget: 1.21");
+        assert_eq!(
+            to_ascii("     → run `deps install`"),
+            "     -> run `deps install`"
+        );
+        assert_eq!(
+            to_ascii("📦 Packing SRR1 · done…"),
+            "Packing SRR1 - done..."
+        );
+        assert_eq!(to_ascii("╔══╗ █"), "+==+ #");
+        assert_eq!(to_ascii("Müller lab"), "Müller lab");
+    }
+}
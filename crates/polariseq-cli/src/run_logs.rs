@@ -0,0 +1,206 @@
+//! Per-run log files and rotation of the main log.
+//!
+//! [`RunLogLayer`] copies every event emitted inside a core `run` span
+//! (including subprocess stderr logged by the backends) to
+//! `<output>/logs/<run_accession>.log`. [`rotate`] gzips the main logs of
+//! earlier invocations and keeps only the newest [`KEEP_LOGS`].
+
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use polariseq_core::md5::MD5_LOG_PREFIX;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Directory of the per-run logs, inside the output directory.
+pub const RUN_LOG_DIR: &str = "logs";
+/// Compressed main logs kept per output directory.
+pub const KEEP_LOGS: usize = 10;
+
+/// Run accession stored on a `run` span.
+struct RunId(String);
+
+#[derive(Default)]
+struct FieldText {
+    run_id: Option<String>,
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldText {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "run_id" => self.run_id = Some(value.to_string()),
+            "message" => self.message = value.to_string(),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "run_id" => self.run_id = Some(format!("{:?}", value)),
+            "message" => self.message = format!("{:?}", value),
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+/// Appends run-scoped events to `logs/<run>.log`.
+pub struct RunLogLayer {
+    dir: PathBuf,
+    files: Mutex<HashMap<String, File>>,
+}
+
+impl RunLogLayer {
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            dir: output_dir.join(RUN_LOG_DIR),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn write(&self, run_id: &str, line: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(run_id) {
+            fs::create_dir_all(&self.dir)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(format!("{}.log", run_id)))?;
+            files.insert(run_id.to_string(), file);
+        }
+        files
+            .get_mut(run_id)
+            .expect("inserted above")
+            .write_all(line.as_bytes())
+    }
+}
+
+impl<S> Layer<S> for RunLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "run" {
+            return;
+        }
+        let mut fields = FieldText::default();
+        attrs.record(&mut fields);
+        if let (Some(run_id), Some(span)) = (fields.run_id, ctx.span(id)) {
+            span.extensions_mut().insert(RunId(run_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        let Some(run_id) = scope
+            .from_root()
+            .find_map(|span| span.extensions().get::<RunId>().map(|r| r.0.clone()))
+        else {
+            return;
+        };
+        let mut fields = FieldText::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        let line = format!(
+            "{} {:>5} {}: {}{}\n",
+            Local::now().to_rfc3339(),
+            meta.level(),
+            meta.target(),
+            fields.message,
+            fields.fields
+        );
+        // Logging must not fail the download.
+        let _ = self.write(&run_id, &line);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(run_id) = span.extensions().get::<RunId>() {
+                self.files.lock().unwrap().remove(&run_id.0);
+            }
+        }
+    }
+}
+
+/// Main logs of earlier invocations in `dir`: `polariseq_*.log`, except
+/// the md5 subcommand's, which live next to hashed data and stay as is.
+fn is_main_log(name: &str, prefix: &str) -> bool {
+    name.starts_with(prefix) && !name.starts_with(MD5_LOG_PREFIX)
+}
+
+/// Gzip earlier main logs in `dir` (all but `current`), then delete all
+/// but the newest [`KEEP_LOGS`] compressed ones.
+pub fn rotate(dir: &Path, prefix: &str, current: &Path) -> io::Result<()> {
+    let mut compressed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if !is_main_log(&name, prefix) || path == current {
+            continue;
+        }
+        if name.ends_with(".log") {
+            let gz = path.with_extension("log.gz");
+            let mut encoder = GzEncoder::new(File::create(&gz)?, Compression::default());
+            io::copy(&mut File::open(&path)?, &mut encoder)?;
+            encoder.finish()?;
+            fs::remove_file(&path)?;
+            compressed.push(gz);
+        } else if name.ends_with(".log.gz") {
+            compressed.push(path);
+        }
+    }
+    // Oldest first; names carry different tags, so order by mtime.
+    compressed.sort_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok());
+    let excess = compressed.len().saturating_sub(KEEP_LOGS);
+    for old in &compressed[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn run_events_land_in_their_own_log() {
+        let dir = std::env::temp_dir().join(format!("polariseq-runlogs-{}", std::process::id()));
+        let subscriber = tracing_subscriber::registry().with(RunLogLayer::new(&dir));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside any run");
+            info_span!("run", run_id = "SRR1").in_scope(|| {
+                info_span!("download", backend = "ftp").in_scope(|| {
+                    tracing::warn!(code = 8, "wget failed");
+                });
+            });
+        });
+        let log = fs::read_to_string(dir.join(RUN_LOG_DIR).join("SRR1.log")).unwrap();
+        assert!(log.contains("WARN"));
+        assert!(log.contains("wget failed code=8"));
+        assert!(!log.contains("outside"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+//! Per-run stage timings written to `<output>/timings.csv`.
+//!
+//! [`TimingsLayer`] measures the wall time of the core's tracing spans
+//! (`metadata`, `download`, `convert`, `compress`, `verify`, `hook`) and
+//! appends one row per run when its `run` span closes, with the average
+//! speed over the run's output size. `download` excludes the nested
+//! `convert` and `compress` time; `metadata` is the job's metadata fetch,
+//! shared by all its runs.
+
+use polariseq_core::backend::run_output_files;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+pub const TIMINGS_FILE: &str = "timings.csv";
+
+const HEADER: &str = "run_accession,backend,metadata_s,download_s,convert_s,compress_s,verify_s,hooks_s,total_s,bytes,mb_per_s\n";
+
+/// Timed spans, in column order after `metadata`.
+const STAGES: [&str; 5] = ["download", "convert", "compress", "verify", "hook"];
+
+/// Start time and identifying fields of a timed span.
+struct Timed {
+    started: Instant,
+    run_id: Option<String>,
+    backend: Option<String>,
+}
+
+#[derive(Default)]
+struct Fields {
+    run_id: Option<String>,
+    backend: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "run_id" => self.run_id = Some(value.to_string()),
+            "backend" => self.backend = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+#[derive(Default)]
+struct RunTiming {
+    first_started: Option<Instant>,
+    /// Seconds per entry of [`STAGES`].
+    stages: [f64; STAGES.len()],
+    backend: Option<String>,
+}
+
+/// Appends a `timings.csv` row per finished run.
+pub struct TimingsLayer {
+    output: PathBuf,
+    metadata_secs: Mutex<f64>,
+    runs: Mutex<HashMap<String, RunTiming>>,
+}
+
+impl TimingsLayer {
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            output: output_dir.to_path_buf(),
+            metadata_secs: Mutex::new(0.0),
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn row(&self, run_id: &str, timing: &RunTiming) -> String {
+        let total = timing
+            .first_started
+            .map(|s| s.elapsed().as_secs_f64())
+            .unwrap_or_default();
+        let [download, convert, compress, verify, hooks] = timing.stages;
+        let bytes: u64 = run_output_files(&self.output, run_id)
+            .iter()
+            .filter_map(|f| f.metadata().ok())
+            .map(|m| m.len())
+            .sum();
+        format!(
+            "{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{:.2}\n",
+            run_id,
+            timing.backend.as_deref().unwrap_or(""),
+            *self.metadata_secs.lock().unwrap(),
+            (download - convert - compress).max(0.0),
+            convert,
+            compress,
+            verify,
+            hooks,
+            total,
+            bytes,
+            bytes as f64 / (1024.0 * 1024.0) / total.max(0.001)
+        )
+    }
+
+    fn append(&self, row: &str) -> io::Result<()> {
+        let path = self.output.join(TIMINGS_FILE);
+        let new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if new {
+            file.write_all(HEADER.as_bytes())?;
+        }
+        file.write_all(row.as_bytes())
+    }
+}
+
+impl<S> Layer<S> for TimingsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let name = attrs.metadata().name();
+        if name != "run" && name != "metadata" && !STAGES.contains(&name) {
+            return;
+        }
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Timed {
+                started: Instant::now(),
+                run_id: fields.run_id,
+                backend: fields.backend,
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let extensions = span.extensions();
+        let Some(timed) = extensions.get::<Timed>() else {
+            return;
+        };
+        let secs = timed.started.elapsed().as_secs_f64();
+        match span.name() {
+            "metadata" => *self.metadata_secs.lock().unwrap() = secs,
+            "run" => {
+                let Some(run_id) = &timed.run_id else {
+                    return;
+                };
+                let timing = self.runs.lock().unwrap().remove(run_id);
+                // Runs cancelled while queued never started a stage.
+                if let Some(timing) = timing {
+                    // Timings must not fail the download.
+                    let _ = self.append(&self.row(run_id, &timing));
+                }
+            }
+            stage => {
+                let Some(run_id) = span.scope().skip(1).find_map(|parent| {
+                    parent
+                        .extensions()
+                        .get::<Timed>()
+                        .and_then(|t| t.run_id.clone())
+                }) else {
+                    return;
+                };
+                let mut runs = self.runs.lock().unwrap();
+                let timing = runs.entry(run_id).or_default();
+                let started = timing.first_started.get_or_insert(timed.started);
+                *started = (*started).min(timed.started);
+                if let Some(index) = STAGES.iter().position(|s| *s == stage) {
+                    timing.stages[index] += secs;
+                }
+                if stage == "download" {
+                    timing.backend = timed.backend.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn one_row_per_run() {
+        let dir = std::env::temp_dir().join(format!("polariseq-timings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("SRR1.fastq.gz"), vec![0u8; 2048]).unwrap();
+        let subscriber = tracing_subscriber::registry().with(TimingsLayer::new(&dir));
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("metadata", accession = "PRJNA1").in_scope(|| {});
+            for run in ["SRR1", "SRR2"] {
+                info_span!("run", run_id = run).in_scope(|| {
+                    info_span!("download", backend = "aws").in_scope(|| {
+                        info_span!("convert").in_scope(|| {});
+                    });
+                    info_span!("verify", backend = "aws").in_scope(|| {});
+                });
+            }
+            // Queued, then cancelled: no row.
+            info_span!("run", run_id = "SRR3").in_scope(|| {});
+        });
+        let csv = std::fs::read_to_string(dir.join(TIMINGS_FILE)).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], HEADER.trim_end());
+        assert!(lines[1].starts_with("SRR1,aws,"));
+        assert!(lines[1].contains(",2048,"));
+        assert!(lines[2].starts_with("SRR2,aws,"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
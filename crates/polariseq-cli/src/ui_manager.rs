@@ -161,8 +161,8 @@ impl UiManager {
                 let (mut completed, mut failed, mut active) = (0usize, 0usize, 0usize);
                 for rp in map.values() {
                     match rp.stage {
-                        RunStage::Completed => completed += 1,
-                        RunStage::Failed => failed += 1,
+                        RunStage::Completed | RunStage::Skipped => completed += 1,
+                        RunStage::Failed | RunStage::Cancelled => failed += 1,
                         RunStage::Downloading | RunStage::Extracting | RunStage::Compressing => {
                             active += 1
                         }
@@ -351,8 +351,8 @@ mod tests {
         let (mut c, mut f, mut a, mut p) = (0, 0, 0, 0);
         for rp in map.values() {
             match rp.stage {
-                RunStage::Completed => c += 1,
-                RunStage::Failed => f += 1,
+                RunStage::Completed | RunStage::Skipped => c += 1,
+                RunStage::Failed | RunStage::Cancelled => f += 1,
                 RunStage::Downloading | RunStage::Extracting | RunStage::Compressing => a += 1,
                 RunStage::Pending => p += 1,
             }
@@ -0,0 +1,176 @@
+//! Accession syntax checks, run before ENA is queried.
+//!
+//! [`parse`] recognises the INSDC read, sample and study accessions
+//! (SRR/ERR/DRR, SRX, SRS/SAM…, SRP, PRJ…) plus GEO, assembly and EGA ones.
+//! Case and stray whitespace are fixed silently; other typos are rejected
+//! with the closest valid spelling, if any. When ENA still returns no rows,
+//! [`no_records_hint`] says what the accession most likely needed instead.
+
+use crate::error::PolariseqError;
+use regex::Regex;
+use std::sync::LazyLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessionKind {
+    /// SRR/ERR/DRR.
+    Run,
+    /// SRX/ERX/DRX.
+    Experiment,
+    /// SRS/ERS/DRS or a BioSample (SAMN/SAMEA/SAMD).
+    Sample,
+    /// SRP/ERP/DRP.
+    Study,
+    /// SRA/ERA/DRA.
+    Submission,
+    /// BioProject (PRJNA/PRJEB/PRJDB).
+    Project,
+    /// GEO series (GSE).
+    GeoSeries,
+    /// GEO sample (GSM).
+    GeoSample,
+    /// GenBank/RefSeq assembly (GCA_/GCF_).
+    Assembly,
+    /// EGA dataset or file (EGAD/EGAF/...).
+    Ega,
+}
+
+static PATTERNS: LazyLock<Vec<(Regex, AccessionKind)>> = LazyLock::new(|| {
+    [
+        (r"^[SED]RR\d{6,}$", AccessionKind::Run),
+        (r"^[SED]RX\d{6,}$", AccessionKind::Experiment),
+        (
+            r"^([SED]RS\d{6,}|SAM(N|EA|D)\d{5,})$",
+            AccessionKind::Sample,
+        ),
+        (r"^[SED]RP\d{6,}$", AccessionKind::Study),
+        (r"^[SED]RA\d{6,}$", AccessionKind::Submission),
+        (r"^PRJ(NA|EB|DB)\d+$", AccessionKind::Project),
+        (r"^GSE\d+$", AccessionKind::GeoSeries),
+        (r"^GSM\d+$", AccessionKind::GeoSample),
+        (r"^GC[AF]_\d{9}(\.\d+)?$", AccessionKind::Assembly),
+        (r"^EGA[A-Z]\d{11}$", AccessionKind::Ega),
+    ]
+    .into_iter()
+    .map(|(pattern, kind)| (Regex::new(pattern).expect("valid pattern"), kind))
+    .collect()
+});
+
+/// Kind of a well-formed accession.
+pub fn kind(accession: &str) -> Option<AccessionKind> {
+    PATTERNS
+        .iter()
+        .find(|(pattern, _)| pattern.is_match(accession))
+        .map(|(_, kind)| *kind)
+}
+
+/// Spellings one slip away from `accession` that are valid: letter O or
+/// I typed for a digit, or two adjacent prefix letters swapped.
+fn corrections(accession: &str) -> Vec<String> {
+    let split = accession
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(accession.len());
+    let (prefix, digits) = accession.split_at(split);
+    let mut candidates = Vec::new();
+    // Look-alike letters in the numeric part, e.g. SRR12O45.
+    let mut prefix_end = prefix.len();
+    while prefix_end > 0 && "OI".contains(&prefix[prefix_end - 1..prefix_end]) {
+        prefix_end -= 1;
+    }
+    let numeric = format!("{}{}", &prefix[prefix_end..], digits)
+        .replace('O', "0")
+        .replace('I', "1");
+    candidates.push(format!("{}{}", &prefix[..prefix_end], numeric));
+    let letters: Vec<char> = prefix.chars().collect();
+    for i in 1..letters.len() {
+        let mut swapped = letters.clone();
+        swapped.swap(i - 1, i);
+        candidates.push(format!("{}{}", swapped.iter().collect::<String>(), digits));
+    }
+    candidates.retain(|c| c != accession && kind(c).is_some());
+    candidates.dedup();
+    candidates
+}
+
+/// Whether `accession` is an SRA-style secondary study or sample accession
+/// (SRP/ERP/DRP, SRS/ERS/DRS) rather than a BioProject or BioSample.
+pub fn is_secondary(accession: &str) -> bool {
+    match kind(accession) {
+        Some(AccessionKind::Study) => true,
+        Some(AccessionKind::Sample) => !accession.starts_with("SAM"),
+        _ => false,
+    }
+}
+
+/// Normalise and check `accession`. Returns the accession to query and its
+/// kind, or an error naming the likely intended spelling.
+pub fn parse(accession: &str) -> Result<(String, AccessionKind), PolariseqError> {
+    let cleaned: String = accession
+        .trim()
+        .trim_matches(|c: char| matches!(c, ',' | ';' | '.' | '"' | '\''))
+        .to_ascii_uppercase();
+    if let Some(kind) = kind(&cleaned) {
+        return Ok((cleaned, kind));
+    }
+    let mut message = format!(
+        "'{}' is not a valid accession (expected e.g. SRR/ERR/DRR, SRX, SRS/SAMN, SRP, PRJNA/PRJEB, GSE, GCA_ or EGAD)",
+        accession
+    );
+    let suggestions = corrections(&cleaned);
+    if !suggestions.is_empty() {
+        message.push_str(&format!("; did you mean {}?", suggestions.join(" or ")));
+    }
+    Err(PolariseqError::InvalidInput(message))
+}
+
+/// Why ENA may have no runs for a well-formed `accession`, and what to try.
+pub fn no_records_hint(accession: &str) -> String {
+    let browser = format!("https://www.ebi.ac.uk/ena/browser/view/{}", accession);
+    match kind(accession) {
+        Some(AccessionKind::Project | AccessionKind::Study) => format!(
+            "the project may hold no raw reads (assemblies or analyses only) or not be public yet; \
+             if it is an umbrella BioProject, pass one of its component projects (see {})",
+            browser
+        ),
+        Some(AccessionKind::GeoSample) => {
+            "GEO samples are not ENA accessions; pass the GSE, or the SRX/SRR listed on the GSM page"
+                .to_string()
+        }
+        Some(AccessionKind::GeoSeries) => {
+            "GEO series without a linked SRA BioProject (e.g. arrays) have no runs; try --geo-suppl"
+                .to_string()
+        }
+        Some(AccessionKind::Assembly | AccessionKind::Ega) => {
+            "this is not a read accession; assemblies download as references and EGA data with --download ega"
+                .to_string()
+        }
+        _ => format!(
+            "it may be suppressed, embargoed or not yet mirrored to ENA; check {}",
+            browser
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixes_case_and_suggests_typos() {
+        assert_eq!(
+            parse(" srr1234567 ").unwrap(),
+            ("SRR1234567".to_string(), AccessionKind::Run)
+        );
+        assert_eq!(parse("PRJNA833659").unwrap().1, AccessionKind::Project);
+        assert_eq!(parse("SAMEA1234567").unwrap().1, AccessionKind::Sample);
+
+        let typo = parse("SRR12O4567").unwrap_err().to_string();
+        assert!(typo.contains("did you mean SRR1204567?"), "{}", typo);
+        let swapped = parse("PRJAN833659").unwrap_err().to_string();
+        assert!(swapped.contains("did you mean PRJNA833659?"), "{}", swapped);
+        let garbage = parse("hello").unwrap_err().to_string();
+        assert!(!garbage.contains("did you mean"), "{}", garbage);
+
+        assert!(is_secondary("SRP123456") && is_secondary("ERS123456"));
+        assert!(!is_secondary("SAMN12345678") && !is_secondary("PRJNA833659"));
+    }
+}
@@ -0,0 +1,149 @@
+//! ENA BAM alignments instead of FASTQ (`download --prefer-bam`).
+//!
+//! Some records, 1000 Genomes among them, carry submitted alignments in
+//! `bam_ftp`. With `--prefer-bam` those runs download their one or two BAM
+//! files (a BAM and its index, say) over FTP, MD5-verified like any FASTQ,
+//! into `<output>/alignments/` (below the run's `output_subdir`, if any).
+//! Files are named like [`long_read::local_name`]. Runs without `bam_ftp`
+//! download their FASTQ as usual.
+
+use crate::long_read;
+use crate::{EnaRecord, ProcessedRecord};
+use std::path::Path;
+
+/// Directory under the output (or the run's `output_subdir`) for BAMs.
+pub const ALIGNMENTS_DIR: &str = "alignments";
+
+/// `(url, md5, bytes)` of the BAM files of `record`, if it has one or two
+/// with an MD5 each.
+fn bam_files(record: &EnaRecord) -> Option<Vec<(&str, &str, u64)>> {
+    let urls: Vec<&str> = record
+        .bam_ftp
+        .as_deref()?
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let md5s: Vec<&str> = record
+        .bam_md5
+        .as_deref()?
+        .split(';')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let bytes: Vec<u64> = record
+        .bam_bytes
+        .as_deref()
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    if !matches!(urls.len(), 1 | 2) || md5s.len() != urls.len() {
+        return None;
+    }
+    Some(
+        urls.into_iter()
+            .zip(md5s)
+            .enumerate()
+            .map(|(i, (url, md5))| (url, md5, bytes.get(i).copied().unwrap_or(0)))
+            .collect(),
+    )
+}
+
+/// Switch the runs of `records` that have BAMs to them: their processed
+/// files become the BAMs, they go to [`ALIGNMENTS_DIR`], and they are routed
+/// to FTP unless a `preferred_backend` says otherwise. Runs with only BAMs
+/// are added to `processed`. Returns the number of runs switched.
+pub fn prefer_bam(records: &mut [EnaRecord], processed: &mut Vec<ProcessedRecord>) -> usize {
+    let mut switched = 0;
+    for record in records.iter_mut() {
+        let Some(files) = bam_files(record) else {
+            continue;
+        };
+        let run = match processed
+            .iter()
+            .position(|p| p.run_accession == record.run_accession)
+        {
+            Some(i) => &mut processed[i],
+            None => {
+                processed.push(ProcessedRecord {
+                    run_accession: record.run_accession.clone(),
+                    fastq_ftp_1_url: String::new(),
+                    fastq_ftp_2_url: None,
+                    fastq_ftp_1_name: String::new(),
+                    fastq_ftp_2_name: None,
+                    fastq_md5_1: String::new(),
+                    fastq_md5_2: None,
+                    fastq_bytes_1: 0,
+                    fastq_bytes_2: None,
+                    sample_title: record.sample_title.clone(),
+                });
+                processed.last_mut().expect("just pushed")
+            }
+        };
+        let name = |url: &str| long_read::local_name(&record.run_accession, url);
+        let (url, md5, bytes) = files[0];
+        run.fastq_ftp_1_url = url.to_string();
+        run.fastq_ftp_1_name = name(url);
+        run.fastq_md5_1 = md5.to_string();
+        run.fastq_bytes_1 = bytes;
+        let second = files.get(1);
+        run.fastq_ftp_2_url = second.map(|f| f.0.to_string());
+        run.fastq_ftp_2_name = second.map(|f| name(f.0));
+        run.fastq_md5_2 = second.map(|f| f.1.to_string());
+        run.fastq_bytes_2 = second.map(|f| f.2);
+
+        let subdir = match &record.output_subdir {
+            Some(subdir) => Path::new(subdir).join(ALIGNMENTS_DIR),
+            None => ALIGNMENTS_DIR.into(),
+        };
+        record.output_subdir = Some(subdir.to_string_lossy().into_owned());
+        record
+            .preferred_backend
+            .get_or_insert_with(|| "ftp".to_string());
+        switched += 1;
+    }
+    switched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_runs_with_bams() {
+        let mut records: Vec<EnaRecord> = serde_json::from_value(serde_json::json!([
+            {
+                "run_accession": "ERR1",
+                "fastq_ftp": "x/ERR1_1.fastq.gz;x/ERR1_2.fastq.gz",
+                "fastq_md5": "a;b",
+                "fastq_bytes": "1;2",
+                "bam_ftp": "x/HG00096.bam;x/HG00096.bam.bai",
+                "bam_md5": "c;d",
+                "bam_bytes": "30;4",
+            },
+            {
+                "run_accession": "ERR2",
+                "fastq_ftp": "x/ERR2.fastq.gz",
+                "fastq_md5": "e",
+                "fastq_bytes": "5",
+            },
+            {
+                "run_accession": "ERR3",
+                "bam_ftp": "x/ERR3.bam",
+                "bam_md5": "f",
+            },
+        ]))
+        .unwrap();
+        let mut processed = crate::process_records(records.clone(), false, None).unwrap();
+        assert_eq!(processed.len(), 2);
+        assert_eq!(prefer_bam(&mut records, &mut processed), 2);
+
+        assert_eq!(processed[0].fastq_ftp_1_name, "ERR1_HG00096.bam");
+        assert_eq!(processed[0].fastq_md5_2.as_deref(), Some("d"));
+        assert_eq!(processed[0].fastq_bytes_1, 30);
+        assert_eq!(records[0].output_subdir.as_deref(), Some("alignments"));
+        assert_eq!(records[0].preferred_backend.as_deref(), Some("ftp"));
+        assert_eq!(processed[1].fastq_ftp_1_name, "ERR2.fastq.gz");
+        assert!(records[1].output_subdir.is_none());
+        assert_eq!(processed[2].fastq_ftp_1_name, "ERR3.bam");
+    }
+}
@@ -0,0 +1,260 @@
+//! Reference assembly (GCA_/GCF_) downloads from the NCBI genomes tree.
+//!
+//! `https://ftp.ncbi.nlm.nih.gov/genomes/all/GCF/000/001/405/` lists one
+//! directory per assembly version (`GCF_000001405.40_GRCh38.p14/`); inside it
+//! sit `<dir>_genomic.fna.gz`, `<dir>_genomic.gff.gz` and `md5checksums.txt`.
+//! Files are fetched with the resumable ranged downloader and verified
+//! against that checksum list.
+
+use crate::aws_s3::{ResumableDownloader, SraMetadata};
+use crate::progress::ProgressSink;
+use anyhow::{anyhow, Context, Result};
+use reqwest::{header, Client};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const NCBI_GENOMES_URL: &str = "https://ftp.ncbi.nlm.nih.gov/genomes/all";
+
+/// Subdirectory of the output directory that receives reference files.
+pub const REFERENCE_DIR: &str = "reference";
+
+/// File suffixes fetched for every assembly.
+const ASSEMBLY_SUFFIXES: &[&str] = &["_genomic.fna.gz", "_genomic.gff.gz"];
+
+/// A single reference file resolved for download.
+#[derive(Debug, Clone)]
+pub struct AssemblyFile {
+    pub name: String,
+    pub url: String,
+    pub md5: Option<String>,
+    pub size: u64,
+}
+
+/// True for `GCA_000001405`, `GCF_000001405.40` and similar.
+pub fn is_assembly_accession(accession: &str) -> bool {
+    split_accession(accession).is_some()
+}
+
+/// Split into (prefix, nine digits, optional version).
+fn split_accession(accession: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (prefix, rest) = accession.split_once('_')?;
+    if prefix != "GCA" && prefix != "GCF" {
+        return None;
+    }
+    let (digits, version) = match rest.split_once('.') {
+        Some((digits, version)) => (digits, Some(version)),
+        None => (rest, None),
+    };
+    let numeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if digits.len() != 9 || !numeric(digits) || version.is_some_and(|v| !numeric(v)) {
+        return None;
+    }
+    Some((prefix, digits, version))
+}
+
+/// Parent directory URL holding every version of the assembly.
+fn parent_url(accession: &str) -> Option<String> {
+    let (prefix, digits, _) = split_accession(accession)?;
+    Some(format!(
+        "{}/{}/{}/{}/{}/",
+        NCBI_GENOMES_URL,
+        prefix,
+        &digits[0..3],
+        &digits[3..6],
+        &digits[6..9]
+    ))
+}
+
+/// Pick the assembly directory from an HTML listing: the exact version when
+/// one was given, otherwise the highest version available.
+fn select_assembly_dir(listing: &str, accession: &str) -> Option<String> {
+    let (prefix, digits, version) = split_accession(accession)?;
+    let stem = format!("{}_{}.", prefix, digits);
+    listing
+        .split("href=\"")
+        .skip(1)
+        .filter_map(|s| s.split('"').next())
+        .map(|href| href.trim_end_matches('/'))
+        .filter_map(|dir| {
+            let rest = dir.strip_prefix(&stem)?;
+            let (dir_version, _) = rest.split_once('_')?;
+            let n: u32 = dir_version.parse().ok()?;
+            match version {
+                Some(v) if v != dir_version => None,
+                _ => Some((n, dir.to_string())),
+            }
+        })
+        .max_by_key(|(n, _)| *n)
+        .map(|(_, dir)| dir)
+}
+
+/// Parse `md5checksums.txt` (`<md5>  ./<file>`) into name → md5.
+fn parse_md5_checksums(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let md5 = parts.next()?;
+            let name = parts.next()?.trim_start_matches("./");
+            Some((name.to_string(), md5.to_lowercase()))
+        })
+        .collect()
+}
+
+async fn get_text(client: &Client, url: &str) -> Result<String> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Request failed: {}", url))?;
+    if !response.status().is_success() {
+        return Err(anyhow!("HTTP {} for {}", response.status(), url));
+    }
+    Ok(response.text().await?)
+}
+
+/// Resolve the FASTA and GFF of an assembly to URLs, sizes and checksums.
+pub async fn resolve_assembly(accession: &str) -> Result<Vec<AssemblyFile>> {
+    let parent =
+        parent_url(accession).ok_or_else(|| anyhow!("Not an assembly accession: {}", accession))?;
+    let client = crate::http::client_builder()
+        .timeout(Duration::from_secs(60))
+        .build()?;
+
+    let listing = get_text(&client, &parent)
+        .await
+        .with_context(|| format!("Assembly {} not found on NCBI", accession))?;
+    let dir = select_assembly_dir(&listing, accession)
+        .ok_or_else(|| anyhow!("Assembly {} not found under {}", accession, parent))?;
+    let base = format!("{}{}/", parent, dir);
+
+    let checksums = match get_text(&client, &format!("{}md5checksums.txt", base)).await {
+        Ok(content) => parse_md5_checksums(&content),
+        Err(e) => {
+            warn!("[{}] md5checksums.txt unavailable: {}", accession, e);
+            HashMap::new()
+        }
+    };
+
+    let mut files = Vec::new();
+    for suffix in ASSEMBLY_SUFFIXES {
+        let name = format!("{}{}", dir, suffix);
+        let url = format!("{}{}", base, name);
+        let response = client.head(&url).send().await?;
+        if !response.status().is_success() {
+            warn!("[{}] {} not published, skipping", accession, name);
+            continue;
+        }
+        let size = response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| anyhow!("No Content-Length for {}", url))?;
+        files.push(AssemblyFile {
+            md5: checksums.get(&name).cloned(),
+            name,
+            url,
+            size,
+        });
+    }
+
+    if files.is_empty() {
+        return Err(anyhow!(
+            "No FASTA/GFF files found for assembly {}",
+            accession
+        ));
+    }
+    Ok(files)
+}
+
+/// Download an assembly's FASTA/GFF into `<output_dir>/reference/` and
+/// return that directory.
+pub async fn download_assembly(
+    accession: &str,
+    output_dir: &Path,
+    chunk_size_mb: u64,
+    max_workers: usize,
+    progress: Option<Arc<dyn ProgressSink>>,
+) -> Result<PathBuf> {
+    let reference_dir = output_dir.join(REFERENCE_DIR);
+    let files = resolve_assembly(accession).await?;
+    info!(
+        "[{}] Downloading {} reference file(s) to {}",
+        accession,
+        files.len(),
+        reference_dir.display()
+    );
+
+    for file in files {
+        let downloader = ResumableDownloader::new(
+            file.name.clone(),
+            SraMetadata {
+                s3_uri: file.url.clone(),
+                http_url: file.url.clone(),
+                md5: file.md5.clone(),
+                size: file.size,
+            },
+            reference_dir.clone(),
+            chunk_size_mb,
+            max_workers,
+            progress.clone(),
+            None,
+        )
+        .await?;
+        if !downloader.start().await? {
+            return Err(anyhow!(
+                "[{}] Verification failed for {}",
+                accession,
+                file.name
+            ));
+        }
+    }
+
+    Ok(reference_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_assembly_accessions() {
+        assert!(is_assembly_accession("GCF_000001405.40"));
+        assert!(is_assembly_accession("GCA_000001405"));
+        assert!(!is_assembly_accession("GCA_0000014"));
+        assert!(!is_assembly_accession("GCX_000001405.1"));
+        assert!(!is_assembly_accession("PRJNA1251654"));
+        assert_eq!(
+            parent_url("GCF_000001405.40").unwrap(),
+            "https://ftp.ncbi.nlm.nih.gov/genomes/all/GCF/000/001/405/"
+        );
+    }
+
+    #[test]
+    fn selects_requested_or_latest_version() {
+        let listing = r#"<a href="GCF_000001405.39_GRCh38.p13/">x</a>
+            <a href="GCF_000001405.40_GRCh38.p14/">y</a>
+            <a href="GCF_000001405.9_NCBI34/">z</a>"#;
+        assert_eq!(
+            select_assembly_dir(listing, "GCF_000001405").unwrap(),
+            "GCF_000001405.40_GRCh38.p14"
+        );
+        assert_eq!(
+            select_assembly_dir(listing, "GCF_000001405.39").unwrap(),
+            "GCF_000001405.39_GRCh38.p13"
+        );
+        assert!(select_assembly_dir(listing, "GCF_000001405.1").is_none());
+    }
+
+    #[test]
+    fn parses_md5_checksum_listing() {
+        let sums =
+            parse_md5_checksums("ABC123  ./GCF_1_genomic.fna.gz\ndef456  ./GCF_1_genomic.gff.gz\n");
+        assert_eq!(sums["GCF_1_genomic.fna.gz"], "abc123");
+        assert_eq!(sums["GCF_1_genomic.gff.gz"], "def456");
+    }
+}
@@ -0,0 +1,93 @@
+//! Audit trail of external commands.
+//!
+//! Every subprocess the crate runs (`wget`, `prefetch`, `fasterq-dump`,
+//! `rsync`, `ssh`, tool probes, ...) is reported by [`record`] as one
+//! tracing event with target [`AUDIT_TARGET`]: the exact argv, working
+//! directory, exit status, duration and the tail of stderr. The CLI writes
+//! these to `commands.jsonl` and keeps them out of the human log, so a
+//! failure can be reproduced exactly and admins can see what ran.
+
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+use std::time::Instant;
+
+pub const AUDIT_TARGET: &str = "commands";
+
+/// Lines of stderr kept per command.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Last [`STDERR_TAIL_LINES`] lines of `stderr`.
+fn tail(stderr: &[u8]) -> String {
+    let text = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Report `command`, started at `started`. `outcome` is its exit status and
+/// stderr, or why it has none (spawn error, cancellation).
+pub fn record(
+    command: &std::process::Command,
+    started: Instant,
+    outcome: Result<(ExitStatus, &[u8]), &str>,
+) {
+    let argv: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let cwd = command
+        .get_current_dir()
+        .map(PathBuf::from)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    let (status, exit_code, stderr_tail) = match outcome {
+        Ok((status, stderr)) => (status.to_string(), status.code(), tail(stderr)),
+        Err(reason) => (reason.to_string(), None, String::new()),
+    };
+    tracing::info!(
+        target: AUDIT_TARGET,
+        argv = %serde_json::to_string(&argv).unwrap_or_default(),
+        cwd = %cwd.display(),
+        status = %status,
+        exit_code,
+        duration_s = started.elapsed().as_secs_f64(),
+        stderr_tail = %stderr_tail,
+        "{}",
+        argv[0]
+    );
+}
+
+/// `command.output()`, recorded.
+pub async fn output(command: &mut tokio::process::Command) -> std::io::Result<Output> {
+    let started = Instant::now();
+    let output = command.output().await;
+    match &output {
+        Ok(out) => record(command.as_std(), started, Ok((out.status, &out.stderr))),
+        Err(e) => record(command.as_std(), started, Err(&e.to_string())),
+    }
+    output
+}
+
+/// Blocking [`output`].
+pub fn output_blocking(command: &mut std::process::Command) -> std::io::Result<Output> {
+    let started = Instant::now();
+    let output = command.output();
+    match &output {
+        Ok(out) => record(command, started, Ok((out.status, &out.stderr))),
+        Err(e) => record(command, started, Err(&e.to_string())),
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_stderr_tail() {
+        let stderr: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        let kept = tail(stderr.as_bytes());
+        assert!(kept.starts_with("line 11\n"));
+        assert!(kept.ends_with("line 30"));
+        assert_eq!(tail(b""), "");
+    }
+}
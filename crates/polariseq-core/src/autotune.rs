@@ -0,0 +1,301 @@
+//! Concurrency autotuning (`download --auto-tune`).
+//!
+//! `-p` and `-t` become ceilings. The job starts with [`START_RUNS`] runs
+//! at a time and [`START_THREADS`] threads per file; every [`INTERVAL`] the
+//! tuner looks at the job's throughput and failed backend attempts:
+//!
+//! - more than a fifth of the attempts failed: one run and a quarter of
+//!   the threads fewer, then no step up for a while;
+//! - the last step up raised throughput by [`MIN_GAIN`] or more: another
+//!   step, alternating between runs and threads;
+//! - it did not: the step is undone and the tuner holds there for a while
+//!   before probing again, so it follows a network that changes.
+//!
+//! Fewer runs take effect as running ones finish; a new thread count
+//! applies to runs that start afterwards. Intervals in which transfers ran
+//! less than half the time (conversion, pauses) are skipped.
+
+use crate::bandwidth::TransferMeter;
+use crate::progress::ProgressSink;
+use crate::DownloadOptions;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How often throughput is measured and the settings adjusted.
+pub const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Ceilings when `-p` / `-t` are not given.
+pub const MAX_RUNS: usize = 16;
+pub const MAX_THREADS: usize = 32;
+
+pub const START_RUNS: usize = 2;
+pub const START_THREADS: usize = 4;
+
+/// Throughput gain a step up must bring to be kept.
+pub const MIN_GAIN: f64 = 0.05;
+
+/// Share of failed attempts above which the tuner backs off.
+const MAX_ERROR_RATE: f64 = 0.2;
+
+/// Intervals without a step up after backing off or undoing a step.
+const HOLD: u32 = 5;
+
+/// Concurrent runs (`-p`) and threads per file (`-t`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub runs: usize,
+    pub threads: usize,
+}
+
+/// The tuning decisions, fed one interval at a time.
+#[derive(Debug)]
+struct Tuner {
+    max: Settings,
+    current: Settings,
+    /// Settings and throughput before the last step up.
+    before_step: Option<(Settings, f64)>,
+    hold: u32,
+    /// Whether the next step up adds a run rather than threads.
+    grow_runs: bool,
+}
+
+impl Tuner {
+    fn new(max: Settings) -> Self {
+        Self {
+            max,
+            current: Settings {
+                runs: START_RUNS.min(max.runs),
+                threads: START_THREADS.min(max.threads),
+            },
+            before_step: None,
+            hold: 0,
+            grow_runs: true,
+        }
+    }
+
+    /// Take one interval's throughput (bytes/s) and backend attempts;
+    /// returns the new settings when they change.
+    fn observe(&mut self, throughput: f64, attempts: u64, failures: u64) -> Option<Settings> {
+        let previous = self.current;
+        if attempts > 0 && failures as f64 > attempts as f64 * MAX_ERROR_RATE {
+            self.current = Settings {
+                runs: previous.runs.saturating_sub(1).max(1),
+                threads: (previous.threads * 3 / 4).max(1),
+            };
+            self.before_step = None;
+            self.hold = HOLD;
+        } else if let Some((settings, base)) = self.before_step.take() {
+            if throughput < base * (1.0 + MIN_GAIN) {
+                self.current = settings;
+                self.hold = HOLD;
+            } else {
+                self.step_up(throughput);
+            }
+        } else if self.hold > 0 {
+            self.hold -= 1;
+        } else {
+            self.step_up(throughput);
+        }
+        (self.current != previous).then_some(self.current)
+    }
+
+    fn step_up(&mut self, throughput: f64) {
+        let mut next = self.current;
+        // Alternate, skipping a dimension at its ceiling.
+        for _ in 0..2 {
+            if self.grow_runs {
+                next.runs = (next.runs + 1).min(self.max.runs);
+            } else {
+                next.threads = (next.threads * 3).div_ceil(2).min(self.max.threads);
+            }
+            self.grow_runs = !self.grow_runs;
+            if next != self.current {
+                self.before_step = Some((self.current, throughput));
+                self.current = next;
+                return;
+            }
+        }
+    }
+}
+
+/// Live autotuning state of a job, shared with the backend loop.
+#[derive(Debug)]
+pub struct AutoTune {
+    tuner: Mutex<Tuner>,
+    runs: AtomicUsize,
+    threads: AtomicUsize,
+    attempts: AtomicU64,
+    failures: AtomicU64,
+    /// Transfers of the whole job.
+    meter: Arc<TransferMeter>,
+}
+
+impl AutoTune {
+    /// Tune within the `-p` / `-t` of `options`.
+    pub fn new(options: &DownloadOptions) -> Arc<Self> {
+        let tuner = Tuner::new(Settings {
+            runs: options.multithreads.max(1),
+            threads: options.aws_threads.max(1),
+        });
+        let current = tuner.current;
+        Arc::new(Self {
+            tuner: Mutex::new(tuner),
+            runs: AtomicUsize::new(current.runs),
+            threads: AtomicUsize::new(current.threads),
+            attempts: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            meter: TransferMeter::new(),
+        })
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            runs: self.runs.load(Ordering::Relaxed),
+            threads: self.threads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `inner`, with the job's transfers also measured here.
+    pub(crate) fn sink(&self, inner: Option<Arc<dyn ProgressSink>>) -> Arc<dyn ProgressSink> {
+        self.meter.sink(inner)
+    }
+
+    pub(crate) fn attempt_finished(&self, ok: bool) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Hold back the permits of `semaphore` (sized for the `-p` ceiling)
+    /// above the current run count, then adjust every [`INTERVAL`] until
+    /// `stop` is cancelled or the handle aborted.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        semaphore: Arc<Semaphore>,
+        stop: CancellationToken,
+    ) -> JoinHandle<()> {
+        let mut held = Vec::new();
+        self.hold_permits(&semaphore, &mut held);
+        let max = self.tuner.lock().unwrap().max;
+        let current = self.settings();
+        info!(
+            "[Auto-tune] Starting at -p {} -t {} (ceilings {} and {})",
+            current.runs, current.threads, max.runs, max.threads
+        );
+        let tune = self.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(Duration::from_secs(1));
+            let mut since = (Instant::now(), tune.meter.totals());
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => return,
+                    _ = tick.tick() => {}
+                }
+                tune.hold_permits(&semaphore, &mut held);
+                let elapsed = since.0.elapsed();
+                if elapsed < INTERVAL {
+                    continue;
+                }
+                let (bytes, busy) = tune.meter.totals();
+                let (_, (last_bytes, last_busy)) = since;
+                since = (Instant::now(), (bytes, busy));
+                tune.evaluate(
+                    bytes.saturating_sub(last_bytes) as f64 / elapsed.as_secs_f64(),
+                    busy.saturating_sub(last_busy) >= elapsed / 2,
+                );
+            }
+        })
+    }
+
+    fn evaluate(&self, throughput: f64, busy: bool) {
+        let attempts = self.attempts.swap(0, Ordering::Relaxed);
+        let failures = self.failures.swap(0, Ordering::Relaxed);
+        if !busy && failures == 0 {
+            return;
+        }
+        let Some(next) = self
+            .tuner
+            .lock()
+            .unwrap()
+            .observe(throughput, attempts, failures)
+        else {
+            return;
+        };
+        info!(
+            "[Auto-tune] {:.1} MB/s, {} of {} attempt(s) failed: now -p {} -t {}",
+            throughput / 1e6,
+            failures,
+            attempts,
+            next.runs,
+            next.threads
+        );
+        self.runs.store(next.runs, Ordering::Relaxed);
+        self.threads.store(next.threads, Ordering::Relaxed);
+    }
+
+    /// Take or release permits until only the current run count is free
+    /// to the runs. Taking waits for running runs to finish.
+    fn hold_permits(&self, semaphore: &Arc<Semaphore>, held: &mut Vec<OwnedSemaphorePermit>) {
+        let max = self.tuner.lock().unwrap().max.runs;
+        let target = max.saturating_sub(self.runs.load(Ordering::Relaxed));
+        held.truncate(target);
+        while held.len() < target {
+            match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => held.push(permit),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_up_while_it_pays_and_backs_off_on_errors() {
+        let mut tuner = Tuner::new(Settings {
+            runs: 3,
+            threads: 8,
+        });
+        let at = |runs, threads| Some(Settings { runs, threads });
+        assert_eq!(Some(tuner.current), at(2, 4));
+
+        assert_eq!(tuner.observe(100.0, 4, 0), at(3, 4));
+        assert_eq!(tuner.observe(150.0, 4, 0), at(3, 6));
+        // Runs are at their ceiling, so threads grow again.
+        assert_eq!(tuner.observe(200.0, 4, 0), at(3, 8));
+        // No gain: back to the last good settings, then hold.
+        assert_eq!(tuner.observe(205.0, 4, 0), at(3, 6));
+        for _ in 0..HOLD {
+            assert_eq!(tuner.observe(200.0, 4, 0), None);
+        }
+        assert_eq!(tuner.observe(200.0, 4, 0), at(3, 8));
+
+        assert_eq!(tuner.observe(50.0, 4, 2), at(2, 6));
+        assert_eq!(tuner.observe(50.0, 0, 0), None);
+    }
+
+    #[tokio::test]
+    async fn holds_back_the_permits_above_the_run_count() {
+        let options = DownloadOptions {
+            multithreads: 5,
+            ..DownloadOptions::default()
+        };
+        let tune = AutoTune::new(&options);
+        let semaphore = Arc::new(Semaphore::new(5));
+        let mut held = Vec::new();
+        tune.hold_permits(&semaphore, &mut held);
+        assert_eq!(semaphore.available_permits(), START_RUNS);
+
+        tune.runs.store(4, Ordering::Relaxed);
+        tune.hold_permits(&semaphore, &mut held);
+        assert_eq!(semaphore.available_permits(), 4);
+    }
+}
@@ -1,30 +1,34 @@
-use crate::progress::{transfer_bar_style, verify_bar_style};
+use crate::connections::ConnectionLimit;
+use crate::error::PolariseqError;
+use crate::prealloc::{self, Preallocation};
+use crate::progress::{start_progress, ProgressKind, ProgressSink};
 use crate::progress_store::ProgressStore;
-use anyhow::{anyhow, Result};
+use crate::write_queue::WriteQueue;
+use crate::{circuit, http, EnaRecord};
+use anyhow::{anyhow, Context, Result};
 use futures::StreamExt;
-use indicatif::{MultiProgress, ProgressBar};
 use md5;
 use quick_xml::events::Event;
 use quick_xml::Reader;
 use reqwest::{header, Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
 use std::str;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::AsyncReadExt;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::{mpsc, Mutex};
-use tracing::{info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn, Instrument};
 
 // ============================
 // 1. Data Structures
 // ============================
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SraMetadata {
     pub s3_uri: String,
     pub http_url: String,
@@ -32,11 +36,171 @@ pub struct SraMetadata {
     pub size: u64,
 }
 
-/// Simple pause/resume token that can be shared between the GUI and the
-/// AWS download workers.
+impl SraMetadata {
+    /// Take ENA's checksum of the object when NCBI listed none, so the
+    /// download is verified by content rather than by size alone. ENA's
+    /// copy must have the same size, otherwise it is a different file and
+    /// `false` is returned.
+    pub fn fill_md5(&mut self, md5: &str, bytes: u64) -> bool {
+        if self.md5.is_some() || (self.size > 0 && bytes != self.size) {
+            return false;
+        }
+        self.md5 = Some(md5.to_ascii_lowercase());
+        true
+    }
+}
+
+/// `sra_md5` and `sra_bytes` of `record`, when ENA lists exactly one SRA
+/// file with both.
+pub fn ena_sra_md5(record: &EnaRecord) -> Option<(String, u64)> {
+    let md5 = record.sra_md5.as_deref().filter(|m| !m.contains(';'))?;
+    let bytes = record.sra_bytes.as_deref()?.parse().ok()?;
+    (md5.len() == 32).then(|| (md5.to_string(), bytes))
+}
+
+/// NCBI's SRA Open Data bucket, holding SRA Normalized runs at
+/// `sra/<run>/<run>`.
+const SRA_ODP_BUCKET: &str = "sra-pub-run-odp";
+
+/// Which SRA quality tier a run may be downloaded in (`--sra-format`).
+///
+/// SRA Lite copies keep the reads but replace per-base qualities with a
+/// constant value, so tools that rely on real qualities must not use them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SraFormat {
+    /// Full-quality (SRA Normalized) only; runs available solely as Lite are skipped.
+    Full,
+    /// Prefer SRA Lite when present (smaller), otherwise full quality.
+    Lite,
+    /// Prefer full quality, falling back to SRA Lite with a warning.
+    #[default]
+    Any,
+}
+
+/// HTTP version of S3 range requests (`--s3-http`).
+///
+/// With HTTP/1.1 each chunk worker holds its own connection. With HTTP/2 the
+/// client offers `h2` during the TLS handshake and, where the endpoint
+/// accepts it, multiplexes all chunk requests over one connection; endpoints
+/// that only speak HTTP/1.1 get the usual connection pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum S3Http {
+    #[default]
+    Http1,
+    Http2,
+}
+
+/// Where the AWS backend keeps a run's `.sra` and intermediate FASTQ
+/// (`--aws-staging`). `run` works in `<output>/<run>/`, the layout
+/// `prefetch` uses, and moves the finished files up into the output
+/// directory; `flat` works in the output directory itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AwsStaging {
+    #[default]
+    Run,
+    Flat,
+}
+
+/// One mode's result from [`benchmark_http`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpBench {
+    pub mode: S3Http,
+    /// Version the endpoint answered with, e.g. `HTTP/1.1`.
+    pub negotiated: String,
+    pub bytes: u64,
+    pub seconds: f64,
+    /// Time to the first response headers, connection setup included.
+    pub first_byte_seconds: f64,
+}
+
+impl HttpBench {
+    pub fn bytes_per_sec(&self) -> u64 {
+        (self.bytes as f64 / self.seconds.max(1e-3)) as u64
+    }
+}
+
+/// Bytes [`benchmark_http`] fetches per mode by default.
+pub const HTTP_BENCH_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Fetch the first `bytes` of `metadata`'s object in each [`S3Http`] mode,
+/// split into `workers` concurrent range requests on a fresh client, and
+/// time them. Nothing is written to disk.
+pub async fn benchmark_http(
+    metadata: &SraMetadata,
+    bytes: u64,
+    workers: usize,
+) -> Result<Vec<HttpBench>> {
+    let bytes = if metadata.size > 0 {
+        bytes.min(metadata.size)
+    } else {
+        bytes
+    };
+    let workers = workers.max(1) as u64;
+    let part = bytes.div_ceil(workers).max(1);
+    let mut results = Vec::new();
+    for mode in [S3Http::Http1, S3Http::Http2] {
+        let client = build_range_client(workers as usize, header::HeaderMap::new(), mode)?;
+        let started = Instant::now();
+        let first = client
+            .get(&metadata.http_url)
+            .header(header::RANGE, "bytes=0-0")
+            .send()
+            .await?
+            .error_for_status()?;
+        let negotiated = format!("{:?}", first.version());
+        let first_byte_seconds = started.elapsed().as_secs_f64();
+        first.bytes().await?;
+
+        let started = Instant::now();
+        let requests = (0..bytes).step_by(part as usize).map(|start| {
+            let request = client.get(&metadata.http_url).header(
+                header::RANGE,
+                format!("bytes={}-{}", start, (start + part).min(bytes) - 1),
+            );
+            async move {
+                let body = request.send().await?.error_for_status()?.bytes().await?;
+                Ok::<_, reqwest::Error>(body.len() as u64)
+            }
+        });
+        let fetched = futures::future::try_join_all(requests).await?;
+        results.push(HttpBench {
+            mode,
+            negotiated,
+            bytes: fetched.iter().sum(),
+            seconds: started.elapsed().as_secs_f64(),
+            first_byte_seconds,
+        });
+    }
+    Ok(results)
+}
+
+/// Outcome of resolving a run's AWS copy under an [`SraFormat`] preference.
+#[derive(Debug, Clone)]
+pub enum SraLookup {
+    Found(SraMetadata),
+    /// Only an SRA Lite copy exists and [`SraFormat::Full`] was requested.
+    LiteOnly,
+    NotFound,
+}
+
+/// Pause/resume and bandwidth control shared between the GUI or the
+/// control socket and the download workers.
 #[derive(Clone, Default)]
 pub struct PauseToken {
     paused: Arc<AtomicBool>,
+    /// Job-wide limit in bytes/s; 0 means unlimited.
+    bandwidth: Arc<AtomicU64>,
+    /// When the limited pipe is next free (virtual clock of the limiter).
+    next_free: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// External transfer processes currently running.
+    transfers: Arc<AtomicUsize>,
 }
 
 impl PauseToken {
@@ -62,6 +226,50 @@ impl PauseToken {
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     }
+
+    /// Limit the whole job to `bytes_per_sec`, or lift the limit.
+    pub fn set_bandwidth(&self, bytes_per_sec: Option<u64>) {
+        self.bandwidth
+            .store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn bandwidth(&self) -> Option<u64> {
+        Some(self.bandwidth.load(Ordering::Relaxed)).filter(|&b| b > 0)
+    }
+
+    /// Wait while paused, then until `bytes` more fit under the bandwidth
+    /// limit shared by every holder of this token.
+    pub async fn throttle(&self, bytes: u64) {
+        self.wait_while_paused().await;
+        let Some(limit) = self.bandwidth() else {
+            return;
+        };
+        let delay = {
+            let mut next_free = self.next_free.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let start = next_free.map_or(now, |t| t.max(now));
+            *next_free = Some(start + Duration::from_secs_f64(bytes as f64 / limit as f64));
+            start - now
+        };
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Share of the bandwidth limit for an external tool (`wget
+    /// --limit-rate`) starting now, split with the transfers already running.
+    pub fn transfer_share(&self) -> Option<u64> {
+        self.bandwidth()
+            .map(|b| (b / (self.transfers.load(Ordering::Relaxed) as u64 + 1)).max(1))
+    }
+
+    pub(crate) fn transfer_started(&self) {
+        self.transfers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transfer_finished(&self) {
+        self.transfers.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -71,9 +279,33 @@ struct ChunkInfo {
     end: u64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 struct ProgressData {
     downloaded_chunks: Vec<usize>,
+    /// MD5 of each chunk as written, so a file failing its final check can
+    /// be repaired by re-fetching only the chunks that no longer match.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    chunk_md5: BTreeMap<usize, String>,
+    /// Chunk size in bytes the chunk ids refer to; missing in progress
+    /// files of older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunk_size: Option<u64>,
+    /// ETag of the remote object the chunks came from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+}
+
+/// Chunks of `new_size` bytes that the finished chunks of `old_size` bytes
+/// in `done` cover completely, for a file of `size` bytes.
+fn remap_chunks(done: &HashSet<usize>, old_size: u64, new_size: u64, size: u64) -> HashSet<usize> {
+    (0..size.div_ceil(new_size))
+        .filter(|&id| {
+            let start = id * new_size;
+            let end = std::cmp::min(start + new_size, size);
+            (start / old_size..=(end - 1) / old_size).all(|old| done.contains(&(old as usize)))
+        })
+        .map(|id| id as usize)
+        .collect()
 }
 
 // ============================
@@ -84,26 +316,89 @@ pub struct SraUtils;
 
 impl SraUtils {
     pub async fn get_metadata(run_id: &str, _api_key: Option<&str>) -> Result<Option<SraMetadata>> {
+        match Self::get_metadata_for_format(run_id, SraFormat::Any).await? {
+            SraLookup::Found(metadata) => Ok(Some(metadata)),
+            SraLookup::LiteOnly | SraLookup::NotFound => Ok(None),
+        }
+    }
+
+    /// Metadata of `run_id`'s SRA Normalized copy built from ENA's `sra_md5`
+    /// and `sra_bytes`, without asking NCBI. The Open Data key is confirmed
+    /// by a HEAD request whose size must match ENA's. `None` (use
+    /// [`Self::get_metadata_for_format`]) when Lite is preferred or S3 does
+    /// not agree.
+    pub async fn metadata_from_ena(
+        run_id: &str,
+        md5: &str,
+        bytes: u64,
+        format: SraFormat,
+    ) -> Option<SraMetadata> {
+        if format == SraFormat::Lite {
+            return None;
+        }
+        let (s3_uri, http_url) = odp_urls(run_id);
+        let client = http::client_builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .ok()?;
+        let size = match circuit::send(client.head(&http_url)).await {
+            Ok(resp) if resp.status().is_success() => resp
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok()),
+            Ok(resp) => {
+                debug!("[{}] HEAD {} returned {}", run_id, http_url, resp.status());
+                None
+            }
+            Err(e) => {
+                debug!("[{}] HEAD {} failed: {}", run_id, http_url, e);
+                None
+            }
+        };
+        if size != Some(bytes) {
+            debug!(
+                "[{}] S3 size {:?} differs from ENA sra_bytes {}; asking NCBI",
+                run_id, size, bytes
+            );
+            return None;
+        }
+        Some(SraMetadata {
+            s3_uri,
+            http_url,
+            md5: Some(md5.to_ascii_lowercase()),
+            size: bytes,
+        })
+    }
+
+    /// Resolve the AWS copy of `run_id`, honouring the quality preference.
+    pub async fn get_metadata_for_format(run_id: &str, format: SraFormat) -> Result<SraLookup> {
+        let xml = Self::fetch_xml(run_id).await?;
+        Ok(select_sra_file(run_id, parse_sra_files(&xml), format))
+    }
+
+    async fn fetch_xml(run_id: &str) -> Result<String> {
         let url = format!(
             "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi?db=sra&id={}&rettype=full&retmode=xml",
             run_id
         );
 
         // Modification 1: Timeout increased to 60 seconds
-        let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+        let client = http::client_builder()
+            .timeout(Duration::from_secs(60))
+            .build()?;
 
         let mut attempt = 0;
         let max_retries = 10; // Modification 2: Max retries increased to 10
 
         loop {
             attempt += 1;
-            let result = client.get(&url).send().await;
+            let result = circuit::send(client.get(&url)).await;
 
             match result {
                 Ok(resp) => {
                     if resp.status().is_success() {
-                        let text = resp.text().await?;
-                        return parse_sra_xml(&text);
+                        return Ok(resp.text().await?);
                     } else {
                         if attempt >= max_retries {
                             return Err(anyhow!("NCBI API Error: Status {}", resp.status()));
@@ -138,9 +433,14 @@ impl SraUtils {
     }
 }
 
-// ... (resolve_urls, parse_sra_xml and other functions remain unchanged, please copy the previous code or keep it as is)
-// To save space, only the SraUtils modification part is listed here. If the ResumableDownloader part has not changed, it does not need to be moved.
-// But for completeness, here is the rest:
+/// `s3://` and `https://` URLs of `run_id` in NCBI's Open Data bucket.
+fn odp_urls(run_id: &str) -> (String, String) {
+    let key = format!("sra/{}/{}", run_id, run_id);
+    (
+        format!("s3://{}/{}", SRA_ODP_BUCKET, key),
+        format!("https://{}.s3.amazonaws.com/{}", SRA_ODP_BUCKET, key),
+    )
+}
 
 fn resolve_urls(raw_url: &str) -> Option<(String, String)> {
     if let Some(rest) = raw_url.strip_prefix("https://") {
@@ -158,12 +458,23 @@ fn resolve_urls(raw_url: &str) -> Option<(String, String)> {
     None
 }
 
-fn parse_sra_xml(xml_text: &str) -> Result<Option<SraMetadata>> {
+/// An AWS-hosted SRA file from the efetch XML, tagged by quality tier.
+#[derive(Debug, Clone)]
+struct SraCandidate {
+    lite: bool,
+    metadata: SraMetadata,
+}
+
+/// Collect every worldwide-egress AWS copy listed under the run's
+/// `<SRAFile>` elements. Lite copies are recognised by their
+/// `semantic_name` ("SRA Lite") or a `.lite` file name.
+fn parse_sra_files(xml_text: &str) -> Vec<SraCandidate> {
     let mut reader = Reader::from_str(xml_text);
     let mut buf = Vec::new();
     let mut current_file_md5: Option<String> = None;
     let mut current_file_size: u64 = 0;
-    let mut found_metadata: Option<SraMetadata> = None;
+    let mut current_file_lite = false;
+    let mut candidates = Vec::new();
 
     loop {
         match reader.read_event_into(&mut buf) {
@@ -174,6 +485,7 @@ fn parse_sra_xml(xml_text: &str) -> Result<Option<SraMetadata>> {
                 {
                     current_file_md5 = None;
                     current_file_size = 0;
+                    current_file_lite = false;
                     for attr in e.attributes().flatten() {
                         let k = str::from_utf8(attr.key.as_ref()).unwrap_or("");
                         let v = str::from_utf8(attr.value.as_ref()).unwrap_or("");
@@ -181,6 +493,10 @@ fn parse_sra_xml(xml_text: &str) -> Result<Option<SraMetadata>> {
                             current_file_md5 = Some(v.to_string());
                         } else if k.eq_ignore_ascii_case("size") {
                             current_file_size = v.parse().unwrap_or(0);
+                        } else if k.eq_ignore_ascii_case("semantic_name") {
+                            current_file_lite |= v.to_ascii_lowercase().contains("lite");
+                        } else if k.eq_ignore_ascii_case("filename") {
+                            current_file_lite |= v.to_ascii_lowercase().contains(".lite");
                         }
                     }
                 } else if name_str.eq_ignore_ascii_case("Alternatives") {
@@ -202,36 +518,75 @@ fn parse_sra_xml(xml_text: &str) -> Result<Option<SraMetadata>> {
                     }
                     if is_aws && is_worldwide && !curr_url.is_empty() {
                         if let Some((s3_uri, http_url)) = resolve_urls(&curr_url) {
-                            found_metadata = Some(SraMetadata {
-                                s3_uri,
-                                http_url,
-                                md5: current_file_md5.clone(),
-                                size: current_file_size,
+                            candidates.push(SraCandidate {
+                                lite: current_file_lite,
+                                metadata: SraMetadata {
+                                    s3_uri,
+                                    http_url,
+                                    md5: current_file_md5.clone(),
+                                    size: current_file_size,
+                                },
                             });
-                            break;
                         }
                     }
                 }
             }
-            Ok(Event::Eof) => break,
+            Ok(Event::Eof) | Err(_) => break,
             _ => {}
         }
         buf.clear();
     }
-    Ok(found_metadata)
+    candidates
+}
+
+fn select_sra_file(run_id: &str, candidates: Vec<SraCandidate>, format: SraFormat) -> SraLookup {
+    let (lite, full): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|c| c.lite);
+    let lite = lite.into_iter().next().map(|c| c.metadata);
+    let full = full.into_iter().next().map(|c| c.metadata);
+
+    match (format, full, lite) {
+        (SraFormat::Lite, _, Some(lite)) => SraLookup::Found(lite),
+        (_, Some(full), _) => SraLookup::Found(full),
+        (SraFormat::Full, None, Some(_)) => {
+            warn!(
+                "[{}] Only an SRA Lite copy (simplified quality scores) is available; skipping (--sra-format full)",
+                run_id
+            );
+            SraLookup::LiteOnly
+        }
+        (_, None, Some(lite)) => {
+            warn!(
+                "[{}] Only an SRA Lite copy is available: base qualities are simplified. Use --sra-format full to skip such runs.",
+                run_id
+            );
+            SraLookup::Found(lite)
+        }
+        (_, None, None) => SraLookup::NotFound,
+    }
 }
 
 pub struct ResumableDownloader {
     run_id: String,
     metadata: SraMetadata,
     filepath: PathBuf,
+    /// Chunks land here; renamed to `filepath` once verified.
+    part_path: PathBuf,
     meta_file: PathBuf,
     chunk_size: u64,
     max_workers: usize,
     client: Client,
-    mp: Option<Arc<MultiProgress>>,
+    /// Extra headers and HTTP version the client was built with.
+    headers: header::HeaderMap,
+    http: S3Http,
+    preallocation: Preallocation,
+    /// Job-wide slots each range request waits for.
+    connections: Option<Arc<ConnectionLimit>>,
+    /// ETag of the remote object, recorded in the `.meta.json`.
+    etag: std::sync::Mutex<Option<String>>,
+    progress: Option<Arc<dyn ProgressSink>>,
     progress_bytes: Option<Arc<AtomicU64>>,
     pause_token: Option<PauseToken>,
+    cancel: CancellationToken,
     progress_store: Option<ProgressStore>,
 }
 
@@ -242,7 +597,7 @@ impl ResumableDownloader {
         save_dir: PathBuf,
         chunk_size_mb: u64,
         max_workers: usize,
-        mp: Option<Arc<MultiProgress>>,
+        progress: Option<Arc<dyn ProgressSink>>,
         progress_store: Option<ProgressStore>,
     ) -> Result<Self> {
         let filename = metadata
@@ -254,27 +609,28 @@ impl ResumableDownloader {
             .to_string();
         let filepath = save_dir.join(&filename);
         let meta_file = filepath.with_extension("meta.json");
+        let part_path = crate::part_path(&filepath);
 
-        // No whole-request body timeout: large Range chunks (e.g. 200 MiB) can
-        // take many minutes on slow links. Rely on connect_timeout + per-chunk
-        // retries with intra-chunk offset resume instead.
-        let client = Client::builder()
-            .http1_only()
-            .connect_timeout(Duration::from_secs(10))
-            .pool_max_idle_per_host(max_workers)
-            .build()?;
+        let client = build_range_client(max_workers, header::HeaderMap::new(), S3Http::Http1)?;
 
         Ok(Self {
             run_id,
             metadata,
             filepath,
+            part_path,
             meta_file,
             chunk_size: chunk_size_mb * 1024 * 1024,
             max_workers,
             client,
-            mp,
+            headers: header::HeaderMap::new(),
+            http: S3Http::Http1,
+            preallocation: Preallocation::Sparse,
+            connections: None,
+            etag: std::sync::Mutex::new(None),
+            progress,
             progress_bytes: None,
             pause_token: None,
+            cancel: CancellationToken::new(),
             progress_store,
         })
     }
@@ -289,57 +645,170 @@ impl ResumableDownloader {
         self
     }
 
-    // ... (load_progress, save_progress, start, verify_integrity methods remain unchanged)
-    fn load_progress(&self) -> HashSet<usize> {
+    /// Stop promptly when `token` is cancelled. Finished chunks stay recorded
+    /// in the `.meta.json` sidecar, so a later run resumes from them.
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Send `Authorization: Bearer <token>` with every range request, for
+    /// authenticated sources such as the EGA download API.
+    pub fn with_bearer_token(mut self, token: &str) -> Result<Self> {
+        let mut value = header::HeaderValue::from_str(&format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        self.headers.insert(header::AUTHORIZATION, value);
+        self.client = build_range_client(self.max_workers, self.headers.clone(), self.http)?;
+        Ok(self)
+    }
+
+    /// Send range requests over `http`, see [`S3Http`].
+    pub fn with_http(mut self, http: S3Http) -> Result<Self> {
+        self.http = http;
+        self.client = build_range_client(self.max_workers, self.headers.clone(), http)?;
+        Ok(self)
+    }
+
+    /// Give new `.part` files their size as `preallocation` asks, see
+    /// [`crate::prealloc`].
+    pub fn with_preallocation(mut self, preallocation: Preallocation) -> Self {
+        self.preallocation = preallocation;
+        self
+    }
+
+    /// Share `limit` with the other downloads of the job, see
+    /// [`crate::connections`].
+    pub fn with_connection_limit(mut self, limit: Option<Arc<ConnectionLimit>>) -> Self {
+        self.connections = limit;
+        self
+    }
+
+    fn load_progress(&self) -> (HashSet<usize>, BTreeMap<usize, String>) {
         if self.meta_file.exists() {
             if let Ok(content) = std::fs::read_to_string(&self.meta_file) {
                 if let Ok(progress) = serde_json::from_str::<ProgressData>(&content) {
-                    return progress.downloaded_chunks.into_iter().collect();
+                    return (
+                        progress.downloaded_chunks.into_iter().collect(),
+                        progress.chunk_md5,
+                    );
                 }
             }
         }
-        HashSet::new()
+        (HashSet::new(), BTreeMap::new())
     }
-    fn save_progress(&self, downloaded_chunks: &HashSet<usize>) -> Result<()> {
+    fn save_progress(
+        &self,
+        downloaded_chunks: &HashSet<usize>,
+        chunk_md5: &BTreeMap<usize, String>,
+    ) -> Result<()> {
         let progress_data = ProgressData {
             downloaded_chunks: downloaded_chunks.iter().cloned().collect(),
+            chunk_md5: chunk_md5.clone(),
+            chunk_size: Some(self.chunk_size),
+            etag: self.etag.lock().unwrap().clone(),
         };
         let content = serde_json::to_string(&progress_data)?;
         std::fs::write(&self.meta_file, content)?;
         Ok(())
     }
 
-    fn invalidate_download(&self) {
-        for path in [&self.filepath, &self.meta_file] {
-            match std::fs::remove_file(path) {
-                Ok(()) => {}
-                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
-                Err(error) => warn!(
-                    "Failed to remove invalid download {}: {error}",
-                    path.display()
-                ),
-            }
+    /// Take the ETag recorded in the `.meta.json` of a partial download, for
+    /// the chunk responses to be checked against. Chunks recorded with
+    /// another `--chunk-size` are mapped onto the current chunks, keeping
+    /// those whose bytes are all there; their MD5s no longer apply and are
+    /// dropped.
+    fn reconcile_progress(&self) {
+        let Some(progress) = std::fs::read_to_string(&self.meta_file)
+            .ok()
+            .and_then(|content| serde_json::from_str::<ProgressData>(&content).ok())
+        else {
+            return;
+        };
+        if let Some(etag) = progress.etag {
+            *self.etag.lock().unwrap() = Some(etag);
+        }
+        let Some(old_size) = progress
+            .chunk_size
+            .filter(|&old| old > 0 && old != self.chunk_size)
+        else {
+            return;
+        };
+        let done: HashSet<usize> = progress.downloaded_chunks.into_iter().collect();
+        let kept = remap_chunks(&done, old_size, self.chunk_size, self.metadata.size);
+        warn!(
+            "[{}] Chunk size changed from {} MB to {} MB since the partial download; {} of {} chunk(s) carried over",
+            self.run_id,
+            old_size / 1024 / 1024,
+            self.chunk_size / 1024 / 1024,
+            kept.len(),
+            self.metadata.size.div_ceil(self.chunk_size)
+        );
+        if let Err(e) = self.save_progress(&kept, &BTreeMap::new()) {
+            warn!("Failed to save progress for {}: {}", self.run_id, e);
+        }
+    }
+
+    /// Quarantine the bad data file(s) and drop the progress so the next
+    /// attempt downloads from scratch.
+    fn invalidate_download(&self, reason: &str) {
+        for path in [&self.filepath, &self.part_path] {
+            crate::quarantine::quarantine_or_remove(path, reason);
+        }
+        match std::fs::remove_file(&self.meta_file) {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => warn!(
+                "Failed to remove invalid download {}: {error}",
+                self.meta_file.display()
+            ),
         }
     }
 
+    /// Download, resume or repair the file and verify it. `Ok(false)` means
+    /// the result failed verification and was quarantined.
     pub async fn start(&self) -> Result<bool> {
+        let verified = match self.fetch(true).await {
+            // The partial download was dropped; start over from chunk 0.
+            Err(e) if e.is::<ObjectChanged>() => {
+                warn!("{:#}; restarting download...", e);
+                *self.etag.lock().unwrap() = None;
+                self.fetch(true).await?
+            }
+            result => result?,
+        };
+        if verified {
+            return Ok(true);
+        }
+        // A failed check quarantines the file, unless only some chunks no
+        // longer match their recorded MD5: those were marked undone and the
+        // rest of the `.part` is kept.
+        if self.part_path.exists() {
+            return self.fetch(false).await;
+        }
+        Ok(false)
+    }
+
+    async fn fetch(&self, allow_repair: bool) -> Result<bool> {
         let start_time = std::time::Instant::now();
 
-        // Preallocation (`set_len`) makes incomplete downloads already have the
-        // full remote size. Only treat a size-matched file as "maybe complete"
-        // when there is no resume meta — `.meta.json` means in-progress chunks
-        // and must not be wiped by an early MD5 check.
+        // Chunks are written to `<name>.part`, which only becomes `<name>`
+        // after verification, so a file under its final name is complete.
+        // Older versions wrote chunks in place next to a `.meta.json`; such
+        // a file is an unfinished download and resumes as the `.part`.
+        if self.filepath.exists() && self.meta_file.exists() && !self.part_path.exists() {
+            std::fs::rename(&self.filepath, &self.part_path)?;
+        }
         if self.filepath.exists() {
             if let Ok(meta) = tokio::fs::metadata(&self.filepath).await {
-                let size_matches = meta.len() == self.metadata.size;
-                let has_resume_meta = self.meta_file.exists();
-
-                if size_matches && !has_resume_meta {
+                if meta.len() == self.metadata.size {
                     info!(
                         "[{}] Existing file with matching size; verifying integrity...",
                         self.run_id
                     );
-                    if self.verify_integrity(0.0, true).await? {
+                    if self
+                        .verify_integrity(&self.filepath, 0.0, true, false)
+                        .await?
+                    {
                         return Ok(true);
                     } else {
                         warn!(
@@ -347,32 +816,69 @@ impl ResumableDownloader {
                             self.run_id
                         );
                     }
-                } else if size_matches && has_resume_meta {
-                    info!(
-                        "[{}] Resuming incomplete download from progress file...",
-                        self.run_id
-                    );
-                } else if !size_matches {
+                } else {
                     warn!(
                         "[{}] Local size {} != remote {}; restarting download...",
                         self.run_id,
                         meta.len(),
                         self.metadata.size
                     );
-                    self.invalidate_download();
+                    self.invalidate_download(&format!(
+                        "size {} != expected {}",
+                        meta.len(),
+                        self.metadata.size
+                    ));
                 }
             }
         }
 
-        if !self.filepath.exists() {
-            if let Some(parent) = self.filepath.parent() {
+        // Preallocation (`set_len`) gives a partial download the full remote
+        // size; the `.meta.json` records which chunks are actually there.
+        if let Ok(meta) = tokio::fs::metadata(&self.part_path).await {
+            if meta.len() == self.metadata.size {
+                info!(
+                    "[{}] Resuming incomplete download from progress file...",
+                    self.run_id
+                );
+                self.reconcile_progress();
+            } else {
+                warn!(
+                    "[{}] Partial size {} != remote {}; restarting download...",
+                    self.run_id,
+                    meta.len(),
+                    self.metadata.size
+                );
+                self.invalidate_download(&format!(
+                    "partial size {} != expected {}",
+                    meta.len(),
+                    self.metadata.size
+                ));
+            }
+        }
+
+        let resumed = self.part_path.exists();
+        if !resumed {
+            if let Some(parent) = self.part_path.parent() {
                 std::fs::create_dir_all(parent)?;
             }
-            let file = File::create(&self.filepath)?;
-            file.set_len(self.metadata.size)?;
+            // Chunks from a previous partial file are gone with it.
+            let _ = std::fs::remove_file(&self.meta_file);
+            let part_path = self.part_path.clone();
+            let (size, mode) = (self.metadata.size, self.preallocation);
+            tokio::task::spawn_blocking(move || prealloc::preallocate(&part_path, size, mode))
+                .await??;
+        }
+        if resumed {
+            let torn = self.mark_torn_chunks().await?;
+            if torn > 0 {
+                warn!(
+                    "[{}] {} chunk(s) written before the last stop do not match their recorded MD5; re-fetching them",
+                    self.run_id, torn
+                );
+            }
         }
 
-        let mut downloaded_chunks = self.load_progress();
+        let (mut downloaded_chunks, mut chunk_md5) = self.load_progress();
         let num_chunks = self.metadata.size.div_ceil(self.chunk_size);
         let mut tasks = Vec::new();
         for i in 0..num_chunks {
@@ -386,18 +892,13 @@ impl ResumableDownloader {
         }
 
         // Setup Progress Bar
-        let pb = if let Some(mp) = &self.mp {
-            // insert_from_back(1) places the bar just above the pinned global
-            // status bar (which lives at the very back of the MultiProgress),
-            // so transient per-file bars never sink below it.
-            mp.insert_from_back(1, ProgressBar::new(self.metadata.size))
-        } else {
-            ProgressBar::new(self.metadata.size)
-        };
-        pb.set_style(transfer_bar_style());
-        pb.set_prefix(self.run_id.clone());
+        let pb = start_progress(
+            self.progress.as_ref(),
+            &self.run_id,
+            ProgressKind::Transfer,
+            self.metadata.size,
+        );
         pb.set_message("Downloading");
-        pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
         // Keep per-file details in the log file without cluttering active progress bars.
         let size_gb = self.metadata.size as f64 / 1024.0 / 1024.0 / 1024.0;
@@ -411,15 +912,17 @@ impl ResumableDownloader {
         info!(target: "download_detail", "{}", details);
 
         if tasks.is_empty() {
-            let msg = format!(
-                "{} │ File exists, starting integrity check...",
-                self.run_id
-            );
+            let msg = format!("{} │ File exists, starting integrity check...", self.run_id);
             pb.println(&msg);
             info!(target: "download_detail", "{}", msg);
-            pb.finish_and_clear();
+            pb.finish();
             return self
-                .verify_integrity(start_time.elapsed().as_secs_f64(), true)
+                .verify_integrity(
+                    &self.part_path,
+                    start_time.elapsed().as_secs_f64(),
+                    true,
+                    allow_repair,
+                )
                 .await;
         }
 
@@ -445,18 +948,44 @@ impl ResumableDownloader {
 
         pb.set_position(global_bytes.load(Ordering::Relaxed));
 
+        // Chunk workers hand their bytes to one writer; when it falls
+        // behind they stop reading, see [`WriteQueue`].
+        let writes = WriteQueue::open(&self.part_path)?;
+        // Period over which the monitor checks whether the disk is the
+        // bottleneck.
+        const DISK_WINDOW: Duration = Duration::from_secs(10);
+
         // Spawn progress monitor
         let pb_monitor = pb.clone();
+        let writes_monitor = writes.clone();
         let gb_monitor = global_bytes.clone();
         let store_monitor = self.progress_store.clone();
         let run_id_monitor = self.run_id.clone();
         let sra_size_monitor = self.metadata.size;
         let monitor_handle = tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_millis(100));
+            let mut window = (Instant::now(), writes_monitor.stats());
+            let mut disk_bound = false;
             loop {
                 interval.tick().await;
                 let bytes = gb_monitor.load(Ordering::Relaxed);
                 pb_monitor.set_position(bytes);
+                if window.0.elapsed() >= DISK_WINDOW {
+                    let stats = writes_monitor.stats();
+                    let now_bound = stats.disk_bound(&window.1, window.0.elapsed());
+                    if now_bound && !disk_bound {
+                        warn!(
+                            "[{}] Disk writes are the bottleneck: the writer was busy for most of the last {}s",
+                            run_id_monitor,
+                            DISK_WINDOW.as_secs()
+                        );
+                        pb_monitor.set_message("Downloading (disk-bound)");
+                    } else if !now_bound && disk_bound {
+                        pb_monitor.set_message("Downloading");
+                    }
+                    disk_bound = now_bound;
+                    window = (Instant::now(), stats);
+                }
                 if let Some(store) = &store_monitor {
                     let mut map = store.write().await;
                     if let Some(rp) = map.get_mut(&run_id_monitor) {
@@ -469,7 +998,10 @@ impl ResumableDownloader {
 
         // Result channel: Ok(chunk_id) on success, Err((chunk, error)) on failure
         // so the coordinator can requeue with a retry budget.
-        let (tx, mut rx) = mpsc::channel::<Result<usize, (ChunkInfo, anyhow::Error)>>(100);
+        let (tx, mut rx) =
+            mpsc::channel::<Result<(usize, ChunkDone), (ChunkInfo, anyhow::Error)>>(100);
+        // Stops the workers when the remote object turns out to have changed.
+        let stop = self.cancel.child_token();
         let shared_tasks = Arc::new(Mutex::new(tasks));
         let outstanding = Arc::new(AtomicU64::new(
             (num_chunks as usize).saturating_sub(downloaded_chunks.len()) as u64,
@@ -478,56 +1010,65 @@ impl ResumableDownloader {
         for _ in 0..self.max_workers {
             let client = self.client.clone();
             let url = self.metadata.http_url.clone();
-            let filepath = self.filepath.clone();
+            let writes_worker = writes.clone();
+            let connections = self.connections.clone();
             let queue = shared_tasks.clone();
             let tx = tx.clone();
             let gb_clone = global_bytes.clone();
             let outstanding_w = outstanding.clone();
             let pause_token_worker = pause_token.clone();
-            tokio::spawn(async move {
-                loop {
-                    if outstanding_w.load(Ordering::SeqCst) == 0 {
-                        break;
-                    }
-                    if let Some(token) = &pause_token_worker {
-                        token.wait_while_paused().await;
-                    }
+            let cancel_worker = stop.clone();
+            tokio::spawn(
+                async move {
+                    loop {
+                        if outstanding_w.load(Ordering::SeqCst) == 0 || cancel_worker.is_cancelled()
+                        {
+                            break;
+                        }
+                        if let Some(token) = &pause_token_worker {
+                            token.wait_while_paused().await;
+                        }
 
-                    let task = {
-                        let mut q = queue.lock().await;
-                        q.pop()
-                    };
-                    match task {
-                        Some(t) => {
-                            match download_chunk_http(
-                                client.clone(),
-                                &url,
-                                &t,
-                                &filepath,
-                                gb_clone.clone(),
-                                pause_token_worker.clone(),
-                            )
-                            .await
-                            {
-                                Ok(_) => {
-                                    if tx.send(Ok(t.id)).await.is_err() {
-                                        break;
+                        let task = {
+                            let mut q = queue.lock().await;
+                            q.pop()
+                        };
+                        match task {
+                            Some(t) => {
+                                let result = tokio::select! {
+                                    result = download_chunk_http(
+                                        client.clone(),
+                                        &url,
+                                        &t,
+                                        &writes_worker,
+                                        connections.as_deref(),
+                                        gb_clone.clone(),
+                                        pause_token_worker.clone(),
+                                    ) => result,
+                                    _ = cancel_worker.cancelled() => break,
+                                };
+                                match result {
+                                    Ok(done) => {
+                                        if tx.send(Ok((t.id, done))).await.is_err() {
+                                            break;
+                                        }
                                     }
-                                }
-                                Err(e) => {
-                                    if tx.send(Err((t, e))).await.is_err() {
-                                        break;
+                                    Err(e) => {
+                                        if tx.send(Err((t, e))).await.is_err() {
+                                            break;
+                                        }
                                     }
                                 }
                             }
-                        }
-                        None => {
-                            // Queue empty but work may be requeued after a failure.
-                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            None => {
+                                // Queue empty but work may be requeued after a failure.
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                            }
                         }
                     }
                 }
-            });
+                .in_current_span(),
+            );
         }
         drop(tx);
 
@@ -537,10 +1078,39 @@ impl ResumableDownloader {
         let mut fatal_errors: Vec<anyhow::Error> = Vec::new();
 
         while outstanding.load(Ordering::SeqCst) > 0 {
-            match rx.recv().await {
-                Some(Ok(chunk_id)) => {
+            let received = tokio::select! {
+                received = rx.recv() => received,
+                _ = self.cancel.cancelled() => {
+                    monitor_handle.abort();
+                    pb.fail("Cancelled");
+                    info!(target: "download_detail", "{} │ Cancelled, progress saved", self.run_id);
+                    return Err(PolariseqError::Cancelled.into());
+                }
+            };
+            match received {
+                Some(Ok((chunk_id, done))) => {
+                    if let Some(etag) = done.etag {
+                        let recorded = self
+                            .etag
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(|| etag.clone())
+                            .clone();
+                        if recorded != etag {
+                            stop.cancel();
+                            monitor_handle.abort();
+                            pb.fail("Remote object changed");
+                            self.invalidate_download(&format!("ETag {} != {}", recorded, etag));
+                            return Err(ObjectChanged(format!(
+                                "[{}] Remote object changed since the partial download (ETag {} != {})",
+                                self.run_id, recorded, etag
+                            ))
+                            .into());
+                        }
+                    }
                     downloaded_chunks.insert(chunk_id);
-                    if let Err(e) = self.save_progress(&downloaded_chunks) {
+                    chunk_md5.insert(chunk_id, done.md5);
+                    if let Err(e) = self.save_progress(&downloaded_chunks, &chunk_md5) {
                         warn!("Failed to save progress for {}: {}", self.run_id, e);
                     }
                     outstanding.fetch_sub(1, Ordering::SeqCst);
@@ -568,20 +1138,36 @@ impl ResumableDownloader {
         }
 
         monitor_handle.abort();
-        pb.finish_and_clear();
+        pb.finish();
+        let stats = writes.stats();
+        if !stats.waited.is_zero() {
+            info!(
+                target: "download_detail",
+                "{} │ Waited {:.1}s for disk writes ({:.1}s writing)",
+                self.run_id,
+                stats.waited.as_secs_f64(),
+                stats.writing.as_secs_f64()
+            );
+        }
 
         if !fatal_errors.is_empty() {
-            return Err(anyhow!(
+            return Err(PolariseqError::Network(format!(
                 "[{}] {} chunk(s) failed permanently (e.g. {})",
                 self.run_id,
                 fatal_errors.len(),
                 fatal_errors[0]
-            ));
+            ))
+            .into());
         }
 
         if downloaded_chunks.len() as u64 == num_chunks {
-            self.verify_integrity(start_time.elapsed().as_secs_f64(), false)
-                .await
+            self.verify_integrity(
+                &self.part_path,
+                start_time.elapsed().as_secs_f64(),
+                false,
+                allow_repair,
+            )
+            .await
         } else {
             let msg = format!(
                 "{} │ Download incomplete. Progress saved, please retry.",
@@ -589,41 +1175,46 @@ impl ResumableDownloader {
             );
             pb.println(&msg);
             warn!("{}", msg);
-            Err(anyhow!("{}", msg))
+            Err(PolariseqError::Network(msg).into())
         }
     }
+    /// Check `path` against the expected MD5 (or size); a verified
+    /// `.part` file is renamed to its final name. With `allow_repair`, an
+    /// MD5 mismatch first looks for chunks to re-fetch ([`Self::mark_bad_chunks`]).
     async fn verify_integrity(
         &self,
+        path: &Path,
         download_duration: f64,
         skipped_download: bool,
+        allow_repair: bool,
     ) -> Result<bool> {
         let start_time = std::time::Instant::now();
         if self.metadata.md5.is_none() {
-            let local_size = tokio::fs::metadata(&self.filepath).await?.len();
+            let local_size = tokio::fs::metadata(path).await?.len();
             if local_size != self.metadata.size {
                 warn!(
                     "{} │ Size mismatch: local={} remote={}",
                     self.run_id, local_size, self.metadata.size
                 );
-                self.invalidate_download();
+                self.invalidate_download(&format!(
+                    "size {} != expected {}",
+                    local_size, self.metadata.size
+                ));
                 return Ok(false);
             }
-            let _ = std::fs::remove_file(&self.meta_file);
+            self.publish(path)?;
             return Ok(true);
         }
 
-        let pb = if let Some(mp) = &self.mp {
-            mp.insert_from_back(1, ProgressBar::new(self.metadata.size))
-        } else {
-            ProgressBar::new(self.metadata.size)
-        };
-
-        pb.set_style(verify_bar_style());
-        pb.set_prefix(self.run_id.clone());
+        let pb = start_progress(
+            self.progress.as_ref(),
+            &self.run_id,
+            ProgressKind::Verify,
+            self.metadata.size,
+        );
         pb.set_message("Verifying");
-        pb.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        let mut file = tokio::fs::File::open(&self.filepath).await?;
+        let mut file = tokio::fs::File::open(path).await?;
         let mut ctx = md5::Context::new();
         let mut buf = vec![0u8; 1024 * 1024];
         loop {
@@ -634,7 +1225,7 @@ impl ResumableDownloader {
             ctx.consume(&buf[..n]);
             pb.inc(n as u64);
         }
-        pb.finish_and_clear();
+        pb.finish();
 
         let local_md5 = format!("{:x}", ctx.compute());
         let expected_md5 = self.metadata.md5.as_ref().unwrap();
@@ -651,7 +1242,7 @@ impl ResumableDownloader {
             );
             info!(target: "download_detail", "{}", msg);
 
-            let _ = std::fs::remove_file(&self.meta_file);
+            self.publish(path)?;
             Ok(true)
         } else {
             let msg = format!(
@@ -659,22 +1250,147 @@ impl ResumableDownloader {
                 self.run_id, local_md5, expected_md5
             );
             warn!("{}", msg);
-            self.invalidate_download();
+            if allow_repair && path == self.part_path {
+                let bad = self.mark_bad_chunks().await?;
+                if bad > 0 {
+                    warn!(
+                        "[{}] {} chunk(s) no longer match their recorded MD5; re-fetching only those",
+                        self.run_id, bad
+                    );
+                    return Ok(false);
+                }
+            }
+            self.invalidate_download(&format!("MD5 {} != expected {}", local_md5, expected_md5));
             Ok(false)
         }
     }
+
+    /// Re-hash every chunk of the `.part` that has a recorded MD5 and mark
+    /// those that differ as undone. Returns how many were marked; `0` means
+    /// the damage cannot be located (e.g. corrupted in transit).
+    async fn mark_bad_chunks(&self) -> Result<usize> {
+        self.recheck_chunks(false).await
+    }
+
+    /// Re-hash the finished chunks next to an unfinished one before resuming.
+    /// These were in flight when the last attempt stopped, so a crash may
+    /// have left them recorded as done with only part of their bytes on
+    /// disk. Such chunks without a recorded MD5 (older progress files) are
+    /// marked undone as well.
+    async fn mark_torn_chunks(&self) -> Result<usize> {
+        self.recheck_chunks(true).await
+    }
+
+    async fn recheck_chunks(&self, boundary_only: bool) -> Result<usize> {
+        let (mut downloaded_chunks, mut chunk_md5) = self.load_progress();
+        let num_chunks = self.metadata.size.div_ceil(self.chunk_size) as usize;
+        let unfinished = |id: usize| id < num_chunks && !downloaded_chunks.contains(&id);
+        let checked: Vec<usize> = downloaded_chunks
+            .iter()
+            .copied()
+            .filter(|&id| !boundary_only || (id > 0 && unfinished(id - 1)) || unfinished(id + 1))
+            .collect();
+        if checked.is_empty() {
+            return Ok(0);
+        }
+        let mut file = tokio::fs::File::open(&self.part_path).await?;
+        let mut bad = Vec::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        for id in checked {
+            let Some(recorded) = chunk_md5.get(&id) else {
+                if boundary_only {
+                    bad.push(id);
+                }
+                continue;
+            };
+            let start = id as u64 * self.chunk_size;
+            let end = std::cmp::min(start + self.chunk_size, self.metadata.size);
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut ctx = md5::Context::new();
+            let mut remaining = end.saturating_sub(start);
+            while remaining > 0 {
+                let want = std::cmp::min(remaining, buf.len() as u64) as usize;
+                let n = file.read(&mut buf[..want]).await?;
+                if n == 0 {
+                    break;
+                }
+                ctx.consume(&buf[..n]);
+                remaining -= n as u64;
+            }
+            if format!("{:x}", ctx.compute()) != *recorded {
+                bad.push(id);
+            }
+        }
+        for id in &bad {
+            downloaded_chunks.remove(id);
+            chunk_md5.remove(id);
+        }
+        if !bad.is_empty() {
+            self.save_progress(&downloaded_chunks, &chunk_md5)?;
+        }
+        Ok(bad.len())
+    }
+
+    /// Move a verified download to its final name and drop its progress.
+    fn publish(&self, path: &Path) -> Result<()> {
+        if path != self.filepath {
+            std::fs::rename(path, &self.filepath).with_context(|| {
+                format!("Failed to move verified {} into place", path.display())
+            })?;
+        }
+        let _ = std::fs::remove_file(&self.meta_file);
+        Ok(())
+    }
+}
+
+fn build_range_client(
+    max_workers: usize,
+    headers: header::HeaderMap,
+    http: S3Http,
+) -> Result<Client> {
+    // No whole-request body timeout: large Range chunks (e.g. 200 MiB) can
+    // take many minutes on slow links. Rely on connect_timeout + per-chunk
+    // retries with intra-chunk offset resume instead.
+    let builder = match http {
+        S3Http::Http1 => http::client_builder().http1_only(),
+        // Many large streams share one connection, so let the flow-control
+        // windows grow with the measured bandwidth.
+        S3Http::Http2 => http::client_builder().http2_adaptive_window(true),
+    };
+    Ok(builder
+        .connect_timeout(Duration::from_secs(10))
+        .pool_max_idle_per_host(max_workers)
+        .default_headers(headers)
+        .build()?)
+}
+
+/// A chunk came with another ETag than the recorded one; the partial
+/// download was dropped and [`ResumableDownloader::start`] starts over.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct ObjectChanged(String);
+
+/// A downloaded chunk: the MD5 of its bytes and the ETag the server sent
+/// with them.
+struct ChunkDone {
+    md5: String,
+    etag: Option<String>,
 }
 
 async fn download_chunk_http(
     client: Client,
     url: &str,
     chunk: &ChunkInfo,
-    filepath: &Path,
+    writes: &WriteQueue,
+    connections: Option<&ConnectionLimit>,
     global_bytes: Arc<AtomicU64>,
     pause_token: Option<PauseToken>,
-) -> Result<()> {
+) -> Result<ChunkDone> {
     let mut retry = 0;
+    let mut etag = None;
     let mut current_offset = chunk.start;
+    // Bytes arrive in order across retries, so one digest covers the chunk.
+    let mut digest = md5::Context::new();
 
     loop {
         // Yield while paused so the user can pause/resume the download.
@@ -683,15 +1399,19 @@ async fn download_chunk_http(
         }
 
         if current_offset > chunk.end {
-            return Ok(());
+            return Ok(ChunkDone {
+                md5: format!("{:x}", digest.compute()),
+                etag,
+            });
         }
 
+        // Held until the response is read or given up on.
+        let slot = match connections {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        };
         let range_header = format!("bytes={}-{}", current_offset, chunk.end);
-        let resp = client
-            .get(url)
-            .header(header::RANGE, range_header)
-            .send()
-            .await;
+        let resp = circuit::send(client.get(url).header(header::RANGE, range_header)).await;
 
         if let Ok(response) = resp {
             let expected_content_range = format!("bytes {}-{}/", current_offset, chunk.end);
@@ -701,6 +1421,15 @@ async fn download_chunk_http(
                 .and_then(|value| value.to_str().ok())
                 .is_some_and(|value| value.starts_with(&expected_content_range));
             if response.status() != StatusCode::PARTIAL_CONTENT || !has_expected_range {
+                if matches!(
+                    response.status(),
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                ) {
+                    if let Some(limit) = connections {
+                        limit.saturated();
+                    }
+                }
+                drop(slot);
                 retry += 1;
                 if retry > 10 {
                     return Err(anyhow!(
@@ -712,29 +1441,31 @@ async fn download_chunk_http(
                 tokio::time::sleep(Duration::from_secs(retry)).await;
                 continue;
             }
+            etag = response
+                .headers()
+                .get(header::ETAG)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
             let mut stream = response.bytes_stream();
-            let mut file = std::fs::OpenOptions::new().write(true).open(filepath)?;
-            file.seek(SeekFrom::Start(current_offset))?;
 
             let mut stream_error = false;
             let offset_start = current_offset;
 
             while let Some(item) = stream.next().await {
-                // Check pause inside the byte stream loop so an active
-                // HTTP connection also stops downloading immediately.
-                if let Some(token) = &pause_token {
-                    token.wait_while_paused().await;
-                }
-
                 match item {
                     Ok(bytes) => {
-                        if file.write_all(&bytes).is_err() {
-                            stream_error = true;
-                            break;
-                        }
+                        digest.consume(&bytes);
                         let len = bytes.len() as u64;
+                        // Waits while the disk is behind, which stops reading
+                        // the response and so slows the sender down.
+                        writes.write(chunk.id, current_offset, bytes).await?;
                         global_bytes.fetch_add(len, Ordering::Relaxed);
                         current_offset += len;
+                        // Pausing or throttling inside the byte stream loop
+                        // also holds back an active HTTP connection.
+                        if let Some(token) = &pause_token {
+                            token.throttle(len).await;
+                        }
                     }
                     Err(_) => {
                         stream_error = true;
@@ -743,8 +1474,13 @@ async fn download_chunk_http(
                 }
             }
 
+            // A chunk only counts as done once its bytes are on disk.
+            writes.flush(chunk.id).await?;
             if !stream_error && current_offset > chunk.end {
-                return Ok(());
+                return Ok(ChunkDone {
+                    md5: format!("{:x}", digest.compute()),
+                    etag,
+                });
             }
 
             // If we made progress, reset retry counter
@@ -752,6 +1488,7 @@ async fn download_chunk_http(
                 retry = 0;
             }
         }
+        drop(slot);
 
         retry += 1;
         if retry > 20 {
@@ -765,6 +1502,35 @@ async fn download_chunk_http(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn falls_back_to_ena_sra_md5() {
+        let record: EnaRecord = serde_json::from_value(serde_json::json!({
+            "run_accession": "SRR1",
+            "sra_md5": "0123456789ABCDEF0123456789abcdef",
+            "sra_bytes": "30",
+        }))
+        .unwrap();
+        let (md5, bytes) = ena_sra_md5(&record).unwrap();
+        let mut metadata = SraMetadata {
+            s3_uri: "s3://sra-pub-run-odp/sra/SRR1/SRR1".to_string(),
+            http_url: "https://sra-pub-run-odp.s3.amazonaws.com/sra/SRR1/SRR1".to_string(),
+            md5: None,
+            size: 31,
+        };
+        assert!(!metadata.fill_md5(&md5, bytes));
+        metadata.size = 30;
+        assert!(metadata.fill_md5(&md5, bytes));
+        assert_eq!(
+            metadata.md5.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert!(!metadata.fill_md5("ffffffffffffffffffffffffffffffff", 30));
+
+        let (s3_uri, http_url) = odp_urls("SRR1");
+        assert_eq!((s3_uri, http_url), (metadata.s3_uri, metadata.http_url));
+    }
 
     #[tokio::test]
     async fn keeps_the_source_object_filename_for_generic_downloads() {
@@ -813,14 +1579,225 @@ mod tests {
         .await
         .unwrap();
 
-        std::fs::write(&downloader.filepath, b"bad").unwrap();
+        std::fs::write(&downloader.part_path, b"bad").unwrap();
         std::fs::write(&downloader.meta_file, r#"{"downloaded_chunks":[0]}"#).unwrap();
 
-        assert!(!downloader.verify_integrity(0.0, false).await.unwrap());
+        assert!(!downloader
+            .verify_integrity(&downloader.part_path, 0.0, false, false)
+            .await
+            .unwrap());
+        assert!(!downloader.part_path.exists());
         assert!(!downloader.filepath.exists());
+        assert!(temp_dir.path().join("quarantine/example.dat").exists());
+        assert!(!downloader.meta_file.exists());
+    }
+
+    #[tokio::test]
+    async fn legacy_in_place_download_resumes_and_moves_into_place() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let downloader = ResumableDownloader::new(
+            "example".to_string(),
+            SraMetadata {
+                s3_uri: "s3://example-bucket/example.dat".to_string(),
+                http_url: "https://example-bucket.s3.amazonaws.com/example.dat".to_string(),
+                md5: Some("900150983cd24fb0d6963f7d28e17f72".to_string()),
+                size: 3,
+            },
+            temp_dir.path().to_path_buf(),
+            64,
+            1,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            downloader.part_path.file_name().unwrap(),
+            "example.dat.part"
+        );
+
+        // Written in place by an older version, every chunk recorded.
+        std::fs::write(&downloader.filepath, b"abc").unwrap();
+        std::fs::write(&downloader.meta_file, r#"{"downloaded_chunks":[0]}"#).unwrap();
+
+        assert!(downloader.start().await.unwrap());
+        assert_eq!(std::fs::read(&downloader.filepath).unwrap(), b"abc");
+        assert!(!downloader.part_path.exists());
+        assert!(!downloader.meta_file.exists());
+    }
+
+    #[tokio::test]
+    async fn repair_marks_only_chunks_that_changed() {
+        const MIB: usize = 1024 * 1024;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let downloader = ResumableDownloader::new(
+            "example".to_string(),
+            SraMetadata {
+                s3_uri: "s3://example-bucket/example.dat".to_string(),
+                http_url: "https://example-bucket.s3.amazonaws.com/example.dat".to_string(),
+                md5: None,
+                size: 3 * MIB as u64,
+            },
+            temp_dir.path().to_path_buf(),
+            1,
+            1,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let mut data = vec![7u8; 3 * MIB];
+        let chunk_md5: BTreeMap<usize, String> = (0..3)
+            .map(|i| {
+                (
+                    i,
+                    format!("{:x}", md5::compute(&data[i * MIB..(i + 1) * MIB])),
+                )
+            })
+            .collect();
+        downloader
+            .save_progress(&[0, 1, 2].into_iter().collect(), &chunk_md5)
+            .unwrap();
+        data[MIB + 10] = 0;
+        std::fs::write(&downloader.part_path, &data).unwrap();
+
+        assert_eq!(downloader.mark_bad_chunks().await.unwrap(), 1);
+        let (downloaded, recorded) = downloader.load_progress();
+        assert_eq!(downloaded, [0, 2].into_iter().collect());
+        assert!(!recorded.contains_key(&1));
+
+        // A torn write in chunk 2, next to the unfinished chunk 1, is found
+        // before resuming.
+        data[2 * MIB + 10] = 0;
+        std::fs::write(&downloader.part_path, &data).unwrap();
+        assert_eq!(downloader.mark_torn_chunks().await.unwrap(), 1);
+        let (downloaded, _) = downloader.load_progress();
+        assert_eq!(downloaded, [0].into_iter().collect());
+    }
+
+    /// Serve `data` with range support and `etag` on a local port; returns
+    /// its URL.
+    async fn serve_ranges(data: Arc<Vec<u8>>, etag: &'static str) -> String {
+        use tokio::io::AsyncWriteExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let data = data.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                    let (start, end) = request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("range: bytes="))
+                        .and_then(|range| {
+                            let (start, end) = range.trim().split_once('-')?;
+                            Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+                        })
+                        .unwrap_or((0, data.len() - 1));
+                    let head = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                         Content-Range: bytes {}-{}/{}\r\nETag: {}\r\nConnection: close\r\n\r\n",
+                        end - start + 1,
+                        start,
+                        end,
+                        data.len(),
+                        etag
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(&data[start..=end]).await;
+                });
+            }
+        });
+        format!("http://{}/example.dat", addr)
+    }
+
+    #[tokio::test]
+    async fn restarts_from_scratch_when_the_remote_object_changed() {
+        const MIB: usize = 1024 * 1024;
+        let data: Vec<u8> = (0..2 * MIB).map(|i| (i % 251) as u8).collect();
+        let url = serve_ranges(Arc::new(data.clone()), "\"new\"").await;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let downloader = ResumableDownloader::new(
+            "example".to_string(),
+            SraMetadata {
+                s3_uri: "s3://example-bucket/example.dat".to_string(),
+                http_url: url,
+                md5: Some(format!("{:x}", md5::compute(&data))),
+                size: 2 * MIB as u64,
+            },
+            temp_dir.path().to_path_buf(),
+            1,
+            1,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Chunk 0 came from an older copy of the object.
+        std::fs::write(&downloader.part_path, vec![0u8; 2 * MIB]).unwrap();
+        std::fs::write(
+            &downloader.meta_file,
+            r#"{"downloaded_chunks":[0],"chunk_md5":{"0":"b6d81b360a5672d80c27430f39153e2c"},"chunk_size":1048576,"etag":"\"old\""}"#,
+        )
+        .unwrap();
+
+        assert!(downloader.start().await.unwrap());
+        assert_eq!(std::fs::read(&downloader.filepath).unwrap(), data);
         assert!(!downloader.meta_file.exists());
     }
 
+    #[tokio::test]
+    async fn remaps_chunks_to_a_new_chunk_size() {
+        const MIB: u64 = 1024 * 1024;
+        let done = [0, 1, 3].into_iter().collect();
+        assert_eq!(
+            remap_chunks(&done, MIB, 2 * MIB, 4 * MIB),
+            [0].into_iter().collect()
+        );
+        assert_eq!(
+            remap_chunks(&done, 2 * MIB, MIB, 3 * MIB),
+            [0, 1, 2].into_iter().collect()
+        );
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let downloader = ResumableDownloader::new(
+            "example".to_string(),
+            SraMetadata {
+                s3_uri: "s3://example-bucket/example.dat".to_string(),
+                http_url: "https://example-bucket.s3.amazonaws.com/example.dat".to_string(),
+                md5: None,
+                size: 4 * MIB,
+            },
+            temp_dir.path().to_path_buf(),
+            2,
+            1,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        std::fs::write(
+            &downloader.meta_file,
+            r#"{"downloaded_chunks":[0,1,3],"chunk_md5":{"0":"x"},"chunk_size":1048576,"etag":"\"a\""}"#,
+        )
+        .unwrap();
+        downloader.reconcile_progress();
+        let (downloaded, recorded) = downloader.load_progress();
+        assert_eq!(downloaded, [0].into_iter().collect());
+        assert!(recorded.is_empty());
+        assert_eq!(downloader.etag.lock().unwrap().as_deref(), Some("\"a\""));
+    }
+
     #[test]
     fn resume_meta_preserves_completed_chunks_when_file_preallocated() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -848,4 +1825,32 @@ mod tests {
         // Early MD5 must only run when meta is absent.
         assert!(meta_file.exists());
     }
+
+    #[test]
+    fn sra_format_selects_full_or_lite_copy() {
+        let xml = r#"<EXPERIMENT_PACKAGE_SET><RUN_SET><RUN accession="SRR1"><SRAFiles>
+            <SRAFile filename="SRR1.lite" size="10" md5="aaa" semantic_name="SRA Lite">
+              <Alternatives url="https://sra-pub-src-1.s3.amazonaws.com/SRR1/SRR1.lite.1" free_egress="worldwide" org="AWS"/>
+            </SRAFile>
+            <SRAFile filename="SRR1" size="30" md5="bbb" semantic_name="SRA Normalized">
+              <Alternatives url="https://sra-pub-run-odp.s3.amazonaws.com/sra/SRR1/SRR1" free_egress="worldwide" org="AWS"/>
+            </SRAFile>
+        </SRAFiles></RUN></RUN_SET></EXPERIMENT_PACKAGE_SET>"#;
+        let pick = |candidates, format| match select_sra_file("SRR1", candidates, format) {
+            SraLookup::Found(m) => m.md5.unwrap(),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(pick(parse_sra_files(xml), SraFormat::Any), "bbb");
+        assert_eq!(pick(parse_sra_files(xml), SraFormat::Lite), "aaa");
+
+        let lite_only: Vec<_> = parse_sra_files(xml)
+            .into_iter()
+            .filter(|c| c.lite)
+            .collect();
+        assert!(matches!(
+            select_sra_file("SRR1", lite_only.clone(), SraFormat::Full),
+            SraLookup::LiteOnly
+        ));
+        assert_eq!(pick(lite_only, SraFormat::Any), "aaa");
+    }
 }
@@ -0,0 +1,532 @@
+//! AWS Open Data backend (`aws` feature): ranged S3 download of the
+//! `.sra`, `fasterq-dump` conversion and parallel gzip.
+
+use super::{BackendContext, DownloadBackend, RunItem};
+use crate::aws_s3::{AwsStaging, ResumableDownloader, SraLookup, SraUtils};
+use crate::error::PolariseqError;
+use crate::long_read;
+use crate::progress_store::{CompressionProgressCallback, RunProgress, RunStage, StageProgress};
+use crate::{audit, compress_fastq_files, generate_md5sum_file, OutputFormat};
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// Where `run_id` is downloaded and converted. Versions before
+/// [`AwsStaging::Run`] wrote the `.sra` straight into `final_dir`; a run
+/// that left its `.sra`, `.part` or `.meta.json` there stays flat, so it
+/// resumes instead of colliding with the `<run>` directory.
+fn staging_dir(final_dir: &Path, run_id: &str, staging: AwsStaging) -> PathBuf {
+    let run_dir = final_dir.join(run_id);
+    if staging == AwsStaging::Flat {
+        return final_dir.to_path_buf();
+    }
+    let prefix = format!("{}.", run_id);
+    let left_flat = run_dir.is_file()
+        || std::fs::read_dir(final_dir).is_ok_and(|entries| {
+            entries.filter_map(|e| e.ok()).any(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                name.starts_with(&prefix)
+                    && (name.ends_with(".part") || name.ends_with(".meta.json"))
+            })
+        });
+    if left_flat {
+        info!(
+            "[{}] Resuming a download left in {} by an earlier version; staging it there",
+            run_id,
+            final_dir.display()
+        );
+        return final_dir.to_path_buf();
+    }
+    run_dir
+}
+
+/// AWS Open Data S3 download, `fasterq-dump` conversion and gzip.
+#[derive(Debug, Default)]
+pub struct AwsBackend;
+
+impl DownloadBackend for AwsBackend {
+    fn name(&self) -> &'static str {
+        "aws"
+    }
+
+    fn supports(&self, item: &RunItem) -> bool {
+        matches!(item, RunItem::Public(_))
+    }
+
+    fn prepare<'a>(
+        &'a self,
+        items: &'a [RunItem],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            crate::validate_config(&ctx.config, crate::DownloadMethod::Aws)?;
+            info!("Starting AWS S3 downloads...");
+            info!(
+                "Config: Parallel Files = {}, Threads/File = {}, Chunk Size = {}MB",
+                ctx.options.multithreads, ctx.options.aws_threads, ctx.options.chunk_size
+            );
+
+            let mut map = ctx.progress_store.write().await;
+            for item in items {
+                let RunItem::Public(record) = item else {
+                    continue;
+                };
+                let sra_size = record.fastq_bytes_1 + record.fastq_bytes_2.unwrap_or(0);
+                let extract_weight = (sra_size as f64) * 3.0;
+                map.insert(
+                    record.run_accession.clone(),
+                    RunProgress {
+                        run_id: record.run_accession.clone(),
+                        stage: RunStage::Pending,
+                        overall_percent: 0.0,
+                        download: StageProgress::new(sra_size as f64),
+                        extraction: StageProgress::new(extract_weight),
+                        compression: StageProgress::new(extract_weight),
+                    },
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        item: &'a RunItem,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let run_id = item.run_id().to_string();
+            // The .sra and intermediate FASTQ stay in the staging directory;
+            // only the finished files go to `final_dir`.
+            let final_dir = ctx.options.output.clone();
+            let output_dir = staging_dir(&final_dir, &run_id, ctx.options.aws_staging);
+            tokio::fs::create_dir_all(&output_dir)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to create staging directory: {}",
+                        output_dir.display()
+                    )
+                })?;
+            let progress_store = ctx.progress_store.clone();
+            let mp = ctx.progress.clone();
+            let observer = ctx.observer.clone();
+            let max_workers = ctx.options.aws_threads;
+            let chunk_size = ctx.options.chunk_size;
+            let fasterq_dump = ctx.config.software.fasterq_dump.display().to_string();
+            let cleanup_sra = ctx.options.cleanup_sra;
+            let output_format = ctx.options.output_format;
+            let long_read = ctx.long_reads.contains(&run_id);
+            let sra_format = ctx.options.sra_format;
+            let process_threads = ctx.process_threads();
+            let fasterq_mem = ctx.fasterq_mem();
+
+            {
+                let mut map = progress_store.write().await;
+                if let Some(rp) = map.get_mut(&run_id) {
+                    rp.stage = RunStage::Downloading;
+                }
+            }
+
+            // A job file from `plan` has the copy resolved already. Else
+            // ENA's record usually has the size and MD5; NCBI is only
+            // asked when it doesn't or S3 disagrees.
+            let known = match (ctx.resolved_sra.get(&run_id), ctx.sra_md5.get(&run_id)) {
+                (Some(resolved), _) => Some(resolved.clone()),
+                (None, Some((md5, bytes))) => {
+                    SraUtils::metadata_from_ena(&run_id, md5, *bytes, sra_format).await
+                }
+                (None, None) => None,
+            };
+            let lookup = match known {
+                Some(metadata) => SraLookup::Found(metadata),
+                None => SraUtils::get_metadata_for_format(&run_id, sra_format).await?,
+            };
+            let metadata = match lookup {
+                SraLookup::Found(mut metadata) => {
+                    if let Some((md5, bytes)) = ctx.sra_md5.get(&run_id) {
+                        if metadata.fill_md5(md5, *bytes) {
+                            info!(target: "download_detail", "[{}] NCBI lists no MD5; verifying against ENA's sra_md5", run_id);
+                        }
+                    }
+                    Some(metadata)
+                }
+                SraLookup::LiteOnly => {
+                    let mut map = progress_store.write().await;
+                    if let Some(rp) = map.get_mut(&run_id) {
+                        rp.stage = RunStage::Skipped;
+                    }
+                    return Ok(());
+                }
+                SraLookup::NotFound => None,
+            };
+            let sra_filename = run_id.clone();
+            let sra_size = metadata.as_ref().map(|m| m.size).unwrap_or(0);
+            info!(target: "download_detail", "[{}] Step 1: Downloading via AWS S3...", run_id);
+
+            if let Some(sra_metadata) = metadata {
+                let mut downloader = ResumableDownloader::new(
+                    run_id.clone(),
+                    sra_metadata,
+                    output_dir.clone(),
+                    chunk_size,
+                    max_workers,
+                    mp,
+                    Some(progress_store.clone()),
+                )
+                .await?
+                .with_cancel_token(ctx.cancel.clone())
+                .with_pause_token(ctx.pause.clone())
+                .with_http(ctx.options.s3_http)?
+                .with_preallocation(ctx.options.preallocate)
+                .with_connection_limit(ctx.connections.clone());
+                // Share the per-file byte counter with the observer so a
+                // status bar can aggregate this run's speed.
+                if let Some(observer) = &observer {
+                    downloader =
+                        downloader.with_progress_bytes(observer.register(&run_id, sra_size));
+                }
+
+                let success = downloader.start().await;
+                // Download phase done — drop it from the live speed set.
+                // Counts (active/completed/failed) come from progress_store.
+                if let Some(observer) = &observer {
+                    observer.unregister(&run_id);
+                }
+                let success = success?;
+                if !success {
+                    let mut map = progress_store.write().await;
+                    if let Some(rp) = map.get_mut(&run_id) {
+                        rp.stage = RunStage::Failed;
+                    }
+                    return Err(PolariseqError::ChecksumMismatch { file: run_id }.into());
+                }
+            } else {
+                warn!("[{}] No AWS S3 URI found", run_id);
+                let mut map = progress_store.write().await;
+                if let Some(rp) = map.get_mut(&run_id) {
+                    rp.stage = RunStage::Failed;
+                }
+                return Err(PolariseqError::Metadata(format!("No S3 URI for {}", run_id)).into());
+            }
+
+            {
+                let mut map = progress_store.write().await;
+                if let Some(rp) = map.get_mut(&run_id) {
+                    rp.download.percent = 100.0;
+                    rp.stage = RunStage::Extracting;
+                    rp.recalculate_overall();
+                }
+            }
+
+            let fq_1 = output_dir.join(format!("{}_1.fastq", run_id));
+            let fq_single = output_dir.join(format!("{}.fastq", run_id));
+            let fq_exists = (fq_1.exists()
+                && fq_1.metadata().map(|m| m.len() > 0).unwrap_or(false))
+                || (fq_single.exists()
+                    && fq_single.metadata().map(|m| m.len() > 0).unwrap_or(false));
+
+            // Captures fasterq-dump stderr so the final error branch can
+            // report *why* conversion failed.
+            let mut fqdump_error: Option<String> = None;
+
+            if fq_exists {
+                info!(target: "download_detail", "[{}] FASTQ files already exist, skipping conversion.", run_id);
+            } else {
+                info!(target: "download_detail", "[{}] Step 2: Converting (fasterq-dump)...", run_id);
+
+                let fasterq_tmp_dir = output_dir.join(".fasterq_tmp").join(&run_id);
+                tokio::fs::create_dir_all(&fasterq_tmp_dir)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to create fasterq-dump temporary directory: {}",
+                            fasterq_tmp_dir.display()
+                        )
+                    })?;
+                let fasterq_tmp_dir = tokio::fs::canonicalize(&fasterq_tmp_dir)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to resolve fasterq-dump temporary directory: {}",
+                            fasterq_tmp_dir.display()
+                        )
+                    })?;
+                let fasterq_output_dir =
+                    tokio::fs::canonicalize(&output_dir)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "Failed to resolve fasterq-dump output directory: {}",
+                                output_dir.display()
+                            )
+                        })?;
+
+                let estimated_fastq_size = sra_size * 3;
+                let started = std::time::Instant::now();
+                let mut command = ctx.config.priority.command(&fasterq_dump);
+                command
+                    .arg(long_read::split_arg(long_read))
+                    .arg("-e")
+                    .arg(process_threads.to_string())
+                    .args(super::fasterq_mem_args(fasterq_mem))
+                    .arg("-O")
+                    .arg(&fasterq_output_dir)
+                    .arg("-t")
+                    .arg(&fasterq_tmp_dir)
+                    .arg("-f")
+                    .arg(&sra_filename)
+                    .current_dir(&output_dir)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true);
+                let child = command.spawn()?;
+
+                let output_dir_mon = output_dir.clone();
+                let run_id_mon = run_id.clone();
+                let store_mon = progress_store.clone();
+                let extract_monitor = tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(Duration::from_millis(500));
+                    loop {
+                        interval.tick().await;
+                        let mut total_size = 0u64;
+                        for name in &[
+                            format!("{}.fastq", run_id_mon),
+                            format!("{}_1.fastq", run_id_mon),
+                            format!("{}_2.fastq", run_id_mon),
+                        ] {
+                            let path = output_dir_mon.join(name);
+                            if let Ok(meta) = tokio::fs::metadata(&path).await {
+                                total_size += meta.len();
+                            }
+                        }
+                        let mut map = store_mon.write().await;
+                        if let Some(rp) = map.get_mut(&run_id_mon) {
+                            rp.extraction.update(total_size, estimated_fastq_size);
+                            rp.extraction.percent = rp.extraction.percent.min(99.0);
+                            rp.recalculate_overall();
+                        }
+                    }
+                });
+
+                // Dropping the wait on cancellation kills fasterq-dump; the
+                // downloaded .sra stays for the next attempt.
+                let output = tokio::select! {
+                    output = child.wait_with_output().instrument(info_span!("convert")) => output,
+                    _ = ctx.cancel.cancelled() => {
+                        extract_monitor.abort();
+                        audit::record(command.as_std(), started, Err("cancelled"));
+                        return Err(PolariseqError::Cancelled.into());
+                    }
+                };
+                extract_monitor.abort();
+                let output = output?;
+                audit::record(
+                    command.as_std(),
+                    started,
+                    Ok((output.status, &output.stderr)),
+                );
+                let fqdump_stderr = String::from_utf8_lossy(&output.stderr);
+                if !fqdump_stderr.trim().is_empty() {
+                    debug!("[{}] fasterq-dump stderr: {}", run_id, fqdump_stderr.trim());
+                }
+
+                if !output.status.success() {
+                    let detail = fqdump_stderr.trim().to_string();
+                    error!(
+                        "[{}] fasterq-dump exited with {}: {}",
+                        run_id, output.status, detail
+                    );
+                    fqdump_error = Some(detail);
+                }
+            }
+
+            {
+                let mut map = progress_store.write().await;
+                if let Some(rp) = map.get_mut(&run_id) {
+                    rp.extraction.percent = 100.0;
+                    rp.extraction.bytes_done = rp.extraction.bytes_total;
+                    rp.stage = RunStage::Compressing;
+                    rp.recalculate_overall();
+                }
+            }
+
+            let fq_exists_after = (fq_1.exists()
+                && fq_1.metadata().map(|m| m.len() > 0).unwrap_or(false))
+                || (fq_single.exists()
+                    && fq_single.metadata().map(|m| m.len() > 0).unwrap_or(false));
+
+            if fq_exists_after {
+                if ctx.cancel.is_cancelled() {
+                    return Err(PolariseqError::Cancelled.into());
+                }
+                info!(target: "download_detail", "[{}] Step 3: Compressing...", run_id);
+
+                let mut fastq_total_size = 0u64;
+                for name in &[
+                    format!("{}.fastq", run_id),
+                    format!("{}_1.fastq", run_id),
+                    format!("{}_2.fastq", run_id),
+                ] {
+                    let path = output_dir.join(name);
+                    if let Ok(meta) = tokio::fs::metadata(&path).await {
+                        fastq_total_size += meta.len();
+                    }
+                }
+
+                let compression_bytes = Arc::new(AtomicU64::new(0));
+                let cb_bytes = compression_bytes.clone();
+                let progress_cb: CompressionProgressCallback =
+                    Arc::new(move |bytes_read, _total| {
+                        cb_bytes.store(bytes_read, Ordering::Relaxed);
+                    });
+
+                let comp_store = progress_store.clone();
+                let comp_run_id = run_id.clone();
+                let comp_bytes_mon = compression_bytes.clone();
+                let comp_total = fastq_total_size;
+                let comp_monitor = tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(Duration::from_millis(200));
+                    loop {
+                        interval.tick().await;
+                        let done = comp_bytes_mon.load(Ordering::Relaxed);
+                        let mut map = comp_store.write().await;
+                        if let Some(rp) = map.get_mut(&comp_run_id) {
+                            rp.compression.update(done, comp_total);
+                            rp.compression.percent = rp.compression.percent.min(99.0);
+                            rp.recalculate_overall();
+                        }
+                    }
+                });
+
+                let finished = if output_format == OutputFormat::Fastq {
+                    let output_dir_compress = output_dir.clone();
+                    let run_id_compress = run_id.clone();
+                    tokio::task::spawn_blocking(move || {
+                        compress_fastq_files(
+                            &output_dir_compress,
+                            &run_id_compress,
+                            process_threads,
+                            Some(progress_cb),
+                        )
+                    })
+                    .instrument(info_span!("compress"))
+                    .await
+                    .context("Compression task panicked")?
+                    .context("Compression failed")?
+                } else {
+                    let imported = crate::unaligned::import_fastq(
+                        &ctx.config,
+                        &output_dir,
+                        &run_id,
+                        output_format,
+                        process_threads,
+                        &ctx.cancel,
+                    )
+                    .instrument(info_span!("compress"))
+                    .await?;
+                    vec![imported]
+                };
+
+                comp_monitor.abort();
+
+                if output_dir != final_dir {
+                    for file in &finished {
+                        let Some(name) = file.file_name() else {
+                            continue;
+                        };
+                        let target = final_dir.join(name);
+                        tokio::fs::rename(file, &target).await.with_context(|| {
+                            format!("Failed to move {} to {}", file.display(), target.display())
+                        })?;
+                    }
+                }
+
+                {
+                    let mut map = progress_store.write().await;
+                    if let Some(rp) = map.get_mut(&run_id) {
+                        rp.compression.percent = 100.0;
+                        rp.compression.bytes_done = rp.compression.bytes_total;
+                        rp.overall_percent = 100.0;
+                        rp.stage = RunStage::Completed;
+                    }
+                }
+
+                if cleanup_sra && output_dir != final_dir {
+                    info!(target: "download_detail", "[{}] Cleaning up staging directory: {}", run_id, output_dir.display());
+                    if let Err(e) = tokio::fs::remove_dir_all(&output_dir).await {
+                        warn!("[{}] Failed to remove staging directory: {}", run_id, e);
+                    }
+                } else if cleanup_sra {
+                    let sra_path = output_dir.join(&sra_filename);
+                    if sra_path.exists() {
+                        info!(target: "download_detail", "[{}] Cleaning up SRA file: {}", run_id, sra_path.display());
+                        if let Err(e) = tokio::fs::remove_file(&sra_path).await {
+                            warn!("[{}] Failed to remove SRA file: {}", run_id, e);
+                        }
+                    }
+                }
+
+                info!("[{}] Done", run_id);
+                Ok(())
+            } else {
+                let reason = fqdump_error.as_deref().unwrap_or("no FASTQ output found");
+                error!("[{}] Conversion failed: {}", run_id, reason);
+                let mut map = progress_store.write().await;
+                if let Some(rp) = map.get_mut(&run_id) {
+                    rp.stage = RunStage::Failed;
+                }
+                Err(PolariseqError::ConversionFailed {
+                    run: run_id,
+                    reason: reason.to_string(),
+                }
+                .into())
+            }
+        })
+    }
+
+    /// Writes `md5.txt` for every `.gz`, `.bam` and `.cram` in the output
+    /// directory.
+    fn finish<'a>(&'a self, ctx: &'a BackendContext) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let outputs: Vec<PathBuf> = std::fs::read_dir(&ctx.options.output)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.extension()
+                        .is_some_and(|ext| ext == "gz" || ext == "bam" || ext == "cram")
+                })
+                .collect();
+            if !outputs.is_empty() {
+                generate_md5sum_file(&ctx.options.output, &outputs)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_downloads_of_earlier_versions_flat() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path();
+        assert_eq!(staging_dir(out, "SRR1", AwsStaging::Run), out.join("SRR1"));
+        assert_eq!(staging_dir(out, "SRR1", AwsStaging::Flat), out);
+
+        std::fs::write(out.join("SRR1"), b"sra").unwrap();
+        assert_eq!(staging_dir(out, "SRR1", AwsStaging::Run), out);
+        std::fs::write(out.join("SRR2.part"), b"").unwrap();
+        std::fs::write(out.join("SRR2.meta.json"), b"{}").unwrap();
+        assert_eq!(staging_dir(out, "SRR2", AwsStaging::Run), out);
+        std::fs::write(out.join("SRR3_1.fastq.gz.part"), b"").unwrap();
+        assert_eq!(staging_dir(out, "SRR3", AwsStaging::Run), out.join("SRR3"));
+    }
+}
@@ -0,0 +1,42 @@
+//! ENA FTP backend (`ftp` feature).
+
+use super::{BackendContext, DownloadBackend, RunItem};
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+
+/// ENA FTP download with `wget -c`, MD5-checked per file.
+#[derive(Debug, Default)]
+pub struct FtpBackend;
+
+impl DownloadBackend for FtpBackend {
+    fn name(&self) -> &'static str {
+        "ftp"
+    }
+
+    fn supports(&self, item: &RunItem) -> bool {
+        matches!(item, RunItem::Public(_))
+    }
+
+    fn download<'a>(
+        &'a self,
+        item: &'a RunItem,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let RunItem::Public(record) = item else {
+                return Err(anyhow!("[{}] No public FTP files", item.run_id()));
+            };
+            crate::ftp::process_downloads(
+                std::slice::from_ref(record),
+                &ctx.config,
+                &ctx.options.output,
+                crate::ftp::Protocol::Ftp,
+                2,
+                ctx.progress.clone(),
+                ctx.cancel.clone(),
+                ctx.pause.clone(),
+            )
+            .await
+        })
+    }
+}
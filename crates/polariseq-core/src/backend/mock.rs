@@ -0,0 +1,170 @@
+//! Simulation backend (`--download mock`).
+//!
+//! Nothing is transferred: each run's files are written under the names
+//! ENA lists, holding a few fabricated reads (gzip-compressed for `.gz`
+//! names); other file types get a one-line placeholder. When the job is
+//! planned, [`apply_checksums`] replaces ENA's MD5s and sizes with those of
+//! the fabricated files, so MD5 tables, resume checks and manifests agree
+//! with what lands on disk. Wrapper pipelines, layouts and samplesheets can
+//! be tested this way without downloading real data.
+
+use super::{BackendContext, DownloadBackend, RunItem};
+use crate::ProcessedRecord;
+use anyhow::{anyhow, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::BoxFuture;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use tracing::info;
+
+/// Reads per fabricated FASTQ file.
+const MOCK_READS: usize = 4;
+const READ_LENGTH: usize = 50;
+
+/// Writes fabricated files instead of downloading.
+#[derive(Debug, Default)]
+pub struct MockBackend;
+
+fn fastq(run_id: &str, mate: u8) -> String {
+    let mut text = String::new();
+    for read in 1..=MOCK_READS {
+        let bases: String = (0..READ_LENGTH)
+            .map(|i| b"ACGT"[(i * 7 + read * 3 + mate as usize) % 4] as char)
+            .collect();
+        let _ = write!(
+            text,
+            "@{}.{} {}/{}\n{}\n+\n{}\n",
+            run_id,
+            read,
+            read,
+            mate,
+            bases,
+            "I".repeat(READ_LENGTH)
+        );
+    }
+    text
+}
+
+/// Content of the fabricated file `name`, mate `mate` (1 or 2) of `run_id`.
+/// The same arguments always give the same bytes.
+pub fn mock_content(run_id: &str, mate: u8, name: &str) -> Vec<u8> {
+    let plain = name.strip_suffix(".gz").unwrap_or(name);
+    let text = if plain.ends_with(".fastq") || plain.ends_with(".fq") {
+        fastq(run_id, mate)
+    } else {
+        format!("polariseq mock file {} of {}\n", name, run_id)
+    };
+    if !name.ends_with(".gz") {
+        return text.into_bytes();
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(text.as_bytes())
+        .and_then(|_| encoder.finish())
+        .expect("writing to a Vec cannot fail")
+}
+
+/// `(mate, file name)` of each file of `record`.
+fn mock_files(record: &ProcessedRecord) -> Vec<(u8, &str)> {
+    let mut files = vec![(1, record.fastq_ftp_1_name.as_str())];
+    files.extend(record.fastq_ftp_2_name.as_deref().map(|name| (2, name)));
+    files
+}
+
+/// Set the MD5s and sizes of `processed` to those of the mock files.
+pub fn apply_checksums(processed: &mut [ProcessedRecord]) {
+    for record in processed {
+        let content = mock_content(&record.run_accession, 1, &record.fastq_ftp_1_name);
+        record.fastq_md5_1 = format!("{:x}", md5::compute(&content));
+        record.fastq_bytes_1 = content.len() as u64;
+        if let Some(name) = &record.fastq_ftp_2_name {
+            let content = mock_content(&record.run_accession, 2, name);
+            record.fastq_md5_2 = Some(format!("{:x}", md5::compute(&content)));
+            record.fastq_bytes_2 = Some(content.len() as u64);
+        }
+    }
+}
+
+impl DownloadBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn supports(&self, item: &RunItem) -> bool {
+        matches!(item, RunItem::Public(_))
+    }
+
+    fn download<'a>(
+        &'a self,
+        item: &'a RunItem,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let RunItem::Public(record) = item else {
+                return Err(anyhow!("[{}] No public files to mock", item.run_id()));
+            };
+            let output = &ctx.options.output;
+            tokio::fs::create_dir_all(output)
+                .await
+                .with_context(|| format!("Failed to create {}", output.display()))?;
+            for (mate, name) in mock_files(record) {
+                let path = output.join(name);
+                tokio::fs::write(&path, mock_content(&record.run_accession, mate, name))
+                    .await
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+            info!(target: "download_detail", "[{}] Wrote mock files", record.run_accession);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{DownloadJob, PolariseqClient};
+    use crate::{Config, DownloadMethod, EnaRecord};
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn mock_job_writes_files_matching_the_md5_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: f\n").unwrap();
+        let client = PolariseqClient::new(config);
+        let job = DownloadJob::accession("PRJNA1", dir.path()).method(DownloadMethod::Mock);
+        let records: Vec<EnaRecord> = serde_json::from_value(serde_json::json!([{
+            "run_accession": "SRR1",
+            "sample_title": "liver",
+            "fastq_ftp": "ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz;ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_2.fastq.gz",
+            "fastq_md5": "0123;4567",
+            "fastq_bytes": "100;200",
+        }]))
+        .unwrap();
+
+        let plan = client.plan_records(&job, records).unwrap();
+        client.write_metadata(&job, &plan).unwrap();
+        client.execute(&job, &plan).await.unwrap();
+
+        let record = &plan.processed[0];
+        let data = std::fs::read(dir.path().join("SRR1_1.fastq.gz")).unwrap();
+        assert_eq!(format!("{:x}", md5::compute(&data)), record.fastq_md5_1);
+        assert_eq!(data.len() as u64, record.fastq_bytes_1);
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(&data[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text.lines().count(), MOCK_READS * 4);
+        assert!(text.starts_with("@SRR1.1 1/1\n"));
+        assert!(dir.path().join("SRR1_2.fastq.gz").exists());
+
+        let table = std::fs::read_to_string(
+            dir.path()
+                .join("PRJNA1_metadata")
+                .join("R1_fastq_md5_PRJNA1.tsv"),
+        )
+        .unwrap();
+        assert!(table.contains(&record.fastq_md5_1));
+    }
+}
@@ -0,0 +1,761 @@
+//! Pluggable download backends.
+//!
+//! Each transfer method (AWS S3 + `fasterq-dump`, ENA FTP, SRA Toolkit
+//! `prefetch`) implements [`DownloadBackend`]. [`run_backends`] drives a
+//! list of runs through an ordered backend chain: the first backend that
+//! supports a run is tried, and when it fails (or its output does not
+//! verify) the run falls through to the next one. [`Route`]s narrow the
+//! chain per run (`--route`, or a TSV's `preferred_backend`). With
+//! `--group-by sample` the runs of one sample share a worker and run in
+//! order, while different samples still download in parallel. Library
+//! users can add their own backends to the chain via
+//! [`PolariseqClient::with_backend`](crate::client::PolariseqClient::with_backend).
+//!
+//! The built-in backends sit behind the `aws`, `ftp` and `sra-tools` cargo
+//! features (all on by default); the `mock` backend, which fabricates
+//! files for testing, is always built.
+//!
+//! EGA files are not ENA runs and keep their own [`EgaDownloader`](crate::ega::EgaDownloader).
+
+use crate::autotune::AutoTune;
+use crate::aws_s3::{PauseToken, SraMetadata};
+use crate::bandwidth::{Bandwidth, TransferMeter};
+use crate::connections::ConnectionLimit;
+use crate::error::PolariseqError;
+use crate::metrics::Metrics;
+use crate::observer::DownloadObserver;
+use crate::overrides;
+use crate::progress::ProgressSink;
+use crate::progress_store::{ProgressStore, RunStage};
+use crate::route::{chain_for, Route};
+use crate::speed::SpeedWatch;
+use crate::{Config, DownloadOptions, ProcessedRecord};
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info_span, warn, Instrument};
+
+/// A run handed to the backend chain.
+#[derive(Debug, Clone)]
+pub enum RunItem {
+    /// Run with public FASTQ files listed by ENA.
+    Public(ProcessedRecord),
+    /// Run without public files, only reachable through the SRA Toolkit
+    /// with a dbGaP credential.
+    Protected(String),
+}
+
+impl RunItem {
+    pub fn run_id(&self) -> &str {
+        match self {
+            Self::Public(record) => &record.run_accession,
+            Self::Protected(run_id) => run_id,
+        }
+    }
+}
+
+/// Cancellation tokens for individual runs, children of the job token.
+#[derive(Debug, Clone, Default)]
+pub struct RunCancellation {
+    tokens: Arc<std::sync::Mutex<HashMap<String, CancellationToken>>>,
+}
+
+impl RunCancellation {
+    fn register(&self, run_id: &str, job: &CancellationToken) -> CancellationToken {
+        let token = job.child_token();
+        self.lock().insert(run_id.to_string(), token.clone());
+        token
+    }
+
+    fn remove(&self, run_id: &str) {
+        self.lock().remove(run_id);
+    }
+
+    /// Cancel one scheduled run; returns false if it is not pending or
+    /// running.
+    pub fn cancel(&self, run_id: &str) -> bool {
+        match self.lock().get(run_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CancellationToken>> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Shared state every backend call receives.
+#[derive(Clone)]
+pub struct BackendContext {
+    pub config: Config,
+    pub options: DownloadOptions,
+    pub progress: Option<Arc<dyn ProgressSink>>,
+    pub observer: Option<Arc<dyn DownloadObserver>>,
+    pub progress_store: ProgressStore,
+    /// Cancels the whole job; inside a backend call, the current run only.
+    pub cancel: CancellationToken,
+    /// Per-run tokens of the runs currently scheduled.
+    pub runs: RunCancellation,
+    /// Job-wide pause state and bandwidth limit.
+    pub pause: PauseToken,
+    /// Called in order before each run starts and after it downloaded and
+    /// verified.
+    pub hooks: Vec<Arc<dyn RunHook>>,
+    /// Per-run backend selection; empty sends every run down the chain.
+    pub routes: Vec<Route>,
+    /// Runs written below `options.output` (`output_subdir` TSV column).
+    pub output_subdirs: HashMap<String, PathBuf>,
+    /// Group key per run (`--group-by`); runs sharing a key go through one
+    /// worker in order. Runs without a key are scheduled on their own.
+    pub run_groups: HashMap<String, String>,
+    /// Runs from Oxford Nanopore or PacBio, see [`long_read`](crate::long_read).
+    pub long_reads: HashSet<String>,
+    /// ENA's `sra_md5` and `sra_bytes` per run: the AWS backend's metadata
+    /// when S3 agrees on the size, else the MD5 for objects NCBI lists
+    /// without one. Prefetch bars use the size as their total.
+    pub sra_md5: HashMap<String, (String, u64)>,
+    /// AWS copies resolved by `plan`; the AWS backend asks neither S3 nor
+    /// NCBI for these runs.
+    pub resolved_sra: HashMap<String, SraMetadata>,
+    pub metrics: Option<Arc<Metrics>>,
+    /// Transfer totals per backend, see [`bandwidth`](crate::bandwidth).
+    pub bandwidth: Option<Arc<Bandwidth>>,
+    /// Job-wide cap on range requests (`--max-connections`).
+    pub connections: Option<Arc<ConnectionLimit>>,
+    /// Run count and threads per file under `--auto-tune`.
+    pub auto_tune: Option<Arc<AutoTune>>,
+    /// Backend that downloaded the current run; set before its hooks run.
+    pub backend: Option<&'static str>,
+    /// When the current run got its download slot.
+    pub started: Option<Instant>,
+}
+
+impl BackendContext {
+    /// Threads for `fasterq-dump` and compression (at least 4).
+    pub fn process_threads(&self) -> usize {
+        self.options.aws_threads.max(4)
+    }
+
+    /// Memory of one `fasterq-dump`, see [`fasterq_mem`].
+    pub fn fasterq_mem(&self) -> Option<u64> {
+        fasterq_mem(&self.options)
+    }
+}
+
+/// Memory of one `fasterq-dump`: `--fasterq-mem`, or the `--max-memory`
+/// budget split across the `-p` runs that may convert at once (the smaller
+/// of the two when both are set). `None` leaves fasterq-dump's default.
+pub fn fasterq_mem(options: &DownloadOptions) -> Option<u64> {
+    let share = options
+        .max_memory
+        .map(|total| total / options.multithreads.max(1) as u64);
+    match (options.fasterq_mem, share) {
+        (Some(mem), Some(share)) => Some(mem.min(share)),
+        (mem, share) => mem.or(share),
+    }
+}
+
+/// `--mem` arguments for `fasterq-dump`, in whole MiB.
+pub fn fasterq_mem_args(mem: Option<u64>) -> Vec<String> {
+    match mem {
+        Some(bytes) => vec!["--mem".to_string(), format!("{}M", (bytes >> 20).max(1))],
+        None => Vec::new(),
+    }
+}
+
+/// A transfer method that can fetch runs into the output directory.
+///
+/// Methods return boxed futures so backends stay object-safe and can be
+/// mixed in one `Vec<Arc<dyn DownloadBackend>>`.
+pub trait DownloadBackend: Send + Sync {
+    /// Short name used in logs (`aws`, `ftp`, `prefetch`, …).
+    fn name(&self) -> &'static str;
+
+    /// Whether this backend can fetch `item` at all.
+    fn supports(&self, item: &RunItem) -> bool;
+
+    /// Validate tools/config and register progress for `items` before any
+    /// run starts. An error removes the backend from the chain.
+    fn prepare<'a>(
+        &'a self,
+        _items: &'a [RunItem],
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Fetch one run into `ctx.options.output`.
+    fn download<'a>(
+        &'a self,
+        item: &'a RunItem,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Check the run's output after a successful download.
+    fn verify<'a>(
+        &'a self,
+        _item: &'a RunItem,
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async { Ok(true) })
+    }
+
+    /// Called once after every run has been attempted.
+    fn finish<'a>(&'a self, _ctx: &'a BackendContext) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Post-processing of a run once its files are downloaded and verified
+/// (upload, notification, QC, …), optionally with setup before it starts.
+/// A failing hook fails the run.
+pub trait RunHook: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Called once `run_id` has a download slot, before any backend runs.
+    /// An error fails the run without downloading it.
+    fn before_run<'a>(
+        &'a self,
+        _run_id: &'a str,
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Handle `run_id`, whose output files are `files`.
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Called when `run_id` failed, in the backends or in a hook. Not
+    /// called for cancelled runs.
+    fn run_failed<'a>(
+        &'a self,
+        _run_id: &'a str,
+        _error: &'a anyhow::Error,
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Files of `run_id` in `output`: `<run>`, `<run>.*` and `<run>_*`, minus
+/// resume state and temporary files.
+pub fn run_output_files(output: &Path, run_id: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(output) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let belongs = name == run_id
+                || name
+                    .strip_prefix(run_id)
+                    .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('_'));
+            belongs
+                && ![".meta.json", ".tmp", ".part"]
+                    .iter()
+                    .any(|s| name.ends_with(s))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Run `items` through `backends` with `ctx.options.multithreads` runs in
+/// flight, falling back along the chain per run.
+pub async fn run_backends(
+    items: Vec<RunItem>,
+    backends: Vec<Arc<dyn DownloadBackend>>,
+    ctx: BackendContext,
+) -> Result<()> {
+    let mut ready: Vec<Arc<dyn DownloadBackend>> = Vec::new();
+    let mut prepare_err = None;
+    for backend in backends {
+        let handled: Vec<RunItem> = items
+            .iter()
+            .filter(|item| !chain_for(item, &ctx.routes, std::slice::from_ref(&backend)).is_empty())
+            .cloned()
+            .collect();
+        if handled.is_empty() {
+            continue;
+        }
+        match backend.prepare(&handled, &ctx).await {
+            Ok(()) => ready.push(backend),
+            Err(e) => {
+                warn!("Backend {} unavailable: {:#}", backend.name(), e);
+                prepare_err.get_or_insert(e);
+            }
+        }
+    }
+    if ready.is_empty() {
+        return match prepare_err {
+            Some(e) => Err(e),
+            None if items.is_empty() => Ok(()),
+            None => Err(anyhow!("No download backend supports the requested runs")),
+        };
+    }
+
+    let ready = Arc::new(ready);
+    let mut ctx = ctx;
+    if let Some(tune) = &ctx.auto_tune {
+        ctx.progress = Some(tune.sink(ctx.progress.take()));
+    }
+    let ctx = Arc::new(ctx);
+    let semaphore = Arc::new(Semaphore::new(ctx.options.multithreads.max(1)));
+    let tuner = ctx
+        .auto_tune
+        .as_ref()
+        .map(|tune| tune.start(semaphore.clone(), ctx.cancel.clone()));
+    let total_tasks = items.len();
+    let mut handles = Vec::new();
+
+    for group in group_runs(items, &ctx.run_groups) {
+        let backends = ready.clone();
+        let ctx = ctx.clone();
+        let sem = semaphore.clone();
+        // Register the whole group up front so queued runs can be cancelled.
+        let tokens: Vec<CancellationToken> = group
+            .iter()
+            .map(|item| ctx.runs.register(item.run_id(), &ctx.cancel))
+            .collect();
+
+        let handle = tokio::spawn(async move {
+            // Runs of one group go one after another, in plan order.
+            let mut results = Vec::with_capacity(group.len());
+            for (item, cancel) in group.into_iter().zip(tokens) {
+                let span = info_span!("run", run_id = item.run_id());
+                let result = run_one(item, cancel, &backends, &ctx, &sem)
+                    .instrument(span)
+                    .await;
+                results.push(result);
+            }
+            results
+        });
+        handles.push(handle);
+    }
+
+    let mut failed = 0usize;
+    let mut first_err: Option<anyhow::Error> = None;
+    for handle in handles {
+        match handle.await {
+            Ok(results) => {
+                for e in results.into_iter().filter_map(Result::err) {
+                    failed += 1;
+                    warn!("Task failed: {:#}", e);
+                    if first_err.is_none() {
+                        first_err = Some(e);
+                    }
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                warn!("Task join error: {}", e);
+                if first_err.is_none() {
+                    first_err = Some(anyhow!("task join error: {}", e));
+                }
+            }
+        }
+    }
+    if let Some(tuner) = tuner {
+        tuner.abort();
+    }
+
+    for backend in ready.iter() {
+        backend.finish(&ctx).await?;
+    }
+
+    if failed > 0 {
+        let msg = format!("{} of {} download task(s) failed", failed, total_tasks);
+        error!("{}", msg);
+        return Err(first_err.unwrap_or_else(|| anyhow!("{}", msg)));
+    }
+    Ok(())
+}
+
+/// Split `items` into groups whose runs must go one after another; runs
+/// without an entry in `groups` are on their own.
+fn group_runs(items: Vec<RunItem>, groups: &HashMap<String, String>) -> Vec<Vec<RunItem>> {
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut out: Vec<Vec<RunItem>> = Vec::new();
+    for item in items {
+        match groups.get(item.run_id()) {
+            Some(key) => match index.get(key.as_str()) {
+                Some(&i) => out[i].push(item),
+                None => {
+                    index.insert(key, out.len());
+                    out.push(vec![item]);
+                }
+            },
+            None => out.push(vec![item]),
+        }
+    }
+    out
+}
+
+/// Download one run once a slot is free, then run its hooks.
+async fn run_one(
+    item: RunItem,
+    cancel: CancellationToken,
+    backends: &[Arc<dyn DownloadBackend>],
+    ctx: &BackendContext,
+    sem: &Semaphore,
+) -> Result<()> {
+    let run_id = item.run_id().to_string();
+    let mut run_ctx = BackendContext {
+        cancel,
+        ..ctx.clone()
+    };
+    run_ctx.options.output =
+        overrides::output_for(&ctx.options.output, &ctx.output_subdirs, &run_id);
+    let meter = ctx.bandwidth.as_ref().map(|_| TransferMeter::new());
+    if let Some(meter) = &meter {
+        run_ctx.progress = Some(meter.sink(run_ctx.progress.take()));
+    }
+    // While paused, no new run starts.
+    let pause = run_ctx.pause.clone();
+    let slot = async {
+        let permit = sem.acquire().await;
+        pause.wait_while_paused().await;
+        permit
+    };
+    let result = tokio::select! {
+        permit = slot => {
+            let _permit = permit.expect("semaphore closed");
+            // Runs wait for their slot with the tuner still adjusting, so
+            // take its thread count only now.
+            if let Some(tune) = &run_ctx.auto_tune {
+                run_ctx.options.aws_threads = tune.settings().threads;
+            }
+            run_ctx.started = Some(Instant::now());
+            if let Some(metrics) = &run_ctx.metrics {
+                metrics.run_started();
+            }
+            let downloaded = match run_start_hooks(&run_id, &run_ctx).await {
+                Ok(()) => download_with_fallback(&item, backends, &run_ctx, meter.as_deref()).await,
+                Err(e) => Err(e),
+            };
+            let result = match downloaded {
+                Ok(backend) => {
+                    run_ctx.backend = Some(backend);
+                    run_hooks(&run_id, &run_ctx).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Some(metrics) = &run_ctx.metrics {
+                metrics.run_finished(
+                    &run_id,
+                    result.is_ok(),
+                    run_ctx.started.map(|s| s.elapsed()).unwrap_or_default(),
+                );
+            }
+            result
+        }
+        _ = run_ctx.cancel.cancelled() => Err(PolariseqError::Cancelled.into()),
+    };
+    ctx.runs.remove(&run_id);
+    if let (Err(e), false) = (&result, run_ctx.cancel.is_cancelled()) {
+        for hook in &run_ctx.hooks {
+            hook.run_failed(&run_id, e, &run_ctx).await;
+        }
+    }
+    if run_ctx.cancel.is_cancelled() {
+        let mut map = ctx.progress_store.write().await;
+        if let Some(rp) = map.get_mut(&run_id) {
+            rp.stage = RunStage::Cancelled;
+        }
+    }
+    result
+}
+
+async fn run_start_hooks(run_id: &str, ctx: &BackendContext) -> Result<()> {
+    for hook in &ctx.hooks {
+        hook.before_run(run_id, ctx)
+            .instrument(info_span!("hook", hook = hook.name()))
+            .await
+            .map_err(|e| anyhow!("[{}] {} failed: {:#}", run_id, hook.name(), e))?;
+    }
+    Ok(())
+}
+
+async fn run_hooks(run_id: &str, ctx: &BackendContext) -> Result<()> {
+    for hook in &ctx.hooks {
+        // Listed per hook, so files an earlier hook wrote (e.g. trimmed
+        // reads) reach the later ones.
+        let files = run_output_files(&ctx.options.output, run_id);
+        hook.after_run(run_id, &files, ctx)
+            .instrument(info_span!("hook", hook = hook.name()))
+            .await
+            .map_err(|e| anyhow!("[{}] {} failed: {:#}", run_id, hook.name(), e))?;
+    }
+    Ok(())
+}
+
+async fn download_with_fallback(
+    item: &RunItem,
+    backends: &[Arc<dyn DownloadBackend>],
+    ctx: &BackendContext,
+    meter: Option<&TransferMeter>,
+) -> Result<&'static str> {
+    let mut last_err = None;
+    for backend in chain_for(item, &ctx.routes, backends) {
+        if let Some(e) = &last_err {
+            warn!(
+                "[{}] {:#}; falling back to {}",
+                item.run_id(),
+                e,
+                backend.name()
+            );
+        }
+        // With a speed floor, the attempt gets its own token and a sink
+        // that measures its transfers.
+        let watched = ctx.options.min_speed.map(|floor| {
+            let watch = SpeedWatch::new(floor);
+            let attempt_ctx = BackendContext {
+                cancel: ctx.cancel.child_token(),
+                progress: Some(watch.sink(ctx.progress.clone())),
+                ..ctx.clone()
+            };
+            let watcher = tokio::spawn(watch.clone().watch(attempt_ctx.cancel.clone()));
+            (watch, watcher, attempt_ctx)
+        });
+        let attempt_ctx = watched.as_ref().map_or(ctx, |(_, _, c)| c);
+        let before = meter.map(TransferMeter::totals);
+        let attempt = async {
+            backend
+                .download(item, attempt_ctx)
+                .instrument(info_span!("download", backend = backend.name()))
+                .await?;
+            if !backend
+                .verify(item, attempt_ctx)
+                .instrument(info_span!("verify", backend = backend.name()))
+                .await?
+            {
+                return Err(anyhow!("{} output failed verification", backend.name()));
+            }
+            Ok(())
+        };
+        let mut outcome = attempt.await;
+        if let (Some((watch, watcher, _)), Some(floor)) = (&watched, ctx.options.min_speed) {
+            watcher.abort();
+            if outcome.is_err() && watch.is_slow() {
+                outcome = Err(PolariseqError::Network(format!(
+                    "{} stayed below --min-speed {}/s for {}s",
+                    backend.name(),
+                    indicatif::HumanBytes(floor.bytes_per_sec),
+                    floor.window_secs
+                ))
+                .into());
+            }
+        }
+        if let (Some(bandwidth), Some(meter), Some((bytes, busy))) = (&ctx.bandwidth, meter, before)
+        {
+            let (now_bytes, now_busy) = meter.totals();
+            bandwidth.record(
+                backend.name(),
+                now_bytes.saturating_sub(bytes),
+                now_busy.saturating_sub(busy),
+                outcome.is_ok(),
+            );
+        }
+        if let Some(tune) = &ctx.auto_tune {
+            tune.attempt_finished(outcome.is_ok());
+        }
+        match outcome {
+            Ok(()) => return Ok(backend.name()),
+            Err(e) if ctx.cancel.is_cancelled() => return Err(e),
+            Err(e) => {
+                if let Some(metrics) = &ctx.metrics {
+                    metrics.backend_failed(backend.name());
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("[{}] No backend supports this run", item.run_id())))
+}
+
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "ftp")]
+mod ftp;
+pub mod mock;
+#[cfg(feature = "sra-tools")]
+mod prefetch;
+
+#[cfg(feature = "aws")]
+pub use aws::AwsBackend;
+#[cfg(feature = "ftp")]
+pub use ftp::FtpBackend;
+pub use mock::MockBackend;
+#[cfg(feature = "sra-tools")]
+pub use prefetch::PrefetchBackend;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Scripted {
+        name: &'static str,
+        fail: bool,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl DownloadBackend for Scripted {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn supports(&self, _item: &RunItem) -> bool {
+            true
+        }
+
+        fn download<'a>(
+            &'a self,
+            item: &'a RunItem,
+            _ctx: &'a BackendContext,
+        ) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.calls.lock().unwrap().push(item.run_id().to_string());
+                if self.fail {
+                    Err(anyhow!("scripted failure"))
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    fn scripted(name: &'static str, fail: bool) -> Arc<Scripted> {
+        Arc::new(Scripted {
+            name,
+            fail,
+            calls: Mutex::new(Vec::new()),
+        })
+    }
+
+    #[tokio::test]
+    async fn failed_runs_fall_through_the_chain() {
+        let config: Config =
+            serde_yaml::from_str("software:\n  fasterq_dump: /bin/true\n  prefetch: /bin/true\n")
+                .unwrap();
+        let ctx = BackendContext {
+            config,
+            options: DownloadOptions::default(),
+            progress: None,
+            observer: None,
+            progress_store: crate::progress_store::new_progress_store(),
+            cancel: CancellationToken::new(),
+            runs: RunCancellation::default(),
+            pause: PauseToken::default(),
+            hooks: Vec::new(),
+            routes: Vec::new(),
+            output_subdirs: HashMap::new(),
+            run_groups: HashMap::new(),
+            long_reads: HashSet::new(),
+            sra_md5: HashMap::new(),
+            resolved_sra: HashMap::new(),
+            metrics: None,
+            bandwidth: None,
+            connections: None,
+            auto_tune: None,
+            backend: None,
+            started: None,
+        };
+        let primary = scripted("primary", true);
+        let fallback = scripted("fallback", false);
+        let backends: Vec<Arc<dyn DownloadBackend>> = vec![primary.clone(), fallback.clone()];
+
+        run_backends(vec![RunItem::Protected("SRR1".into())], backends, ctx)
+            .await
+            .unwrap();
+        assert_eq!(*primary.calls.lock().unwrap(), vec!["SRR1"]);
+        assert_eq!(*fallback.calls.lock().unwrap(), vec!["SRR1"]);
+    }
+
+    #[test]
+    fn fasterq_memory_is_split_across_runs() {
+        const GIB: u64 = 1 << 30;
+        let mut options = DownloadOptions {
+            multithreads: 4,
+            ..DownloadOptions::default()
+        };
+        assert_eq!(fasterq_mem(&options), None);
+        options.max_memory = Some(32 * GIB);
+        assert_eq!(fasterq_mem(&options), Some(8 * GIB));
+        options.fasterq_mem = Some(2 * GIB);
+        assert_eq!(fasterq_mem(&options), Some(2 * GIB));
+        options.fasterq_mem = Some(16 * GIB);
+        assert_eq!(fasterq_mem(&options), Some(8 * GIB));
+        assert_eq!(fasterq_mem_args(Some(8 * GIB)), ["--mem", "8192M"]);
+        assert!(fasterq_mem_args(None).is_empty());
+    }
+
+    #[test]
+    fn runs_of_a_group_stay_together_in_order() {
+        let items = ["SRR1", "SRR2", "SRR3", "SRR4"]
+            .map(|run| RunItem::Protected(run.into()))
+            .to_vec();
+        let groups = HashMap::from([
+            ("SRR1".to_string(), "SAMN1".to_string()),
+            ("SRR3".to_string(), "SAMN1".to_string()),
+            ("SRR4".to_string(), "SAMN2".to_string()),
+        ]);
+        let ids: Vec<Vec<String>> = group_runs(items, &groups)
+            .iter()
+            .map(|group| group.iter().map(|i| i.run_id().to_string()).collect())
+            .collect();
+        assert_eq!(ids, [vec!["SRR1", "SRR3"], vec!["SRR2"], vec!["SRR4"]]);
+    }
+
+    #[test]
+    fn run_output_files_match_the_run_only() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "SRR1",
+            "SRR1_1.fastq.gz",
+            "SRR1.fastq.gz",
+            "SRR1.meta.json",
+            "SRR10_1.fastq.gz",
+        ] {
+            std::fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        let names: Vec<String> = run_output_files(dir.path(), "SRR1")
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["SRR1", "SRR1.fastq.gz", "SRR1_1.fastq.gz"]);
+    }
+
+    #[test]
+    fn run_tokens_follow_the_job_token() {
+        let job = CancellationToken::new();
+        let runs = RunCancellation::default();
+        let srr1 = runs.register("SRR1", &job);
+        let srr2 = runs.register("SRR2", &job);
+
+        assert!(runs.cancel("SRR1"));
+        assert!(srr1.is_cancelled() && !srr2.is_cancelled() && !job.is_cancelled());
+        runs.remove("SRR2");
+        assert!(!runs.cancel("SRR2"));
+        job.cancel();
+        assert!(srr2.is_cancelled());
+    }
+}
@@ -0,0 +1,124 @@
+//! SRA Toolkit backend (`sra-tools` feature), also used for dbGaP
+//! controlled-access runs.
+
+use super::{BackendContext, DownloadBackend, RunItem};
+use crate::controlled::{self, AccessCredential};
+use crate::overrides;
+use crate::route::Route;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// SRA Toolkit `prefetch` + `fasterq-dump`. With a credential it also
+/// handles dbGaP-protected runs.
+#[derive(Debug, Default)]
+pub struct PrefetchBackend {
+    credential: Option<AccessCredential>,
+}
+
+impl PrefetchBackend {
+    pub fn new(credential: Option<AccessCredential>) -> Self {
+        Self { credential }
+    }
+}
+
+/// Whether `prefetch` is the first backend `item` tries: protected runs,
+/// which nothing else fetches, and runs whose route names it first.
+fn prefetch_first(item: &RunItem, routes: &[Route]) -> bool {
+    match routes.iter().find(|route| route.matches.matches(item)) {
+        Some(route) => route.backends.first().is_some_and(|b| b == "prefetch"),
+        None => matches!(item, RunItem::Protected(_)),
+    }
+}
+
+impl DownloadBackend for PrefetchBackend {
+    fn name(&self) -> &'static str {
+        "prefetch"
+    }
+
+    fn supports(&self, item: &RunItem) -> bool {
+        matches!(item, RunItem::Public(_)) || self.credential.is_some()
+    }
+
+    fn prepare<'a>(
+        &'a self,
+        items: &'a [RunItem],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            controlled::validate_tools(&ctx.config)?;
+            if let Some(credential) = &self.credential {
+                info!(
+                    "Starting controlled-access pipeline for {} run(s) with {}",
+                    items.len(),
+                    credential.path().display()
+                );
+            }
+            if ctx.options.prefetch_batch {
+                // One batch per output directory (`output_subdir`).
+                let mut batches: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+                for item in items.iter().filter(|i| prefetch_first(i, &ctx.routes)) {
+                    let run_id = item.run_id();
+                    let output =
+                        overrides::output_for(&ctx.options.output, &ctx.output_subdirs, run_id);
+                    batches.entry(output).or_default().push(run_id.to_string());
+                }
+                for (output, runs) in &batches {
+                    std::fs::create_dir_all(output)?;
+                    let batch = controlled::prefetch_batch(
+                        runs,
+                        &ctx.config,
+                        self.credential.as_ref(),
+                        output,
+                        &ctx.cancel,
+                    );
+                    if let Err(e) = batch.await {
+                        warn!("Batch prefetch failed, fetching runs one by one: {:#}", e);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        item: &'a RunItem,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(controlled::download_run(
+            item.run_id(),
+            &ctx.config,
+            self.credential.as_ref(),
+            &ctx.options.output,
+            ctx.process_threads(),
+            ctx.fasterq_mem(),
+            ctx.options.cleanup_sra,
+            ctx.options.output_format,
+            ctx.long_reads.contains(item.run_id()),
+            ctx.progress.as_ref(),
+            ctx.sra_md5
+                .get(item.run_id())
+                .map_or(0, |(_, bytes)| *bytes),
+            &ctx.cancel,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route::parse_routes;
+
+    #[test]
+    fn batches_runs_that_start_with_prefetch() {
+        let protected = RunItem::Protected("SRR1".into());
+        assert!(prefetch_first(&protected, &[]));
+        let routes = parse_routes(&["SRR1=prefetch,aws".to_string()]).unwrap();
+        assert!(prefetch_first(&protected, &routes));
+        let routes = parse_routes(&["protected=aws,prefetch".to_string()]).unwrap();
+        assert!(!prefetch_first(&protected, &routes));
+    }
+}
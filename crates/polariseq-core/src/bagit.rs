@@ -0,0 +1,193 @@
+//! BagIt packaging for `download --bagit`.
+//!
+//! [`make_bag`] turns the output directory into a BagIt 1.0 bag (RFC 8493)
+//! once the job has finished: the files of every planned run move into
+//! `data/`, `manifest-md5.txt` lists the payload, and `bag-info.txt` is
+//! filled from the ENA metadata of the job. Files already in `data/` from
+//! an earlier invocation stay in the bag, so a resumed job re-bags cleanly.
+//! Logs, metadata TSVs and other top-level files remain as tag files.
+
+use crate::backend::run_output_files;
+use crate::client::JobPlan;
+use crate::md5::{collect_files, compute_md5};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Payload directory of the bag.
+pub const PAYLOAD_DIR: &str = "data";
+
+/// Move the planned runs' files into `output/data` and write the bag
+/// declaration, payload manifest, `bag-info.txt` and tag manifest.
+/// `accession` is recorded as the bag's External-Identifier.
+pub fn make_bag(output: &Path, plan: &JobPlan, accession: Option<&str>) -> Result<()> {
+    let data = output.join(PAYLOAD_DIR);
+    fs::create_dir_all(&data).with_context(|| format!("Failed to create {}", data.display()))?;
+    for record in &plan.processed {
+        for file in run_output_files(output, &record.run_accession) {
+            let target = data.join(file.file_name().unwrap_or_default());
+            fs::rename(&file, &target)
+                .with_context(|| format!("Failed to move {} into the bag", file.display()))?;
+        }
+    }
+
+    let payload = collect_files(&data)?;
+    let mut manifest = String::new();
+    let mut bytes = 0u64;
+    for file in &payload {
+        bytes += fs::metadata(file)?.len();
+        let _ = writeln!(
+            manifest,
+            "{}  {}",
+            compute_md5(file)?,
+            bag_path(output, file)
+        );
+    }
+    fs::write(output.join("manifest-md5.txt"), manifest)?;
+    fs::write(
+        output.join("bagit.txt"),
+        "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n",
+    )?;
+    fs::write(
+        output.join("bag-info.txt"),
+        bag_info(plan, accession, bytes, payload.len()),
+    )?;
+
+    let mut tags = String::new();
+    for name in ["bagit.txt", "bag-info.txt", "manifest-md5.txt"] {
+        let _ = writeln!(tags, "{}  {}", compute_md5(&output.join(name))?, name);
+    }
+    fs::write(output.join("tagmanifest-md5.txt"), tags)?;
+
+    info!(
+        "BagIt bag written to {} ({} payload file(s), {} bytes)",
+        output.display(),
+        payload.len(),
+        bytes
+    );
+    Ok(())
+}
+
+/// `data/...` path of `file` with `/` separators, as manifests require.
+fn bag_path(output: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(output).unwrap_or(file);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Distinct non-empty values of one metadata field, comma-separated.
+fn distinct<'a>(values: impl Iterator<Item = Option<&'a String>>) -> Option<String> {
+    let values: BTreeSet<&str> = values
+        .flatten()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    (!values.is_empty()).then(|| values.into_iter().collect::<Vec<_>>().join(", "))
+}
+
+fn bag_info(plan: &JobPlan, accession: Option<&str>, bytes: u64, files: usize) -> String {
+    let records = &plan.records;
+    let mut fields: Vec<(&str, Option<String>)> = vec![
+        (
+            "Source-Organization",
+            distinct(records.iter().map(|r| r.center_name.as_ref())),
+        ),
+        ("External-Identifier", accession.map(str::to_string)),
+        (
+            "External-Description",
+            distinct(records.iter().map(|r| r.study_title.as_ref())),
+        ),
+        (
+            "Study-Accession",
+            distinct(records.iter().map(|r| r.study_accession.as_ref())),
+        ),
+        (
+            "Scientific-Name",
+            distinct(records.iter().map(|r| r.scientific_name.as_ref())),
+        ),
+        (
+            "Instrument-Platform",
+            distinct(records.iter().map(|r| r.instrument_platform.as_ref())),
+        ),
+        (
+            "Bagging-Date",
+            Some(Local::now().format("%Y-%m-%d").to_string()),
+        ),
+        (
+            "Bag-Software-Agent",
+            Some(format!("polariseq {}", env!("CARGO_PKG_VERSION"))),
+        ),
+        ("Payload-Oxum", Some(format!("{}.{}", bytes, files))),
+    ];
+    fields.retain(|(_, value)| value.is_some());
+    let mut info = String::new();
+    for (label, value) in fields {
+        // Tag values are single lines.
+        let value = value.unwrap_or_default().replace(['\r', '\n'], " ");
+        let _ = writeln!(info, "{}: {}", label, value);
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnaRecord, ProcessedRecord};
+
+    fn record(run: &str, center: &str) -> EnaRecord {
+        serde_json::from_value(serde_json::json!({
+            "run_accession": run,
+            "study_accession": "PRJNA1",
+            "center_name": center,
+            "study_title": "A study\nwith two lines",
+        }))
+        .unwrap()
+    }
+
+    fn processed(run: &str) -> ProcessedRecord {
+        ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: String::new(),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: format!("{}.fastq.gz", run),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: String::new(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 0,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        }
+    }
+
+    #[test]
+    fn moves_runs_into_a_bag() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path();
+        fs::write(output.join("SRR1_1.fastq.gz"), b"abc").unwrap();
+        fs::write(output.join("SRR2.fastq.gz"), b"de").unwrap();
+        fs::write(output.join("ena_metadata_PRJNA1.tsv"), b"x").unwrap();
+        let plan = JobPlan {
+            records: vec![record("SRR1", "LAB"), record("SRR2", "LAB")],
+            processed: vec![processed("SRR1"), processed("SRR2")],
+            ..JobPlan::default()
+        };
+        make_bag(output, &plan, Some("PRJNA1")).unwrap();
+
+        assert!(output.join("data/SRR1_1.fastq.gz").exists());
+        assert!(output.join("ena_metadata_PRJNA1.tsv").exists());
+        let manifest = fs::read_to_string(output.join("manifest-md5.txt")).unwrap();
+        assert!(manifest.contains("900150983cd24fb0d6963f7d28e17f72  data/SRR1_1.fastq.gz\n"));
+        let info = fs::read_to_string(output.join("bag-info.txt")).unwrap();
+        assert!(info.contains("Source-Organization: LAB\n"));
+        assert!(info.contains("External-Description: A study with two lines\n"));
+        assert!(info.contains("Payload-Oxum: 5.2\n"));
+        assert_eq!(collect_files(&output.join(PAYLOAD_DIR)).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,260 @@
+//! Transfer speed per backend, logged when a job finishes.
+//!
+//! Each run's progress sink is wrapped in a [`TransferMeter`] that counts
+//! transferred bytes and the time at least one transfer was running. After
+//! every backend attempt the difference is added to that backend's totals
+//! in [`Bandwidth`], failed attempts included, so conversion and hooks do
+//! not count and a slow backend shows up even when another one took over.
+//! [`Bandwidth::log`] compares the backends, e.g. to pick `--download`.
+
+use crate::progress::{start_progress, ProgressHandle, ProgressKind, ProgressSink};
+use indicatif::{HumanBytes, HumanDuration};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Totals of one backend on a job.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BackendBandwidth {
+    /// Runs the backend downloaded.
+    pub runs: usize,
+    pub bytes: u64,
+    /// Time its transfers were running, summed over runs.
+    pub seconds: f64,
+}
+
+impl BackendBandwidth {
+    /// Average speed of one run's transfers, in MB/s.
+    pub fn mb_per_s(&self) -> f64 {
+        self.bytes as f64 / 1e6 / self.seconds.max(0.001)
+    }
+}
+
+/// Per-backend totals of a job.
+#[derive(Debug, Default)]
+pub struct Bandwidth {
+    backends: Mutex<BTreeMap<String, BackendBandwidth>>,
+}
+
+impl Bandwidth {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Add one attempt of `backend`; `ok` when it downloaded its run.
+    pub(crate) fn record(&self, backend: &str, bytes: u64, busy: Duration, ok: bool) {
+        let mut backends = self.backends.lock().unwrap();
+        let totals = backends.entry(backend.to_string()).or_default();
+        totals.runs += usize::from(ok);
+        totals.bytes += bytes;
+        totals.seconds += busy.as_secs_f64();
+    }
+
+    /// Backends that transferred data, fastest first.
+    pub fn report(&self) -> Vec<(String, BackendBandwidth)> {
+        let mut report: Vec<_> = self
+            .backends
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, totals)| totals.bytes > 0)
+            .map(|(name, totals)| (name.clone(), totals.clone()))
+            .collect();
+        report.sort_by(|a, b| b.1.mb_per_s().total_cmp(&a.1.mb_per_s()));
+        report
+    }
+
+    /// Log the report; nothing when no transfer was measured.
+    pub fn log(&self) {
+        let report = self.report();
+        if report.is_empty() {
+            return;
+        }
+        info!("Transfer speed by backend on this job:");
+        for (name, totals) in &report {
+            info!(
+                "  {:<9} {:>4} run(s) {:>10} in {:>8} {:>8.1} MB/s",
+                name,
+                totals.runs,
+                HumanBytes(totals.bytes).to_string(),
+                HumanDuration(Duration::from_secs_f64(totals.seconds)).to_string(),
+                totals.mb_per_s()
+            );
+        }
+        if let [(fastest, first), .., (slowest, last)] = report.as_slice() {
+            info!(
+                "{} was {:.1}x as fast as {} here; consider `--download {}` as this site's default",
+                fastest,
+                first.mb_per_s() / last.mb_per_s().max(0.001),
+                slowest,
+                fastest
+            );
+        }
+    }
+}
+
+/// Bytes transferred by one run and the time its transfers were running.
+#[derive(Debug, Default)]
+pub(crate) struct TransferMeter {
+    bytes: AtomicU64,
+    busy: Mutex<Busy>,
+}
+
+#[derive(Debug, Default)]
+struct Busy {
+    active: usize,
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl TransferMeter {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// `inner`, with transfer handles also counted here.
+    pub(crate) fn sink(
+        self: &Arc<Self>,
+        inner: Option<Arc<dyn ProgressSink>>,
+    ) -> Arc<dyn ProgressSink> {
+        Arc::new(MeterSink {
+            meter: self.clone(),
+            inner,
+        })
+    }
+
+    /// Bytes so far and the time transfers were running, up to now.
+    pub(crate) fn totals(&self) -> (u64, Duration) {
+        let busy = self.busy.lock().unwrap();
+        let running = busy.since.map(|since| since.elapsed()).unwrap_or_default();
+        (self.bytes.load(Ordering::Relaxed), busy.total + running)
+    }
+
+    fn transfer_started(&self) {
+        let mut busy = self.busy.lock().unwrap();
+        busy.active += 1;
+        busy.since.get_or_insert_with(Instant::now);
+    }
+
+    fn transfer_ended(&self) {
+        let mut busy = self.busy.lock().unwrap();
+        busy.active = busy.active.saturating_sub(1);
+        if busy.active == 0 {
+            if let Some(since) = busy.since.take() {
+                busy.total += since.elapsed();
+            }
+        }
+    }
+}
+
+struct MeterSink {
+    meter: Arc<TransferMeter>,
+    inner: Option<Arc<dyn ProgressSink>>,
+}
+
+impl ProgressSink for MeterSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        let inner = start_progress(self.inner.as_ref(), id, kind, total);
+        if kind != ProgressKind::Transfer {
+            return inner;
+        }
+        self.meter.transfer_started();
+        Arc::new(MeterHandle {
+            meter: self.meter.clone(),
+            // The first position is where a resumed transfer starts.
+            last: AtomicU64::new(u64::MAX),
+            done: AtomicBool::new(false),
+            inner,
+        })
+    }
+}
+
+struct MeterHandle {
+    meter: Arc<TransferMeter>,
+    last: AtomicU64,
+    done: AtomicBool,
+    inner: Arc<dyn ProgressHandle>,
+}
+
+impl MeterHandle {
+    fn end(&self) {
+        if !self.done.swap(true, Ordering::Relaxed) {
+            self.meter.transfer_ended();
+        }
+    }
+}
+
+impl Drop for MeterHandle {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+impl ProgressHandle for MeterHandle {
+    fn set_position(&self, bytes: u64) {
+        let last = self.last.swap(bytes, Ordering::Relaxed);
+        if last != u64::MAX {
+            let delta = bytes.saturating_sub(last);
+            self.meter.bytes.fetch_add(delta, Ordering::Relaxed);
+        }
+        self.inner.set_position(bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        self.meter.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.inc(bytes);
+    }
+    fn set_message(&self, message: &str) {
+        self.inner.set_message(message);
+    }
+    fn println(&self, line: &str) {
+        self.inner.println(line);
+    }
+    fn finish(&self) {
+        self.end();
+        self.inner.finish();
+    }
+    fn fail(&self, reason: &str) {
+        self.end();
+        self.inner.fail(reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meters_transfers_and_ranks_backends() {
+        let meter = TransferMeter::new();
+        let sink = meter.sink(None);
+        let first = sink.start("SRR1_1.fastq.gz", ProgressKind::Transfer, 0);
+        let second = sink.start("SRR1_2.fastq.gz", ProgressKind::Transfer, 0);
+        sink.start("SRR1_1.fastq.gz", ProgressKind::Verify, 0)
+            .inc(999);
+        first.set_position(1_000);
+        first.set_position(4_000);
+        second.inc(500);
+        first.finish();
+        assert!(meter.busy.lock().unwrap().since.is_some());
+        drop(second);
+        let (bytes, busy) = meter.totals();
+        assert_eq!(bytes, 3_500);
+        assert_eq!(meter.totals().1, busy);
+
+        let bandwidth = Bandwidth::default();
+        bandwidth.record("ftp", 10_000_000, Duration::from_secs(10), true);
+        bandwidth.record("aws", 0, Duration::from_secs(60), false);
+        bandwidth.record("aws", 40_000_000, Duration::from_secs(10), true);
+        bandwidth.record("mock", 0, Duration::ZERO, true);
+        let report = bandwidth.report();
+        // The failed aws attempt's minute counts against it.
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].0, "ftp");
+        assert_eq!(report[1].0, "aws");
+        assert_eq!(report[1].1.runs, 1);
+        assert!((report[1].1.mb_per_s() - 40.0 / 70.0).abs() < 1e-9);
+    }
+}
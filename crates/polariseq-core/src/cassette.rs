@@ -0,0 +1,296 @@
+//! Recorded API responses (`--record-http` / `--replay-http`).
+//!
+//! While recording, every response that goes through [`crate::circuit::send`]
+//! (ENA portal, NCBI E-utilities, GEO, S3 HEADs) is buffered and appended to
+//! a JSON cassette. Replaying answers the same requests from the cassette
+//! without touching the network, so a metadata run can be repeated
+//! exactly, attached to a bug report, or tested offline. Ranged requests
+//! carry data, not metadata, and always go to the network.
+
+use anyhow::{Context, Result};
+use reqwest::header::{self, HeaderName, HeaderValue};
+use reqwest::{Method, Request, Response, ResponseBuilderExt, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// One request and the response it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Body, when it is UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Body, when it is not.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_bytes: Option<Vec<u8>>,
+}
+
+impl Interaction {
+    fn body(&self) -> Vec<u8> {
+        match (&self.body, &self.body_bytes) {
+            (Some(text), _) => text.clone().into_bytes(),
+            (None, Some(bytes)) => bytes.clone(),
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+/// Interactions in the order they were recorded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    /// Polariseq release that recorded the cassette.
+    #[serde(default)]
+    pub polariseq: String,
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("{} is not an HTTP cassette", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Serves a cassette's interactions in recorded order.
+#[derive(Debug, Default)]
+struct Player {
+    cassette: Cassette,
+    /// Responses served so far, by request.
+    served: HashMap<(String, String), usize>,
+}
+
+impl Player {
+    /// The next recorded answer to `method url`. A request asked more often
+    /// than it was recorded gets its last answer again.
+    fn next(&mut self, method: &str, url: &str) -> Option<&Interaction> {
+        let matches: Vec<usize> = self
+            .cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.method == method && i.url == url)
+            .map(|(index, _)| index)
+            .collect();
+        let served = self
+            .served
+            .entry((method.to_string(), url.to_string()))
+            .or_default();
+        let index = *matches.get(*served).or(matches.last())?;
+        *served += 1;
+        Some(&self.cassette.interactions[index])
+    }
+}
+
+enum Mode {
+    Off,
+    Record { path: PathBuf, cassette: Cassette },
+    Replay(Player),
+}
+
+static MODE: Mutex<Mode> = Mutex::new(Mode::Off);
+
+fn with_mode<T>(f: impl FnOnce(&mut Mode) -> T) -> T {
+    f(&mut MODE.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Record API responses to `path`, which is rewritten after each one.
+pub fn record(path: &Path) -> Result<()> {
+    let cassette = Cassette {
+        polariseq: env!("CARGO_PKG_VERSION").to_string(),
+        interactions: Vec::new(),
+    };
+    cassette.write(path)?;
+    info!("Recording HTTP responses to {}", path.display());
+    with_mode(|mode| {
+        *mode = Mode::Record {
+            path: path.to_path_buf(),
+            cassette,
+        }
+    });
+    Ok(())
+}
+
+/// Answer API requests from the cassette at `path`.
+pub fn replay(path: &Path) -> Result<()> {
+    let cassette = Cassette::read(path)?;
+    info!(
+        "Replaying {} HTTP response(s) from {} (recorded by polariseq {})",
+        cassette.interactions.len(),
+        path.display(),
+        cassette.polariseq
+    );
+    with_mode(|mode| {
+        *mode = Mode::Replay(Player {
+            cassette,
+            ..Player::default()
+        })
+    });
+    Ok(())
+}
+
+pub fn is_replaying() -> bool {
+    with_mode(|mode| matches!(mode, Mode::Replay(_)))
+}
+
+/// Whether a cassette covers `request`: ranged data transfers are left out.
+fn covers(request: &Request) -> bool {
+    !request.headers().contains_key(header::RANGE)
+}
+
+fn response(url: Url, interaction: &Interaction) -> Response {
+    let mut response = ::http::Response::builder()
+        .url(url)
+        .body(interaction.body())
+        .expect("a response without headers is valid");
+    *response.status_mut() =
+        StatusCode::from_u16(interaction.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    for (name, value) in &interaction.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response.into()
+}
+
+/// The recorded answer to `request` when replaying. A request missing from
+/// the cassette gets a 404 rather than going to the network.
+pub(crate) fn replayed(request: &Request) -> Option<Response> {
+    if !covers(request) {
+        return None;
+    }
+    let (method, url) = (request.method().as_str(), request.url().as_str());
+    with_mode(|mode| {
+        let Mode::Replay(player) = mode else {
+            return None;
+        };
+        Some(match player.next(method, url) {
+            Some(interaction) => response(request.url().clone(), interaction),
+            None => {
+                error!(
+                    "[Replay] {} {} is not in the cassette; record it again with --record-http",
+                    method, url
+                );
+                response(
+                    request.url().clone(),
+                    &Interaction {
+                        method: method.to_string(),
+                        url: url.to_string(),
+                        status: StatusCode::NOT_FOUND.as_u16(),
+                        headers: BTreeMap::new(),
+                        body: Some(format!("{} {} is not in the cassette\n", method, url)),
+                        body_bytes: None,
+                    },
+                )
+            }
+        })
+    })
+}
+
+/// Whether the response to `request` should be recorded.
+pub(crate) fn records(request: &Request) -> bool {
+    covers(request) && with_mode(|mode| matches!(mode, Mode::Record { .. }))
+}
+
+/// Buffer `response` to `method url`, add it to the cassette and hand back
+/// an equivalent response.
+pub(crate) async fn record_response(
+    method: Method,
+    url: Url,
+    response: Response,
+) -> reqwest::Result<Response> {
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            // The body is stored decoded and whole.
+            *name != header::CONTENT_ENCODING && *name != header::TRANSFER_ENCODING
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let bytes = response.bytes().await?.to_vec();
+    let (body, body_bytes) = match String::from_utf8(bytes) {
+        Ok(text) => (Some(text), None),
+        Err(e) => (None, Some(e.into_bytes())),
+    };
+    let interaction = Interaction {
+        method: method.to_string(),
+        url: url.to_string(),
+        status,
+        headers,
+        body,
+        body_bytes,
+    };
+    let replayed = self::response(url, &interaction);
+    with_mode(|mode| {
+        if let Mode::Record { path, cassette } = mode {
+            cassette.interactions.push(interaction);
+            if let Err(e) = cassette.write(path) {
+                warn!("{:#}", e);
+            }
+        }
+    });
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interaction(url: &str, body: &str) -> Interaction {
+        Interaction {
+            method: "GET".into(),
+            url: url.into(),
+            status: 200,
+            headers: BTreeMap::from([("content-length".into(), body.len().to_string())]),
+            body: Some(body.into()),
+            body_bytes: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_responses_in_recorded_order() {
+        let page = "https://www.ebi.ac.uk/ena/portal/api/filereport?accession=PRJNA1";
+        let mut player = Player {
+            cassette: Cassette {
+                polariseq: "1.4.2".into(),
+                interactions: vec![
+                    interaction(page, "first"),
+                    interaction("https://eutils.ncbi.nlm.nih.gov/x", "other"),
+                    interaction(page, "second"),
+                ],
+            },
+            ..Player::default()
+        };
+
+        let bodies: Vec<String> = (0..3)
+            .map(|_| player.next("GET", page).unwrap().body.clone().unwrap())
+            .collect();
+        assert_eq!(bodies, ["first", "second", "second"]);
+        assert!(player.next("HEAD", page).is_none());
+
+        let recorded = interaction(page, "first");
+        let response = response(Url::parse(page).unwrap(), &recorded);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.url().as_str(), page);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "5");
+        assert_eq!(response.text().await.unwrap(), "first");
+    }
+}
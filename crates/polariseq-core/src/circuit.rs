@@ -0,0 +1,218 @@
+//! Host-wide back-off for the HTTP APIs (ENA portal, NCBI E-utilities, S3).
+//!
+//! Every request sent through [`send`] first waits for its host's circuit.
+//! A 429, or a 503 with `Retry-After`, closes the host to all tasks for
+//! that long instead of letting each task retry on its own schedule.
+//! [`FAILURE_THRESHOLD`] server errors or connection failures in a row open
+//! the circuit for [`COOLDOWN`]; after that a single probe request goes
+//! through and the other tasks wait for its outcome. Responses are also
+//! recorded or replayed here when a [`crate::cassette`] is in use.
+
+use crate::cassette;
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Consecutive failures that open a host's circuit.
+pub const FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open circuit holds requests back.
+pub const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Pause after a 429 without `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Longest `Retry-After` honoured, so a bogus value cannot stall a job.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// A probe that has not reported back after this long is abandoned.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often waiting tasks check on a probe.
+const PROBE_POLL: Duration = Duration::from_millis(500);
+
+static HOSTS: LazyLock<Mutex<HashMap<String, Circuit>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// What a response says about its host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Healthy,
+    /// Rate limited: hold every request back for this long.
+    RetryAfter(Duration),
+    Failed,
+    /// Says nothing about the host (e.g. a 404).
+    Neutral,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+    probe_started: Option<Instant>,
+}
+
+impl Circuit {
+    /// How long a new request must wait, or `None` to send it now.
+    fn delay(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.open_until {
+            if until > now {
+                return Some(until - now);
+            }
+            // Cooldown over: this request is the probe.
+            self.open_until = None;
+            self.probe_started = Some(now);
+            return None;
+        }
+        match self.probe_started {
+            Some(started) if now.duration_since(started) < PROBE_TIMEOUT => Some(PROBE_POLL),
+            _ => None,
+        }
+    }
+
+    /// Update the circuit with a response; returns the pause it opened.
+    fn record(&mut self, outcome: Outcome, now: Instant) -> Option<Duration> {
+        let probing = self.probe_started.take().is_some();
+        match outcome {
+            Outcome::Healthy => {
+                self.failures = 0;
+                None
+            }
+            Outcome::RetryAfter(pause) => self.open(now, pause),
+            Outcome::Failed => {
+                self.failures += 1;
+                if probing || self.failures >= FAILURE_THRESHOLD {
+                    self.failures = 0;
+                    self.open(now, COOLDOWN)
+                } else {
+                    None
+                }
+            }
+            Outcome::Neutral => None,
+        }
+    }
+
+    fn open(&mut self, now: Instant, pause: Duration) -> Option<Duration> {
+        let until = now + pause.min(MAX_RETRY_AFTER);
+        // A longer pause already in force stands.
+        if self.open_until.is_some_and(|current| current >= until) {
+            return None;
+        }
+        self.open_until = Some(until);
+        Some(until - now)
+    }
+}
+
+/// Parse `Retry-After`: delta-seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+fn classify(result: &reqwest::Result<Response>) -> Outcome {
+    match result {
+        Ok(response) => match response.status() {
+            StatusCode::TOO_MANY_REQUESTS => {
+                Outcome::RetryAfter(retry_after(response).unwrap_or(DEFAULT_RETRY_AFTER))
+            }
+            StatusCode::SERVICE_UNAVAILABLE => match retry_after(response) {
+                Some(pause) => Outcome::RetryAfter(pause),
+                None => Outcome::Failed,
+            },
+            status if status.is_server_error() => Outcome::Failed,
+            status if status.is_success() || status.is_redirection() => Outcome::Healthy,
+            _ => Outcome::Neutral,
+        },
+        Err(e) if e.is_connect() || e.is_timeout() => Outcome::Failed,
+        Err(_) => Outcome::Neutral,
+    }
+}
+
+fn with_circuit<T>(host: &str, f: impl FnOnce(&mut Circuit) -> T) -> T {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    f(hosts.entry(host.to_string()).or_default())
+}
+
+/// Send `request` once its host's circuit lets it through, and feed the
+/// response back into the circuit. Retrying stays with the caller.
+pub async fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    if let Some(response) = cassette::replayed(&request) {
+        return Ok(response);
+    }
+    let recorded =
+        cassette::records(&request).then(|| (request.method().clone(), request.url().clone()));
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let mut warned = false;
+    while let Some(wait) = with_circuit(&host, |c| c.delay(Instant::now())) {
+        if !warned && wait > PROBE_POLL {
+            warn!(
+                "[Network] {} is backing off; holding requests for {}s",
+                host,
+                wait.as_secs().max(1)
+            );
+            warned = true;
+        }
+        tokio::time::sleep(wait).await;
+    }
+    let result = client.execute(request).await;
+    let outcome = classify(&result);
+    if let Some(pause) = with_circuit(&host, |c| c.record(outcome, Instant::now())) {
+        warn!(
+            "[Network] {} {}; pausing all requests to it for {}s",
+            host,
+            match outcome {
+                Outcome::RetryAfter(_) => "asked to retry later",
+                _ => "keeps failing",
+            },
+            pause.as_secs().max(1)
+        );
+    }
+    match (recorded, result) {
+        (Some((method, url)), Ok(response)) => {
+            cassette::record_response(method, url, response).await
+        }
+        (_, result) => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_on_retry_after_and_after_repeated_failures() {
+        let now = Instant::now();
+        let mut circuit = Circuit::default();
+        assert_eq!(circuit.delay(now), None);
+
+        let pause = Duration::from_secs(20);
+        assert_eq!(circuit.record(Outcome::RetryAfter(pause), now), Some(pause));
+        assert_eq!(
+            circuit.delay(now + Duration::from_secs(5)),
+            Some(Duration::from_secs(15))
+        );
+
+        // After the pause one probe goes out; the others wait on it.
+        let later = now + pause;
+        assert_eq!(circuit.delay(later), None);
+        assert_eq!(circuit.delay(later), Some(PROBE_POLL));
+        assert_eq!(circuit.record(Outcome::Healthy, later), None);
+        assert_eq!(circuit.delay(later), None);
+
+        for _ in 1..FAILURE_THRESHOLD {
+            assert_eq!(circuit.record(Outcome::Failed, later), None);
+        }
+        assert_eq!(circuit.record(Outcome::Failed, later), Some(COOLDOWN));
+        assert_eq!(circuit.delay(later), Some(COOLDOWN));
+    }
+}
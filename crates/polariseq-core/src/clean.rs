@@ -0,0 +1,123 @@
+//! Sweep of stale resume state (`polariseq clean`, end of `download`).
+//!
+//! Interrupted downloads leave `.meta.json` chunk maps, `.part` files,
+//! `prefetch` `.lock` files and empty run directories behind. A resumed job
+//! picks them up again, so only entries untouched for a number of days
+//! count as abandoned. The job's own `.polariseq.lock` is never removed.
+
+use crate::accession::{self, AccessionKind};
+use crate::lock::LOCK_FILE;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Age after which resume state counts as stale by default.
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 7;
+
+/// What a sweep removed, or would remove with `dry_run`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanReport {
+    pub removed: Vec<PathBuf>,
+    /// Bytes of the removed files.
+    pub bytes: u64,
+}
+
+/// `days` as a [`Duration`].
+pub fn days(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 3600)
+}
+
+fn is_resume_state(name: &str) -> bool {
+    name != LOCK_FILE
+        && [".meta.json", ".part", ".lock"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+fn is_stale(meta: &std::fs::Metadata, cutoff: SystemTime) -> bool {
+    meta.modified().is_ok_and(|modified| modified <= cutoff)
+}
+
+/// Remove resume state under `dir` not modified for `max_age`, then run
+/// directories (named like `SRR000001`) left empty. With `dry_run` nothing is
+/// removed, only reported.
+pub fn clean(dir: &Path, max_age: Duration, dry_run: bool) -> Result<CleanReport> {
+    let cutoff = SystemTime::now()
+        .checked_sub(max_age)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut report = CleanReport::default();
+    sweep(dir, cutoff, dry_run, &mut report)?;
+    Ok(report)
+}
+
+/// Sweep `dir`; returns how many entries it keeps.
+fn sweep(dir: &Path, cutoff: SystemTime, dry_run: bool, report: &mut CleanReport) -> Result<usize> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    let mut kept = 0;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let file_type = entry.file_type()?;
+        // A directory's time changes when its files go, so read it first.
+        let meta = entry.metadata()?;
+        if file_type.is_dir() {
+            let left = sweep(&path, cutoff, dry_run, report)?;
+            let run_dir = accession::kind(&name) == Some(AccessionKind::Run);
+            if left == 0 && run_dir && is_stale(&meta, cutoff) {
+                if !dry_run {
+                    std::fs::remove_dir(&path)
+                        .with_context(|| format!("Failed to remove {}", path.display()))?;
+                }
+                report.removed.push(path);
+                continue;
+            }
+        } else if file_type.is_file() && is_resume_state(&name) && is_stale(&meta, cutoff) {
+            if !dry_run {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+            report.bytes += meta.len();
+            report.removed.push(path);
+            continue;
+        }
+        kept += 1;
+    }
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweeps_stale_resume_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("SRR000001")).unwrap();
+        std::fs::write(root.join("SRR000001/SRR000001.sra.lock"), b"").unwrap();
+        std::fs::write(root.join("SRR000002.meta.json"), b"{}").unwrap();
+        std::fs::write(root.join("SRR000002.part"), b"12345").unwrap();
+        std::fs::write(root.join("SRR000003_1.fastq.gz"), b"data").unwrap();
+        std::fs::write(root.join(LOCK_FILE), b"").unwrap();
+        std::fs::create_dir_all(root.join("scripts")).unwrap();
+
+        let report = clean(root, days(DEFAULT_MAX_AGE_DAYS), false).unwrap();
+        assert!(report.removed.is_empty());
+
+        let report = clean(root, Duration::ZERO, true).unwrap();
+        assert_eq!(report.removed.len(), 4);
+        assert_eq!(report.bytes, 7);
+        assert!(root.join("SRR000002.part").exists());
+
+        clean(root, Duration::ZERO, false).unwrap();
+        assert!(!root.join("SRR000001").exists());
+        assert!(!root.join("SRR000002.part").exists());
+        assert!(root.join("SRR000003_1.fastq.gz").exists());
+        assert!(root.join(LOCK_FILE).exists());
+        assert!(root.join("scripts").exists());
+    }
+}
@@ -0,0 +1,883 @@
+//! High-level embedding API.
+//!
+//! [`PolariseqClient`] owns the configuration and optional UI hooks;
+//! [`DownloadJob`] describes one ENA project or run list to fetch. The
+//! `polariseq` CLI is a thin wrapper over this module, so other Rust tools
+//! can get the same behaviour without shelling out to the binary:
+//!
+//! ```no_run
+//! use polariseq_core::client::{DownloadJob, PolariseqClient};
+//! use polariseq_core::DownloadMethod;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = PolariseqClient::from_yaml("polariseq.yaml".as_ref())?;
+//! let job = DownloadJob::accession("PRJNA1251654", "data")
+//!     .method(DownloadMethod::Aws)
+//!     .pe_only(true);
+//! let plan = client.run(&job).await?;
+//! println!("downloaded {} run(s)", plan.processed.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::accession;
+use crate::alignments;
+use crate::autotune::AutoTune;
+use crate::aws_s3::{self, AwsStaging, PauseToken, S3Http, SraFormat, SraMetadata};
+#[cfg(feature = "aws")]
+use crate::backend::AwsBackend;
+#[cfg(feature = "ftp")]
+use crate::backend::FtpBackend;
+#[cfg(feature = "sra-tools")]
+use crate::backend::PrefetchBackend;
+use crate::backend::{
+    mock, run_backends, BackendContext, DownloadBackend, MockBackend, RunCancellation, RunHook,
+    RunItem,
+};
+use crate::bandwidth::Bandwidth;
+use crate::cassette;
+use crate::clean;
+use crate::connections::ConnectionLimit;
+use crate::controlled::{self, AccessCredential};
+use crate::doctor::{self, Check, CheckStatus};
+use crate::error::PolariseqError;
+use crate::long_read;
+use crate::metrics::Metrics;
+use crate::observer::DownloadObserver;
+use crate::overrides;
+use crate::prealloc::Preallocation;
+use crate::prescan;
+use crate::progress::ProgressSink;
+use crate::progress_store::{
+    new_progress_store, ProgressStore, RunProgress, RunStage, StageProgress,
+};
+use crate::route::{self, Route};
+use crate::speed::MinSpeed;
+use crate::unaligned;
+use crate::{
+    fetch_ena_data, load_config, process_records, read_tsv_data, resolve_secondary_accession,
+    save_md5_files, save_md5_manifest, save_metadata_tsv, validate_config, Config, DownloadMethod,
+    DownloadOptions, EnaRecord, GroupBy, OutputFormat, ProcessedRecord, RegexFilters, SortBy,
+};
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use indicatif::HumanBytes;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn, Instrument};
+
+/// One download request: where the runs come from and how to fetch them.
+///
+/// Wraps [`DownloadOptions`] (shared with the GUI) plus settings that are not
+/// serialisable, such as a controlled-access credential.
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub options: DownloadOptions,
+    pub credential: Option<AccessCredential>,
+}
+
+impl DownloadJob {
+    /// Download every run of an ENA/SRA project or run accession.
+    pub fn accession(accession: &str, output: impl Into<PathBuf>) -> Self {
+        Self::from(DownloadOptions {
+            accession: Some(accession.to_string()),
+            output: output.into(),
+            ..DownloadOptions::default()
+        })
+    }
+
+    /// Download the runs listed in an ENA-style TSV file.
+    pub fn tsv(path: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        Self::from(DownloadOptions {
+            tsv: Some(path.into()),
+            output: output.into(),
+            ..DownloadOptions::default()
+        })
+    }
+
+    pub fn method(mut self, method: DownloadMethod) -> Self {
+        self.options.download_method = method;
+        self
+    }
+
+    /// Concurrent runs, and concurrent range requests per run (AWS).
+    pub fn workers(mut self, file_workers: usize, chunk_workers: usize) -> Self {
+        self.options.multithreads = file_workers.max(1);
+        self.options.aws_threads = chunk_workers.max(1);
+        self
+    }
+
+    pub fn chunk_size_mb(mut self, chunk_size_mb: u64) -> Self {
+        self.options.chunk_size = chunk_size_mb.max(1);
+        self
+    }
+
+    pub fn pe_only(mut self, pe_only: bool) -> Self {
+        self.options.pe_only = pe_only;
+        self
+    }
+
+    pub fn cleanup_sra(mut self, cleanup_sra: bool) -> Self {
+        self.options.cleanup_sra = cleanup_sra;
+        self
+    }
+
+    pub fn sra_format(mut self, format: SraFormat) -> Self {
+        self.options.sra_format = format;
+        self
+    }
+
+    /// Regex filters on sample titles and run accessions.
+    pub fn filters(
+        mut self,
+        filter_sample: Vec<String>,
+        filter_run: Vec<String>,
+        exclude_sample: Vec<String>,
+        exclude_run: Vec<String>,
+    ) -> Self {
+        self.options.filter_sample = filter_sample;
+        self.options.filter_run = filter_run;
+        self.options.exclude_sample = exclude_sample;
+        self.options.exclude_run = exclude_run;
+        self
+    }
+
+    /// Per-run backend chains (`fastq=ftp`, `SRR*=aws,ftp`, ...), see
+    /// [`route`](crate::route).
+    pub fn routes(mut self, routes: Vec<String>) -> Self {
+        self.options.routes = routes;
+        self
+    }
+
+    /// Schedule runs per sample instead of per run, see [`GroupBy`].
+    pub fn group_by(mut self, group_by: GroupBy) -> Self {
+        self.options.group_by = group_by;
+        self
+    }
+
+    /// Move a run to its next backend when its transfers stay below the
+    /// floor, see [`crate::speed`].
+    pub fn min_speed(mut self, min_speed: Option<MinSpeed>) -> Self {
+        self.options.min_speed = min_speed;
+        self
+    }
+
+    /// Prefetch runs that start with `prefetch` in one batch, see
+    /// [`controlled::prefetch_batch`].
+    pub fn prefetch_batch(mut self, prefetch_batch: bool) -> Self {
+        self.options.prefetch_batch = prefetch_batch;
+        self
+    }
+
+    /// One `md5_manifest.tsv` instead of the R1/R2 MD5 tables, see
+    /// [`crate::save_md5_manifest`].
+    pub fn md5_manifest(mut self, md5_manifest: bool) -> Self {
+        self.options.md5_manifest = md5_manifest;
+        self
+    }
+
+    /// Order of the planned records and metadata files, see [`SortBy`].
+    pub fn sort_by(mut self, sort_by: SortBy) -> Self {
+        self.options.sort_by = sort_by;
+        self
+    }
+
+    /// HTTP version of AWS range requests, see [`S3Http`].
+    pub fn s3_http(mut self, s3_http: S3Http) -> Self {
+        self.options.s3_http = s3_http;
+        self
+    }
+
+    /// Where the AWS backend stages a run, see [`AwsStaging`].
+    pub fn aws_staging(mut self, aws_staging: AwsStaging) -> Self {
+        self.options.aws_staging = aws_staging;
+        self
+    }
+
+    /// Cap on simultaneous range requests across the job, `None` for no
+    /// cap, see [`ConnectionLimit`].
+    pub fn max_connections(mut self, max_connections: Option<usize>) -> Self {
+        self.options.max_connections = max_connections;
+        self
+    }
+
+    /// How the AWS backend preallocates `.part` files, see [`Preallocation`].
+    pub fn preallocate(mut self, preallocate: Preallocation) -> Self {
+        self.options.preallocate = preallocate;
+        self
+    }
+
+    /// `fasterq-dump --mem` of each conversion, in bytes.
+    pub fn fasterq_mem(mut self, bytes: Option<u64>) -> Self {
+        self.options.fasterq_mem = bytes;
+        self
+    }
+
+    /// Memory budget shared by the conversions in flight, see
+    /// [`crate::backend::fasterq_mem`].
+    pub fn max_memory(mut self, bytes: Option<u64>) -> Self {
+        self.options.max_memory = bytes;
+        self
+    }
+
+    /// Remove resume state older than `days` once the job ends, see
+    /// [`crate::clean`].
+    pub fn clean_stale_days(mut self, days: Option<u64>) -> Self {
+        self.options.clean_stale_days = days;
+        self
+    }
+
+    /// Download `bam_ftp` alignments instead of FASTQ where a run has them.
+    pub fn prefer_bam(mut self, prefer_bam: bool) -> Self {
+        self.options.prefer_bam = prefer_bam;
+        self
+    }
+
+    /// Write runs converted from SRA as unaligned BAM/CRAM, see
+    /// [`unaligned`](crate::unaligned).
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.options.output_format = format;
+        self
+    }
+
+    /// dbGaP credential used for runs without public files.
+    pub fn credential(mut self, credential: AccessCredential) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.options.output
+    }
+}
+
+impl From<DownloadOptions> for DownloadJob {
+    fn from(options: DownloadOptions) -> Self {
+        Self {
+            options,
+            credential: None,
+        }
+    }
+}
+
+/// What a job resolved to before anything is downloaded.
+#[derive(Debug, Clone, Default)]
+pub struct JobPlan {
+    /// Records ENA (or the TSV) returned, before filtering.
+    pub fetched: usize,
+    /// ENA records after regex filtering.
+    pub records: Vec<EnaRecord>,
+    /// Runs with public FASTQ files, ready for the selected backend.
+    pub processed: Vec<ProcessedRecord>,
+    /// Runs with no public files (possibly dbGaP-protected).
+    pub protected_runs: Vec<String>,
+    /// AWS copies resolved ahead of time, see [`crate::jobfile`].
+    pub resolved_sra: HashMap<String, SraMetadata>,
+}
+
+impl JobPlan {
+    /// True when the plan contains nothing the job can download.
+    pub fn is_empty(&self, job: &DownloadJob) -> bool {
+        self.processed.is_empty() && (job.credential.is_none() || self.protected_runs.is_empty())
+    }
+}
+
+/// Entry point for embedding Polariseq downloads.
+#[derive(Clone)]
+pub struct PolariseqClient {
+    config: Config,
+    progress: Option<Arc<dyn ProgressSink>>,
+    observer: Option<Arc<dyn DownloadObserver>>,
+    progress_store: ProgressStore,
+    backends: Vec<Arc<dyn DownloadBackend>>,
+    hooks: Vec<Arc<dyn RunHook>>,
+    metrics: Option<Arc<Metrics>>,
+    cancel: CancellationToken,
+    runs: RunCancellation,
+    pause: PauseToken,
+}
+
+impl PolariseqClient {
+    pub fn new(config: Config) -> Self {
+        if let Err(e) = crate::http::configure(&config.http) {
+            warn!("{:#}; the http section of the config is ignored", e);
+        }
+        Self {
+            config,
+            progress: None,
+            observer: None,
+            progress_store: new_progress_store(),
+            backends: Vec::new(),
+            hooks: Vec::new(),
+            metrics: None,
+            cancel: CancellationToken::new(),
+            runs: RunCancellation::default(),
+            pause: PauseToken::default(),
+        }
+    }
+
+    /// Load `polariseq.yaml` and build a client from it.
+    pub fn from_yaml(path: &Path) -> Result<Self> {
+        Ok(Self::new(load_config(path)?))
+    }
+
+    /// Report per-file progress to `progress` (bars, JSON lines, …).
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Receive download lifecycle events and live byte counters.
+    pub fn with_observer(mut self, observer: Arc<dyn DownloadObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Count bytes, runs and backend failures into `metrics` (e.g. for the
+    /// Prometheus endpoint).
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Share per-run stage progress (e.g. with the HTTP progress API).
+    pub fn with_progress_store(mut self, store: ProgressStore) -> Self {
+        self.progress_store = store;
+        self
+    }
+
+    /// Append a backend to the chain. Once any backend is added, the chain
+    /// replaces the one derived from the job's download method; runs fall
+    /// back along it in insertion order.
+    pub fn with_backend(mut self, backend: Arc<dyn DownloadBackend>) -> Self {
+        self.backends.push(backend);
+        self
+    }
+
+    /// Run `hook` after each downloaded and verified run, after the hooks
+    /// added before it.
+    pub fn with_hook(mut self, hook: Arc<dyn RunHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Stop jobs when `token` is cancelled (e.g. on Ctrl-C). Running tools
+    /// are killed and ranged downloads keep their `.meta.json`, so the same
+    /// job resumes where it stopped.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Token that cancels every job of this client.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Pause, resume and throttle jobs through `token` (e.g. from the GUI or
+    /// the control socket).
+    pub fn with_pause_token(mut self, token: PauseToken) -> Self {
+        self.pause = token;
+        self
+    }
+
+    /// Token that pauses and throttles every job of this client.
+    pub fn pause_token(&self) -> PauseToken {
+        self.pause.clone()
+    }
+
+    /// Cancel a single pending or running run; false if it is not scheduled.
+    pub fn cancel_run(&self, run_id: &str) -> bool {
+        self.runs.cancel(run_id)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn progress_store(&self) -> ProgressStore {
+        self.progress_store.clone()
+    }
+
+    /// Fetch metadata, apply filters and split runs into public and
+    /// protected. Nothing is written or downloaded.
+    pub async fn plan(&self, job: &DownloadJob) -> Result<JobPlan> {
+        let options = &job.options;
+        // Reject bad patterns before any network round-trip.
+        RegexFilters::new(options)?;
+
+        let records = if let Some(accession) = &options.accession {
+            fetch_accession(accession).await?
+        } else if let Some(tsv_path) = &options.tsv {
+            read_tsv_data(tsv_path)?
+        } else {
+            return Err(PolariseqError::InvalidInput(
+                "Either --accession or --tsv must be provided".to_string(),
+            )
+            .into());
+        };
+        self.plan_records(job, records)
+    }
+
+    /// [`PolariseqClient::plan`] for records the caller already fetched
+    /// (e.g. runs of a GEO series' BioProject).
+    pub fn plan_records(&self, job: &DownloadJob, records: Vec<EnaRecord>) -> Result<JobPlan> {
+        let options = &job.options;
+        let fetched = records.len();
+        info!("Total records fetched: {}", fetched);
+
+        let mut records = RegexFilters::new(options)?.apply(records);
+        overrides::apply(&mut records)?;
+        options.sort_by.sort(&mut records);
+        info!("Records after filtering: {}", records.len());
+
+        let protected_runs = records
+            .iter()
+            .filter(|r| controlled::is_protected(r))
+            .map(|r| r.run_accession.clone())
+            .collect();
+        let mut processed = process_records(records.clone(), options.pe_only, None)?;
+        if options.prefer_bam {
+            let switched = alignments::prefer_bam(&mut records, &mut processed);
+            info!("Runs switched to BAM alignments: {}", switched);
+        }
+        if options.download_method == DownloadMethod::Mock {
+            mock::apply_checksums(&mut processed);
+        }
+
+        Ok(JobPlan {
+            fetched,
+            records,
+            processed,
+            protected_runs,
+            resolved_sra: HashMap::new(),
+        })
+    }
+
+    /// Write `ena_metadata*.tsv` and the R1/R2 MD5 tables, or with
+    /// `md5_manifest` the combined manifest, for a plan.
+    pub fn write_metadata(&self, job: &DownloadJob, plan: &JobPlan) -> Result<()> {
+        let accession = job.options.accession.as_deref();
+        save_metadata_tsv(&plan.records, job.output(), accession)?;
+        if job.options.md5_manifest {
+            save_md5_manifest(&plan.records, &plan.processed, job.output(), accession)?;
+            return Ok(());
+        }
+        save_md5_files(&plan.processed, job.output(), accession)
+    }
+
+    /// Backend chain for `job`: the configured backends, or the job's
+    /// download method followed by `prefetch` when a credential is set and
+    /// then any other backend its routes name. Backends named only in a
+    /// TSV's `preferred_backend` column are added by [`Self::execute`].
+    pub fn backends_for(&self, job: &DownloadJob) -> Result<Vec<Arc<dyn DownloadBackend>>> {
+        if !self.backends.is_empty() {
+            return Ok(self.backends.clone());
+        }
+        let primary: Result<Arc<dyn DownloadBackend>> = match job.options.download_method {
+            #[cfg(feature = "aws")]
+            DownloadMethod::Aws => Ok(Arc::new(AwsBackend)),
+            #[cfg(feature = "ftp")]
+            DownloadMethod::Ftp => Ok(Arc::new(FtpBackend)),
+            DownloadMethod::Mock => Ok(Arc::new(MockBackend)),
+            DownloadMethod::Ega => Err(anyhow!(
+                "EGA jobs use ega::EgaDownloader; ENA run lists cannot be fetched from EGA"
+            )),
+            #[allow(unreachable_patterns)]
+            method => {
+                let feature = if method == DownloadMethod::Aws {
+                    "aws"
+                } else {
+                    "ftp"
+                };
+                Err(missing_feature(&format!("{:?} downloads", method), feature))
+            }
+        };
+        #[allow(unused_mut)]
+        let mut backends = vec![primary?];
+        if let Some(credential) = &job.credential {
+            #[cfg(feature = "sra-tools")]
+            backends.push(Arc::new(PrefetchBackend::new(Some(credential.clone()))));
+            #[cfg(not(feature = "sra-tools"))]
+            {
+                let _ = credential;
+                return Err(missing_feature("Controlled-access runs", "sra-tools"));
+            }
+        }
+        add_routed_backends(&mut backends, &route::parse_routes(&job.options.routes)?)?;
+        Ok(backends)
+    }
+
+    /// Download everything in `plan` through the backend chain. Protected
+    /// runs are only queued when the job carries a credential.
+    pub async fn execute(&self, job: &DownloadJob, plan: &JobPlan) -> Result<()> {
+        let mut routes = overrides::routes(&plan.records)?;
+        routes.extend(route::parse_routes(&job.options.routes)?);
+        let mut backends = self.backends_for(job)?;
+        if self.backends.is_empty() {
+            add_routed_backends(&mut backends, &routes)?;
+        }
+
+        let output_subdirs = overrides::output_subdirs(&plan.records);
+        let mut by_output: BTreeMap<PathBuf, Vec<ProcessedRecord>> = BTreeMap::new();
+        for record in &plan.processed {
+            let output =
+                overrides::output_for(job.output(), &output_subdirs, &record.run_accession);
+            by_output.entry(output).or_default().push(record.clone());
+        }
+        let mut verified = HashSet::new();
+        for (output, records) in &by_output {
+            std::fs::create_dir_all(output)?;
+            verified.extend(prescan::verified_runs(output, records).await);
+        }
+        if !verified.is_empty() {
+            let mut map = self.progress_store.write().await;
+            for run_id in &verified {
+                info!("[{}] Already downloaded and verified; skipping", run_id);
+                map.insert(
+                    run_id.clone(),
+                    RunProgress {
+                        run_id: run_id.clone(),
+                        stage: RunStage::Skipped,
+                        overall_percent: 100.0,
+                        download: StageProgress::new(0.0),
+                        extraction: StageProgress::new(0.0),
+                        compression: StageProgress::new(0.0),
+                    },
+                );
+            }
+        }
+        let mut items: Vec<RunItem> = plan
+            .processed
+            .iter()
+            .filter(|r| !verified.contains(&r.run_accession))
+            .cloned()
+            .map(RunItem::Public)
+            .collect();
+        if job.credential.is_some() {
+            items.extend(plan.protected_runs.iter().cloned().map(RunItem::Protected));
+        }
+        overrides::sort_by_priority(&mut items, &plan.records);
+        let run_groups = match job.options.group_by {
+            GroupBy::Run => HashMap::new(),
+            GroupBy::Sample => plan
+                .records
+                .iter()
+                .filter_map(|r| {
+                    let sample = r
+                        .sample_accession
+                        .as_ref()
+                        .or(r.secondary_sample_accession.as_ref())
+                        .filter(|s| !s.is_empty())?;
+                    Some((r.run_accession.clone(), sample.clone()))
+                })
+                .collect(),
+        };
+        let bandwidth = Bandwidth::new();
+        let ctx = BackendContext {
+            config: self.config.clone(),
+            options: job.options.clone(),
+            progress: match &self.metrics {
+                Some(metrics) => Some(metrics.sink(self.progress.clone())),
+                None => self.progress.clone(),
+            },
+            observer: self.observer.clone(),
+            progress_store: self.progress_store.clone(),
+            cancel: self.cancel.clone(),
+            runs: self.runs.clone(),
+            pause: self.pause.clone(),
+            hooks: self.hooks.clone(),
+            routes,
+            output_subdirs,
+            run_groups,
+            long_reads: plan
+                .records
+                .iter()
+                .filter(|r| long_read::is_long_read(r))
+                .map(|r| r.run_accession.clone())
+                .collect(),
+            sra_md5: plan
+                .records
+                .iter()
+                .filter_map(|r| Some((r.run_accession.clone(), aws_s3::ena_sra_md5(r)?)))
+                .collect(),
+            resolved_sra: plan.resolved_sra.clone(),
+            metrics: self.metrics.clone(),
+            bandwidth: Some(bandwidth.clone()),
+            connections: job.options.max_connections.map(ConnectionLimit::new),
+            auto_tune: job.options.auto_tune.then(|| AutoTune::new(&job.options)),
+            backend: None,
+            started: None,
+        };
+        let result = run_backends(items, backends, ctx).await;
+        bandwidth.log();
+        if let Some(days) = job.options.clean_stale_days {
+            match clean::clean(job.output(), clean::days(days), false) {
+                Ok(report) if !report.removed.is_empty() => info!(
+                    "Removed {} stale resume file(s) older than {} days, reclaimed {}",
+                    report.removed.len(),
+                    days,
+                    HumanBytes(report.bytes)
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("Sweeping stale resume files failed: {:#}", e),
+            }
+        }
+        result
+    }
+
+    /// Check the external tools `job` will run, before any metadata is
+    /// fetched. Custom backend chains and dry runs are not checked.
+    pub fn preflight(&self, job: &DownloadJob) -> Result<()> {
+        if !self.backends.is_empty() || job.options.dry_run {
+            return Ok(());
+        }
+        validate_config(&self.config, job.options.download_method)?;
+        if job.credential.is_some() {
+            controlled::validate_tools(&self.config)?;
+        }
+        unaligned::validate(&self.config, job.options.output_format)?;
+        self.config.priority.validate_tools()?;
+        if !job.options.routes.is_empty() {
+            // Also rejects unknown backend names in the routes.
+            for backend in self.backends_for(job)? {
+                match backend.name() {
+                    "aws" => validate_config(&self.config, DownloadMethod::Aws)?,
+                    "ftp" => validate_config(&self.config, DownloadMethod::Ftp)?,
+                    _ => controlled::validate_tools(&self.config)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Probe only the endpoints `job` uses: the ENA portal (and E-utilities
+    /// for a GSE) when it looks up an accession, and those of each backend
+    /// in its chain. Fails with a hint when the lookup or every backend is
+    /// unreachable; a backend that is down next to a working one warns.
+    /// A replayed cassette stands in for the lookup.
+    pub async fn network_preflight(&self, job: &DownloadJob) -> Result<()> {
+        if let Some(accession) = job
+            .options
+            .accession
+            .as_ref()
+            .filter(|_| !cassette::is_replaying())
+        {
+            let mut endpoints = vec![doctor::ENA_PORTAL];
+            if accession
+                .split(',')
+                .any(|a| accession::kind(a.trim()) == Some(accession::AccessionKind::GeoSeries))
+            {
+                endpoints.push(doctor::NCBI_EUTILS);
+            }
+            let checks = doctor::check_endpoints(&endpoints).await;
+            if let Some(failures) = network_failures(&checks) {
+                return Err(PolariseqError::Network(failures).into());
+            }
+        }
+        if job.options.dry_run || !self.backends.is_empty() {
+            return Ok(());
+        }
+
+        let mut down = Vec::new();
+        let mut probed = 0;
+        for backend in self.backends_for(job)? {
+            let endpoints =
+                doctor::backend_endpoints(backend.name(), self.config.prefetch.transport);
+            if endpoints.is_empty() {
+                continue;
+            }
+            probed += 1;
+            let checks = doctor::check_endpoints(&endpoints).await;
+            for check in checks.iter().filter(|c| c.status == CheckStatus::Warn) {
+                warn!(
+                    "{}: {}; {}",
+                    check.name,
+                    check.detail,
+                    check.fix.as_deref().unwrap_or("")
+                );
+            }
+            if let Some(failures) = network_failures(&checks) {
+                down.push((backend.name(), failures));
+            }
+        }
+        if probed > 0 && down.len() == probed {
+            let failures: Vec<String> = down.into_iter().map(|(_, f)| f).collect();
+            return Err(PolariseqError::Network(failures.join("\n")).into());
+        }
+        for (backend, failures) in down {
+            warn!(
+                "{} backend unreachable, other backends will serve its runs:\n{}",
+                backend, failures
+            );
+        }
+        Ok(())
+    }
+
+    /// Preflight, plan, write metadata and execute in one call.
+    pub async fn run(&self, job: &DownloadJob) -> Result<JobPlan> {
+        self.preflight(job)?;
+        let plan = self.plan(job).await?;
+        if plan.records.is_empty() {
+            return Ok(plan);
+        }
+        self.write_metadata(job, &plan)?;
+        if !job.options.dry_run && !plan.is_empty(job) {
+            self.execute(job, &plan).await?;
+        }
+        Ok(plan)
+    }
+}
+
+/// Failed checks as `name unreachable: detail` lines with their fixes.
+fn network_failures(checks: &[Check]) -> Option<String> {
+    let lines: Vec<String> = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .map(|c| {
+            format!(
+                "{} unreachable: {}\n  hint: {}",
+                c.name,
+                c.detail,
+                c.fix.as_deref().unwrap_or_default()
+            )
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Metadata requests in flight for a multi-accession job.
+pub const METADATA_CONCURRENCY: usize = 4;
+
+/// All runs of one or more comma-separated project accessions at ENA,
+/// fetched [`METADATA_CONCURRENCY`] at a time. A run listed under several
+/// accessions appears once, in the order of the first.
+pub async fn fetch_accession(accessions: &str) -> Result<Vec<EnaRecord>> {
+    let parts: Vec<&str> = accessions
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
+    if parts.len() <= 1 {
+        return fetch_one_accession(accessions).await;
+    }
+    let fetched: Vec<Vec<EnaRecord>> = stream::iter(parts)
+        .map(fetch_one_accession)
+        .buffered(METADATA_CONCURRENCY)
+        .try_collect()
+        .await?;
+    let mut seen = HashSet::new();
+    Ok(fetched
+        .into_iter()
+        .flatten()
+        .filter(|r| seen.insert(r.run_accession.clone()))
+        .collect())
+}
+
+/// Runs of one accession. SRP/SRS-style accessions are looked up by their
+/// primary one; no runs at all is an error.
+async fn fetch_one_accession(accession: &str) -> Result<Vec<EnaRecord>> {
+    let (accession, _) = accession::parse(accession)?;
+    let query = match resolve_secondary_accession(&accession).await {
+        Ok(Some(primary)) => {
+            info!("{} is {} at ENA", accession, primary);
+            primary
+        }
+        Ok(None) => accession.clone(),
+        Err(e) => {
+            warn!("{:#}; querying {} directly", e, accession);
+            accession.clone()
+        }
+    };
+    let records = fetch_ena_data(&query)
+        .instrument(info_span!("metadata", accession = accession.as_str()))
+        .await?;
+    if records.is_empty() {
+        return Err(PolariseqError::Metadata(format!(
+            "ENA returned no runs for {}: {}",
+            accession,
+            accession::no_records_hint(&accession)
+        ))
+        .into());
+    }
+    Ok(records)
+}
+
+/// Append the built-in backends `routes` name that `backends` lacks.
+fn add_routed_backends(
+    backends: &mut Vec<Arc<dyn DownloadBackend>>,
+    routes: &[Route],
+) -> Result<()> {
+    for route in routes {
+        for name in &route.backends {
+            if !backends.iter().any(|b| b.name() == name) {
+                backends.push(routed_backend(name)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Built-in backend named by a `--route` rule. A `prefetch` added this way
+/// fetches public runs only; protected runs need the job's credential.
+fn routed_backend(name: &str) -> Result<Arc<dyn DownloadBackend>> {
+    match name {
+        #[cfg(feature = "aws")]
+        "aws" => Ok(Arc::new(AwsBackend)),
+        #[cfg(feature = "ftp")]
+        "ftp" => Ok(Arc::new(FtpBackend)),
+        #[cfg(feature = "sra-tools")]
+        "prefetch" => Ok(Arc::new(PrefetchBackend::new(None))),
+        #[allow(unreachable_patterns)]
+        "aws" | "ftp" => Err(missing_feature(&format!("{} routes", name), name)),
+        #[allow(unreachable_patterns)]
+        "prefetch" => Err(missing_feature("prefetch routes", "sra-tools")),
+        other => Err(PolariseqError::InvalidInput(format!(
+            "Unknown backend '{}' in a route (expected aws, ftp or prefetch)",
+            other
+        ))
+        .into()),
+    }
+}
+
+/// Error for a backend compiled out of this build.
+fn missing_feature(what: &str, feature: &str) -> anyhow::Error {
+    PolariseqError::Config(format!(
+        "{} need polariseq built with the `{}` cargo feature",
+        what, feature
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_builder_fills_download_options() {
+        let job = DownloadJob::accession("PRJNA1", "out")
+            .method(DownloadMethod::Ftp)
+            .workers(0, 16)
+            .chunk_size_mb(50)
+            .pe_only(true);
+        assert_eq!(job.options.accession.as_deref(), Some("PRJNA1"));
+        assert_eq!(job.output(), Path::new("out"));
+        assert_eq!(job.options.download_method, DownloadMethod::Ftp);
+        assert_eq!((job.options.multithreads, job.options.aws_threads), (1, 16));
+        assert_eq!(job.options.chunk_size, 50);
+        assert!(job.options.pe_only);
+        assert!(job.credential.is_none());
+    }
+
+    #[test]
+    fn empty_plan_without_public_or_authorized_runs() {
+        let job = DownloadJob::tsv("runs.tsv", "out");
+        let plan = JobPlan {
+            protected_runs: vec!["SRR1".into()],
+            ..JobPlan::default()
+        };
+        assert!(plan.is_empty(&job));
+    }
+}
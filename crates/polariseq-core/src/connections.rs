@@ -0,0 +1,116 @@
+//! Job-wide cap on simultaneous range requests (`--max-connections`).
+//!
+//! Every AWS chunk worker holds a connection while its request runs, so
+//! `-p 8 -t 16` would open 128 connections to the same host, enough for
+//! EBI/NCBI to rate-limit or block the client. [`ConnectionLimit`] shares
+//! one pool of slots between all files of a job. When the server answers
+//! with 429 or 503 anyway, [`ConnectionLimit::saturated`] lowers the cap by
+//! a quarter, at most once per [`SATURATION_HOLD`]; it is not raised again
+//! during the job.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Default of `--max-connections`.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// Lowest cap a saturated server can push the job down to.
+const MIN_CONNECTIONS: usize = 2;
+
+/// Time after a cut in which further 429/503 answers do not cut again, so
+/// the requests already in flight when the server pushed back count once.
+pub const SATURATION_HOLD: Duration = Duration::from_secs(60);
+
+/// Slots for the range requests of a job, shared through an `Arc`.
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    slots: Arc<Semaphore>,
+    cap: AtomicUsize,
+    last_cut: Mutex<Option<Instant>>,
+}
+
+impl ConnectionLimit {
+    pub fn new(cap: usize) -> Arc<Self> {
+        let cap = cap.max(1);
+        Arc::new(Self {
+            slots: Arc::new(Semaphore::new(cap)),
+            cap: AtomicUsize::new(cap),
+            last_cut: Mutex::new(None),
+        })
+    }
+
+    /// Wait for a free slot; the request holds it until the permit drops.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("connection semaphore closed")
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap.load(Ordering::Relaxed)
+    }
+
+    /// The server answered 429 or 503: lower the cap, see the module docs.
+    pub fn saturated(&self) {
+        let mut last_cut = self.last_cut.lock().unwrap();
+        let now = Instant::now();
+        if last_cut.is_some_and(|at| now.duration_since(at) < SATURATION_HOLD) {
+            return;
+        }
+        let cap = self.cap();
+        let cut = (cap / 4).max(1).min(cap.saturating_sub(MIN_CONNECTIONS));
+        if cut == 0 {
+            return;
+        }
+        *last_cut = Some(now);
+        // Slots in use are taken out of the pool as they come back.
+        let forgotten = self.slots.forget_permits(cut);
+        if forgotten < cut {
+            let slots = self.slots.clone();
+            tokio::spawn(async move {
+                if let Ok(permits) = slots.acquire_many_owned((cut - forgotten) as u32).await {
+                    permits.forget();
+                }
+            });
+        }
+        self.cap.store(cap - cut, Ordering::Relaxed);
+        warn!(
+            "Server is rate-limiting range requests; lowering the connection cap from {} to {}",
+            cap,
+            cap - cut
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shares_slots_and_backs_off_once_per_hold() {
+        let limit = ConnectionLimit::new(8);
+        let held = limit.acquire().await;
+        assert_eq!(limit.slots.available_permits(), 7);
+
+        limit.saturated();
+        assert_eq!(limit.cap(), 6);
+        assert_eq!(limit.slots.available_permits(), 5);
+        limit.saturated();
+        assert_eq!(limit.cap(), 6);
+
+        drop(held);
+        assert_eq!(limit.slots.available_permits(), 6);
+
+        let small = ConnectionLimit::new(3);
+        small.saturated();
+        assert_eq!(small.cap(), MIN_CONNECTIONS);
+        *small.last_cut.lock().unwrap() = None;
+        small.saturated();
+        assert_eq!(small.cap(), MIN_CONNECTIONS);
+    }
+}
@@ -0,0 +1,252 @@
+//! Runtime control of a running job (`download --control`).
+//!
+//! The job listens on a Unix socket, `<output>/polariseq.sock` by default.
+//! Each line sent is one [`ControlCommand`] and gets one reply line
+//! starting with `ok` or `error`:
+//!
+//! ```text
+//! pause | resume | bandwidth <RATE|off> | cancel <RUN> | status
+//! ```
+//!
+//! Pausing holds back new runs and in-process transfers and stops running
+//! `wget` processes; the bandwidth limit applies to AWS ranged downloads
+//! and to `wget` transfers started after it is set.
+
+use crate::client::PolariseqClient;
+use crate::error::PolariseqError;
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+pub const CONTROL_SOCKET: &str = "polariseq.sock";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    /// Job-wide limit in bytes/s; `None` lifts it.
+    Bandwidth(Option<u64>),
+    /// Cancel one pending or running run.
+    Cancel(String),
+    Status,
+}
+
+/// Parse `500k`, `50M`, `1.5G` (powers of 1024, like `wget --limit-rate`)
+/// or plain bytes per second.
+pub fn parse_rate(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '/');
+    let unit = text[digits.len()..].to_ascii_lowercase();
+    let factor: f64 = match unit.trim_end_matches("/s").trim_end_matches('b') {
+        "" => 1.0,
+        "k" => 1024.0,
+        "m" => 1024.0 * 1024.0,
+        "g" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(anyhow!("unknown rate unit '{}'", unit)),
+    };
+    let value: f64 = digits
+        .parse()
+        .map_err(|_| anyhow!("invalid rate '{}'", text))?;
+    if value.is_nan() || value <= 0.0 {
+        return Err(anyhow!("rate must be positive"));
+    }
+    Ok((value * factor) as u64)
+}
+
+impl FromStr for ControlCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default().to_ascii_lowercase();
+        let argument = words.next();
+        Ok(match (command.as_str(), argument) {
+            ("pause", None) => Self::Pause,
+            ("resume", None) => Self::Resume,
+            ("status", None) => Self::Status,
+            ("bandwidth", Some("off" | "unlimited")) => Self::Bandwidth(None),
+            ("bandwidth", Some(rate)) => Self::Bandwidth(Some(parse_rate(rate)?)),
+            ("cancel", Some(run)) => Self::Cancel(run.to_string()),
+            _ => {
+                return Err(PolariseqError::InvalidInput(format!(
+                    "Unknown control command '{}': expected pause, resume, bandwidth <RATE|off>, cancel <RUN> or status",
+                    line.trim()
+                ))
+                .into())
+            }
+        })
+    }
+}
+
+/// Applies [`ControlCommand`]s to the jobs of one client.
+#[derive(Clone)]
+pub struct Controller {
+    client: PolariseqClient,
+}
+
+impl Controller {
+    pub fn new(client: &PolariseqClient) -> Self {
+        Self {
+            client: client.clone(),
+        }
+    }
+
+    /// Apply `command` and return the reply line (without `ok `).
+    pub fn apply(&self, command: &ControlCommand) -> Result<String> {
+        let pause = self.client.pause_token();
+        match command {
+            ControlCommand::Pause => {
+                pause.pause();
+                tracing::info!("Paused through the control socket");
+                Ok("paused".into())
+            }
+            ControlCommand::Resume => {
+                pause.resume();
+                tracing::info!("Resumed through the control socket");
+                Ok("resumed".into())
+            }
+            ControlCommand::Bandwidth(limit) => {
+                pause.set_bandwidth(*limit);
+                let text = format_rate(*limit);
+                tracing::info!("Bandwidth limit set to {} through the control socket", text);
+                Ok(format!("bandwidth {}", text))
+            }
+            ControlCommand::Cancel(run) => {
+                if self.client.cancel_run(run) {
+                    tracing::warn!("[{}] Cancelled through the control socket", run);
+                    Ok(format!("cancelled {}", run))
+                } else {
+                    Err(anyhow!("{} is not pending or running", run))
+                }
+            }
+            ControlCommand::Status => Ok(format!(
+                "{} bandwidth {}",
+                if pause.is_paused() {
+                    "paused"
+                } else {
+                    "running"
+                },
+                format_rate(pause.bandwidth())
+            )),
+        }
+    }
+
+    /// Reply line for one received line.
+    fn handle(&self, line: &str) -> String {
+        match line.parse().and_then(|command| self.apply(&command)) {
+            Ok(reply) => format!("ok {}", reply),
+            Err(e) => format!("error {:#}", e),
+        }
+    }
+}
+
+fn format_rate(limit: Option<u64>) -> String {
+    match limit {
+        Some(bytes) => format!("{}/s", indicatif::HumanBytes(bytes)),
+        None => "unlimited".into(),
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::Controller;
+    use anyhow::{Context, Result};
+    use std::path::Path;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio_util::sync::CancellationToken;
+
+    /// Serve `controller` on the Unix socket `path` until `stop` fires.
+    pub async fn serve(path: &Path, controller: Controller, stop: CancellationToken) -> Result<()> {
+        // A socket left behind by a killed job would block the bind.
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        tracing::info!("Control socket: {}", path.display());
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => accepted?.0,
+                _ = stop.cancelled() => break,
+            };
+            let controller = controller.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply = controller.handle(&line);
+                    if write
+                        .write_all(format!("{}\n", reply).as_bytes())
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    /// Send one command line to the job listening on `path` and return its
+    /// reply.
+    pub async fn send(path: &Path, line: &str) -> Result<String> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("No job is listening on {}", path.display()))?;
+        let (read, mut write) = stream.into_split();
+        write.write_all(format!("{}\n", line).as_bytes()).await?;
+        let reply = BufReader::new(read)
+            .lines()
+            .next_line()
+            .await?
+            .unwrap_or_default();
+        Ok(reply)
+    }
+}
+
+#[cfg(unix)]
+pub use socket::{send, serve};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, SoftwarePaths};
+
+    #[test]
+    fn parses_and_applies_commands() {
+        assert_eq!(parse_rate("500k").unwrap(), 512_000);
+        assert_eq!(parse_rate("1.5M").unwrap(), 1_572_864);
+        assert_eq!(parse_rate("2MB/s").unwrap(), 2_097_152);
+        assert!(parse_rate("10x").is_err());
+        assert_eq!(
+            "cancel SRR1".parse::<ControlCommand>().unwrap(),
+            ControlCommand::Cancel("SRR1".into())
+        );
+        assert!("jump".parse::<ControlCommand>().is_err());
+
+        let client = PolariseqClient::new(Config {
+            software: SoftwarePaths {
+                prefetch: "prefetch".into(),
+                fasterq_dump: "fasterq-dump".into(),
+                blastdbcmd: None,
+                samtools: None,
+            },
+            public_data: Default::default(),
+            ega: None,
+            http: Default::default(),
+            prefetch: Default::default(),
+            priority: Default::default(),
+            profiles: Default::default(),
+        });
+        let controller = Controller::new(&client);
+        assert_eq!(controller.handle("pause"), "ok paused");
+        assert!(client.pause_token().is_paused());
+        assert_eq!(controller.handle("bandwidth 1M"), "ok bandwidth 1.00 MiB/s");
+        assert_eq!(client.pause_token().bandwidth(), Some(1 << 20));
+        assert_eq!(
+            controller.handle("status"),
+            "ok paused bandwidth 1.00 MiB/s"
+        );
+        assert!(controller.handle("cancel SRR9").starts_with("error"));
+    }
+}
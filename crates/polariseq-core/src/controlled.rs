@@ -0,0 +1,523 @@
+//! Controlled-access (dbGaP) downloads through the SRA Toolkit.
+//!
+//! Protected runs are never mirrored to ENA or to the public AWS bucket, so
+//! the only way to fetch them is `prefetch` with a dbGaP repository key
+//! (`.ngc`) or a JWT cart. The credential is passed unchanged to both
+//! `prefetch` and `fasterq-dump`; the rest of the pipeline (conversion,
+//! native gzip, optional `.sra` cleanup) matches the AWS path.
+
+use crate::{Config, EnaRecord};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "sra-tools")]
+use {
+    crate::error::PolariseqError,
+    crate::progress::{start_progress, ProgressHandle, ProgressKind, ProgressSink},
+    crate::{run_cancellable, OutputFormat},
+    anyhow::Context,
+    std::process::Stdio,
+    std::sync::Arc,
+    std::time::Duration,
+    tokio::process::Command,
+    tokio_util::sync::CancellationToken,
+    tracing::{error, info, warn},
+};
+
+/// `prefetch --max-size` used for protected runs; dbGaP runs routinely
+/// exceed the SRA Toolkit default of 20G.
+#[cfg(feature = "sra-tools")]
+const PREFETCH_MAX_SIZE: &str = "100G";
+
+/// `prefetch --transport`: many sites only allow HTTPS out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PrefetchTransport {
+    Https,
+    /// Aspera, where `ascp` is installed.
+    Fasp,
+    Both,
+}
+
+impl PrefetchTransport {
+    fn as_arg(self) -> &'static str {
+        match self {
+            Self::Https => "https",
+            Self::Fasp => "fasp",
+            Self::Both => "both",
+        }
+    }
+}
+
+/// `prefetch:` section of `polariseq.yaml`; unset options keep the SRA
+/// Toolkit's defaults. The `download` flags of the same names override it.
+///
+/// ```yaml
+/// prefetch:
+///   transport: https
+///   resume: true
+///   eliminate_quals: false
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefetchConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport: Option<PrefetchTransport>,
+    /// `--resume yes|no`: continue partial downloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume: Option<bool>,
+    /// `--eliminate-quals`: fetch SRA Lite without quality scores.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub eliminate_quals: bool,
+}
+
+impl PrefetchConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// `prefetch` options for these settings, limited to `max_size` and
+    /// verified after download.
+    pub fn args(&self, max_size: &str) -> Vec<String> {
+        let mut args = vec![
+            "--max-size".to_string(),
+            max_size.to_string(),
+            "--verify".to_string(),
+            "yes".to_string(),
+        ];
+        if let Some(transport) = self.transport {
+            args.extend(["--transport".to_string(), transport.as_arg().to_string()]);
+        }
+        if let Some(resume) = self.resume {
+            let resume = if resume { "yes" } else { "no" };
+            args.extend(["--resume".to_string(), resume.to_string()]);
+        }
+        if self.eliminate_quals {
+            args.push("--eliminate-quals".to_string());
+        }
+        args
+    }
+}
+
+/// Credential used to authorize controlled-access downloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessCredential {
+    /// dbGaP repository key (`prj_<id>.ngc`), passed as `--ngc`.
+    Ngc(PathBuf),
+    /// JWT cart from the SRA Run Selector, passed as `--perm`.
+    Jwt(PathBuf),
+}
+
+impl AccessCredential {
+    /// Classify a credential file by extension: `.jwt` / `.cart` are JWT
+    /// carts, everything else is treated as an NGC repository key.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Err(anyhow!(
+                "Controlled-access credential not found: {}",
+                path.display()
+            ));
+        }
+        let ext = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        Ok(match ext.as_str() {
+            "jwt" | "cart" => Self::Jwt(path.to_path_buf()),
+            _ => Self::Ngc(path.to_path_buf()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Ngc(path) | Self::Jwt(path) => path,
+        }
+    }
+
+    /// Arguments understood by both `prefetch` and `fasterq-dump`.
+    pub fn tool_args(&self) -> Vec<OsString> {
+        let flag = match self {
+            Self::Ngc(_) => "--ngc",
+            Self::Jwt(_) => "--perm",
+        };
+        vec![OsString::from(flag), self.path().as_os_str().to_owned()]
+    }
+}
+
+/// True when ENA lists no public FASTQ, SRA or submitted files for the run,
+/// which is how dbGaP-protected runs (and TSV rows carrying only an
+/// accession) show up.
+pub fn is_protected(record: &EnaRecord) -> bool {
+    let empty = |value: Option<&str>| value.is_none_or(|v| v.trim().is_empty());
+    record.fastq_ftp.trim().is_empty()
+        && empty(record.sra_ftp.as_deref())
+        && empty(record.submitted_ftp.as_deref())
+}
+
+/// Locate a run's `.sra` / `.sralite` inside the prefetch run directory.
+#[cfg(feature = "sra-tools")]
+fn find_local_sra(run_dir: &Path, run_id: &str) -> Option<PathBuf> {
+    ["sra", "sralite"]
+        .iter()
+        .map(|ext| run_dir.join(format!("{}.{}", run_id, ext)))
+        .find(|p| p.metadata().map(|m| m.len() > 0).unwrap_or(false))
+}
+
+#[cfg(feature = "sra-tools")]
+fn has_fastq_output(output_dir: &Path, run_id: &str) -> bool {
+    [format!("{}_1.fastq", run_id), format!("{}.fastq", run_id)]
+        .iter()
+        .any(|name| {
+            output_dir
+                .join(name)
+                .metadata()
+                .map(|m| m.len() > 0)
+                .unwrap_or(false)
+        })
+}
+
+/// Bytes prefetch has written for `run_id`: its largest file in `run_dir`,
+/// whether the finished `.sra` / `.sralite` or a partial download.
+#[cfg(feature = "sra-tools")]
+fn prefetched_bytes(run_dir: &Path, run_id: &str) -> u64 {
+    std::fs::read_dir(run_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(run_id))
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Move `bar` along with the files prefetch writes, once a second, until
+/// the task is aborted.
+#[cfg(feature = "sra-tools")]
+async fn watch_prefetch(run_dir: PathBuf, run_id: String, bar: Arc<dyn ProgressHandle>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tick.tick().await;
+        bar.set_position(prefetched_bytes(&run_dir, &run_id));
+    }
+}
+
+/// Option file of a batch `prefetch`, in the output directory.
+#[cfg(feature = "sra-tools")]
+pub const BATCH_LIST: &str = ".prefetch_batch.txt";
+
+/// Runs of `runs` without a local `.sra` / `.sralite` under `output_dir`.
+#[cfg(feature = "sra-tools")]
+fn missing_runs<'a>(output_dir: &Path, runs: &'a [String]) -> Vec<&'a str> {
+    runs.iter()
+        .map(String::as_str)
+        .filter(|run| find_local_sra(&output_dir.join(run), run).is_none())
+        .collect()
+}
+
+/// Prefetch the runs of `runs` missing under `output_dir` with a single
+/// `prefetch --option-file` listing one accession per line, so prefetch
+/// schedules its own connections instead of one process per run. Runs it
+/// does not fetch are left to the per-run prefetch of [`download_run`].
+/// Returns the number of runs listed.
+#[cfg(feature = "sra-tools")]
+pub async fn prefetch_batch(
+    runs: &[String],
+    config: &Config,
+    credential: Option<&AccessCredential>,
+    output_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let missing = missing_runs(output_dir, runs);
+    if missing.len() < 2 {
+        return Ok(0);
+    }
+    let list = output_dir.join(BATCH_LIST);
+    tokio::fs::write(&list, missing.join("\n") + "\n").await?;
+    info!(
+        "Prefetching {} run(s) in one batch ({})",
+        missing.len(),
+        list.display()
+    );
+    let prefetch = &config.software.prefetch;
+    let output = run_cancellable(
+        Command::new(prefetch)
+            .arg("--option-file")
+            .arg(BATCH_LIST)
+            .args(credential.map(|c| c.tool_args()).unwrap_or_default())
+            .arg("-O")
+            .arg(".")
+            .args(config.prefetch.args(PREFETCH_MAX_SIZE))
+            .current_dir(output_dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped()),
+        cancel,
+    )
+    .await
+    .with_context(|| format!("Failed to run {}", prefetch.display()))?;
+    let _ = tokio::fs::remove_file(&list).await;
+    if !output.status.success() {
+        let left = missing_runs(output_dir, runs).len();
+        warn!(
+            "Batch prefetch exited with {}; {} run(s) will be prefetched one by one: {}",
+            output.status,
+            left,
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .last()
+                .unwrap_or_default()
+                .trim()
+        );
+    }
+    Ok(missing.len())
+}
+
+/// Check that both SRA Toolkit binaries needed for protected runs exist.
+pub fn validate_tools(config: &Config) -> Result<()> {
+    crate::check_executable(&config.software.prefetch, "prefetch")?;
+    crate::check_executable(&config.software.fasterq_dump, "fasterq-dump")
+}
+
+/// Prefetch, convert and compress a single run. Without a credential this
+/// is the plain SRA Toolkit path and works for any public run. Cancelling
+/// `cancel` kills the running tool; a partial `.sra` is resumed next time.
+#[cfg(feature = "sra-tools")]
+#[allow(clippy::too_many_arguments)]
+pub async fn download_run(
+    run_id: &str,
+    config: &Config,
+    credential: Option<&AccessCredential>,
+    output_dir: &Path,
+    process_threads: usize,
+    fasterq_mem: Option<u64>,
+    cleanup_sra: bool,
+    output_format: OutputFormat,
+    long_read: bool,
+    progress: Option<&Arc<dyn ProgressSink>>,
+    sra_bytes: u64,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let prefetch = &config.software.prefetch;
+    let fasterq_dump = &config.software.fasterq_dump;
+    let credential_args = credential.map(|c| c.tool_args()).unwrap_or_default();
+    let run_dir = output_dir.join(run_id);
+
+    if find_local_sra(&run_dir, run_id).is_some() {
+        info!("[{}] SRA file exists, skipping prefetch.", run_id);
+    } else {
+        match credential {
+            Some(_) => info!("[{}] Step 1: Prefetching (controlled access)...", run_id),
+            None => info!("[{}] Step 1: Prefetching...", run_id),
+        }
+        // A bar against ENA's `sra_bytes`, or a spinner without them.
+        let kind = if sra_bytes > 0 {
+            ProgressKind::Transfer
+        } else {
+            ProgressKind::Spinner
+        };
+        let bar = start_progress(progress, run_id, kind, sra_bytes);
+        bar.set_message("Prefetching");
+        let watcher = tokio::spawn(watch_prefetch(
+            run_dir.clone(),
+            run_id.to_string(),
+            bar.clone(),
+        ));
+        let output = run_cancellable(
+            Command::new(prefetch)
+                .arg(run_id)
+                .args(&credential_args)
+                .arg("-O")
+                .arg(".")
+                .args(config.prefetch.args(PREFETCH_MAX_SIZE))
+                .current_dir(output_dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+            cancel,
+        )
+        .await;
+        watcher.abort();
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                bar.fail("prefetch did not run");
+                return Err(e).with_context(|| format!("Failed to run {}", prefetch.display()));
+            }
+        };
+        if output.status.success() {
+            bar.set_position(prefetched_bytes(&run_dir, run_id));
+            bar.finish();
+        } else {
+            bar.fail(&format!("prefetch exited with {}", output.status));
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("[{}] prefetch failed: {}", run_id, stderr.trim());
+            return Err(PolariseqError::Network(format!(
+                "prefetch failed for {}: {}",
+                run_id,
+                stderr.trim()
+            ))
+            .into());
+        }
+    }
+
+    if has_fastq_output(output_dir, run_id) {
+        info!("[{}] FASTQ files exist, skipping conversion.", run_id);
+    } else {
+        info!("[{}] Step 2: Converting (fasterq-dump)...", run_id);
+        let fasterq_tmp_dir = output_dir.join(".fasterq_tmp").join(run_id);
+        tokio::fs::create_dir_all(&fasterq_tmp_dir)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to create fasterq-dump temporary directory: {}",
+                    fasterq_tmp_dir.display()
+                )
+            })?;
+        let output = run_cancellable(
+            config
+                .priority
+                .command(fasterq_dump)
+                .arg(crate::long_read::split_arg(long_read))
+                .arg("-e")
+                .arg(process_threads.to_string())
+                .args(crate::backend::fasterq_mem_args(fasterq_mem))
+                .args(&credential_args)
+                .arg("-O")
+                .arg(".")
+                .arg("-t")
+                .arg(&fasterq_tmp_dir)
+                .arg("-f")
+                .arg(run_id)
+                .current_dir(output_dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+            cancel,
+        )
+        .await
+        .with_context(|| format!("Failed to run {}", fasterq_dump.display()))?;
+        if !output.status.success() {
+            warn!(
+                "[{}] fasterq-dump error: {}. Checking output...",
+                run_id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+
+    if !has_fastq_output(output_dir, run_id) {
+        error!("[{}] Conversion failed, no output found.", run_id);
+        return Err(PolariseqError::ConversionFailed {
+            run: run_id.to_string(),
+            reason: "no FASTQ output found".to_string(),
+        }
+        .into());
+    }
+
+    if output_format == OutputFormat::Fastq {
+        info!("[{}] Step 3: Compressing...", run_id);
+        let output_dir_compress = output_dir.to_path_buf();
+        let run_id_compress = run_id.to_string();
+        tokio::task::spawn_blocking(move || {
+            crate::compress_fastq_files(
+                &output_dir_compress,
+                &run_id_compress,
+                process_threads,
+                None,
+            )
+        })
+        .await
+        .context("Compression task panicked")?
+        .context("Compression failed")?;
+    } else {
+        info!("[{}] Step 3: Importing (samtools import)...", run_id);
+        crate::unaligned::import_fastq(
+            config,
+            output_dir,
+            run_id,
+            output_format,
+            process_threads,
+            cancel,
+        )
+        .await?;
+    }
+
+    if cleanup_sra && run_dir.exists() {
+        info!(
+            "[{}] Cleaning up SRA directory: {}",
+            run_id,
+            run_dir.display()
+        );
+        if let Err(e) = tokio::fs::remove_dir_all(&run_dir).await {
+            warn!("[{}] Failed to remove SRA directory: {}", run_id, e);
+        }
+    }
+
+    info!("[{}] Done", run_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_credentials_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let ngc = dir.path().join("prj_1234.ngc");
+        let jwt = dir.path().join("cart.jwt");
+        std::fs::write(&ngc, b"key").unwrap();
+        std::fs::write(&jwt, b"token").unwrap();
+
+        let ngc = AccessCredential::from_path(&ngc).unwrap();
+        assert!(matches!(ngc, AccessCredential::Ngc(_)));
+        assert_eq!(ngc.tool_args()[0], "--ngc");
+
+        let jwt = AccessCredential::from_path(&jwt).unwrap();
+        assert!(matches!(jwt, AccessCredential::Jwt(_)));
+        assert_eq!(jwt.tool_args()[0], "--perm");
+
+        assert!(AccessCredential::from_path(&dir.path().join("missing.ngc")).is_err());
+    }
+
+    #[test]
+    fn prefetch_options_from_yaml() {
+        let config: PrefetchConfig =
+            serde_yaml::from_str("transport: https\nresume: false\n").unwrap();
+        assert_eq!(
+            config.args("20G").join(" "),
+            "--max-size 20G --verify yes --transport https --resume no"
+        );
+        assert!(PrefetchConfig::default().is_empty());
+    }
+
+    #[cfg(feature = "sra-tools")]
+    #[test]
+    fn batches_only_runs_without_sra_files() {
+        assert_eq!(prefetched_bytes(Path::new("/nonexistent"), "SRR1"), 0);
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("SRR1")).unwrap();
+        std::fs::write(dir.path().join("SRR1/SRR1.sralite"), b"sra").unwrap();
+        std::fs::create_dir(dir.path().join("SRR2")).unwrap();
+        std::fs::write(dir.path().join("SRR2/SRR2.sra"), b"").unwrap();
+        let runs = ["SRR1".to_string(), "SRR2".to_string(), "SRR3".to_string()];
+        assert_eq!(missing_runs(dir.path(), &runs), ["SRR2", "SRR3"]);
+        std::fs::write(dir.path().join("SRR1/SRR1.sra.prf"), b"partial").unwrap();
+        assert_eq!(prefetched_bytes(&dir.path().join("SRR1"), "SRR1"), 7);
+    }
+
+    #[test]
+    fn records_without_public_files_are_protected() {
+        let mut record: EnaRecord = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader("run_accession\nSRR000001\n".as_bytes())
+            .deserialize()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(is_protected(&record));
+
+        record.fastq_ftp =
+            "ftp.sra.ebi.ac.uk/vol1/fastq/SRR000/SRR000001/SRR000001.fastq.gz".into();
+        assert!(!is_protected(&record));
+    }
+}
@@ -197,6 +197,7 @@ fn find_sra_tools_in_dir(dir: &Path) -> Option<SoftwarePaths> {
         prefetch,
         fasterq_dump,
         blastdbcmd: None,
+        samtools: None,
     })
 }
 
@@ -238,9 +239,109 @@ pub fn find_sra_tools_in_path() -> Option<SoftwarePaths> {
         prefetch,
         fasterq_dump,
         blastdbcmd: None,
+        samtools: None,
     })
 }
 
+/// Directories searched after `PATH`, for tools installed by hand or by a
+/// package manager that did not touch the user's `PATH`.
+fn standard_tool_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = ["/usr/local/bin", "/opt/sratoolkit/bin", "/opt/homebrew/bin"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".local").join("bin"));
+        dirs.push(home.join("bin"));
+    }
+    dirs
+}
+
+/// Locate one tool: managed install (sra-tools only), `PATH`, then the
+/// standard install directories.
+pub fn locate_tool(name: &str) -> Option<PathBuf> {
+    let managed = sra_tools_install_dir(DEFAULT_SRA_TOOLS_VERSION);
+    if managed.exists() {
+        if let Some(path) = find_executable(&managed, name) {
+            return Some(path);
+        }
+    }
+    which::which(name).ok().or_else(|| {
+        standard_tool_dirs()
+            .iter()
+            .find_map(|dir| find_executable(dir, name))
+    })
+}
+
+/// Tool paths for running without `polariseq.yaml`.
+///
+/// A tool that is not found keeps its bare name; only the backends that
+/// need it fail, via [`validate_config`](crate::validate_config).
+pub fn detect_software_paths() -> SoftwarePaths {
+    let locate = |name: &str| locate_tool(name).unwrap_or_else(|| PathBuf::from(name));
+    SoftwarePaths {
+        prefetch: locate("prefetch"),
+        fasterq_dump: locate("fasterq-dump"),
+        blastdbcmd: locate_tool("blastdbcmd"),
+        samtools: locate_tool("samtools"),
+    }
+}
+
+/// Commented `polariseq.yaml` for `polariseq init`. Tools that were not
+/// found are written with their bare name and a hint to install them.
+pub fn config_template(paths: &SoftwarePaths) -> String {
+    let scalar = |path: &Path| {
+        serde_yaml::to_string(&path.to_string_lossy())
+            .map(|s| s.trim_end().to_string())
+            .unwrap_or_else(|_| path.display().to_string())
+    };
+    let tool = |key: &str, path: &Path| {
+        let hint = if path.is_absolute() && path.exists() {
+            ""
+        } else {
+            "  # not found; run `polariseq deps install`"
+        };
+        format!("  {}: {}{}\n", key, scalar(path), hint)
+    };
+
+    let mut yaml = String::from(
+        "# Polariseq configuration (generated by `polariseq init`).\n\
+         # POLARISEQ_PREFETCH / POLARISEQ_FASTERQ_DUMP / POLARISEQ_BLASTDBCMD /\n\
+         # POLARISEQ_SAMTOOLS and --prefetch-path / --fasterq-dump-path override\n\
+         # these paths.\n\
+         \n\
+         # External tools. fasterq-dump is needed by the AWS backend, prefetch\n\
+         # by the SRA Toolkit backend (--ngc). FTP downloads need neither.\n\
+         software:\n",
+    );
+    yaml.push_str(&tool("prefetch", &paths.prefetch));
+    yaml.push_str(&tool("fasterq_dump", &paths.fasterq_dump));
+    match &paths.blastdbcmd {
+        Some(path) => yaml.push_str(&tool("blastdbcmd", path)),
+        None => {
+            yaml.push_str("  # blastdbcmd: /path/to/blastdbcmd  # only for `polariseq validate`\n")
+        }
+    }
+    match &paths.samtools {
+        Some(path) => yaml.push_str(&tool("samtools", path)),
+        None => {
+            yaml.push_str("  # samtools: /path/to/samtools  # only for --output-format ubam|cram\n")
+        }
+    }
+    yaml.push_str(
+        "\n\
+         # Public databases for `polariseq public-data -n <name>`.\n\
+         public_data: {}\n\
+         #  ncbi_nt:\n\
+         #    s3_url: s3://ncbi-blast-databases/<date>/\n\
+         #    description: \"NCBI nt database\"\n\
+         #    database_type: folder\n\
+         #    exclude: \"*\"\n\
+         #    include: \"nt.*\"\n",
+    );
+    yaml
+}
+
 /// Install sra-tools into the managed dependency directory.
 pub async fn install_sra_tools(
     version: Option<&str>,
@@ -502,8 +603,14 @@ pub fn write_software_paths_to_yaml(yaml_path: &Path, paths: &SoftwarePaths) ->
                 prefetch: paths.prefetch.clone(),
                 fasterq_dump: paths.fasterq_dump.clone(),
                 blastdbcmd: paths.blastdbcmd.clone(),
+                samtools: paths.samtools.clone(),
             },
             public_data: Default::default(),
+            ega: None,
+            http: Default::default(),
+            prefetch: Default::default(),
+            priority: Default::default(),
+            profiles: Default::default(),
         })
     } else {
         Config {
@@ -511,8 +618,14 @@ pub fn write_software_paths_to_yaml(yaml_path: &Path, paths: &SoftwarePaths) ->
                 prefetch: paths.prefetch.clone(),
                 fasterq_dump: paths.fasterq_dump.clone(),
                 blastdbcmd: paths.blastdbcmd.clone(),
+                samtools: paths.samtools.clone(),
             },
             public_data: Default::default(),
+            ega: None,
+            http: Default::default(),
+            prefetch: Default::default(),
+            priority: Default::default(),
+            profiles: Default::default(),
         }
     };
 
@@ -526,3 +639,29 @@ pub fn write_software_paths_to_yaml(yaml_path: &Path, paths: &SoftwarePaths) ->
     info!("Updated configuration: {}", yaml_path.display());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_template_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefetch = dir.path().join("my tools: v3").join("prefetch");
+        std::fs::create_dir_all(prefetch.parent().unwrap()).unwrap();
+        std::fs::write(&prefetch, "").unwrap();
+        let paths = SoftwarePaths {
+            prefetch: prefetch.clone(),
+            fasterq_dump: PathBuf::from("fasterq-dump"),
+            blastdbcmd: None,
+            samtools: None,
+        };
+
+        let yaml = config_template(&paths);
+        assert!(yaml.contains("fasterq_dump: fasterq-dump  # not found"));
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.software.prefetch, prefetch);
+        assert_eq!(config.software.fasterq_dump, PathBuf::from("fasterq-dump"));
+        assert!(config.software.blastdbcmd.is_none() && config.public_data.is_empty());
+    }
+}
@@ -0,0 +1,554 @@
+//! Object-storage destinations for `download --dest`.
+//!
+//! [`ObjectStoreHook`] uploads each verified run's files to
+//! `s3://bucket/prefix/` or `gs://bucket/prefix/` and can delete the local
+//! copy afterwards, so local disk is only a staging area. [`mirror_plan`]
+//! skips local disk entirely and streams the published files into
+//! multipart uploads. Google Cloud Storage is reached through its
+//! S3-compatible XML API with HMAC keys.
+
+use crate::aws_s3::{SraLookup, SraUtils};
+use crate::backend::{BackendContext, RunHook};
+use crate::client::{DownloadJob, JobPlan};
+use crate::error::PolariseqError;
+use crate::DownloadMethod;
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_s3::config::{Credentials, RequestChecksumCalculation};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// HMAC key pair for `gs://` destinations (Cloud Storage → Settings →
+/// Interoperability). Without them the AWS credential chain is used.
+pub const ENV_GCS_HMAC_KEY: &str = "POLARISEQ_GCS_HMAC_KEY";
+pub const ENV_GCS_HMAC_SECRET: &str = "POLARISEQ_GCS_HMAC_SECRET";
+
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+/// Files above this size are sent as multipart uploads (PutObject is
+/// limited to 5 GiB).
+const MULTIPART_THRESHOLD: u64 = 1024 * 1024 * 1024;
+const PART_SIZE: u64 = 256 * 1024 * 1024;
+/// Part size when streaming; each in-flight file buffers one part in memory.
+const STREAM_PART_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Store {
+    S3,
+    Gcs,
+}
+
+/// `s3://bucket[/prefix]` or `gs://bucket[/prefix]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub store: Store,
+    pub bucket: String,
+    /// Key prefix without leading or trailing `/`; may be empty.
+    pub prefix: String,
+}
+
+impl Destination {
+    /// Object key of `file_name`.
+    pub fn key(&self, file_name: &str) -> String {
+        if self.prefix.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, file_name)
+        }
+    }
+
+    pub fn url(&self, key: &str) -> String {
+        let scheme = match self.store {
+            Store::S3 => "s3",
+            Store::Gcs => "gs",
+        };
+        format!("{}://{}/{}", scheme, self.bucket, key)
+    }
+}
+
+impl FromStr for Destination {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = || {
+            PolariseqError::InvalidInput(format!(
+                "Invalid destination '{}': expected s3://bucket[/prefix] or gs://bucket[/prefix]",
+                url
+            ))
+        };
+        let (store, rest) = if let Some(rest) = url.strip_prefix("s3://") {
+            (Store::S3, rest)
+        } else if let Some(rest) = url.strip_prefix("gs://") {
+            (Store::Gcs, rest)
+        } else {
+            return Err(invalid().into());
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(invalid().into());
+        }
+        Ok(Self {
+            store,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+/// S3-compatible client bound to a [`Destination`].
+pub struct ObjectStore {
+    dest: Destination,
+    client: aws_sdk_s3::Client,
+}
+
+impl ObjectStore {
+    /// Build the client for `dest` and check that its bucket is reachable.
+    pub async fn connect(dest: Destination) -> Result<Self> {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+        if dest.store == Store::Gcs {
+            loader = loader
+                .endpoint_url(GCS_ENDPOINT)
+                .region(aws_config::Region::new("auto"));
+            if let (Ok(key), Ok(secret)) = (
+                std::env::var(ENV_GCS_HMAC_KEY),
+                std::env::var(ENV_GCS_HMAC_SECRET),
+            ) {
+                loader = loader.credentials_provider(Credentials::new(
+                    key,
+                    secret,
+                    None,
+                    None,
+                    "polariseq-gcs-hmac",
+                ));
+            }
+        }
+        let shared = loader.load().await;
+        let mut config = aws_sdk_s3::config::Builder::from(&shared);
+        if dest.store == Store::Gcs {
+            // GCS rejects the CRC headers newer SDKs add by default.
+            config = config.request_checksum_calculation(RequestChecksumCalculation::WhenRequired);
+        }
+        let client = aws_sdk_s3::Client::from_conf(config.build());
+
+        client
+            .head_bucket()
+            .bucket(&dest.bucket)
+            .send()
+            .await
+            .map_err(|e| {
+                PolariseqError::Config(format!(
+                    "Cannot access destination bucket '{}': {}",
+                    dest.bucket, e
+                ))
+            })?;
+        Ok(Self { dest, client })
+    }
+
+    pub fn destination(&self) -> &Destination {
+        &self.dest
+    }
+
+    /// Upload `path` as `<prefix>/<file name>` and check the remote size.
+    pub async fn upload_file(&self, path: &Path) -> Result<()> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let key = self.dest.key(&name);
+        let size = tokio::fs::metadata(path).await?.len();
+        if size > MULTIPART_THRESHOLD {
+            let mut upload = MultipartUpload::start(self, &key).await?;
+            let mut offset = 0;
+            while offset < size {
+                let length = PART_SIZE.min(size - offset);
+                let body = ByteStream::read_from()
+                    .path(path)
+                    .offset(offset)
+                    .length(aws_sdk_s3::primitives::Length::Exact(length))
+                    .build()
+                    .await;
+                let sent = match body {
+                    Ok(body) => upload.put_part(body).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = sent {
+                    upload.abort().await;
+                    return Err(e);
+                }
+                offset += length;
+            }
+            upload.complete().await?;
+        } else {
+            self.client
+                .put_object()
+                .bucket(&self.dest.bucket)
+                .key(&key)
+                .body(ByteStream::from_path(path).await?)
+                .send()
+                .await
+                .with_context(|| format!("Upload of {} failed", name))?;
+        }
+        self.check_size(&key, size).await?;
+        info!(
+            "   Uploaded {} → {} ({})",
+            name,
+            self.dest.url(&key),
+            indicatif::HumanBytes(size)
+        );
+        Ok(())
+    }
+
+    /// Stream `url` into `<prefix>/<name>` without touching local disk,
+    /// hashing the bytes on the way. The object is only completed when the
+    /// MD5 matches `md5` (if given); otherwise the upload is aborted.
+    pub async fn mirror_url(
+        &self,
+        http: &reqwest::Client,
+        url: &str,
+        name: &str,
+        md5: Option<&str>,
+    ) -> Result<u64> {
+        let key = self.dest.key(name);
+        let response = http
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("GET {} failed", url))?;
+        let mut upload = MultipartUpload::start(self, &key).await?;
+
+        let streamed = async {
+            let mut stream = response.bytes_stream();
+            let mut hasher = md5::Context::new();
+            let mut buffer = Vec::with_capacity(STREAM_PART_SIZE);
+            let mut total = 0u64;
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                hasher.consume(&chunk);
+                total += chunk.len() as u64;
+                buffer.extend_from_slice(&chunk);
+                if buffer.len() >= STREAM_PART_SIZE {
+                    let part = std::mem::replace(&mut buffer, Vec::with_capacity(STREAM_PART_SIZE));
+                    upload.put_part(ByteStream::from(part)).await?;
+                }
+            }
+            // The last part may be smaller than the 5 MiB minimum.
+            if !buffer.is_empty() || upload.parts.is_empty() {
+                upload.put_part(ByteStream::from(buffer)).await?;
+            }
+            let actual = format!("{:x}", hasher.compute());
+            if let Some(expected) = md5.filter(|m| !m.is_empty()) {
+                if !actual.eq_ignore_ascii_case(expected) {
+                    warn!("[{}] MD5 {} does not match {}", name, actual, expected);
+                    return Err(PolariseqError::ChecksumMismatch {
+                        file: name.to_string(),
+                    }
+                    .into());
+                }
+            }
+            anyhow::Ok(total)
+        }
+        .await;
+
+        match streamed {
+            Ok(total) => {
+                upload.complete().await?;
+                self.check_size(&key, total).await?;
+                Ok(total)
+            }
+            Err(e) => {
+                upload.abort().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn check_size(&self, key: &str, size: u64) -> Result<()> {
+        let remote_size = self
+            .client
+            .head_object()
+            .bucket(&self.dest.bucket)
+            .key(key)
+            .send()
+            .await?
+            .content_length()
+            .unwrap_or(-1);
+        if remote_size != size as i64 {
+            return Err(anyhow!(
+                "{} has {} bytes, expected {}",
+                self.dest.url(key),
+                remote_size,
+                size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An open multipart upload; parts are numbered in call order.
+struct MultipartUpload<'a> {
+    store: &'a ObjectStore,
+    key: String,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+}
+
+impl<'a> MultipartUpload<'a> {
+    async fn start(store: &'a ObjectStore, key: &str) -> Result<Self> {
+        let upload_id = store
+            .client
+            .create_multipart_upload()
+            .bucket(&store.dest.bucket)
+            .key(key)
+            .send()
+            .await?
+            .upload_id
+            .ok_or_else(|| anyhow!("No upload id for {}", key))?;
+        Ok(Self {
+            store,
+            key: key.to_string(),
+            upload_id,
+            parts: Vec::new(),
+        })
+    }
+
+    async fn put_part(&mut self, body: ByteStream) -> Result<()> {
+        let number = self.parts.len() as i32 + 1;
+        let part = self
+            .store
+            .client
+            .upload_part()
+            .bucket(&self.store.dest.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .part_number(number)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Part {} of {} failed", number, self.key))?;
+        self.parts.push(
+            CompletedPart::builder()
+                .part_number(number)
+                .set_e_tag(part.e_tag)
+                .build(),
+        );
+        Ok(())
+    }
+
+    async fn complete(self) -> Result<()> {
+        self.store
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.store.dest.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(self.parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .with_context(|| format!("Completing upload of {} failed", self.key))?;
+        Ok(())
+    }
+
+    async fn abort(self) {
+        let _ = self
+            .store
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.store.dest.bucket)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await;
+    }
+}
+
+/// Uploads finished runs to an [`ObjectStore`], optionally deleting the
+/// local files.
+pub struct ObjectStoreHook {
+    store: ObjectStore,
+    remove_local: bool,
+}
+
+impl ObjectStoreHook {
+    pub async fn new(dest: Destination, remove_local: bool) -> Result<Self> {
+        Ok(Self {
+            store: ObjectStore::connect(dest).await?,
+            remove_local,
+        })
+    }
+}
+
+impl RunHook for ObjectStoreHook {
+    fn name(&self) -> &'static str {
+        "upload"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if files.is_empty() {
+                return Err(anyhow!("no output files found for {}", run_id));
+            }
+            for file in files {
+                self.store.upload_file(file).await?;
+                if self.remove_local {
+                    tokio::fs::remove_file(file).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// HTTPS form of an ENA FASTQ location (`ftp.sra.ebi.ac.uk/...`).
+fn ena_https_url(url: &str) -> String {
+    let path = url
+        .strip_prefix("ftp://")
+        .or_else(|| url.strip_prefix("https://"))
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    format!("https://{}", path)
+}
+
+/// (name, URL, MD5) of every file `plan` would download, as published:
+/// ENA FASTQ for FTP jobs, the resolved `.sra` for AWS jobs.
+async fn mirror_sources(
+    job: &DownloadJob,
+    plan: &JobPlan,
+) -> Result<Vec<(String, String, Option<String>)>> {
+    let mut sources = Vec::new();
+    for record in &plan.processed {
+        match job.options.download_method {
+            DownloadMethod::Ftp => {
+                sources.push((
+                    record.fastq_ftp_1_name.clone(),
+                    ena_https_url(&record.fastq_ftp_1_url),
+                    Some(record.fastq_md5_1.clone()),
+                ));
+                if let (Some(name), Some(url)) = (&record.fastq_ftp_2_name, &record.fastq_ftp_2_url)
+                {
+                    sources.push((name.clone(), ena_https_url(url), record.fastq_md5_2.clone()));
+                }
+            }
+            DownloadMethod::Aws => {
+                let run = &record.run_accession;
+                match SraUtils::get_metadata_for_format(run, job.options.sra_format).await? {
+                    SraLookup::Found(metadata) => {
+                        sources.push((run.clone(), metadata.http_url, metadata.md5))
+                    }
+                    SraLookup::LiteOnly => warn!("[{}] Only SRA Lite is available; skipped", run),
+                    SraLookup::NotFound => warn!("[{}] No AWS copy; skipped", run),
+                }
+            }
+            DownloadMethod::Ega => {
+                return Err(anyhow!("--dest-stream does not support EGA downloads"));
+            }
+            DownloadMethod::Mock => {
+                return Err(anyhow!("--dest-stream does not support mock downloads"));
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Mirror every file of `plan` into `store` without local staging,
+/// `job.options.multithreads` files at a time. Files are copied as
+/// published (no SRA → FASTQ conversion). Fails if any file fails.
+pub async fn mirror_plan(
+    store: ObjectStore,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let sources = mirror_sources(job, plan).await?;
+    let total = sources.len();
+    info!(
+        "Streaming {} file(s) to {}",
+        total,
+        store.destination().url(&store.destination().key(""))
+    );
+    let store = Arc::new(store);
+    let http = crate::http::client_builder().build()?;
+    let semaphore = Arc::new(Semaphore::new(job.options.multithreads.max(1)));
+    let mut tasks = Vec::new();
+    for (name, url, md5) in sources {
+        let store = store.clone();
+        let http = http.clone();
+        let semaphore = semaphore.clone();
+        let cancel = cancel.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            let result = tokio::select! {
+                result = store.mirror_url(&http, &url, &name, md5.as_deref()) => result,
+                _ = cancel.cancelled() => Err(PolariseqError::Cancelled.into()),
+            };
+            match &result {
+                Ok(bytes) => info!(
+                    "   Streamed {} → {} ({})",
+                    name,
+                    store.destination().url(&store.destination().key(&name)),
+                    indicatif::HumanBytes(*bytes)
+                ),
+                Err(e) => warn!("[{}] Streaming failed: {:#}", name, e),
+            }
+            result.map(|_| ())
+        }));
+    }
+
+    let mut failed = 0;
+    for task in tasks {
+        if !matches!(task.await, Ok(Ok(()))) {
+            failed += 1;
+        }
+    }
+    if cancel.is_cancelled() {
+        return Err(PolariseqError::Cancelled.into());
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} file(s) failed to stream", failed, total));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_destinations() {
+        let dest: Destination = "s3://bucket/some/prefix/".parse().unwrap();
+        assert_eq!(dest.store, Store::S3);
+        assert_eq!(dest.key("SRR1_1.fastq.gz"), "some/prefix/SRR1_1.fastq.gz");
+
+        let dest: Destination = "gs://bucket".parse().unwrap();
+        assert_eq!(dest.store, Store::Gcs);
+        assert_eq!(dest.url(&dest.key("SRR1")), "gs://bucket/SRR1");
+
+        assert!("s3://".parse::<Destination>().is_err());
+        assert!("https://bucket/x".parse::<Destination>().is_err());
+    }
+
+    #[test]
+    fn ena_locations_are_fetched_over_https() {
+        assert_eq!(
+            ena_https_url("ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz"),
+            "https://ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz"
+        );
+        assert_eq!(ena_https_url("ftp://host/x.gz"), "https://host/x.gz");
+    }
+}
@@ -0,0 +1,459 @@
+//! Coordinator/worker downloads across several nodes.
+//!
+//! A coordinator plans the job once and serves its runs from a
+//! [`WorkQueue`] over HTTP (`POST /claim`, `POST /heartbeat`,
+//! `POST /report`, `GET /status`, all JSON). Workers on other nodes loop
+//! over [`run_worker`]: claim a run, download it with their own tools and
+//! output directory, and report the outcome. Runs whose worker stops
+//! sending heartbeats for [`LEASE`] go back to the queue.
+
+use crate::client::{DownloadJob, JobPlan, PolariseqClient};
+use crate::{DownloadOptions, ProcessedRecord};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// How long a claimed run survives without a heartbeat.
+pub const LEASE: Duration = Duration::from_secs(300);
+/// How often workers renew their lease.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a worker without a run waits before claiming again while other
+/// workers still hold runs, which may come back when their leases expire.
+pub const CLAIM_RETRY: Duration = Duration::from_secs(30);
+
+/// A worker asking for work, or renewing the lease on `run_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerRequest {
+    pub worker: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+/// Answer to a claim: the job's options and the next run, or no run when
+/// none is pending.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claim {
+    pub options: DownloadOptions,
+    pub record: Option<ProcessedRecord>,
+    /// Runs other workers still hold; the queue is drained once this is 0
+    /// and no run was handed out.
+    #[serde(default)]
+    pub claimed: usize,
+}
+
+/// Outcome of one run on one worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub run_id: String,
+    pub worker: String,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub seconds: u64,
+}
+
+/// Aggregated state of the queue, as served by `GET /status`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueStatus {
+    pub pending: usize,
+    /// Run → worker currently holding it.
+    pub claimed: HashMap<String, String>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub reports: Vec<RunReport>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: VecDeque<ProcessedRecord>,
+    claimed: HashMap<String, (String, Instant, ProcessedRecord)>,
+    reports: Vec<RunReport>,
+    /// Runs in `reports`, so a repeated report is not counted twice.
+    reported: HashSet<String>,
+}
+
+/// Runs waiting for, held by, or finished on workers.
+#[derive(Clone)]
+pub struct WorkQueue {
+    options: DownloadOptions,
+    state: Arc<Mutex<QueueState>>,
+    finished: Arc<Notify>,
+}
+
+impl WorkQueue {
+    pub fn new(options: DownloadOptions, records: Vec<ProcessedRecord>) -> Self {
+        Self {
+            options,
+            state: Arc::new(Mutex::new(QueueState {
+                pending: records.into(),
+                ..QueueState::default()
+            })),
+            finished: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Hand the next run to `worker`, first requeueing expired leases.
+    pub fn claim(&self, worker: &str) -> Claim {
+        self.claim_with_lease(worker, LEASE)
+    }
+
+    fn claim_with_lease(&self, worker: &str, lease: Duration) -> Claim {
+        let mut state = self.state.lock().unwrap();
+        let expired: Vec<String> = state
+            .claimed
+            .iter()
+            .filter(|(_, (_, seen, _))| seen.elapsed() > lease)
+            .map(|(run, _)| run.clone())
+            .collect();
+        for run in expired {
+            if let Some((holder, _, record)) = state.claimed.remove(&run) {
+                warn!("[{}] Lease of {} expired; requeued", run, holder);
+                state.pending.push_back(record);
+            }
+        }
+
+        let record = state.pending.pop_front();
+        if let Some(record) = &record {
+            state.claimed.insert(
+                record.run_accession.clone(),
+                (worker.to_string(), Instant::now(), record.clone()),
+            );
+        }
+        Claim {
+            options: self.options.clone(),
+            claimed: state.claimed.len() - usize::from(record.is_some()),
+            record,
+        }
+    }
+
+    /// Renew `worker`'s lease on `run_id`; false if it no longer holds it.
+    pub fn heartbeat(&self, worker: &str, run_id: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.claimed.get_mut(run_id) {
+            Some((holder, seen, _)) if holder == worker => {
+                *seen = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record a run's outcome. Only the worker holding the run reports it;
+    /// a late report for a requeued run still counts while the run is
+    /// pending, which is then not handed out again. Reports for runs that
+    /// are held by another worker, already reported or unknown are ignored.
+    pub fn report(&self, report: RunReport) {
+        let mut state = self.state.lock().unwrap();
+        if state.reported.contains(&report.run_id) {
+            warn!(
+                "[{}] Ignoring repeated report from {}",
+                report.run_id, report.worker
+            );
+            return;
+        }
+        match state.claimed.get(&report.run_id) {
+            Some((holder, _, _)) if *holder != report.worker => {
+                warn!(
+                    "[{}] Ignoring late report from {}; the run is now held by {}",
+                    report.run_id, report.worker, holder
+                );
+                return;
+            }
+            Some(_) => {
+                state.claimed.remove(&report.run_id);
+            }
+            None => {
+                let Some(at) = state
+                    .pending
+                    .iter()
+                    .position(|r| r.run_accession == report.run_id)
+                else {
+                    warn!(
+                        "[{}] Ignoring report from {} for a run that is not queued",
+                        report.run_id, report.worker
+                    );
+                    return;
+                };
+                state.pending.remove(at);
+            }
+        }
+        state.reported.insert(report.run_id.clone());
+        state.reports.push(report);
+        if state.pending.is_empty() && state.claimed.is_empty() {
+            self.finished.notify_waiters();
+        }
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let state = self.state.lock().unwrap();
+        let succeeded = state.reports.iter().filter(|r| r.ok).count();
+        QueueStatus {
+            pending: state.pending.len(),
+            claimed: state
+                .claimed
+                .iter()
+                .map(|(run, (worker, _, _))| (run.clone(), worker.clone()))
+                .collect(),
+            succeeded,
+            failed: state.reports.len() - succeeded,
+            reports: state.reports.clone(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.pending.is_empty() && state.claimed.is_empty()
+    }
+
+    /// Wait until every run has been reported.
+    pub async fn wait_finished(&self) -> QueueStatus {
+        loop {
+            let notified = self.finished.notified();
+            if self.is_finished() {
+                return self.status();
+            }
+            // Expired leases are only requeued when a worker claims; idle
+            // workers keep claiming, but a queue whose workers all died
+            // waits for a new one, so say so.
+            if tokio::time::timeout(LEASE, notified).await.is_err() {
+                let status = self.status();
+                info!(
+                    "Waiting for workers: {} pending, {} claimed, {} done",
+                    status.pending,
+                    status.claimed.len(),
+                    status.succeeded + status.failed
+                );
+            }
+        }
+    }
+}
+
+/// Totals of one worker's session.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Claim and download runs from `coordinator` (e.g. `http://node1:7070`)
+/// into `output` until the queue is drained. While no run is pending but
+/// other workers still hold some, claim again every [`CLAIM_RETRY`], so
+/// the runs of a worker that died are picked up.
+pub async fn run_worker(
+    client: &PolariseqClient,
+    coordinator: &str,
+    worker: &str,
+    output: &Path,
+) -> Result<WorkerSummary> {
+    let base = coordinator.trim_end_matches('/').to_string();
+    let http = reqwest::Client::new();
+    let mut summary = WorkerSummary::default();
+    let mut checked = false;
+
+    loop {
+        let claim: Claim = http
+            .post(format!("{}/claim", base))
+            .json(&WorkerRequest {
+                worker: worker.to_string(),
+                run_id: None,
+            })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to reach coordinator {}", base))?
+            .json()
+            .await?;
+        let Some(record) = claim.record else {
+            if claim.claimed == 0 {
+                info!("Queue drained");
+                return Ok(summary);
+            }
+            tokio::select! {
+                _ = client.cancellation_token().cancelled() => return Ok(summary),
+                _ = tokio::time::sleep(CLAIM_RETRY) => continue,
+            }
+        };
+
+        let run_id = record.run_accession.clone();
+        let mut options = claim.options;
+        options.output = output.to_path_buf();
+        let job = DownloadJob::from(options);
+        if !checked {
+            let preflight = match client.preflight(&job) {
+                Ok(()) => client.network_preflight(&job).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = preflight {
+                // Give the run back as failed instead of holding it until
+                // the lease expires.
+                let report = RunReport {
+                    run_id,
+                    worker: worker.to_string(),
+                    ok: false,
+                    error: Some(format!("{:#}", e)),
+                    seconds: 0,
+                };
+                if let Err(post) = http
+                    .post(format!("{}/report", base))
+                    .json(&report)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                {
+                    warn!("[{}] Failed to report to {}: {}", report.run_id, base, post);
+                }
+                return Err(e);
+            }
+            checked = true;
+        }
+        info!("[{}] Claimed from {}", run_id, base);
+
+        let heartbeat = {
+            let http = http.clone();
+            let url = format!("{}/heartbeat", base);
+            let request = WorkerRequest {
+                worker: worker.to_string(),
+                run_id: Some(run_id.clone()),
+            };
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    let _ = http.post(&url).json(&request).send().await;
+                }
+            })
+        };
+        let started = Instant::now();
+        let plan = JobPlan {
+            processed: vec![record],
+            ..JobPlan::default()
+        };
+        let result = client.execute(&job, &plan).await;
+        heartbeat.abort();
+
+        let report = RunReport {
+            run_id: run_id.clone(),
+            worker: worker.to_string(),
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            seconds: started.elapsed().as_secs(),
+        };
+        match &result {
+            Ok(()) => summary.succeeded += 1,
+            Err(e) => {
+                warn!("[{}] Failed: {:#}", run_id, e);
+                summary.failed += 1;
+            }
+        }
+        http.post(format!("{}/report", base))
+            .json(&report)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to report {} to {}", run_id, base))?;
+        if client.cancellation_token().is_cancelled() {
+            return result.map(|_| summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(run: &str) -> ProcessedRecord {
+        ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: String::new(),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: String::new(),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: String::new(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 0,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        }
+    }
+
+    #[test]
+    fn queue_hands_out_runs_and_requeues_expired_leases() {
+        let options = DownloadJob::accession("PRJNA1", "out").options;
+        let queue = WorkQueue::new(options, vec![record("SRR1"), record("SRR2")]);
+
+        let a = queue.claim("a").record.unwrap();
+        assert_eq!(a.run_accession, "SRR1");
+        assert!(queue.heartbeat("a", "SRR1"));
+        assert!(!queue.heartbeat("b", "SRR1"));
+
+        // b's claim sees a's lease as expired and gets SRR2; SRR1 is requeued.
+        let b = queue.claim_with_lease("b", Duration::ZERO).record.unwrap();
+        assert_eq!(b.run_accession, "SRR2");
+        assert_eq!(queue.status().pending, 1);
+
+        queue.report(RunReport {
+            run_id: "SRR2".into(),
+            worker: "b".into(),
+            ok: true,
+            error: None,
+            seconds: 1,
+        });
+        let c = queue.claim("c").record.unwrap();
+        assert_eq!(c.run_accession, "SRR1");
+
+        // Nothing pending, but c still holds SRR1: b must keep polling.
+        let idle = queue.claim("b");
+        assert!(idle.record.is_none());
+        assert_eq!(idle.claimed, 1);
+
+        // a's late report does not take SRR1 away from c.
+        queue.report(RunReport {
+            run_id: "SRR1".into(),
+            worker: "a".into(),
+            ok: true,
+            error: None,
+            seconds: 1,
+        });
+        assert_eq!(queue.status().claimed["SRR1"], "c");
+        queue.report(RunReport {
+            run_id: "SRR1".into(),
+            worker: "c".into(),
+            ok: false,
+            error: Some("boom".into()),
+            seconds: 1,
+        });
+
+        let drained = queue.claim("a");
+        assert!(drained.record.is_none());
+        assert_eq!(drained.claimed, 0);
+        let status = queue.status();
+        assert_eq!((status.succeeded, status.failed), (1, 1));
+        assert!(queue.is_finished());
+    }
+
+    #[test]
+    fn counts_each_run_once() {
+        let options = DownloadJob::accession("PRJNA1", "out").options;
+        let queue = WorkQueue::new(options, vec![record("SRR1")]);
+        let report = |run_id: &str| RunReport {
+            run_id: run_id.into(),
+            worker: "a".into(),
+            ok: true,
+            error: None,
+            seconds: 1,
+        };
+        assert_eq!(queue.claim("a").record.unwrap().run_accession, "SRR1");
+        queue.report(report("SRR1"));
+        queue.report(report("SRR1"));
+        queue.report(report("SRR9"));
+        let status = queue.status();
+        assert_eq!((status.succeeded, status.failed), (1, 0));
+        assert_eq!(status.reports.len(), 1);
+    }
+}
@@ -0,0 +1,445 @@
+//! Environment diagnosis for `polariseq doctor`.
+//!
+//! Each check reports a status plus, when it is not OK, the command or
+//! setting that fixes it. Nothing here downloads data or changes the
+//! configuration.
+
+use crate::controlled::PrefetchTransport;
+use crate::Config;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+/// Oldest SRA Toolkit release whose `prefetch`/`fasterq-dump` flags we use.
+pub const MIN_SRA_TOOLS_VERSION: (u32, u32, u32) = (3, 0, 0);
+
+const TOOL_TIMEOUT: Duration = Duration::from_secs(10);
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one diagnostic check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// Suggested fix when the check did not pass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        name: &str,
+        status: CheckStatus,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// First `x.y[.z]` version number in a tool's `--version` output.
+pub fn parse_version(text: &str) -> Option<(u32, u32, u32)> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter(|token| token.contains('.'))
+        .find_map(|token| {
+            let mut parts = token.split('.').map(|p| p.parse::<u32>().ok());
+            let major = parts.next()??;
+            let minor = parts.next()??;
+            let patch = parts.next().flatten().unwrap_or(0);
+            Some((major, minor, patch))
+        })
+}
+
+/// Run `<tool> --version` and return the first non-empty output line.
+pub async fn tool_version(path: &Path) -> Result<String> {
+    let output = tokio::time::timeout(
+        TOOL_TIMEOUT,
+        crate::audit::output(
+            Command::new(path)
+                .arg("--version")
+                .stdin(Stdio::null())
+                .kill_on_drop(true),
+        ),
+    )
+    .await
+    .map_err(|_| anyhow!("`{} --version` timed out", path.display()))?
+    .with_context(|| format!("Failed to run {}", path.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`{} --version` exited with {}",
+            path.display(),
+            output.status
+        ));
+    }
+    // sra-tools print their version to stdout, some builds to stderr.
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("`{} --version` printed nothing", path.display()))
+}
+
+async fn check_tool(name: &str, path: &Path, min: Option<(u32, u32, u32)>, fix: &str) -> Check {
+    let resolved = if path.is_absolute() {
+        Some(path.to_path_buf())
+    } else {
+        which::which(path).ok()
+    };
+    let Some(resolved) = resolved.filter(|p| p.exists()) else {
+        return Check::problem(
+            name,
+            CheckStatus::Fail,
+            format!("not found ({})", path.display()),
+            fix,
+        );
+    };
+    match tool_version(&resolved).await {
+        Ok(line) => match (min, parse_version(&line)) {
+            (Some(min), Some(found)) if found < min => Check::problem(
+                name,
+                CheckStatus::Fail,
+                format!("{} is older than {}.{}.{}", line, min.0, min.1, min.2),
+                fix,
+            ),
+            _ => Check::ok(name, format!("{} ({})", line, resolved.display())),
+        },
+        Err(e) => Check::problem(name, CheckStatus::Fail, format!("{:#}", e), fix),
+    }
+}
+
+/// External tools used by the download backends.
+pub async fn check_tools(config: &Config) -> Vec<Check> {
+    let sra_fix = "run `polariseq deps install` or set the path in polariseq.yaml";
+    vec![
+        check_tool(
+            "prefetch",
+            &config.software.prefetch,
+            Some(MIN_SRA_TOOLS_VERSION),
+            sra_fix,
+        )
+        .await,
+        check_tool(
+            "fasterq-dump",
+            &config.software.fasterq_dump,
+            Some(MIN_SRA_TOOLS_VERSION),
+            sra_fix,
+        )
+        .await,
+        check_tool(
+            "wget",
+            Path::new("wget"),
+            None,
+            "install wget (needed by the FTP backend), e.g. `apt install wget`",
+        )
+        .await,
+    ]
+}
+
+/// How an endpoint is probed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// A HEAD request; any HTTP response counts as reachable.
+    Https(&'static str),
+    /// A TCP connection to host and port. Aspera moves data over UDP on
+    /// the same port, which cannot be probed without a transfer.
+    Tcp(&'static str, u16),
+}
+
+/// An endpoint the metadata lookup or a backend depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Endpoint {
+    pub name: &'static str,
+    pub probe: Probe,
+    /// Whether the backend cannot work without it; otherwise an
+    /// unreachable endpoint only warns.
+    pub required: bool,
+    pub fix: &'static str,
+}
+
+const NETWORK_FIX: &str = "check DNS (/etc/resolv.conf), firewall, or set https_proxy";
+
+/// ENA portal API, used for the metadata of every accession.
+pub const ENA_PORTAL: Endpoint = Endpoint {
+    name: "ENA portal API (metadata)",
+    probe: Probe::Https("https://www.ebi.ac.uk/ena/portal/api/"),
+    required: true,
+    fix: "check DNS (/etc/resolv.conf), firewall, or set https_proxy; -T with a saved ena_metadata.tsv skips the lookup",
+};
+
+/// NCBI E-utilities: AWS run lookups and GEO series.
+pub const NCBI_EUTILS: Endpoint = Endpoint {
+    name: "NCBI E-utilities",
+    probe: Probe::Https("https://eutils.ncbi.nlm.nih.gov/entrez/eutils/"),
+    required: true,
+    fix: NETWORK_FIX,
+};
+
+const ENA_FTP_HTTPS: Endpoint = Endpoint {
+    name: "ENA FTP host over HTTPS (ftp backend)",
+    probe: Probe::Https("https://ftp.sra.ebi.ac.uk/vol1/"),
+    required: true,
+    fix: "check DNS, firewall or https_proxy, or use --download aws",
+};
+
+const ENA_FTP_PORT: Endpoint = Endpoint {
+    name: "ENA FTP port 21 (ftp backend)",
+    probe: Probe::Tcp("ftp.sra.ebi.ac.uk", 21),
+    required: false,
+    fix: "port 21 is blocked; files come over HTTP(S) instead",
+};
+
+const AWS_ODP: Endpoint = Endpoint {
+    name: "AWS Open Data S3 (aws backend)",
+    probe: Probe::Https("https://sra-pub-run-odp.s3.amazonaws.com/"),
+    required: true,
+    fix: "allow HTTPS to *.s3.amazonaws.com, or use --download ftp",
+};
+
+const NCBI_LOCATE: Endpoint = Endpoint {
+    name: "NCBI SRA locator (prefetch backend)",
+    probe: Probe::Https("https://locate.ncbi.nlm.nih.gov/sdl/2/retrieve"),
+    required: true,
+    fix: NETWORK_FIX,
+};
+
+const NCBI_ASPERA: Endpoint = Endpoint {
+    name: "NCBI Aspera port 33001 (prefetch fasp)",
+    probe: Probe::Tcp("ftp.ncbi.nlm.nih.gov", 33001),
+    required: true,
+    fix: "allow TCP and UDP 33001 out, or use --prefetch-transport https",
+};
+
+/// Endpoints `backend` depends on; `transport` is the prefetch transport.
+pub fn backend_endpoints(backend: &str, transport: Option<PrefetchTransport>) -> Vec<Endpoint> {
+    match backend {
+        "ftp" => vec![ENA_FTP_HTTPS, ENA_FTP_PORT],
+        "aws" => vec![AWS_ODP, NCBI_EUTILS],
+        "prefetch" => {
+            let mut endpoints = vec![NCBI_LOCATE];
+            match transport {
+                Some(PrefetchTransport::Fasp) => endpoints.push(NCBI_ASPERA),
+                // HTTPS remains when Aspera is blocked.
+                Some(PrefetchTransport::Both) => endpoints.push(Endpoint {
+                    required: false,
+                    ..NCBI_ASPERA
+                }),
+                _ => {}
+            }
+            endpoints
+        }
+        _ => Vec::new(),
+    }
+}
+
+async fn probe(client: &reqwest::Client, endpoint: &Endpoint) -> Check {
+    let status = if endpoint.required {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Warn
+    };
+    let result = match endpoint.probe {
+        Probe::Https(url) => client
+            .head(url)
+            .send()
+            .await
+            .map(|response| format!("HTTP {}", response.status().as_u16()))
+            .map_err(|e| e.to_string()),
+        Probe::Tcp(host, port) => {
+            match tokio::time::timeout(NETWORK_TIMEOUT, TcpStream::connect((host, port))).await {
+                Ok(Ok(_)) => Ok(format!("{}:{} open", host, port)),
+                Ok(Err(e)) => Err(format!("{}:{}: {}", host, port, e)),
+                Err(_) => Err(format!("{}:{}: timed out", host, port)),
+            }
+        }
+    };
+    match result {
+        Ok(detail) => Check::ok(endpoint.name, detail),
+        Err(detail) => Check::problem(endpoint.name, status, detail, endpoint.fix),
+    }
+}
+
+/// Reachability of `endpoints`, in order.
+pub async fn check_endpoints(endpoints: &[Endpoint]) -> Vec<Check> {
+    let client = match crate::http::client_builder()
+        .timeout(NETWORK_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Check::problem(
+                "network",
+                CheckStatus::Fail,
+                e.to_string(),
+                "check the TLS setup of this machine",
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    for endpoint in endpoints {
+        checks.push(probe(&client, endpoint).await);
+    }
+    checks
+}
+
+/// Reachability of the metadata API and every backend's endpoints.
+pub async fn check_network(config: &Config) -> Vec<Check> {
+    let mut endpoints = vec![ENA_PORTAL];
+    for backend in ["ftp", "aws", "prefetch"] {
+        for endpoint in backend_endpoints(backend, config.prefetch.transport) {
+            if !endpoints.contains(&endpoint) {
+                endpoints.push(endpoint);
+            }
+        }
+    }
+    check_endpoints(&endpoints).await
+}
+
+/// Free bytes on the filesystem holding `path` (via `df`, Unix only).
+fn free_space(path: &Path) -> Option<u64> {
+    let output = crate::audit::output_blocking(
+        std::process::Command::new("df")
+            .arg("-Pk")
+            .arg(path)
+            .stderr(Stdio::null()),
+    )
+    .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let available_kb: u64 = text
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(available_kb * 1024)
+}
+
+/// Write permission and free space of the output directory.
+pub fn check_output_dir(output: &Path, min_free_bytes: u64) -> Vec<Check> {
+    let name = "output directory";
+    if let Err(e) = std::fs::create_dir_all(output) {
+        return vec![Check::problem(
+            name,
+            CheckStatus::Fail,
+            format!("cannot create {}: {}", output.display(), e),
+            "pick another -o/--output or fix the permissions",
+        )];
+    }
+    let mut checks = vec![match tempfile::tempfile_in(output) {
+        Ok(_) => Check::ok(name, format!("{} is writable", output.display())),
+        Err(e) => Check::problem(
+            name,
+            CheckStatus::Fail,
+            format!("{} is not writable: {}", output.display(), e),
+            "pick another -o/--output or fix the permissions",
+        ),
+    }];
+    checks.push(match free_space(output) {
+        Some(free) if free < min_free_bytes => Check::problem(
+            "free space",
+            CheckStatus::Warn,
+            format!("{} free", indicatif::HumanBytes(free)),
+            format!(
+                "free up space or choose an output on a larger volume (>= {})",
+                indicatif::HumanBytes(min_free_bytes)
+            ),
+        ),
+        Some(free) => Check::ok(
+            "free space",
+            format!("{} free", indicatif::HumanBytes(free)),
+        ),
+        None => Check::problem(
+            "free space",
+            CheckStatus::Warn,
+            "could not be determined",
+            "check free space manually",
+        ),
+    });
+    checks
+}
+
+/// All checks, tools first.
+pub async fn run_checks(config: &Config, output: &Path, min_free_bytes: u64) -> Vec<Check> {
+    let mut checks = check_tools(config).await;
+    checks.extend(check_network(config).await);
+    checks.extend(check_output_dir(output, min_free_bytes));
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tool_versions() {
+        assert_eq!(parse_version("prefetch : 3.1.1"), Some((3, 1, 1)));
+        assert_eq!(
+            parse_version("GNU Wget 1.21.2 built on linux-gnu."),
+            Some((1, 21, 2))
+        );
+        assert_eq!(parse_version("fasterq-dump : 2.11"), Some((2, 11, 0)));
+        assert_eq!(parse_version("no version here"), None);
+        assert!(parse_version("prefetch : 2.11.0").unwrap() < MIN_SRA_TOOLS_VERSION);
+    }
+
+    #[test]
+    fn endpoints_follow_the_backend() {
+        let names = |backend, transport| -> Vec<&str> {
+            backend_endpoints(backend, transport)
+                .iter()
+                .map(|e| e.name)
+                .collect()
+        };
+        assert_eq!(names("aws", None), [AWS_ODP.name, NCBI_EUTILS.name]);
+        assert_eq!(
+            names("prefetch", Some(PrefetchTransport::Https)),
+            [NCBI_LOCATE.name]
+        );
+        let both = backend_endpoints("prefetch", Some(PrefetchTransport::Both));
+        assert_eq!(both[1].probe, Probe::Tcp("ftp.ncbi.nlm.nih.gov", 33001));
+        assert!(!both[1].required);
+        assert!(backend_endpoints("prefetch", Some(PrefetchTransport::Fasp))[1].required);
+        assert!(!backend_endpoints("ftp", None)[1].required);
+        assert!(backend_endpoints("custom", None).is_empty());
+    }
+
+    #[test]
+    fn writable_output_dir_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let checks = check_output_dir(&dir.path().join("out"), 0);
+        assert_eq!(checks[0].status, CheckStatus::Ok);
+        assert!(checks.iter().all(|c| c.status != CheckStatus::Fail));
+    }
+}
@@ -0,0 +1,478 @@
+//! EGA (European Genome-phenome Archive) download backend.
+//!
+//! EGA datasets (`EGAD…`) and files (`EGAF…`) are controlled-access: the
+//! user authenticates with their EGA username and password (or an already
+//! issued access token), file listings and checksums come from the EGA
+//! metadata API, and the payload is streamed in plain (decrypted) form over
+//! HTTP Range requests through the same [`ResumableDownloader`] used for S3.
+
+use crate::aws_s3::{ResumableDownloader, SraMetadata};
+use crate::observer::{CompletedInfo, DownloadObserver};
+use crate::progress::ProgressSink;
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+pub const DEFAULT_API_URL: &str = "https://ega.ebi.ac.uk:8443/v2";
+pub const DEFAULT_AUTH_URL: &str =
+    "https://idp.ega-archive.org/realms/EGA/protocol/openid-connect/token";
+pub const DEFAULT_CLIENT_ID: &str = "sf-client";
+
+/// Environment variables read by [`EgaCredentials::from_env`].
+pub const ENV_USERNAME: &str = "POLARISEQ_EGA_USERNAME";
+pub const ENV_PASSWORD: &str = "POLARISEQ_EGA_PASSWORD";
+pub const ENV_TOKEN: &str = "POLARISEQ_EGA_TOKEN";
+
+/// Size of the IV prepended to Crypt4GH/`.cip` payloads; the plain download
+/// is this much shorter than the archived file size.
+const CIP_HEADER_BYTES: u64 = 16;
+
+/// `ega:` section of `polariseq.yaml`. Every field is optional.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EgaConfig {
+    pub api_url: Option<String>,
+    pub auth_url: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl EgaConfig {
+    fn api_url(&self) -> &str {
+        self.api_url
+            .as_deref()
+            .unwrap_or(DEFAULT_API_URL)
+            .trim_end_matches('/')
+    }
+}
+
+/// How to authenticate against EGA.
+#[derive(Clone)]
+pub enum EgaCredentials {
+    /// Username/password exchanged for an access token (OIDC password grant).
+    Password { username: String, password: String },
+    /// Pre-issued bearer token.
+    Token(String),
+}
+
+impl std::fmt::Debug for EgaCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Password { username, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Token(_) => f.write_str("Token(..)"),
+        }
+    }
+}
+
+impl EgaCredentials {
+    /// Resolve credentials from `POLARISEQ_EGA_TOKEN`, or from a username
+    /// (`username` argument, else `POLARISEQ_EGA_USERNAME`) plus
+    /// `POLARISEQ_EGA_PASSWORD`. Secrets are never taken from the command line.
+    pub fn from_env(username: Option<&str>) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let username = username.map(str::to_string).or_else(|| var(ENV_USERNAME));
+        match (username, var(ENV_PASSWORD), var(ENV_TOKEN)) {
+            (Some(username), Some(password), _) => Ok(Self::Password { username, password }),
+            (_, _, Some(token)) => Ok(Self::Token(token)),
+            (Some(_), None, None) => Err(anyhow!(
+                "EGA password missing: set {} for the EGA account",
+                ENV_PASSWORD
+            )),
+            (None, _, None) => Err(anyhow!(
+                "EGA credentials missing: pass --ega-user and set {}, or set {}",
+                ENV_PASSWORD,
+                ENV_TOKEN
+            )),
+        }
+    }
+}
+
+/// A downloadable EGA file as described by the metadata API.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EgaFile {
+    pub file_id: String,
+    #[serde(default)]
+    pub dataset_id: Option<String>,
+    #[serde(default)]
+    pub display_file_name: Option<String>,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    pub file_size: u64,
+    #[serde(default)]
+    pub unencrypted_checksum: Option<String>,
+    #[serde(default)]
+    pub file_status: Option<String>,
+}
+
+impl EgaFile {
+    /// Local file name: the display name without the `.cip` suffix, falling
+    /// back to the file accession.
+    pub fn local_name(&self) -> String {
+        let name = self
+            .display_file_name
+            .as_deref()
+            .or(self.file_name.as_deref())
+            .and_then(|n| n.rsplit('/').next())
+            .filter(|n| !n.is_empty())
+            .unwrap_or(&self.file_id);
+        name.strip_suffix(".cip").unwrap_or(name).to_string()
+    }
+
+    /// Size of the plain (decrypted) payload.
+    pub fn plain_size(&self) -> u64 {
+        let encrypted = self
+            .display_file_name
+            .as_deref()
+            .or(self.file_name.as_deref())
+            .is_some_and(|n| n.ends_with(".cip"));
+        if encrypted {
+            self.file_size.saturating_sub(CIP_HEADER_BYTES)
+        } else {
+            self.file_size
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.file_status
+            .as_deref()
+            .is_none_or(|s| s.eq_ignore_ascii_case("available"))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Authenticated EGA metadata client.
+#[derive(Clone)]
+pub struct EgaClient {
+    client: Client,
+    api_url: String,
+    token: String,
+}
+
+impl EgaClient {
+    /// Authenticate and return a client holding a bearer token.
+    pub async fn connect(config: &EgaConfig, credentials: &EgaCredentials) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+        let token = match credentials {
+            EgaCredentials::Token(token) => token.clone(),
+            EgaCredentials::Password { username, password } => {
+                let auth_url = config.auth_url.as_deref().unwrap_or(DEFAULT_AUTH_URL);
+                let mut form = vec![
+                    ("grant_type", "password"),
+                    (
+                        "client_id",
+                        config.client_id.as_deref().unwrap_or(DEFAULT_CLIENT_ID),
+                    ),
+                    ("scope", "openid"),
+                    ("username", username.as_str()),
+                    ("password", password.as_str()),
+                ];
+                if let Some(secret) = config.client_secret.as_deref() {
+                    form.push(("client_secret", secret));
+                }
+                let response = client
+                    .post(auth_url)
+                    .form(&form)
+                    .send()
+                    .await
+                    .context("Failed to reach the EGA authentication server")?;
+                if !response.status().is_success() {
+                    return Err(anyhow!(
+                        "EGA authentication failed for {} (HTTP {})",
+                        username,
+                        response.status()
+                    ));
+                }
+                response
+                    .json::<TokenResponse>()
+                    .await
+                    .context("Invalid EGA token response")?
+                    .access_token
+            }
+        };
+        Ok(Self {
+            client,
+            api_url: config.api_url().to_string(),
+            token,
+        })
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}{}", self.api_url, path);
+        let response = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .with_context(|| format!("EGA request failed: {}", url))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("EGA API returned HTTP {} for {}", status, url));
+        }
+        response
+            .json::<T>()
+            .await
+            .with_context(|| format!("Invalid EGA response from {}", url))
+    }
+
+    /// List the files of a dataset the account is authorized for.
+    pub async fn dataset_files(&self, dataset_id: &str) -> Result<Vec<EgaFile>> {
+        self.get_json(&format!("/metadata/datasets/{}/files", dataset_id))
+            .await
+    }
+
+    /// Fetch a single file record, including its plain checksum.
+    pub async fn file(&self, file_id: &str) -> Result<EgaFile> {
+        self.get_json(&format!("/metadata/files/{}", file_id)).await
+    }
+
+    /// Expand an `EGAD…` or `EGAF…` accession into downloadable files.
+    pub async fn resolve(&self, accession: &str) -> Result<Vec<EgaFile>> {
+        match accession_kind(accession) {
+            Some(AccessionKind::Dataset) => self.dataset_files(accession).await,
+            Some(AccessionKind::File) => Ok(vec![self.file(accession).await?]),
+            None => Err(anyhow!(
+                "Not an EGA dataset/file accession: {} (expected EGAD… or EGAF…)",
+                accession
+            )),
+        }
+    }
+
+    /// Ranged download URL for the plain (decrypted) payload of a file.
+    pub fn download_url(&self, file_id: &str) -> String {
+        format!("{}/files/{}?destinationFormat=plain", self.api_url, file_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessionKind {
+    Dataset,
+    File,
+}
+
+pub fn accession_kind(accession: &str) -> Option<AccessionKind> {
+    let digits = |rest: &str| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit());
+    if let Some(rest) = accession.strip_prefix("EGAD") {
+        digits(rest).then_some(AccessionKind::Dataset)
+    } else if let Some(rest) = accession.strip_prefix("EGAF") {
+        digits(rest).then_some(AccessionKind::File)
+    } else {
+        None
+    }
+}
+
+/// Downloads EGA files concurrently with resume and MD5 verification.
+#[derive(Clone)]
+pub struct EgaDownloader {
+    client: EgaClient,
+    file_workers: usize,
+    inner_workers: usize,
+    chunk_size_mb: u64,
+    progress: Option<Arc<dyn ProgressSink>>,
+    observer: Option<Arc<dyn DownloadObserver>>,
+}
+
+impl EgaDownloader {
+    pub fn new(client: EgaClient) -> Self {
+        Self {
+            client,
+            file_workers: 4,
+            inner_workers: 4,
+            chunk_size_mb: 64,
+            progress: None,
+            observer: None,
+        }
+    }
+
+    pub fn with_workers(mut self, file_workers: usize, inner_workers: usize) -> Self {
+        self.file_workers = file_workers.max(1);
+        self.inner_workers = inner_workers.max(1);
+        self
+    }
+
+    pub fn with_chunk_size_mb(mut self, chunk_size_mb: u64) -> Self {
+        self.chunk_size_mb = chunk_size_mb.max(1);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn DownloadObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Download every file into `output_dir/<dataset_id>/`, or straight into
+    /// `output_dir` when the file record carries no dataset.
+    pub async fn download_files(&self, files: &[EgaFile], output_dir: &Path) -> Result<()> {
+        let files: Vec<EgaFile> = files
+            .iter()
+            .filter(|f| {
+                let available = f.is_available();
+                if !available {
+                    warn!("[{}] Skipping unavailable EGA file", f.file_id);
+                }
+                available
+            })
+            .cloned()
+            .collect();
+        if let Some(observer) = &self.observer {
+            observer.set_total(files.len() as u64);
+        }
+
+        let semaphore = Arc::new(Semaphore::new(self.file_workers));
+        let mut handles = Vec::with_capacity(files.len());
+        for file in files {
+            let downloader = self.clone();
+            let semaphore = semaphore.clone();
+            let save_dir = match &file.dataset_id {
+                Some(dataset) => output_dir.join(dataset),
+                None => output_dir.to_path_buf(),
+            };
+            handles.push(tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await.expect("semaphore closed");
+                downloader.download_file(&file, save_dir).await
+            }));
+        }
+
+        let total = handles.len();
+        let mut failed = 0usize;
+        let mut first_error = None;
+        for handle in handles {
+            if let Err(e) = handle.await.context("EGA download task panicked")? {
+                warn!("{:#}", e);
+                failed += 1;
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+        if let Some(error) = first_error {
+            return Err(error.context(format!("{} of {} EGA file(s) failed", failed, total)));
+        }
+        Ok(())
+    }
+
+    async fn download_file(&self, file: &EgaFile, save_dir: PathBuf) -> Result<()> {
+        // Dataset listings may omit checksums; the per-file record has them.
+        let md5 = match &file.unencrypted_checksum {
+            Some(md5) => Some(md5.clone()),
+            None => self
+                .client
+                .file(&file.file_id)
+                .await
+                .ok()
+                .and_then(|f| f.unencrypted_checksum),
+        };
+        if md5.is_none() {
+            warn!(
+                "[{}] No checksum from EGA; verifying size only",
+                file.file_id
+            );
+        }
+
+        let size = file.plain_size();
+        let start = std::time::Instant::now();
+        let counter = self
+            .observer
+            .as_ref()
+            .map(|observer| observer.register(&file.file_id, size));
+
+        let mut downloader = ResumableDownloader::new(
+            file.file_id.clone(),
+            SraMetadata {
+                s3_uri: format!("ega://{}/{}", file.file_id, file.local_name()),
+                http_url: self.client.download_url(&file.file_id),
+                md5,
+                size,
+            },
+            save_dir,
+            self.chunk_size_mb,
+            self.inner_workers,
+            self.progress.clone(),
+            None,
+        )
+        .await?
+        .with_bearer_token(self.client.token())?;
+        if let Some(counter) = counter {
+            downloader = downloader.with_progress_bytes(counter);
+        }
+
+        let outcome = downloader.start().await;
+
+        if let Some(observer) = &self.observer {
+            observer.unregister(&file.file_id);
+            match &outcome {
+                Ok(true) => {
+                    let elapsed = start.elapsed().as_secs_f64();
+                    observer.complete(CompletedInfo {
+                        id: file.file_id.clone(),
+                        total_bytes: size,
+                        elapsed_secs: elapsed,
+                        avg_speed_bps: size as f64 / elapsed.max(0.001),
+                    });
+                }
+                _ => observer.fail(&file.file_id),
+            }
+        }
+
+        if outcome? {
+            info!("[{}] {} downloaded", file.file_id, file.local_name());
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "[{}] EGA download failed verification",
+                file.file_id
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_ega_accessions() {
+        assert_eq!(
+            accession_kind("EGAD00001000001"),
+            Some(AccessionKind::Dataset)
+        );
+        assert_eq!(accession_kind("EGAF00000000014"), Some(AccessionKind::File));
+        assert_eq!(accession_kind("EGAS00001000001"), None);
+        assert_eq!(accession_kind("EGAD"), None);
+        assert_eq!(accession_kind("SRR000001"), None);
+    }
+
+    #[test]
+    fn strips_cip_suffix_and_header() {
+        let file: EgaFile = serde_json::from_str(
+            r#"{"fileId":"EGAF00000000014","datasetId":"EGAD00001000001",
+                "displayFileName":"sample/reads_1.fastq.gz.cip","fileSize":1016,
+                "unencryptedChecksum":"d41d8cd98f00b204e9800998ecf8427e","fileStatus":"available"}"#,
+        )
+        .unwrap();
+        assert_eq!(file.local_name(), "reads_1.fastq.gz");
+        assert_eq!(file.plain_size(), 1000);
+        assert!(file.is_available());
+    }
+}
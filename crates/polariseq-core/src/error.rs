@@ -0,0 +1,109 @@
+//! Typed failure categories.
+//!
+//! The crate still returns `anyhow::Result` everywhere; the points that know
+//! *why* something failed raise a [`PolariseqError`] instead of a plain
+//! message. Callers recover the category with [`classify`] (it survives
+//! `.context(..)` wrapping) and the CLI maps it to a process exit code.
+
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PolariseqError {
+    /// ENA/SRA/GEO metadata could not be fetched, parsed or was empty.
+    #[error("metadata error: {0}")]
+    Metadata(String),
+
+    /// A transfer failed after retries (HTTP status, connection, chunk budget).
+    #[error("network error: {0}")]
+    Network(String),
+
+    /// A downloaded file does not match its published checksum or size.
+    #[error("checksum mismatch for {file}")]
+    ChecksumMismatch { file: String },
+
+    /// `fasterq-dump` or compression produced no usable FASTQ.
+    #[error("conversion failed for {run}: {reason}")]
+    ConversionFailed { run: String, reason: String },
+
+    /// A required external tool is not installed where configured.
+    #[error("{tool} not found at configured path: {}", path.display())]
+    DependencyMissing { tool: String, path: PathBuf },
+
+    /// A tool exists but is not executable or fails `--version`.
+    #[error("{tool} is not usable: {reason}")]
+    DependencyUnusable { tool: String, reason: String },
+
+    /// Missing or unreadable `polariseq.yaml`.
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    /// Bad accession, filter pattern or flag combination.
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+
+    /// The job was cancelled before it finished.
+    #[error("cancelled")]
+    Cancelled,
+}
+
+impl PolariseqError {
+    /// Stable machine-readable name, e.g. for reports and logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Metadata(_) => "metadata",
+            Self::Network(_) => "network",
+            Self::ChecksumMismatch { .. } => "checksum-mismatch",
+            Self::ConversionFailed { .. } => "conversion-failed",
+            Self::DependencyMissing { .. } => "dependency-missing",
+            Self::DependencyUnusable { .. } => "dependency-unusable",
+            Self::Config(_) => "config",
+            Self::InvalidInput(_) => "invalid-input",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Process exit code for this category. `1` stays the generic failure
+    /// and `2` matches clap's usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::InvalidInput(_) => 2,
+            Self::Config(_) => 3,
+            Self::DependencyMissing { .. } | Self::DependencyUnusable { .. } => 4,
+            Self::Metadata(_) => 5,
+            Self::Network(_) => 6,
+            Self::ChecksumMismatch { .. } => 7,
+            Self::ConversionFailed { .. } => 8,
+            Self::Cancelled => 130,
+        }
+    }
+}
+
+/// The first [`PolariseqError`] in `err`'s cause chain, if any.
+pub fn classify(err: &anyhow::Error) -> Option<&PolariseqError> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<PolariseqError>())
+}
+
+/// Exit code for `err`: its category's code, or `1` when untyped.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    classify(err).map_or(1, PolariseqError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn classification_survives_context() {
+        let err = Err::<(), _>(PolariseqError::ChecksumMismatch {
+            file: "SRR1_1.fastq.gz".into(),
+        })
+        .context("FTP download failed")
+        .unwrap_err();
+        assert_eq!(classify(&err).unwrap().kind(), "checksum-mismatch");
+        assert_eq!(exit_code(&err), 7);
+        assert_eq!(exit_code(&anyhow::anyhow!("untyped")), 1);
+    }
+}
@@ -1,7 +1,9 @@
-use crate::progress::{spinner_style, transfer_bar_style};
-use crate::{Config, ProcessedRecord};
-use anyhow::{anyhow, Result};
-use indicatif::{MultiProgress, ProgressBar};
+use crate::aws_s3::PauseToken;
+use crate::error::PolariseqError;
+use crate::progress::{start_progress, ProgressKind, ProgressSink};
+use crate::quarantine::quarantine_or_remove;
+use crate::{run_pausable_lines, Config, ProcessedRecord};
+use anyhow::Result;
 use std::path::Path;
 use std::process::Stdio;
 use std::sync::Arc;
@@ -9,19 +11,23 @@ use tokio::fs::{self, File}; // Import fs for checking file size
 use tokio::io::AsyncReadExt;
 use tokio::process::Command;
 use tokio::sync::Semaphore;
-use tokio::time::{sleep, Duration}; // Import time
-use tracing::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn, Instrument};
 
 pub enum Protocol {
     Ftp,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_downloads(
     records: &[ProcessedRecord],
     _config: &Config,
     output_dir: &Path,
     _protocol: Protocol,
     threads: usize,
+    progress: Option<Arc<dyn ProgressSink>>,
+    cancel: CancellationToken,
+    pause: PauseToken,
 ) -> Result<()> {
     info!(
         "Starting FTP download pipeline with {} threads...",
@@ -29,7 +35,6 @@ pub async fn process_downloads(
     );
 
     let semaphore = Arc::new(Semaphore::new(threads));
-    let mp = Arc::new(MultiProgress::new());
     let mut handles = Vec::new();
 
     struct Task {
@@ -63,7 +68,9 @@ pub async fn process_downloads(
     }
     for task in tasks {
         let sem = semaphore.clone();
-        let mp = mp.clone();
+        let progress = progress.clone();
+        let cancel = cancel.clone();
+        let pause = pause.clone();
         let output_dir = output_dir.to_path_buf();
 
         let t_url = task.url.clone();
@@ -71,118 +78,166 @@ pub async fn process_downloads(
         let t_file = task.filename.clone();
         let t_size = task.total_size; // 
 
-        let (cmd_bin, cmd_args, cmd_string_for_script) = (
-            "wget".to_string(),
-            vec!["-c".to_string(), t_url.clone()],
-            format!("wget -c {}", t_url),
-        );
+        // wget writes `<name>.part`; it is renamed once the MD5 matches.
+        let t_part = format!("{}.part", t_file);
 
-        let handle = tokio::spawn(async move {
-            let _permit = sem.acquire().await.expect("semaphore closed");
-
-            // ProgressBar init: Show bar if size available, else show Spinner
-            let pb = if t_size > 0 {
-                let p = mp.add(ProgressBar::new(t_size));
-                p.set_style(transfer_bar_style());
-                p
-            } else {
-                let p = mp.add(ProgressBar::new_spinner());
-                p.set_style(spinner_style());
-                p
-            };
-
-            pb.set_prefix(t_file.clone());
-            pb.enable_steady_tick(Duration::from_millis(120));
-
-            let output_file_path = output_dir.join(&t_file);
-
-            // Check existing file
-            if output_file_path.exists() {
-                // If file exists and size matches (simple check), or MD5 matches
-                if let Ok(meta) = fs::metadata(&output_file_path).await {
-                    if meta.len() == t_size && t_size > 0 {
-                        // Size matches, verify MD5 first
-                        pb.set_message("Checking existing file...");
-                        if let Ok(true) = verify_md5(&output_file_path, &t_md5).await {
-                            pb.finish_and_clear();
-                            return Ok(());
-                        }
-                    } else if meta.len() > 0 {
-                        // Set current progress before resuming
-                        pb.set_position(meta.len());
-                    }
-                }
-            }
+        let handle = tokio::spawn(
+            async move {
+                let _permit = sem.acquire().await.expect("semaphore closed");
 
-            pb.set_message("Downloading");
+                // ProgressBar init: Show bar if size available, else show Spinner
+                let kind = if t_size > 0 {
+                    ProgressKind::Transfer
+                } else {
+                    ProgressKind::Spinner
+                };
+                let pb = start_progress(progress.as_ref(), &t_file, kind, t_size);
 
-            // Start background monitor: Check file size every 500ms and update progress
-            let monitor_path = output_file_path.clone();
-            let monitor_pb = pb.clone();
-            let monitor_handle = tokio::spawn(async move {
-                loop {
-                    sleep(Duration::from_millis(500)).await;
-                    if let Ok(meta) = fs::metadata(&monitor_path).await {
-                        monitor_pb.set_position(meta.len());
+                let output_file_path = output_dir.join(&t_file);
+                let part_file_path = output_dir.join(&t_part);
+
+                // Check existing file
+                if output_file_path.exists() {
+                    // If file exists and size matches (simple check), or MD5 matches
+                    if let Ok(meta) = fs::metadata(&output_file_path).await {
+                        if meta.len() == t_size && t_size > 0 {
+                            // Size matches, verify MD5 first
+                            pb.set_message("Checking existing file...");
+                            if let Ok(true) = verify_md5(&output_file_path, &t_md5).await {
+                                pb.finish();
+                                return Ok(());
+                            }
+                            // Complete but corrupt: resuming onto it cannot help.
+                            quarantine_or_remove(&output_file_path, "MD5 mismatch");
+                        }
+                    }
+                    // Left unfinished by a version that downloaded in place.
+                    if output_file_path.exists() && !part_file_path.exists() {
+                        fs::rename(&output_file_path, &part_file_path).await?;
                     }
                 }
-            });
+                if let Ok(meta) = fs::metadata(&part_file_path).await {
+                    // Set current progress before resuming
+                    pb.set_position(meta.len());
+                }
 
-            // Execute download command
-            let output = Command::new(&cmd_bin)
-                .args(&cmd_args)
-                .current_dir(&output_dir)
-                .stdout(Stdio::null())
-                .stderr(Stdio::piped())
-                .output()
-                .await;
-
-            // Download finished, stop monitor
-            monitor_handle.abort();
-
-            match output {
-                Ok(out) => {
-                    if !out.status.success() {
-                        let stderr = String::from_utf8_lossy(&out.stderr);
-                        pb.finish_with_message(format!("Failed (Exit {})", out.status));
-                        error!(
-                            "Command failed: {}\nError: {}",
-                            cmd_string_for_script, stderr
+                pb.set_message("Downloading");
+
+                // Try each mirror in turn; wget -c resumes the same `.part`.
+                let mirrors = mirror_urls(&t_url);
+                let mut served_by = None;
+                let mut last_failure = None;
+                for url in &mirrors {
+                    if let Some((failure, status)) = &last_failure {
+                        warn!(
+                            "{} failed ({}, {}); retrying on {}",
+                            t_file, failure, status, url
                         );
-                        return Err(anyhow!("Download failed"));
+                        pb.set_message("Retrying on mirror");
+                    }
+                    // Execute download command; a job bandwidth limit is split
+                    // with the transfers already running.
+                    let mut command = Command::new("wget");
+                    command.args(crate::http::wget_args());
+                    if let Some(rate) = pause.transfer_share() {
+                        command.arg(format!("--limit-rate={}", rate));
+                    }
+                    // wget's dot lines drive the bar and its rate.
+                    let line_pb = pb.clone();
+                    let output = run_pausable_lines(
+                        command
+                            .args(["-c", "--progress=dot:mega", "-O", &t_part, url])
+                            .current_dir(&output_dir)
+                            .stdout(Stdio::null()),
+                        &cancel,
+                        &pause,
+                        move |line| {
+                            if let Some((bytes, rate)) = parse_dot_line(line) {
+                                line_pb.set_position(bytes);
+                                if let Some(rate) = rate {
+                                    line_pb.set_message(&format!("Downloading ({}B/s)", rate));
+                                }
+                            }
+                        },
+                    )
+                    .await;
+                    match output {
+                        Ok(out) if out.status.success() => {
+                            served_by = Some(url.clone());
+                            break;
+                        }
+                        Ok(out) => {
+                            let stderr = String::from_utf8_lossy(&out.stderr);
+                            let failure = WgetFailure::classify(out.status.code(), &stderr);
+                            error!(
+                                "Command failed: wget -c -O {} {} ({})\nError: {}",
+                                t_part,
+                                url,
+                                failure,
+                                wget_messages(&stderr)
+                            );
+                            last_failure = Some((failure, out.status));
+                            // Another mirror cannot fix the local disk.
+                            if failure.is_local() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            pb.fail(&format!("Exec Error: {}", e));
+                            return Err(e);
+                        }
                     }
                 }
-                Err(e) => {
-                    pb.finish_with_message(format!("Exec Error: {}", e));
-                    return Err(anyhow::anyhow!(e));
-                }
-            }
 
-            // Complete progress bar (in case monitor missed the last update)
-            if t_size > 0 {
-                pb.set_position(t_size);
-            }
+                let Some(served_by) = served_by else {
+                    let (failure, status) = last_failure.expect("at least one mirror");
+                    pb.fail(&format!("Failed: {} (Exit {})", failure, status));
+                    return Err(PolariseqError::Network(format!(
+                        "wget failed for {} on {} mirror(s): {} ({})",
+                        t_file,
+                        mirrors.len(),
+                        failure,
+                        status
+                    ))
+                    .into());
+                };
 
-            pb.set_message("Verifying MD5");
-            match verify_md5(&output_file_path, &t_md5).await {
-                Ok(true) => {
-                    pb.finish_and_clear();
-                    Ok(())
-                }
-                Ok(false) => {
-                    pb.finish_with_message("MD5 Mismatch");
-                    warn!(
-                        "MD5 Mismatch for {}: expected {}, but check failed.",
-                        t_file, t_md5
-                    );
-                    Err(anyhow!("MD5 mismatch"))
+                // Complete progress bar (in case monitor missed the last update)
+                if t_size > 0 {
+                    pb.set_position(t_size);
                 }
-                Err(e) => {
-                    pb.finish_with_message(format!("Check Error: {}", e));
-                    Err(e)
+
+                pb.set_message("Verifying MD5");
+                match verify_md5(&part_file_path, &t_md5).await {
+                    Ok(true) => {
+                        fs::rename(&part_file_path, &output_file_path).await?;
+                        if let Err(e) = record_source(&output_dir, &t_file, &served_by) {
+                            warn!("Failed to record the source of {}: {}", t_file, e);
+                        }
+                        pb.finish();
+                        Ok(())
+                    }
+                    Ok(false) => {
+                        pb.fail("MD5 Mismatch");
+                        warn!(
+                            "MD5 Mismatch for {}: expected {}, but check failed.",
+                            t_file, t_md5
+                        );
+                        // The retry must not resume onto the bad bytes.
+                        quarantine_or_remove(
+                            &part_file_path,
+                            &format!("MD5 mismatch (expected {})", t_md5),
+                        );
+                        Err(PolariseqError::ChecksumMismatch { file: t_file }.into())
+                    }
+                    Err(e) => {
+                        pb.fail(&format!("Check Error: {}", e));
+                        Err(e)
+                    }
                 }
             }
-        });
+            .in_current_span(),
+        );
         handles.push(handle);
     }
 
@@ -208,7 +263,6 @@ pub async fn process_downloads(
         }
     }
 
-    mp.clear().ok();
     if failed > 0 {
         return Err(first_err.unwrap_or_else(|| {
             anyhow::anyhow!("{} FTP download task(s) failed", failed)
@@ -217,6 +271,151 @@ pub async fn process_downloads(
     Ok(())
 }
 
+/// Bytes per dot of `wget --progress=dot:mega`.
+const DOT_BYTES: u64 = 64 * 1024;
+
+/// Bytes fetched so far and the current rate from one
+/// `wget --progress=dot:mega` line, e.g.
+/// `  3072K ........ ........ ........  2% 5.21M 9m13s`. Dots already on
+/// disk when resuming are shown as commas and count too.
+fn parse_dot_line(line: &str) -> Option<(u64, Option<&str>)> {
+    let (offset, rest) = line.trim_start().split_once("K ")?;
+    let offset: u64 = offset.parse().ok()?;
+    let dots: usize = rest
+        .split_whitespace()
+        .take_while(|t| t.chars().all(|c| c == '.' || c == ','))
+        .map(str::len)
+        .sum();
+    if dots == 0 {
+        return None;
+    }
+    let rate = rest
+        .split_whitespace()
+        .skip_while(|t| !t.ends_with('%'))
+        .nth(1)
+        .map(|t| t.split('=').next().unwrap_or(t));
+    Some((offset * 1024 + dots as u64 * DOT_BYTES, rate))
+}
+
+/// wget's stderr without its progress lines.
+fn wget_messages(stderr: &str) -> String {
+    stderr
+        .lines()
+        .filter(|line| parse_dot_line(line).is_none() && !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Why a wget transfer failed, from its exit code and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WgetFailure {
+    Dns,
+    Auth,
+    NotFound,
+    RateLimited,
+    DiskFull,
+    /// Writing the output failed for another reason (permissions, ...).
+    LocalIo,
+    Network,
+}
+
+impl WgetFailure {
+    pub fn classify(code: Option<i32>, stderr: &str) -> Self {
+        let stderr = stderr.to_ascii_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|n| stderr.contains(n));
+        if has(&["no space left on device", "disk quota exceeded"]) {
+            Self::DiskFull
+        } else if has(&[
+            "unable to resolve host",
+            "name or service not known",
+            "temporary failure in name resolution",
+        ]) {
+            Self::Dns
+        } else if has(&["too many requests", "too many connections", "421 "]) {
+            Self::RateLimited
+        } else if code == Some(6) || has(&["unauthorized", "forbidden", "login incorrect"]) {
+            Self::Auth
+        } else if has(&["not found", "no such file"]) {
+            Self::NotFound
+        } else if code == Some(3) {
+            Self::LocalIo
+        } else {
+            Self::Network
+        }
+    }
+
+    /// The failure is on this machine, not the server's.
+    pub fn is_local(self) -> bool {
+        matches!(self, Self::DiskFull | Self::LocalIo)
+    }
+}
+
+impl std::fmt::Display for WgetFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Dns => "dns",
+            Self::Auth => "auth",
+            Self::NotFound => "not-found",
+            Self::RateLimited => "rate-limited",
+            Self::DiskFull => "disk-full",
+            Self::LocalIo => "local-io",
+            Self::Network => "network",
+        })
+    }
+}
+
+/// ENA's FTP host; its files are also served over HTTPS and by the FIRE
+/// object store.
+const ENA_FTP_HOST: &str = "ftp.sra.ebi.ac.uk";
+
+/// FIRE endpoints exposing `vol1/` as `fire/public/era/`.
+const ENA_FIRE_HOSTS: [&str; 2] = ["hh.fire.sdo.ebi.ac.uk", "hl.fire.sdo.ebi.ac.uk"];
+
+/// `<output>/mirrors.tsv`: which URL each FTP-backend file came from.
+pub const SOURCES_FILE: &str = "mirrors.tsv";
+
+/// `url` as ENA lists it, then the same file on the other ENA endpoints:
+/// FTP and HTTPS on the primary host, then the FIRE mirrors. Other URLs
+/// have no mirrors.
+pub fn mirror_urls(url: &str) -> Vec<String> {
+    let bare = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let mut urls = vec![url.to_string()];
+    if let Some(path) = bare
+        .strip_prefix(ENA_FTP_HOST)
+        .and_then(|p| p.strip_prefix('/'))
+    {
+        urls.push(format!("ftp://{}/{}", ENA_FTP_HOST, path));
+        urls.push(format!("https://{}/{}", ENA_FTP_HOST, path));
+        if let Some(rest) = path.strip_prefix("vol1/") {
+            for host in ENA_FIRE_HOSTS {
+                urls.push(format!("https://{}/fire/public/era/{}", host, rest));
+            }
+        }
+    }
+    urls.dedup();
+    urls
+}
+
+/// Append `file` and the URL it was fetched from to [`SOURCES_FILE`].
+fn record_source(output_dir: &Path, file: &str, url: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut sources = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_dir.join(SOURCES_FILE))?;
+    writeln!(sources, "{}\t{}", file, url)
+}
+
+/// URL `file` was last fetched from, per [`SOURCES_FILE`].
+pub fn recorded_source(output_dir: &Path, file: &str) -> Option<String> {
+    let sources = std::fs::read_to_string(output_dir.join(SOURCES_FILE)).ok()?;
+    sources
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .rfind(|(name, _)| *name == file)
+        .map(|(_, url)| url.to_string())
+}
+
 async fn verify_md5(path: &Path, expected: &str) -> Result<bool> {
     if !path.exists() {
         return Ok(false);
@@ -234,3 +433,77 @@ async fn verify_md5(path: &Path, expected: &str) -> Result<bool> {
     let digest = context.compute();
     Ok(format!("{:x}", digest) == expected)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ena_urls_fall_back_to_mirrors() {
+        let urls = mirror_urls("ftp.sra.ebi.ac.uk/vol1/fastq/SRR000/SRR000001/SRR000001.fastq.gz");
+        assert_eq!(urls.len(), 5);
+        assert_eq!(
+            urls[0],
+            "ftp.sra.ebi.ac.uk/vol1/fastq/SRR000/SRR000001/SRR000001.fastq.gz"
+        );
+        assert_eq!(
+            urls[3],
+            "https://hh.fire.sdo.ebi.ac.uk/fire/public/era/fastq/SRR000/SRR000001/SRR000001.fastq.gz"
+        );
+        assert_eq!(mirror_urls("https://example.org/a.fastq.gz").len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        record_source(dir.path(), "a.fastq.gz", &urls[0]).unwrap();
+        record_source(dir.path(), "a.fastq.gz", &urls[3]).unwrap();
+        assert_eq!(
+            recorded_source(dir.path(), "a.fastq.gz").as_deref(),
+            Some(urls[3].as_str())
+        );
+        assert_eq!(recorded_source(dir.path(), "b.fastq.gz"), None);
+    }
+
+    #[test]
+    fn reads_wget_progress_and_failures() {
+        assert_eq!(
+            parse_dot_line("  3072K ........ ........ ........  2% 5.21M 9m13s"),
+            Some((3072 * 1024 + 24 * DOT_BYTES, Some("5.21M")))
+        );
+        assert_eq!(
+            parse_dot_line("  6144K ,,,,,,,, ....              100% 6.1M=2.1s"),
+            Some((6144 * 1024 + 12 * DOT_BYTES, Some("6.1M")))
+        );
+        assert_eq!(
+            parse_dot_line("Resolving ftp.sra.ebi.ac.uk... 193.62.193.165"),
+            None
+        );
+
+        let classify = WgetFailure::classify;
+        assert_eq!(
+            classify(
+                Some(4),
+                "wget: unable to resolve host address 'ftp.sra.ebi.ac.uk'"
+            ),
+            WgetFailure::Dns
+        );
+        assert_eq!(
+            classify(Some(8), "ERROR 429: Too Many Requests."),
+            WgetFailure::RateLimited
+        );
+        assert_eq!(
+            classify(
+                Some(3),
+                "Cannot write to 'a.part' (No space left on device)."
+            ),
+            WgetFailure::DiskFull
+        );
+        assert_eq!(
+            classify(Some(8), "ERROR 404: Not Found."),
+            WgetFailure::NotFound
+        );
+        assert_eq!(
+            classify(Some(4), "Connection timed out."),
+            WgetFailure::Network
+        );
+        assert!(WgetFailure::DiskFull.is_local());
+    }
+}
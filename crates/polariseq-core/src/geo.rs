@@ -0,0 +1,234 @@
+//! GEO series (GSE) support: BioProject resolution and supplementary files.
+//!
+//! Raw reads of a GEO series live in SRA/ENA under its BioProject, which the
+//! series' SOFT "quick" view lists as `!Series_relation = BioProject: …`.
+//! Processed supplementary files (count matrices, `GSE…_RAW.tar`, …) only
+//! exist on the GEO FTP tree under `geo/series/GSEnnn/<GSE>/suppl/`.
+
+use crate::aws_s3::{ResumableDownloader, SraMetadata};
+use crate::circuit;
+use crate::progress::ProgressSink;
+use anyhow::{anyhow, Context, Result};
+use reqwest::{header, Client};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const GEO_QUERY_URL: &str = "https://www.ncbi.nlm.nih.gov/geo/query/acc.cgi";
+const GEO_SERIES_URL: &str = "https://ftp.ncbi.nlm.nih.gov/geo/series";
+
+/// Subdirectory of the output directory that receives supplementary files.
+pub const SUPPL_DIR: &str = "geo_suppl";
+
+/// Checksum manifest written next to the supplementary files.
+pub const SUPPL_MANIFEST: &str = "md5.txt";
+
+/// A supplementary file listed under a series' `suppl/` directory.
+#[derive(Debug, Clone)]
+pub struct SupplFile {
+    pub name: String,
+    pub url: String,
+    pub size: u64,
+}
+
+/// True for `GSE` followed by digits.
+pub fn is_gse_accession(accession: &str) -> bool {
+    accession
+        .strip_prefix("GSE")
+        .is_some_and(|d| !d.is_empty() && d.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// `GSE123456` → `…/geo/series/GSE123nnn/GSE123456/suppl/`.
+fn suppl_url(gse: &str) -> String {
+    let digits = &gse[3..];
+    let stub = if digits.len() > 3 {
+        &digits[..digits.len() - 3]
+    } else {
+        ""
+    };
+    format!("{}/GSE{}nnn/{}/suppl/", GEO_SERIES_URL, stub, gse)
+}
+
+/// Extract the BioProject accession from a SOFT series record.
+fn parse_bioproject(soft: &str) -> Option<String> {
+    soft.lines()
+        .filter(|line| line.starts_with("!Series_relation"))
+        .filter_map(|line| line.split_once("BioProject:"))
+        .filter_map(|(_, link)| link.trim().rsplit('/').next())
+        .find(|acc| acc.starts_with("PRJ"))
+        .map(str::to_string)
+}
+
+/// File names linked from an HTML directory listing (no subdirectories,
+/// sort links or parent links).
+fn parse_listing(listing: &str) -> Vec<String> {
+    listing
+        .split("href=\"")
+        .skip(1)
+        .filter_map(|s| s.split('"').next())
+        .filter(|href| {
+            !href.is_empty()
+                && !href.ends_with('/')
+                && !href.starts_with('?')
+                && !href.starts_with('/')
+                && !href.contains("://")
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+fn http_client() -> Result<Client> {
+    Ok(crate::http::client_builder()
+        .timeout(Duration::from_secs(60))
+        .build()?)
+}
+
+/// Resolve a GEO series to the BioProject that holds its raw runs.
+pub async fn resolve_bioproject(gse: &str) -> Result<String> {
+    let response = circuit::send(http_client()?.get(GEO_QUERY_URL).query(&[
+        ("acc", gse),
+        ("targ", "self"),
+        ("form", "text"),
+        ("view", "quick"),
+    ]))
+    .await
+    .with_context(|| format!("Failed to query GEO for {}", gse))?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "GEO returned HTTP {} for {}",
+            response.status(),
+            gse
+        ));
+    }
+    let soft = response.text().await?;
+    parse_bioproject(&soft).ok_or_else(|| {
+        anyhow!(
+            "{} has no linked BioProject yet; its raw data may not be in SRA",
+            gse
+        )
+    })
+}
+
+/// List a series' supplementary files with their sizes.
+pub async fn list_suppl_files(gse: &str) -> Result<Vec<SupplFile>> {
+    let client = http_client()?;
+    let base = suppl_url(gse);
+    let response = client
+        .get(&base)
+        .send()
+        .await
+        .with_context(|| format!("Failed to list {}", base))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !response.status().is_success() {
+        return Err(anyhow!("HTTP {} for {}", response.status(), base));
+    }
+    let listing = response.text().await?;
+
+    let mut files = Vec::new();
+    for name in parse_listing(&listing) {
+        let url = format!("{}{}", base, name);
+        let size = client
+            .head(&url)
+            .send()
+            .await?
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| anyhow!("No Content-Length for {}", url))?;
+        files.push(SupplFile { name, url, size });
+    }
+    Ok(files)
+}
+
+/// Download every supplementary file of `gse` into
+/// `<output_dir>/geo_suppl/<gse>/` and write an MD5 manifest beside them.
+/// GEO publishes no checksums, so files are size-checked on download and the
+/// manifest records the local hashes for later `md5 verify` runs.
+pub async fn download_suppl(
+    gse: &str,
+    output_dir: &Path,
+    chunk_size_mb: u64,
+    max_workers: usize,
+    progress: Option<Arc<dyn ProgressSink>>,
+) -> Result<PathBuf> {
+    let files = list_suppl_files(gse).await?;
+    let suppl_dir = output_dir.join(SUPPL_DIR).join(gse);
+    if files.is_empty() {
+        warn!("[{}] No supplementary files published on GEO", gse);
+        return Ok(suppl_dir);
+    }
+    info!(
+        "[{}] Downloading {} supplementary file(s) to {}",
+        gse,
+        files.len(),
+        suppl_dir.display()
+    );
+
+    for file in &files {
+        let downloader = ResumableDownloader::new(
+            file.name.clone(),
+            SraMetadata {
+                s3_uri: file.url.clone(),
+                http_url: file.url.clone(),
+                md5: None,
+                size: file.size,
+            },
+            suppl_dir.clone(),
+            chunk_size_mb,
+            max_workers,
+            progress.clone(),
+            None,
+        )
+        .await?;
+        if !downloader.start().await? {
+            return Err(anyhow!("[{}] Size check failed for {}", gse, file.name));
+        }
+    }
+
+    crate::md5::generate_md5_manifest(
+        &suppl_dir,
+        &suppl_dir.join(SUPPL_MANIFEST),
+        max_workers,
+        progress,
+    )
+    .await?;
+    Ok(suppl_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_series_suppl_urls() {
+        assert!(is_gse_accession("GSE123456"));
+        assert!(!is_gse_accession("GSM123456"));
+        assert!(!is_gse_accession("GSE"));
+        assert_eq!(
+            suppl_url("GSE123456"),
+            "https://ftp.ncbi.nlm.nih.gov/geo/series/GSE123nnn/GSE123456/suppl/"
+        );
+        assert_eq!(
+            suppl_url("GSE12"),
+            "https://ftp.ncbi.nlm.nih.gov/geo/series/GSEnnn/GSE12/suppl/"
+        );
+    }
+
+    #[test]
+    fn parses_soft_and_listing() {
+        let soft = "^SERIES = GSE1\n!Series_relation = SRA: https://www.ncbi.nlm.nih.gov/sra?term=SRP1\n\
+                    !Series_relation = BioProject: https://www.ncbi.nlm.nih.gov/bioproject/PRJNA42\n";
+        assert_eq!(parse_bioproject(soft).as_deref(), Some("PRJNA42"));
+
+        let listing = r#"<a href="?C=N;O=D">Name</a><a href="/geo/series/GSE1nnn/GSE1234/">Parent</a>
+            <a href="GSE1234_RAW.tar">GSE1234_RAW.tar</a><a href="GSE1234_counts.txt.gz">c</a>"#;
+        assert_eq!(
+            parse_listing(listing),
+            vec!["GSE1234_RAW.tar", "GSE1234_counts.txt.gz"]
+        );
+    }
+}
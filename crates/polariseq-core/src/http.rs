@@ -0,0 +1,175 @@
+//! Identity sent with requests to ENA, NCBI and S3.
+//!
+//! ENA and NCBI both ask bulk users to identify themselves, and a named
+//! client is far easier to debug when a host starts blocking. Requests
+//! carry `User-Agent: polariseq/<version> (mailto:<contact>)` and any extra
+//! headers from the `http:` section of `polariseq.yaml`:
+//!
+//! ```yaml
+//! http:
+//!   contact: me@example.org
+//!   headers:
+//!     X-Project: soil-metagenomes
+//!   retries: 5        # ENA metadata requests, after the first attempt
+//!   retry_delay: 2    # seconds before the first retry, doubled each time
+//! ```
+//!
+//! The section is applied process-wide by [`configure`] when the config is
+//! loaded or a [`PolariseqClient`](crate::client::PolariseqClient) is built.
+
+use crate::error::PolariseqError;
+use anyhow::Result;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Retries of a failed ENA metadata request unless `retries` is set.
+pub const DEFAULT_RETRIES: u32 = 5;
+/// Seconds before the first retry unless `retry_delay` is set.
+pub const DEFAULT_RETRY_DELAY_SECS: u64 = 2;
+/// Cap of the doubled retry delay.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+/// `http:` section of `polariseq.yaml`. Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Email address appended to the User-Agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// Replaces the whole User-Agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Retries of a failed ENA metadata request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Seconds before the first retry; each further retry waits twice as
+    /// long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<u64>,
+}
+
+impl HttpConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn user_agent(&self) -> String {
+        if let Some(agent) = &self.user_agent {
+            return agent.clone();
+        }
+        let agent = format!("polariseq/{}", env!("CARGO_PKG_VERSION"));
+        match &self.contact {
+            Some(contact) => format!("{} (mailto:{})", agent, contact),
+            None => agent,
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(DEFAULT_RETRIES)
+    }
+
+    /// Wait before retry number `retry` (from 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let first = Duration::from_secs(self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY_SECS));
+        first
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// [`headers`](Self::headers) as a header map.
+    pub fn header_map(&self) -> Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let invalid = || {
+                PolariseqError::Config(format!(
+                    "Invalid HTTP header in config: {}: {}",
+                    name, value
+                ))
+            };
+            map.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?,
+                HeaderValue::from_str(value).map_err(|_| invalid())?,
+            );
+        }
+        Ok(map)
+    }
+}
+
+static CURRENT: RwLock<HttpConfig> = RwLock::new(HttpConfig {
+    contact: None,
+    user_agent: None,
+    headers: BTreeMap::new(),
+    retries: None,
+    retry_delay: None,
+});
+
+/// Use `config` for every request from now on.
+pub fn configure(config: &HttpConfig) -> Result<()> {
+    config.header_map()?;
+    *CURRENT.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+    Ok(())
+}
+
+/// The identity in force.
+pub fn current() -> HttpConfig {
+    CURRENT.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// `reqwest` client builder carrying the User-Agent and extra headers.
+pub fn client_builder() -> reqwest::ClientBuilder {
+    let config = current();
+    reqwest::Client::builder()
+        .user_agent(config.user_agent())
+        .default_headers(config.header_map().unwrap_or_default())
+}
+
+/// The same identity as `wget` arguments.
+pub fn wget_args() -> Vec<String> {
+    let config = current();
+    std::iter::once(format!("--user-agent={}", config.user_agent()))
+        .chain(
+            config
+                .headers
+                .iter()
+                .map(|(name, value)| format!("--header={}: {}", name, value)),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contact_goes_into_the_user_agent() {
+        let config: HttpConfig =
+            serde_yaml::from_str("contact: me@example.org\nheaders:\n  X-Project: soil\n").unwrap();
+        assert_eq!(
+            config.user_agent(),
+            format!(
+                "polariseq/{} (mailto:me@example.org)",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(config.header_map().unwrap()["x-project"], "soil");
+
+        let bad = HttpConfig {
+            headers: BTreeMap::from([("Bad Header".to_string(), "x".to_string())]),
+            ..HttpConfig::default()
+        };
+        assert!(bad.header_map().is_err());
+        assert!(HttpConfig::default().is_empty());
+
+        let retry: HttpConfig = serde_yaml::from_str("retries: 3\nretry_delay: 5\n").unwrap();
+        assert_eq!(retry.retries(), 3);
+        assert_eq!(retry.backoff(1), Duration::from_secs(5));
+        assert_eq!(retry.backoff(3), Duration::from_secs(20));
+        assert_eq!(retry.backoff(30), MAX_RETRY_DELAY);
+        assert_eq!(HttpConfig::default().retries(), DEFAULT_RETRIES);
+    }
+}
@@ -0,0 +1,174 @@
+//! File-count estimate of a job (`--max-files`).
+//!
+//! Parallel filesystems often cap the number of files per user or volume,
+//! and a large project can run into the cap long before it runs out of
+//! bytes. Before anything is written, the job's peak file count is
+//! estimated from the plan and checked against `--max-files` or, without
+//! it, the free inodes `df -i` reports for the output directory.
+
+use crate::client::{DownloadJob, JobPlan};
+use crate::{DownloadMethod, OutputFormat};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Job-level files: logs, metadata and MD5 tables, `md5.txt`,
+/// `timings.csv`, `commands.jsonl`, the lock and the `logs/` directory.
+const JOB_FILES: u64 = 10;
+
+/// Peak file count of a job.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEstimate {
+    /// Outputs, logs and kept `.sra` files that remain after the job.
+    pub kept: u64,
+    /// Resume state and intermediates of the runs in flight.
+    pub temporary: u64,
+}
+
+impl FileEstimate {
+    pub fn total(&self) -> u64 {
+        self.kept + self.temporary
+    }
+}
+
+/// Files a download of `plan` creates at its peak: per run its FASTQ
+/// (or one BAM/CRAM), a log, and for SRA conversions the staging directory
+/// and `.sra` unless `--cleanup-sra`; per run in flight its `.part`,
+/// `.meta.json`, intermediate FASTQ and `fasterq-dump` temporary directory.
+pub fn download_files(job: &DownloadJob, plan: &JobPlan) -> FileEstimate {
+    let options = &job.options;
+    let converts = options.download_method == DownloadMethod::Aws;
+    let outputs = |paired: bool| {
+        if converts && options.output_format != OutputFormat::Fastq {
+            1
+        } else {
+            1 + paired as u64
+        }
+    };
+    let kept_sra = if options.cleanup_sra { 0 } else { 2 };
+
+    let mut kept = JOB_FILES;
+    let mut runs = 0u64;
+    for record in &plan.processed {
+        kept += outputs(record.fastq_ftp_2_url.is_some()) + 1;
+        if converts {
+            kept += kept_sra;
+        }
+        runs += 1;
+    }
+    if job.credential.is_some() {
+        // Protected runs always go through prefetch and fasterq-dump.
+        let protected = plan.protected_runs.len() as u64;
+        let outputs = match options.output_format {
+            OutputFormat::Fastq => 2,
+            _ => 1,
+        };
+        kept += protected * (outputs + 1 + kept_sra);
+        runs += protected;
+    }
+    let per_run = if converts || job.credential.is_some() {
+        6
+    } else {
+        2
+    };
+    FileEstimate {
+        kept,
+        temporary: runs.min(options.multithreads.max(1) as u64) * per_run,
+    }
+}
+
+/// Files `--only-scripts` writes for `runs` runs: one script each plus the
+/// `scripts/` directory, or a single combined script.
+pub fn script_files(runs: usize, combined: bool) -> u64 {
+    if combined {
+        2
+    } else {
+        runs as u64 + 1
+    }
+}
+
+/// Free inodes on the filesystem holding `path` (via `df -i`, Unix only).
+/// `None` when `df` fails or the filesystem has no fixed inode count
+/// (e.g. btrfs reports 0).
+pub fn free_inodes(path: &Path) -> Option<u64> {
+    let output = crate::audit::output_blocking(
+        std::process::Command::new("df")
+            .arg("-Pi")
+            .arg(path)
+            .stderr(Stdio::null()),
+    )
+    .ok()?;
+    parse_df_inodes(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The `IFree` column of `df -Pi` output, found by its header.
+fn parse_df_inodes(text: &str) -> Option<u64> {
+    let mut lines = text.lines();
+    let header: Vec<String> = lines
+        .next()?
+        .split_whitespace()
+        .map(str::to_ascii_lowercase)
+        .collect();
+    let total = header.iter().position(|h| h == "inodes")?;
+    let free = header.iter().position(|h| h == "ifree")?;
+    let values: Vec<&str> = lines.next()?.split_whitespace().collect();
+    match values.get(total)?.parse::<u64>().ok()? {
+        0 => None,
+        _ => values.get(free)?.parse().ok(),
+    }
+}
+
+/// The file-count limit of `output`: `max_files`, else its free inodes.
+pub fn file_limit(output: &Path, max_files: Option<u64>) -> Option<u64> {
+    max_files.or_else(|| free_inodes(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DownloadOptions, ProcessedRecord};
+
+    #[test]
+    fn estimates_files_of_a_plan() {
+        let record = |run: &str, paired: bool| ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: String::new(),
+            fastq_ftp_2_url: paired.then(String::new),
+            fastq_ftp_1_name: String::new(),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: String::new(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 0,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        };
+        let plan = JobPlan {
+            records: Vec::new(),
+            processed: vec![record("SRR1", true), record("SRR2", false)],
+            ..JobPlan::default()
+        };
+        let job = DownloadJob::from(DownloadOptions {
+            download_method: DownloadMethod::Ftp,
+            multithreads: 1,
+            ..DownloadOptions::default()
+        });
+        // 2 + 1 FASTQ, a log each, one run in flight with two .part files.
+        assert_eq!(
+            download_files(&job, &plan),
+            FileEstimate {
+                kept: JOB_FILES + 5,
+                temporary: 2,
+            }
+        );
+        let job = job.method(DownloadMethod::Aws);
+        assert_eq!(download_files(&job, &plan).kept, JOB_FILES + 9);
+        assert_eq!(script_files(2, false), 3);
+
+        let df = "Filesystem Inodes IUsed IFree IUse% Mounted on\n\
+                  /dev/sda1 6553600 553600 6000000 9% /\n";
+        assert_eq!(parse_df_inodes(df), Some(6_000_000));
+        let btrfs = "Filesystem Inodes IUsed IFree IUse% Mounted on\n/dev/sdb 0 0 0 - /data\n";
+        assert_eq!(parse_df_inodes(btrfs), None);
+    }
+}
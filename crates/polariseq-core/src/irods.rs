@@ -0,0 +1,187 @@
+//! iRODS destinations for `download --dest irods:///zone/home/user/coll`.
+//!
+//! [`IrodsHook`] puts each verified run's files into the collection with
+//! `iput -K` (server-side checksum verification), or registers them in
+//! place with `ireg -K` when the URL ends in `?register` and the output
+//! directory sits on storage the iRODS server can see. Every data object
+//! is tagged with `run_accession`, `md5` and `source` AVUs. The icommands
+//! must be installed and `iinit` done beforehand.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::error::PolariseqError;
+use crate::md5::compute_md5;
+use crate::run_cancellable;
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// An iRODS collection, and whether files are registered instead of copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrodsTarget {
+    pub collection: String,
+    pub register: bool,
+}
+
+impl IrodsTarget {
+    pub fn matches(url: &str) -> bool {
+        url.starts_with("irods://")
+    }
+
+    fn object(&self, file: &Path) -> String {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        format!("{}/{}", self.collection, name)
+    }
+}
+
+impl FromStr for IrodsTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = || {
+            PolariseqError::InvalidInput(format!(
+                "Invalid destination '{}': expected irods:///zone/path[?register]",
+                url
+            ))
+        };
+        let rest = url.strip_prefix("irods://").ok_or_else(invalid)?;
+        let (path, register) = match rest.strip_suffix("?register") {
+            Some(path) => (path, true),
+            None => (rest, false),
+        };
+        let collection = path.trim_end_matches('/');
+        if !collection.starts_with('/') || collection.len() < 2 || collection.contains('?') {
+            return Err(invalid().into());
+        }
+        Ok(Self {
+            collection: collection.to_string(),
+            register,
+        })
+    }
+}
+
+/// Run one icommand, failing with its stderr.
+async fn icommand(tool: &str, args: &[&str], cancel: &CancellationToken) -> Result<()> {
+    let output = run_cancellable(Command::new(tool).args(args), cancel).await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Puts or registers finished runs into an [`IrodsTarget`].
+pub struct IrodsHook {
+    target: IrodsTarget,
+    remove_local: bool,
+}
+
+impl IrodsHook {
+    pub fn new(target: IrodsTarget, remove_local: bool) -> Result<Self> {
+        for tool in ["iput", "ireg", "imeta", "imkdir"] {
+            crate::deps::locate_tool(tool).ok_or_else(|| PolariseqError::DependencyMissing {
+                tool: tool.to_string(),
+                path: PathBuf::from(tool),
+            })?;
+        }
+        if target.register && remove_local {
+            return Err(PolariseqError::InvalidInput(
+                "--dest-remove-local cannot be used with a ?register iRODS destination".into(),
+            )
+            .into());
+        }
+        Ok(Self {
+            target,
+            remove_local,
+        })
+    }
+}
+
+impl RunHook for IrodsHook {
+    fn name(&self) -> &'static str {
+        "irods"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if files.is_empty() {
+                return Err(anyhow!("no output files found for {}", run_id));
+            }
+            let cancel = &ctx.cancel;
+            icommand("imkdir", &["-p", &self.target.collection], cancel).await?;
+            for file in files {
+                let object = self.target.object(file);
+                let md5 = {
+                    let file = file.clone();
+                    tokio::task::spawn_blocking(move || compute_md5(&file)).await??
+                };
+                if self.target.register {
+                    let path = std::path::absolute(file)?;
+                    let path = path.to_string_lossy();
+                    icommand("ireg", &["-K", "-f", &path, &object], cancel).await?;
+                } else {
+                    let path = file.to_string_lossy();
+                    icommand("iput", &["-K", "-f", &path, &object], cancel).await?;
+                }
+                for (attr, value) in [
+                    ("run_accession", run_id),
+                    ("md5", md5.as_str()),
+                    ("source", "polariseq"),
+                ] {
+                    icommand("imeta", &["set", "-d", &object, attr, value], cancel).await?;
+                }
+                if self.remove_local {
+                    tokio::fs::remove_file(file).await?;
+                }
+            }
+            info!(
+                "[{}] {} {} file(s) in iRODS {}",
+                run_id,
+                if self.target.register {
+                    "Registered"
+                } else {
+                    "Put"
+                },
+                files.len(),
+                self.target.collection
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_irods_collections() {
+        let target: IrodsTarget = "irods:///tempZone/home/alice/seq/".parse().unwrap();
+        assert_eq!(target.collection, "/tempZone/home/alice/seq");
+        assert!(!target.register);
+        assert_eq!(
+            target.object(Path::new("out/SRR1_1.fastq.gz")),
+            "/tempZone/home/alice/seq/SRR1_1.fastq.gz"
+        );
+        assert!(
+            "irods:///tempZone/x?register"
+                .parse::<IrodsTarget>()
+                .unwrap()
+                .register
+        );
+        assert!("irods://host/x".parse::<IrodsTarget>().is_err());
+        assert!("irods:///".parse::<IrodsTarget>().is_err());
+    }
+}
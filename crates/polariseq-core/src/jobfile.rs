@@ -0,0 +1,224 @@
+//! Job files for clusters whose compute nodes cannot reach ENA or NCBI
+//! (`plan` and `execute`).
+//!
+//! `plan` runs on a node with internet access: it fetches and filters the
+//! metadata like `download` and, for the AWS backend, resolves each run's
+//! S3 object, size and MD5. Options and plan go into one JSON file, which
+//! `execute` reads on the compute side to download from ENA FTP or S3
+//! without calling the ENA portal or NCBI E-utilities.
+
+use crate::aws_s3::{self, SraLookup, SraMetadata, SraUtils};
+use crate::client::{DownloadJob, JobPlan, METADATA_CONCURRENCY};
+use crate::error::PolariseqError;
+use crate::{DownloadMethod, DownloadOptions, EnaRecord, ProcessedRecord};
+use anyhow::{Context, Result};
+use chrono::Local;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Format of [`JobFile`]; bumped on incompatible changes.
+pub const JOB_FILE_VERSION: u32 = 1;
+
+pub const DEFAULT_JOB_FILE: &str = "polariseq_job.json";
+
+/// A planned job as written by `plan`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFile {
+    pub version: u32,
+    /// Polariseq release that wrote the file.
+    pub polariseq: String,
+    pub created: String,
+    pub options: DownloadOptions,
+    pub fetched: usize,
+    pub records: Vec<EnaRecord>,
+    pub processed: Vec<ProcessedRecord>,
+    pub protected_runs: Vec<String>,
+    /// AWS copy of each run, by run accession.
+    #[serde(default)]
+    pub sra: BTreeMap<String, SraMetadata>,
+}
+
+impl JobFile {
+    pub fn new(job: &DownloadJob, plan: &JobPlan) -> Self {
+        Self {
+            version: JOB_FILE_VERSION,
+            polariseq: env!("CARGO_PKG_VERSION").to_string(),
+            created: Local::now().to_rfc3339(),
+            options: job.options.clone(),
+            fetched: plan.fetched,
+            records: plan.records.clone(),
+            processed: plan.processed.clone(),
+            protected_runs: plan.protected_runs.clone(),
+            sra: plan
+                .resolved_sra
+                .iter()
+                .map(|(run, metadata)| (run.clone(), metadata.clone()))
+                .collect(),
+        }
+    }
+
+    /// The job and its plan. A credential is not stored, so protected
+    /// runs are left out.
+    pub fn into_job(self) -> (DownloadJob, JobPlan) {
+        let plan = JobPlan {
+            fetched: self.fetched,
+            records: self.records,
+            processed: self.processed,
+            protected_runs: self.protected_runs,
+            resolved_sra: self.sra.into_iter().collect(),
+        };
+        (DownloadJob::from(self.options), plan)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        /// The fields every format version has.
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+            #[serde(default)]
+            polariseq: String,
+        }
+
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let invalid = |e: serde_json::Error| {
+            PolariseqError::InvalidInput(format!("{} is not a job file: {}", path.display(), e))
+        };
+        let header: Header = serde_json::from_slice(&data).map_err(invalid)?;
+        if header.version != JOB_FILE_VERSION {
+            return Err(PolariseqError::InvalidInput(format!(
+                "{} has job file format {} (written by polariseq {}), this release reads {}; run plan again",
+                path.display(),
+                header.version,
+                header.polariseq,
+                JOB_FILE_VERSION
+            ))
+            .into());
+        }
+        Ok(serde_json::from_slice(&data).map_err(invalid)?)
+    }
+}
+
+/// AWS copies of the runs `plan` sends to the AWS backend, resolved like
+/// the backend does: from ENA's `sra_md5`/`sra_bytes` when S3 agrees,
+/// else through NCBI. Runs without a copy in the requested format are
+/// left for `execute` to look up.
+pub async fn resolve_sra(
+    job: &DownloadJob,
+    plan: &JobPlan,
+) -> Result<HashMap<String, SraMetadata>> {
+    if job.options.download_method != DownloadMethod::Aws {
+        return Ok(HashMap::new());
+    }
+    let format = job.options.sra_format;
+    let records: HashMap<&str, &EnaRecord> = plan
+        .records
+        .iter()
+        .map(|r| (r.run_accession.as_str(), r))
+        .collect();
+    let runs: Vec<(&str, Option<(String, u64)>)> = plan
+        .processed
+        .iter()
+        .filter_map(|p| {
+            let record = records.get(p.run_accession.as_str());
+            let ftp_only = record.and_then(|r| r.preferred_backend.as_deref()) == Some("ftp");
+            let ena = record.and_then(|r| aws_s3::ena_sra_md5(r));
+            (!ftp_only).then_some((p.run_accession.as_str(), ena))
+        })
+        .collect();
+    let total = runs.len();
+
+    let lookups: Vec<(&str, SraLookup)> = stream::iter(runs)
+        .map(|(run_id, ena)| async move {
+            let known = match &ena {
+                Some((md5, bytes)) => {
+                    SraUtils::metadata_from_ena(run_id, md5, *bytes, format).await
+                }
+                None => None,
+            };
+            let lookup = match known {
+                Some(metadata) => SraLookup::Found(metadata),
+                None => SraUtils::get_metadata_for_format(run_id, format).await?,
+            };
+            anyhow::Ok((run_id, lookup))
+        })
+        .buffered(METADATA_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    let mut resolved = HashMap::new();
+    for (run_id, lookup) in lookups {
+        match lookup {
+            SraLookup::Found(mut metadata) => {
+                if let Some((md5, bytes)) = records.get(run_id).and_then(|r| aws_s3::ena_sra_md5(r))
+                {
+                    metadata.fill_md5(&md5, bytes);
+                }
+                resolved.insert(run_id.to_string(), metadata);
+            }
+            SraLookup::LiteOnly | SraLookup::NotFound => warn!(
+                "[{}] No AWS copy in the requested format; execute will ask NCBI again",
+                run_id
+            ),
+        }
+    }
+    info!(
+        "Resolved the AWS copy of {} of {} run(s)",
+        resolved.len(),
+        total
+    );
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_the_plan_and_rejects_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_JOB_FILE);
+        let record: EnaRecord =
+            serde_json::from_value(serde_json::json!({ "run_accession": "SRR1" })).unwrap();
+        let mut plan = JobPlan {
+            fetched: 3,
+            records: vec![record],
+            protected_runs: vec!["SRR9".into()],
+            ..JobPlan::default()
+        };
+        plan.resolved_sra.insert(
+            "SRR1".into(),
+            SraMetadata {
+                s3_uri: "s3://sra-pub-run-odp/sra/SRR1/SRR1".into(),
+                http_url: "https://sra-pub-run-odp.s3.amazonaws.com/sra/SRR1/SRR1".into(),
+                md5: Some("900150983cd24fb0d6963f7d28e17f72".into()),
+                size: 3,
+            },
+        );
+        let job = DownloadJob::accession("PRJNA1", "/scratch/out").method(DownloadMethod::Aws);
+
+        JobFile::new(&job, &plan).write(&path).unwrap();
+        let (job, plan) = JobFile::read(&path).unwrap().into_job();
+        assert_eq!(job.options.accession.as_deref(), Some("PRJNA1"));
+        assert_eq!(job.output(), Path::new("/scratch/out"));
+        assert_eq!(plan.fetched, 3);
+        assert_eq!(plan.records[0].run_accession, "SRR1");
+        assert_eq!(plan.protected_runs, ["SRR9"]);
+        assert_eq!(plan.resolved_sra["SRR1"].size, 3);
+
+        let mut file: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        file["version"] = serde_json::json!(JOB_FILE_VERSION + 1);
+        std::fs::write(&path, file.to_string()).unwrap();
+        let err = JobFile::read(&path).unwrap_err();
+        assert!(err.to_string().contains("run plan again"));
+    }
+}
@@ -1,31 +1,108 @@
 //! Polariseq library
 
+pub mod accession;
+pub mod alignments;
+pub mod assembly;
+pub mod audit;
+pub mod autotune;
 pub mod aws_s3;
+pub mod backend;
+pub mod bagit;
+pub mod bandwidth;
+pub mod cassette;
+pub mod circuit;
+pub mod clean;
+pub mod client;
+pub mod connections;
+pub mod control;
+pub mod controlled;
 pub mod deps;
+#[cfg(feature = "aws")]
+pub mod dest;
+pub mod distributed;
+pub mod doctor;
+pub mod ega;
+pub mod error;
+#[cfg(feature = "ftp")]
 pub mod ftp;
+pub mod geo;
+pub mod http;
+pub mod inodes;
+pub mod irods;
+pub mod jobfile;
+pub mod lock;
+pub mod long_read;
+pub mod manifest;
 pub mod md5;
+pub mod metadata_diff;
+pub mod metrics;
+pub mod multiqc;
+pub mod notify;
 pub mod observer;
+pub mod overrides;
+pub mod prealloc;
+pub mod prescan;
+pub mod priority;
+pub mod profile;
 pub mod progress;
 pub mod progress_store;
+pub mod provenance;
 pub mod public_data;
+pub mod push;
+pub mod qc;
+pub mod quarantine;
+pub mod remote;
+pub mod rocrate;
+pub mod route;
+pub mod scripts;
+pub mod shell;
+pub mod shell_hook;
+pub mod sidecar;
+pub mod speed;
+pub mod tenx;
+pub mod trim;
+pub mod unaligned;
+#[cfg(feature = "aws")]
 pub mod upload;
+pub mod urls;
+pub mod workflow;
+pub mod write_queue;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
+use error::PolariseqError;
 use gzp::{deflate::Gzip, ZBuilder};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
 // Configuration
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
+    /// Auto-detected when the section is omitted.
+    #[serde(default = "deps::detect_software_paths")]
     pub software: SoftwarePaths,
     #[serde(default)]
     pub public_data: HashMap<String, public_data::PublicDatabase>,
+    /// Optional EGA endpoint overrides; defaults target the public EGA API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ega: Option<ega::EgaConfig>,
+    /// User-Agent contact and extra headers for ENA/NCBI/S3 requests.
+    #[serde(default, skip_serializing_if = "http::HttpConfig::is_empty")]
+    pub http: http::HttpConfig,
+    /// `prefetch` transport, resume and quality options.
+    #[serde(default, skip_serializing_if = "controlled::PrefetchConfig::is_empty")]
+    pub prefetch: controlled::PrefetchConfig,
+    /// `nice`, `ionice` and `taskset` for conversion tools.
+    #[serde(default, skip_serializing_if = "priority::PriorityConfig::is_empty")]
+    pub priority: priority::PriorityConfig,
+    /// Named download settings for `download --profile`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, profile::Profile>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -33,6 +110,34 @@ pub struct SoftwarePaths {
     pub prefetch: PathBuf,
     pub fasterq_dump: PathBuf,
     pub blastdbcmd: Option<PathBuf>,
+    /// Only for `--output-format ubam|cram`.
+    pub samtools: Option<PathBuf>,
+}
+
+impl SoftwarePaths {
+    /// Environment variables that override the YAML / detected tool paths.
+    pub const ENV_PREFETCH: &'static str = "POLARISEQ_PREFETCH";
+    pub const ENV_FASTERQ_DUMP: &'static str = "POLARISEQ_FASTERQ_DUMP";
+    pub const ENV_BLASTDBCMD: &'static str = "POLARISEQ_BLASTDBCMD";
+    pub const ENV_SAMTOOLS: &'static str = "POLARISEQ_SAMTOOLS";
+
+    /// Apply the `POLARISEQ_*` tool path variables that are set and non-empty.
+    pub fn with_env_overrides(mut self) -> Self {
+        let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+        if let Some(path) = var(Self::ENV_PREFETCH) {
+            self.prefetch = path.into();
+        }
+        if let Some(path) = var(Self::ENV_FASTERQ_DUMP) {
+            self.fasterq_dump = path.into();
+        }
+        if let Some(path) = var(Self::ENV_BLASTDBCMD) {
+            self.blastdbcmd = Some(path.into());
+        }
+        if let Some(path) = var(Self::ENV_SAMTOOLS) {
+            self.samtools = Some(path.into());
+        }
+        self
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -87,9 +192,43 @@ pub struct EnaRecord {
     pub nominal_sdev: Option<String>,
     pub first_created: Option<String>,
     pub bam_ftp: Option<String>,
+    pub bam_md5: Option<String>,
+    pub bam_bytes: Option<String>,
     pub fastq_file_role: Option<String>,
     pub submitted_file_role: Option<String>,
     pub sra_file_role: Option<String>,
+    /// Primary and secondary accessions of the run's study and sample
+    /// (`PRJNA1=SRP1;SAMN1=SRS1`), filled in when records are loaded.
+    #[serde(default)]
+    pub accession_map: String,
+    /// Optional per-run overrides from the input TSV; see [`overrides`].
+    pub preferred_backend: Option<String>,
+    pub output_subdir: Option<String>,
+    pub sample_name: Option<String>,
+    pub priority: Option<i64>,
+}
+
+impl EnaRecord {
+    /// Set [`accession_map`](Self::accession_map) from the study and sample
+    /// accession columns, unless the input already had one.
+    pub fn fill_accession_map(&mut self) {
+        if !self.accession_map.is_empty() {
+            return;
+        }
+        let pairs = [
+            (&self.study_accession, &self.secondary_study_accession),
+            (&self.sample_accession, &self.secondary_sample_accession),
+        ];
+        self.accession_map = pairs
+            .into_iter()
+            .filter_map(|(primary, secondary)| {
+                let primary = primary.as_deref().filter(|s| !s.is_empty())?;
+                let secondary = secondary.as_deref().filter(|s| !s.is_empty())?;
+                Some(format!("{}={}", primary, secondary))
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,8 +248,88 @@ pub struct ProcessedRecord {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum DownloadMethod {
+    #[serde(alias = "ftp")]
     Ftp,
+    #[serde(alias = "aws")]
     Aws,
+    /// EGA download API (EGAD/EGAF accessions, token authentication).
+    #[serde(alias = "ega")]
+    Ega,
+    /// Fabricated files instead of downloads, for testing pipelines.
+    #[serde(alias = "mock")]
+    Mock,
+}
+
+/// How runs are scheduled across workers (`--group-by`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum GroupBy {
+    /// Every run is scheduled on its own.
+    #[default]
+    Run,
+    /// Runs of one sample go through the same worker, one after another.
+    Sample,
+}
+
+/// Order of the records in the plan and the metadata files (`--sort-by`),
+/// so that reruns write byte-identical tables.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SortBy {
+    /// Run accession.
+    #[default]
+    Run,
+    /// Sample accession, then run.
+    Sample,
+    /// Study accession, then sample and run.
+    Study,
+    /// Keep the order of the ENA response or input TSV.
+    None,
+}
+
+impl SortBy {
+    /// Sort `records` stably; accessions with the same prefix compare by
+    /// number, so `SRR9` comes before `SRR10`.
+    pub fn sort(self, records: &mut [EnaRecord]) {
+        fn key(accession: Option<&str>) -> (&str, usize, &str) {
+            let accession = accession.unwrap_or_default();
+            let digits = accession.trim_start_matches(|c: char| !c.is_ascii_digit());
+            let prefix = &accession[..accession.len() - digits.len()];
+            (prefix, digits.len(), digits)
+        }
+        fn run(r: &EnaRecord) -> (&str, usize, &str) {
+            key(Some(&r.run_accession))
+        }
+        fn sample(r: &EnaRecord) -> (&str, usize, &str) {
+            key(r.sample_accession.as_deref())
+        }
+        fn study(r: &EnaRecord) -> (&str, usize, &str) {
+            key(r.study_accession.as_deref())
+        }
+        match self {
+            SortBy::Run => records.sort_by(|a, b| run(a).cmp(&run(b))),
+            SortBy::Sample => records.sort_by(|a, b| (sample(a), run(a)).cmp(&(sample(b), run(b)))),
+            SortBy::Study => records
+                .sort_by(|a, b| (study(a), sample(a), run(a)).cmp(&(study(b), sample(b), run(b)))),
+            SortBy::None => {}
+        }
+    }
+}
+
+/// Final format of runs converted locally from SRA (`--output-format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+    /// Gzipped FASTQ files.
+    #[default]
+    Fastq,
+    /// One unaligned BAM per run, see [`unaligned`].
+    Ubam,
+    /// One unaligned CRAM per run.
+    Cram,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -131,6 +350,60 @@ pub struct DownloadOptions {
     pub exclude_run: Vec<String>,
     pub cleanup_sra: bool,
     pub dry_run: bool,
+    #[serde(default)]
+    pub sra_format: aws_s3::SraFormat,
+    /// `--route` rules, see [`route`].
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(default)]
+    pub group_by: GroupBy,
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    /// Download `bam_ftp` alignments instead of FASTQ, see [`alignments`].
+    #[serde(default)]
+    pub prefer_bam: bool,
+    /// `--min-speed` floor that moves a slow run to its next backend, see
+    /// [`speed`].
+    #[serde(default)]
+    pub min_speed: Option<speed::MinSpeed>,
+    /// Adjust concurrent runs and threads per file while downloading, with
+    /// `multithreads` and `aws_threads` as ceilings, see [`autotune`].
+    #[serde(default)]
+    pub auto_tune: bool,
+    /// HTTP version of AWS range requests (`--s3-http`).
+    #[serde(default)]
+    pub s3_http: aws_s3::S3Http,
+    /// Per-run staging directory of the AWS backend (`--aws-staging`).
+    #[serde(default)]
+    pub aws_staging: aws_s3::AwsStaging,
+    /// Range requests open at once across the job (`--max-connections`);
+    /// `None` is unlimited, see [`connections`].
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// How AWS `.part` files are preallocated (`--preallocate`).
+    #[serde(default)]
+    pub preallocate: prealloc::Preallocation,
+    /// Sweep resume state older than this many days at the end of the job,
+    /// see [`clean`].
+    #[serde(default)]
+    pub clean_stale_days: Option<u64>,
+    /// `fasterq-dump --mem` in bytes (`--fasterq-mem`).
+    #[serde(default)]
+    pub fasterq_mem: Option<u64>,
+    /// Memory for all conversions of the job together (`--max-memory`),
+    /// see [`backend::fasterq_mem`].
+    #[serde(default)]
+    pub max_memory: Option<u64>,
+    /// Fetch runs that start with `prefetch` in one `prefetch --option-file`
+    /// call before converting them (`--prefetch-batch`).
+    #[serde(default)]
+    pub prefetch_batch: bool,
+    /// Write one `md5_manifest.tsv` with run, size, layout and final path
+    /// columns instead of the R1/R2 MD5 tables (`--md5-manifest`).
+    #[serde(default)]
+    pub md5_manifest: bool,
+    #[serde(default)]
+    pub sort_by: SortBy,
 }
 
 impl Default for DownloadOptions {
@@ -151,6 +424,23 @@ impl Default for DownloadOptions {
             exclude_run: Vec::new(),
             cleanup_sra: false,
             dry_run: false,
+            sra_format: aws_s3::SraFormat::Any,
+            routes: Vec::new(),
+            group_by: GroupBy::Run,
+            output_format: OutputFormat::Fastq,
+            prefer_bam: false,
+            min_speed: None,
+            auto_tune: false,
+            s3_http: aws_s3::S3Http::Http1,
+            aws_staging: aws_s3::AwsStaging::Run,
+            max_connections: Some(connections::DEFAULT_MAX_CONNECTIONS),
+            preallocate: prealloc::Preallocation::Sparse,
+            clean_stale_days: None,
+            fasterq_mem: None,
+            max_memory: None,
+            prefetch_batch: false,
+            md5_manifest: false,
+            sort_by: SortBy::Run,
         }
     }
 }
@@ -185,53 +475,374 @@ impl Default for UploadOptions {
 
 pub fn load_config(yaml_path: &Path) -> Result<Config> {
     if !yaml_path.exists() {
-        return Err(anyhow!(
+        return Err(PolariseqError::Config(format!(
             "YAML configuration file not found: {}",
             yaml_path.display()
-        ));
+        ))
+        .into());
     }
     let content = std::fs::read_to_string(yaml_path)?;
-    let config: Config = serde_yaml::from_str(&content)?;
+    let mut config: Config = serde_yaml::from_str(&content).map_err(|e| {
+        PolariseqError::Config(format!("Invalid YAML in {}: {}", yaml_path.display(), e))
+    })?;
+    config.software = config.software.with_env_overrides();
+    http::configure(&config.http)?;
     Ok(config)
 }
 
-pub async fn fetch_ena_data(accession: &str) -> Result<Vec<EnaRecord>> {
-    use csv::ReaderBuilder;
+impl Config {
+    /// Configuration without a YAML file: tool paths are auto-detected and
+    /// no public databases are defined.
+    pub fn detect() -> Self {
+        Self {
+            software: deps::detect_software_paths().with_env_overrides(),
+            public_data: HashMap::new(),
+            ega: None,
+            http: http::HttpConfig::default(),
+            prefetch: controlled::PrefetchConfig::default(),
+            priority: priority::PriorityConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
 
-    let fields = "run_accession,study_accession,secondary_study_accession,sample_accession,secondary_sample_accession,experiment_accession,submission_accession,tax_id,scientific_name,instrument_platform,instrument_model,library_name,nominal_length,library_layout,library_strategy,library_source,library_selection,read_count,center_name,first_public,last_updated,experiment_title,study_title,study_alias,run_alias,fastq_bytes,fastq_md5,fastq_ftp,fastq_aspera,fastq_galaxy,submitted_bytes,submitted_md5,submitted_ftp,submitted_aspera,submitted_galaxy,submitted_format,sra_bytes,sra_md5,sra_ftp,sra_aspera,sra_galaxy,sample_alias,sample_title,nominal_sdev,first_created,bam_ftp,fastq_file_role,submitted_file_role,sra_file_role";
-    let url = format!("https://www.ebi.ac.uk/ena/portal/api/filereport?accession={}&result=read_run&fields={}&format=tsv", accession, fields);
+/// Load `yaml_path` if it exists, otherwise fall back to [`Config::detect`].
+/// Either way `POLARISEQ_PREFETCH` / `POLARISEQ_FASTERQ_DUMP` /
+/// `POLARISEQ_BLASTDBCMD` take precedence.
+pub fn load_config_or_detect(yaml_path: &Path) -> Result<Config> {
+    if yaml_path.exists() {
+        return load_config(yaml_path);
+    }
+    info!(
+        "{} not found; using tools detected on PATH",
+        yaml_path.display()
+    );
+    Ok(Config::detect())
+}
 
-    let client = reqwest::Client::builder().build()?;
-    let response = client.get(&url).send().await?;
-    if !response.status().is_success() {
-        return Err(anyhow!(
+/// Columns requested from the ENA filereport, i.e. the fields of [`EnaRecord`]
+/// that ENA knows.
+const ENA_FIELDS: &str = "run_accession,study_accession,secondary_study_accession,sample_accession,secondary_sample_accession,experiment_accession,submission_accession,tax_id,scientific_name,instrument_platform,instrument_model,library_name,nominal_length,library_layout,library_strategy,library_source,library_selection,read_count,center_name,first_public,last_updated,experiment_title,study_title,study_alias,run_alias,fastq_bytes,fastq_md5,fastq_ftp,fastq_aspera,fastq_galaxy,submitted_bytes,submitted_md5,submitted_ftp,submitted_aspera,submitted_galaxy,submitted_format,sra_bytes,sra_md5,sra_ftp,sra_aspera,sra_galaxy,sample_alias,sample_title,nominal_sdev,first_created,bam_ftp,bam_md5,bam_bytes,fastq_file_role,submitted_file_role,sra_file_role";
+
+/// Runs per filereport request. Larger projects are fetched in pages, so
+/// no single response is big enough to time out.
+pub const ENA_PAGE_SIZE: usize = 20_000;
+
+/// Leading bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Incremental parser of a filereport TSV whose body arrives in chunks.
+/// A row shorter than the header means the response was cut off; a last
+/// line without newline is parsed like any other.
+///
+/// Columns are matched to [`EnaRecord`] by the header line: reordered,
+/// added or missing optional columns do not break parsing. A body that is
+/// still gzip-compressed (sent without `Content-Encoding`) is inflated as
+/// it arrives.
+#[derive(Default)]
+struct TsvStream {
+    headers: Option<csv::StringRecord>,
+    gunzip: Option<flate2::write::GzDecoder<Vec<u8>>>,
+    started: bool,
+    pending: Vec<u8>,
+    records: Vec<EnaRecord>,
+}
+
+impl TsvStream {
+    /// Parse the complete lines of `chunk` and keep the rest for later.
+    fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        if !self.started && !chunk.is_empty() {
+            self.started = true;
+            if chunk.starts_with(&GZIP_MAGIC) {
+                self.gunzip = Some(flate2::write::GzDecoder::new(Vec::new()));
+            }
+        }
+        match &mut self.gunzip {
+            Some(gunzip) => {
+                gunzip
+                    .write_all(chunk)
+                    .map_err(|e| truncated(format!("invalid gzip data ({})", e)))?;
+                self.pending.append(gunzip.get_mut());
+            }
+            None => self.pending.extend_from_slice(chunk),
+        }
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Ok(());
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+        self.parse(&lines)
+    }
+
+    fn parse(&mut self, lines: &[u8]) -> Result<()> {
+        let text = String::from_utf8_lossy(lines);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(b'\t')
+            .from_reader(text.as_bytes());
+        for row in reader.records() {
+            let row = row?;
+            match &self.headers {
+                None => self.headers = Some(row),
+                Some(headers) if row.len() < headers.len() => {
+                    return Err(truncated(format!(
+                        "a row has {} of {} columns",
+                        row.len(),
+                        headers.len()
+                    )));
+                }
+                Some(headers) => {
+                    let mut row = row;
+                    row.truncate(headers.len());
+                    let mut record: EnaRecord = row.deserialize(Some(headers))?;
+                    record.fill_accession_map();
+                    self.records.push(record);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records of the body, including a last line without newline.
+    fn finish(mut self) -> Result<Vec<EnaRecord>> {
+        if let Some(gunzip) = self.gunzip.take() {
+            let rest = gunzip
+                .finish()
+                .map_err(|e| truncated(format!("incomplete gzip data ({})", e)))?;
+            self.push(&rest)?;
+        }
+        let rest = std::mem::take(&mut self.pending);
+        self.parse(&rest)?;
+        Ok(self.records)
+    }
+}
+
+fn truncated(reason: String) -> anyhow::Error {
+    PolariseqError::Network(format!("ENA response was truncated: {}", reason)).into()
+}
+
+/// ENA refused some of the requested fields, e.g. after renaming one.
+#[derive(Debug, thiserror::Error)]
+#[error("ENA rejected the requested fields: {0}")]
+struct RejectedFields(String);
+
+/// The fields of [`ENA_FIELDS`] listed in `offered`, the TSV of ENA's
+/// `returnFields` endpoint. `run_accession` is always kept.
+fn offered_fields(offered: &str) -> String {
+    let offered: std::collections::HashSet<&str> = offered
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split('\t').next())
+        .map(str::trim)
+        .collect();
+    ENA_FIELDS
+        .split(',')
+        .filter(|field| *field == "run_accession" || offered.contains(field))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// [`ENA_FIELDS`] reduced to what ENA currently offers for runs.
+async fn current_fields(client: &reqwest::Client) -> Result<String> {
+    let url = "https://www.ebi.ac.uk/ena/portal/api/returnFields?result=read_run&format=tsv";
+    let response = circuit::send(client.get(url))
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| PolariseqError::Network(format!("ENA returnFields failed: {}", e)))?;
+    Ok(offered_fields(&response.text().await?))
+}
+
+/// One page of the filereport of `accession`, parsed as it streams in.
+async fn fetch_ena_page(
+    client: &reqwest::Client,
+    accession: &str,
+    fields: &str,
+    offset: usize,
+) -> Result<Vec<EnaRecord>> {
+    let url = format!(
+        "https://www.ebi.ac.uk/ena/portal/api/filereport?accession={}&result=read_run&fields={}&format=tsv&limit={}&offset={}",
+        accession, fields, ENA_PAGE_SIZE, offset
+    );
+    let mut response = circuit::send(client.get(&url))
+        .await
+        .map_err(|e| PolariseqError::Network(format!("ENA portal request failed: {}", e)))?;
+    let status = response.status();
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Err(
+            PolariseqError::Network(format!("ENA portal returned status {}", status)).into(),
+        );
+    }
+    if status == reqwest::StatusCode::BAD_REQUEST {
+        let message = response.text().await.unwrap_or_default();
+        let message = message.trim().to_string();
+        if message.to_ascii_lowercase().contains("field") {
+            return Err(RejectedFields(message).into());
+        }
+        return Err(PolariseqError::Metadata(format!(
+            "ENA rejected the request for {}: {}",
+            accession, message
+        ))
+        .into());
+    }
+    if !status.is_success() {
+        return Err(PolariseqError::Metadata(format!(
             "Failed to get response. Status code: {}",
-            response.status()
-        ));
+            status
+        ))
+        .into());
     }
-    let text = response.text().await?;
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .delimiter(b'\t')
-        .from_reader(text.as_bytes());
+    let expected = response.content_length();
+    let mut received = 0;
+    let mut stream = TsvStream::default();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| PolariseqError::Network(format!("ENA response broke off: {}", e)))?
+    {
+        received += chunk.len() as u64;
+        stream.push(&chunk)?;
+    }
+    if let Some(expected) = expected.filter(|&n| n != received) {
+        return Err(truncated(format!(
+            "received {} of {} bytes",
+            received, expected
+        )));
+    }
+    stream.finish()
+}
+
+/// [`fetch_ena_page`] retried with exponential backoff after network
+/// errors, 5xx and 429 responses and truncated bodies, as set in the
+/// `http:` section of the config.
+async fn fetch_ena_page_with_retries(
+    client: &reqwest::Client,
+    accession: &str,
+    fields: &str,
+    offset: usize,
+) -> Result<Vec<EnaRecord>> {
+    let config = http::current();
+    let mut retry = 0;
+    loop {
+        let err = match fetch_ena_page(client, accession, fields, offset).await {
+            Ok(page) => return Ok(page),
+            Err(err) => err,
+        };
+        let transient = matches!(error::classify(&err), Some(PolariseqError::Network(_)));
+        if !transient || retry >= config.retries() {
+            return Err(err);
+        }
+        retry += 1;
+        let wait = config.backoff(retry);
+        warn!(
+            "[Network] {:#}; retrying {} in {}s ({}/{})",
+            err,
+            accession,
+            wait.as_secs(),
+            retry,
+            config.retries()
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// All runs of `accession` at ENA, [`ENA_PAGE_SIZE`] at a time. A failed
+/// page is retried on its own, so earlier pages are not fetched again.
+/// Should ENA drop or rename a requested field, the request is repeated
+/// with the fields it still offers.
+pub async fn fetch_ena_data(accession: &str) -> Result<Vec<EnaRecord>> {
+    let client = http::client_builder().gzip(true).build()?;
+    let mut fields = ENA_FIELDS.to_string();
     let mut records = Vec::new();
-    for result in reader.deserialize() {
-        let record: EnaRecord = result?;
-        records.push(record);
+    loop {
+        let result = fetch_ena_page_with_retries(&client, accession, &fields, records.len()).await;
+        let page = match result {
+            Ok(page) => page,
+            Err(err) => {
+                let Some(rejected) = err.downcast_ref::<RejectedFields>() else {
+                    return Err(err);
+                };
+                let offered = if fields == ENA_FIELDS {
+                    current_fields(&client).await?
+                } else {
+                    fields.clone()
+                };
+                if offered == fields {
+                    return Err(PolariseqError::Metadata(rejected.to_string()).into());
+                }
+                let dropped: Vec<&str> = ENA_FIELDS
+                    .split(',')
+                    .filter(|f| !offered.split(',').any(|o| o == *f))
+                    .collect();
+                warn!(
+                    "ENA no longer offers {}; fetching the other fields",
+                    dropped.join(", ")
+                );
+                fields = offered;
+                continue;
+            }
+        };
+        let last = page.len() < ENA_PAGE_SIZE;
+        records.extend(page);
+        if last {
+            return Ok(records);
+        }
+        info!("Fetched {} runs of {} so far", records.len(), accession);
     }
-    Ok(records)
 }
 
+/// Primary accession (PRJ…/SAM…) of a secondary study or sample accession
+/// (SRP/ERP/DRP, SRS/ERS/DRS), looked up in the ENA portal. `None` if ENA
+/// does not know it or it is not a secondary accession.
+pub async fn resolve_secondary_accession(accession: &str) -> Result<Option<String>> {
+    let (result, field) = match accession::kind(accession) {
+        Some(accession::AccessionKind::Study) => ("study", "secondary_study_accession"),
+        Some(accession::AccessionKind::Sample) if accession::is_secondary(accession) => {
+            ("sample", "secondary_sample_accession")
+        }
+        _ => return Ok(None),
+    };
+    let url = format!(
+        "https://www.ebi.ac.uk/ena/portal/api/search?result={}&query={}%3D%22{}%22&fields={}_accession&format=tsv",
+        result, field, accession, result
+    );
+    let client = http::client_builder().build()?;
+    let response = circuit::send(client.get(&url))
+        .await
+        .map_err(|e| PolariseqError::Network(format!("ENA portal request failed: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(PolariseqError::Metadata(format!(
+            "ENA could not resolve {} (status {})",
+            accession,
+            response.status()
+        ))
+        .into());
+    }
+    // Header line, then `<primary>[\t...]` per match.
+    Ok(response
+        .text()
+        .await?
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split('\t').next())
+        .map(str::trim)
+        .filter(|primary| !primary.is_empty())
+        .map(str::to_string))
+}
+
+/// Records of a run-list TSV. `#` lines are skipped, so the
+/// `ena_metadata*.tsv` written by [`save_metadata_tsv`] reads back.
 pub fn read_tsv_data(tsv_path: &Path) -> Result<Vec<EnaRecord>> {
     use csv::ReaderBuilder;
 
     let mut reader = ReaderBuilder::new()
         .has_headers(true)
         .delimiter(b'\t')
+        .comment(Some(b'#'))
         .from_path(tsv_path)?;
     let mut records = Vec::new();
     for result in reader.deserialize() {
-        let record: EnaRecord = result?;
+        let mut record: EnaRecord = result?;
+        record.fill_accession_map();
         records.push(record);
     }
     Ok(records)
@@ -251,28 +862,45 @@ impl RegexFilters {
             .iter()
             .map(|s| Regex::new(s))
             .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!("Invalid regex pattern for filter_sample: {}", e))?;
+            .map_err(|e| {
+                PolariseqError::InvalidInput(format!(
+                    "Invalid regex pattern for filter_sample: {}",
+                    e
+                ))
+            })?;
 
         let include_run = options
             .filter_run
             .iter()
             .map(|s| Regex::new(s))
             .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!("Invalid regex pattern for filter_run: {}", e))?;
+            .map_err(|e| {
+                PolariseqError::InvalidInput(format!("Invalid regex pattern for filter_run: {}", e))
+            })?;
 
         let exclude_sample = options
             .exclude_sample
             .iter()
             .map(|s| Regex::new(s))
             .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!("Invalid regex pattern for exclude_sample: {}", e))?;
+            .map_err(|e| {
+                PolariseqError::InvalidInput(format!(
+                    "Invalid regex pattern for exclude_sample: {}",
+                    e
+                ))
+            })?;
 
         let exclude_run = options
             .exclude_run
             .iter()
             .map(|s| Regex::new(s))
             .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| anyhow!("Invalid regex pattern for exclude_run: {}", e))?;
+            .map_err(|e| {
+                PolariseqError::InvalidInput(format!(
+                    "Invalid regex pattern for exclude_run: {}",
+                    e
+                ))
+            })?;
 
         Ok(Self {
             include_sample,
@@ -282,6 +910,23 @@ impl RegexFilters {
         })
     }
 
+    /// Keep only the records accepted by [`RegexFilters::should_include`].
+    pub fn apply(&self, records: Vec<EnaRecord>) -> Vec<EnaRecord> {
+        let total = records.len();
+        let filtered: Vec<EnaRecord> = records
+            .into_iter()
+            .filter(|record| self.should_include(record))
+            .collect();
+        let filtered_count = total - filtered.len();
+        if filtered_count > 0 {
+            info!(
+                "Filtered out {} records based on regex patterns",
+                filtered_count
+            );
+        }
+        filtered
+    }
+
     pub fn should_include(&self, record: &EnaRecord) -> bool {
         if !self.include_sample.is_empty()
             && !self
@@ -332,21 +977,19 @@ pub fn process_records(
             }
         }
 
-        let ftp_urls: Vec<&str> = record
-            .fastq_ftp
-            .split(';')
-            .filter(|s| !s.is_empty())
-            .collect();
-        let md5s: Vec<&str> = record
-            .fastq_md5
-            .split(';')
-            .filter(|s| !s.is_empty())
-            .collect();
-        let sizes: Vec<u64> = record
-            .fastq_bytes
+        let submitted = long_read::submitted_files(&record);
+        let (ftp, md5, bytes) =
+            submitted.unwrap_or((&record.fastq_ftp, &record.fastq_md5, &record.fastq_bytes));
+        let ftp_urls: Vec<&str> = ftp.split(';').filter(|s| !s.is_empty()).collect();
+        let md5s: Vec<&str> = md5.split(';').filter(|s| !s.is_empty()).collect();
+        let sizes: Vec<u64> = bytes
             .split(';')
             .filter_map(|s| s.parse::<u64>().ok())
             .collect();
+        let file_name = |url: &str| match submitted {
+            Some(_) => long_read::local_name(&record.run_accession, url),
+            None => url.rsplit('/').next().unwrap_or("").to_string(),
+        };
 
         if ftp_urls.is_empty() || md5s.is_empty() {
             continue;
@@ -356,7 +999,7 @@ pub fn process_records(
         }
 
         let fastq_ftp_1_url = ftp_urls[0].to_string();
-        let fastq_ftp_1_name = fastq_ftp_1_url.rsplit('/').next().unwrap_or("").to_string();
+        let fastq_ftp_1_name = file_name(&fastq_ftp_1_url);
         let fastq_md5_1 = md5s[0].to_string();
         let fastq_bytes_1 = *sizes.first().unwrap_or(&0);
 
@@ -364,7 +1007,7 @@ pub fn process_records(
             if ftp_urls.len() >= 2 && md5s.len() >= 2 {
                 (
                     Some(ftp_urls[1].to_string()),
-                    Some(ftp_urls[1].rsplit('/').next().unwrap_or("").to_string()),
+                    Some(file_name(ftp_urls[1])),
                     Some(md5s[1].to_string()),
                     sizes.get(1).copied(),
                 )
@@ -388,6 +1031,165 @@ pub fn process_records(
     Ok(processed)
 }
 
+/// Directory for per-project metadata files: `<output>/<acc>_metadata` when
+/// an accession is known, otherwise the output directory itself.
+fn metadata_dir(output_dir: &Path, accession: Option<&str>) -> Result<PathBuf> {
+    match accession {
+        Some(acc) => {
+            let meta_dir = output_dir.join(format!("{}_metadata", acc));
+            std::fs::create_dir_all(&meta_dir)?;
+            Ok(meta_dir)
+        }
+        None => Ok(output_dir.to_path_buf()),
+    }
+}
+
+/// Write the R1/R2 `md5<TAB>file<TAB>sample` tables published by ENA.
+pub fn save_md5_files(
+    records: &[ProcessedRecord],
+    output_dir: &Path,
+    accession: Option<&str>,
+) -> Result<()> {
+    let save_dir = metadata_dir(output_dir, accession)?;
+    info!(
+        "Saving MD5 files to {}...",
+        save_dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| save_dir.display().to_string())
+    );
+    let (r1_path, r2_path) = if let Some(acc) = accession {
+        (
+            save_dir.join(format!("R1_fastq_md5_{}.tsv", acc)),
+            save_dir.join(format!("R2_fastq_md5_{}.tsv", acc)),
+        )
+    } else {
+        (
+            save_dir.join("R1_fastq_md5.tsv"),
+            save_dir.join("R2_fastq_md5.tsv"),
+        )
+    };
+
+    let mut r1_file = File::create(&r1_path)?;
+    let mut r2_file = File::create(&r2_path)?;
+
+    for record in records {
+        writeln!(
+            r1_file,
+            "{}\t{}\t{}",
+            record.fastq_md5_1, record.fastq_ftp_1_name, record.sample_title
+        )?;
+        if let (Some(md5), Some(name)) = (&record.fastq_md5_2, &record.fastq_ftp_2_name) {
+            writeln!(r2_file, "{}\t{}\t{}", md5, name, record.sample_title)?;
+        }
+    }
+    info!("MD5 files saved");
+    Ok(())
+}
+
+/// Header of [`save_md5_manifest`]; `file` is the ENA file name and `path`
+/// where it ends up below the output directory.
+pub const MD5_MANIFEST_HEADER: &str =
+    "run_accession\tread\tmd5\tbytes\tlibrary_layout\tsample_title\tfile\tpath";
+
+/// Write `md5_manifest[_<acc>].tsv`: one row per file of `processed`, with
+/// the library layout from `records` and the `output_subdir` of the run.
+pub fn save_md5_manifest(
+    records: &[EnaRecord],
+    processed: &[ProcessedRecord],
+    output_dir: &Path,
+    accession: Option<&str>,
+) -> Result<PathBuf> {
+    let save_dir = metadata_dir(output_dir, accession)?;
+    let path = match accession {
+        Some(acc) => save_dir.join(format!("md5_manifest_{}.tsv", acc)),
+        None => save_dir.join("md5_manifest.tsv"),
+    };
+    let layouts: HashMap<&str, &str> = records
+        .iter()
+        .filter_map(|r| Some((r.run_accession.as_str(), r.library_layout.as_deref()?)))
+        .collect();
+    let subdirs = overrides::output_subdirs(records);
+
+    let mut file = File::create(&path)?;
+    writeln!(file, "{}", MD5_MANIFEST_HEADER)?;
+    for record in processed {
+        let run = record.run_accession.as_str();
+        let mut files = vec![(
+            &record.fastq_ftp_1_name,
+            &record.fastq_md5_1,
+            record.fastq_bytes_1,
+        )];
+        if let (Some(name), Some(md5)) = (&record.fastq_ftp_2_name, &record.fastq_md5_2) {
+            files.push((name, md5, record.fastq_bytes_2.unwrap_or(0)));
+        }
+        for (read, (name, md5, bytes)) in files.into_iter().enumerate() {
+            let final_path = match subdirs.get(run) {
+                Some(subdir) => subdir.join(name),
+                None => PathBuf::from(name),
+            };
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                run,
+                read + 1,
+                md5,
+                bytes,
+                layouts.get(run).copied().unwrap_or_default(),
+                record.sample_title,
+                name,
+                final_path.display()
+            )?;
+        }
+    }
+    info!(
+        "MD5 manifest saved to {}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    Ok(path)
+}
+
+/// Write the full ENA records as `ena_metadata[_<acc>].tsv`.
+pub fn save_metadata_tsv(
+    records: &[EnaRecord],
+    output_dir: &Path,
+    accession: Option<&str>,
+) -> Result<()> {
+    let save_dir = metadata_dir(output_dir, accession)?;
+    let path = if let Some(acc) = accession {
+        save_dir.join(format!("ena_metadata_{}.tsv", acc))
+    } else {
+        save_dir.join("ena_metadata.tsv")
+    };
+    info!(
+        "Saving ENA metadata to {}...",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    let mut file = File::create(&path)?;
+    if let Some(acc) = accession {
+        writeln!(file, "# Project Accession: {}", acc)?;
+    }
+
+    let mut wtr = csv::WriterBuilder::new().delimiter(b'\t').from_writer(file);
+
+    for record in records {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()?;
+    info!("Metadata saved");
+    Ok(())
+}
+
+/// In-progress name of `path` (`<name>.part`). Downloads and compression
+/// write there and rename to `path` only once the file is verified, so
+/// anything watching the output directory never sees a truncated file.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
 /// Compress all FASTQ files for a given run_id in output_dir using native parallel gzip.
 /// Returns the list of created .fastq.gz files. Deletes original .fastq files on success.
 pub fn compress_fastq_files(
@@ -420,8 +1222,9 @@ pub fn compress_fastq_files(
         let input = File::open(&input_path)
             .with_context(|| format!("Failed to open {}", input_path.display()))?;
         let input = BufReader::new(input);
-        let output = File::create(&output_path)
-            .with_context(|| format!("Failed to create {}", output_path.display()))?;
+        let partial_path = part_path(&output_path);
+        let output = File::create(&partial_path)
+            .with_context(|| format!("Failed to create {}", partial_path.display()))?;
 
         let mut writer = ZBuilder::<Gzip, _>::new()
             .num_threads(threads)
@@ -439,6 +1242,8 @@ pub fn compress_fastq_files(
         writer
             .finish()
             .with_context(|| format!("Failed to finalize {}", output_path.display()))?;
+        std::fs::rename(&partial_path, &output_path)
+            .with_context(|| format!("Failed to move {} into place", output_path.display()))?;
 
         std::fs::remove_file(&input_path)
             .with_context(|| format!("Failed to remove original {}", input_path.display()))?;
@@ -505,23 +1310,222 @@ pub fn generate_md5sum_file_at(md5_path: &Path, files: &[PathBuf]) -> Result<Pat
     Ok(md5_path.to_path_buf())
 }
 
+/// Check the external tools `method` runs: each must exist, be executable
+/// and answer `--version`.
 pub fn validate_config(config: &Config, method: DownloadMethod) -> Result<()> {
     match method {
         DownloadMethod::Aws => {
             check_executable(&config.software.fasterq_dump, "fasterq-dump")?;
         }
-        DownloadMethod::Ftp => {}
+        DownloadMethod::Ftp => {
+            check_executable(Path::new("wget"), "wget")?;
+        }
+        DownloadMethod::Ega | DownloadMethod::Mock => {}
     }
     Ok(())
 }
 
+/// Run `command` to completion, killing the child if `cancel` fires first.
+pub async fn run_cancellable(
+    command: &mut tokio::process::Command,
+    cancel: &CancellationToken,
+) -> Result<std::process::Output> {
+    let started = std::time::Instant::now();
+    let child = match command
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            audit::record(command.as_std(), started, Err(&e.to_string()));
+            return Err(e.into());
+        }
+    };
+    let result = tokio::select! {
+        output = child.wait_with_output() => output.map_err(Into::into),
+        _ = cancel.cancelled() => Err(PolariseqError::Cancelled.into()),
+    };
+    record_result(command, started, &result);
+    result
+}
+
+fn record_result(
+    command: &tokio::process::Command,
+    started: std::time::Instant,
+    result: &Result<std::process::Output>,
+) {
+    match result {
+        Ok(output) => audit::record(
+            command.as_std(),
+            started,
+            Ok((output.status, &output.stderr)),
+        ),
+        Err(e) => audit::record(command.as_std(), started, Err(&format!("{:#}", e))),
+    }
+}
+
+/// Like [`run_cancellable`], but stops the child (`SIGSTOP`) while `pause`
+/// is paused and continues it on resume. Elsewhere than Unix the child
+/// keeps running.
+pub async fn run_pausable(
+    command: &mut tokio::process::Command,
+    cancel: &CancellationToken,
+    pause: &aws_s3::PauseToken,
+) -> Result<std::process::Output> {
+    let started = std::time::Instant::now();
+    let child = match command
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            audit::record(command.as_std(), started, Err(&e.to_string()));
+            return Err(e.into());
+        }
+    };
+    let pid = child.id();
+    let result = wait_pausable(child.wait_with_output(), pid, cancel, pause).await;
+    record_result(command, started, &result);
+    result
+}
+
+/// [`run_pausable`] that also hands each stderr line to `on_line` as it
+/// arrives, for tools that report progress there. The lines are still
+/// returned as the output's `stderr`; stdout is not captured.
+pub async fn run_pausable_lines<F>(
+    command: &mut tokio::process::Command,
+    cancel: &CancellationToken,
+    pause: &aws_s3::PauseToken,
+    mut on_line: F,
+) -> Result<std::process::Output>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    use tokio::io::AsyncBufReadExt;
+    let started = std::time::Instant::now();
+    let mut child = match command
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            audit::record(command.as_std(), started, Err(&e.to_string()));
+            return Err(e.into());
+        }
+    };
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let reader = tokio::spawn(async move {
+        let mut collected = Vec::new();
+        let mut lines = tokio::io::BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            on_line(&line);
+            collected.extend_from_slice(line.as_bytes());
+            collected.push(b'\n');
+        }
+        collected
+    });
+    let pid = child.id();
+    let result = match wait_pausable(child.wait(), pid, cancel, pause).await {
+        Ok(status) => Ok(std::process::Output {
+            status,
+            stdout: Vec::new(),
+            stderr: reader.await.unwrap_or_default(),
+        }),
+        Err(e) => {
+            reader.abort();
+            Err(e)
+        }
+    };
+    record_result(command, started, &result);
+    result
+}
+
+/// Await `wait`, stopping process `pid` while `pause` is paused.
+async fn wait_pausable<T>(
+    wait: impl std::future::Future<Output = std::io::Result<T>>,
+    pid: Option<u32>,
+    cancel: &CancellationToken,
+    pause: &aws_s3::PauseToken,
+) -> Result<T> {
+    tokio::pin!(wait);
+    pause.transfer_started();
+    let mut stopped = false;
+    let result = loop {
+        tokio::select! {
+            output = &mut wait => break output.map_err(Into::into),
+            _ = cancel.cancelled() => break Err(PolariseqError::Cancelled.into()),
+            _ = tokio::time::sleep(std::time::Duration::from_millis(200)) => {
+                if pause.is_paused() != stopped {
+                    stopped = !stopped;
+                    if let Some(pid) = pid {
+                        signal_process(pid, if stopped { "STOP" } else { "CONT" }).await;
+                    }
+                }
+            }
+        }
+    };
+    pause.transfer_finished();
+    result
+}
+
+#[cfg(unix)]
+async fn signal_process(pid: u32, signal: &str) {
+    let _ = tokio::process::Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg(pid.to_string())
+        .status()
+        .await;
+}
+
+#[cfg(not(unix))]
+async fn signal_process(_pid: u32, _signal: &str) {}
+
+/// Resolve `path` (bare names through `PATH`) and make sure it runs.
 fn check_executable(path: &Path, name: &str) -> Result<()> {
-    if !path.exists() {
-        return Err(anyhow::anyhow!(
-            "{} not found at configured path: {}",
-            name,
-            path.display()
-        ));
+    let missing = || PolariseqError::DependencyMissing {
+        tool: name.to_string(),
+        path: path.to_path_buf(),
+    };
+    let unusable = |reason: String| PolariseqError::DependencyUnusable {
+        tool: name.to_string(),
+        reason,
+    };
+
+    let resolved = if path.exists() || path.components().count() > 1 {
+        path.to_path_buf()
+    } else {
+        which::which(path).map_err(|_| missing())?
+    };
+    if !resolved.is_file() {
+        return Err(missing().into());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if resolved.metadata()?.permissions().mode() & 0o111 == 0 {
+            return Err(unusable(format!("{} is not executable", resolved.display())).into());
+        }
+    }
+    let status = audit::output_blocking(
+        std::process::Command::new(&resolved)
+            .arg("--version")
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null()),
+    )
+    .map_err(|e| unusable(format!("failed to run {}: {}", resolved.display(), e)))?
+    .status;
+    if !status.success() {
+        return Err(unusable(format!(
+            "`{} --version` exited with {}",
+            resolved.display(),
+            status
+        ))
+        .into());
     }
     Ok(())
 }
@@ -565,4 +1569,192 @@ mod tests {
         assert!(contents.contains("@read1/1"));
         assert!(contents.contains("ACGTACGT"));
     }
+
+    #[test]
+    fn missing_yaml_falls_back_to_detected_tools() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = load_config_or_detect(&tmp.path().join("polariseq.yaml")).unwrap();
+        assert!(config.public_data.is_empty());
+        // EGA needs no external tools, whatever is installed here.
+        assert!(validate_config(&config, DownloadMethod::Ega).is_ok());
+
+        let yaml = tmp.path().join("public.yaml");
+        std::fs::write(&yaml, "public_data: {}\n").unwrap();
+        assert!(load_config(&yaml).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_executable_runs_version() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let tool = |name: &str, body: &str, mode: u32| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            path
+        };
+        let kind = |path: &Path| {
+            check_executable(path, "tool")
+                .err()
+                .map(|e| error::classify(&e).unwrap().kind())
+        };
+
+        assert_eq!(kind(&tool("ok", "exit 0", 0o755)), None);
+        assert_eq!(
+            kind(&tool("broken", "exit 3", 0o755)),
+            Some("dependency-unusable")
+        );
+        assert_eq!(
+            kind(&tool("plain", "exit 0", 0o644)),
+            Some("dependency-unusable")
+        );
+        assert_eq!(kind(&dir.path().join("absent")), Some("dependency-missing"));
+    }
+
+    #[test]
+    fn tsv_records_get_an_accession_map() {
+        let tmp = tempfile::tempdir().unwrap();
+        let tsv = tmp.path().join("runs.tsv");
+        std::fs::write(
+            &tsv,
+            "run_accession\tstudy_accession\tsecondary_study_accession\tsample_accession\n\
+             SRR1\tPRJNA1\tSRP1\tSAMN1\n",
+        )
+        .unwrap();
+        let records = read_tsv_data(&tsv).unwrap();
+        assert_eq!(records[0].accession_map, "PRJNA1=SRP1");
+    }
+
+    #[test]
+    fn parses_a_filereport_split_across_chunks() {
+        let body = b"run_accession\tstudy_accession\tfastq_md5\n\
+                     SRR1\tPRJNA1\ta;b\nSRR2\tPRJNA1\tc\nSRR3\tPRJNA1\t";
+        let mut stream = TsvStream::default();
+        for chunk in body.chunks(7) {
+            stream.push(chunk).unwrap();
+        }
+        let records = stream.finish().unwrap();
+        let runs: Vec<&str> = records.iter().map(|r| r.run_accession.as_str()).collect();
+        assert_eq!(runs, ["SRR1", "SRR2", "SRR3"]);
+        assert_eq!(records[0].fastq_md5, "a;b");
+        assert_eq!(records[2].study_accession.as_deref(), Some("PRJNA1"));
+        assert_eq!(records[2].fastq_md5, "");
+    }
+
+    #[test]
+    fn flags_a_filereport_cut_off_mid_row() {
+        let body = b"run_accession\tstudy_accession\tfastq_md5\n\
+                     SRR1\tPRJNA1\ta;b\nSRR2\tPRJNA1\tc\n";
+        let parse = |body: &[u8]| {
+            let mut stream = TsvStream::default();
+            for chunk in body.chunks(7) {
+                stream.push(chunk)?;
+            }
+            stream.finish()
+        };
+        for cut in [body.len() - 4, body.len() - 9] {
+            let err = parse(&body[..cut]).unwrap_err();
+            let kind = error::classify(&err).map(PolariseqError::kind);
+            assert_eq!(kind, Some("network"), "{:#}", err);
+        }
+        assert_eq!(parse(&body[..body.len() - 1]).unwrap().len(), 2);
+        assert!(parse(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn tolerates_gzip_bodies_and_schema_changes() {
+        let body = b"fastq_md5\tnew_field\trun_accession\n\
+                     a\tx\tSRR1\n\
+                     b\ty\tSRR2\textra\n";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(body).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut stream = TsvStream::default();
+        for chunk in gzip.chunks(5) {
+            stream.push(chunk).unwrap();
+        }
+        let records = stream.finish().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].run_accession, "SRR2");
+        assert_eq!(records[1].fastq_md5, "b");
+        assert_eq!(records[1].study_accession, None);
+
+        let mut stream = TsvStream::default();
+        stream.push(&gzip[..gzip.len() - 4]).unwrap();
+        assert!(stream.finish().is_err());
+
+        let offered = "columnId\tdescription\nrun_accession\trun\nfastq_md5\tmd5\nsra_md5\tmd5\n";
+        assert_eq!(offered_fields(offered), "run_accession,fastq_md5,sra_md5");
+    }
+
+    #[test]
+    fn sorts_records_by_accession_number() {
+        let mut records: Vec<EnaRecord> = serde_json::from_value(serde_json::json!([
+            {"run_accession": "SRR10", "sample_accession": "SAMN1"},
+            {"run_accession": "ERR5", "sample_accession": "SAMN2"},
+            {"run_accession": "SRR9", "sample_accession": "SAMN2"},
+        ]))
+        .unwrap();
+        let runs = |records: &[EnaRecord]| -> Vec<String> {
+            records.iter().map(|r| r.run_accession.clone()).collect()
+        };
+        SortBy::None.sort(&mut records);
+        assert_eq!(runs(&records), ["SRR10", "ERR5", "SRR9"]);
+        SortBy::Run.sort(&mut records);
+        assert_eq!(runs(&records), ["ERR5", "SRR9", "SRR10"]);
+        SortBy::Sample.sort(&mut records);
+        assert_eq!(runs(&records), ["SRR10", "ERR5", "SRR9"]);
+    }
+
+    #[test]
+    fn md5_manifest_has_a_row_per_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let records: Vec<EnaRecord> = serde_json::from_value(serde_json::json!([
+            {"run_accession": "SRR1", "library_layout": "PAIRED", "output_subdir": "liver"},
+            {"run_accession": "SRR2", "library_layout": "SINGLE"},
+        ]))
+        .unwrap();
+        let processed = vec![
+            ProcessedRecord {
+                run_accession: "SRR1".into(),
+                fastq_ftp_1_url: String::new(),
+                fastq_ftp_2_url: Some(String::new()),
+                fastq_ftp_1_name: "SRR1_1.fastq.gz".into(),
+                fastq_ftp_2_name: Some("SRR1_2.fastq.gz".into()),
+                fastq_md5_1: "a1".into(),
+                fastq_md5_2: Some("a2".into()),
+                fastq_bytes_1: 10,
+                fastq_bytes_2: Some(20),
+                sample_title: "liver (rep 1)".into(),
+            },
+            ProcessedRecord {
+                run_accession: "SRR2".into(),
+                fastq_ftp_1_url: String::new(),
+                fastq_ftp_2_url: None,
+                fastq_ftp_1_name: "SRR2.fastq.gz".into(),
+                fastq_ftp_2_name: None,
+                fastq_md5_1: "b1".into(),
+                fastq_md5_2: None,
+                fastq_bytes_1: 30,
+                fastq_bytes_2: None,
+                sample_title: "blood".into(),
+            },
+        ];
+
+        let path = save_md5_manifest(&records, &processed, tmp.path(), Some("PRJNA1")).unwrap();
+        assert!(path.ends_with("PRJNA1_metadata/md5_manifest_PRJNA1.tsv"));
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], MD5_MANIFEST_HEADER);
+        assert_eq!(
+            lines[2],
+            "SRR1\t2\ta2\t20\tPAIRED\tliver (rep 1)\tSRR1_2.fastq.gz\tliver/SRR1_2.fastq.gz"
+        );
+        assert_eq!(
+            lines[3],
+            "SRR2\t1\tb1\t30\tSINGLE\tblood\tSRR2.fastq.gz\tSRR2.fastq.gz"
+        );
+    }
 }
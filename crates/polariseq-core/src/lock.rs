@@ -0,0 +1,99 @@
+//! Advisory lock on an output directory.
+//!
+//! Two jobs writing the same `.sra` chunks and `.meta.json` files corrupt
+//! each other without any error, so a download holds an exclusive lock on
+//! `<output>/.polariseq.lock` for as long as it runs. The lock is released
+//! by the OS when the process exits, so a crashed job never leaves a stale
+//! lock behind; the file itself stays and only records the last holder.
+
+use crate::error::PolariseqError;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub const LOCK_FILE: &str = ".polariseq.lock";
+
+/// Held for the lifetime of a job; dropping it releases the lock.
+#[derive(Debug)]
+pub struct OutputLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl OutputLock {
+    /// Lock `output`, which must exist. If another process holds the lock,
+    /// fail, or with `force` warn and return `None` so the job runs
+    /// unlocked.
+    pub fn acquire(output: &Path, force: bool) -> Result<Option<Self>> {
+        let path = output.join(LOCK_FILE);
+        // No truncation before the lock is ours: the file names the holder.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                let holder = match holder.trim() {
+                    "" => "another polariseq process".to_string(),
+                    holder => holder.to_string(),
+                };
+                if force {
+                    warn!(
+                        "{} is locked by {}; continuing because of --force",
+                        output.display(),
+                        holder
+                    );
+                    return Ok(None);
+                }
+                return Err(PolariseqError::InvalidInput(format!(
+                    "{} is in use by {}; wait for it to finish, use another output directory, or pass --force",
+                    output.display(),
+                    holder
+                ))
+                .into());
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+        file.set_len(0)?;
+        writeln!(
+            file,
+            "pid {} since {}",
+            std::process::id(),
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+        )?;
+        Ok(Some(Self { _file: file, path }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_fails_unless_forced() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = OutputLock::acquire(dir.path(), false).unwrap().unwrap();
+        assert_eq!(lock.path(), dir.path().join(LOCK_FILE));
+
+        let err = OutputLock::acquire(dir.path(), false).unwrap_err();
+        assert!(err.to_string().contains("in use by pid "));
+        assert!(OutputLock::acquire(dir.path(), true).unwrap().is_none());
+
+        drop(lock);
+        assert!(OutputLock::acquire(dir.path(), false).unwrap().is_some());
+    }
+}
@@ -0,0 +1,112 @@
+//! Oxford Nanopore and PacBio runs.
+//!
+//! ENA's `fastq_ftp` of a long-read run is a conversion that may drop the
+//! submitter's read names, modification tags or signal data, so when the
+//! run has one or two submitted FASTQ, BAM or fast5/pod5 tarball files,
+//! the FTP backend downloads those instead, prefixed with the run
+//! accession when their name lacks it. Runs converted from SRA keep one
+//! read per spot (`fasterq-dump --split-spot`) instead of the paired
+//! `--split-3` layout.
+
+use crate::EnaRecord;
+
+/// `instrument_platform` values of long-read runs.
+const LONG_READ_PLATFORMS: [&str; 2] = ["OXFORD_NANOPORE", "PACBIO_SMRT"];
+
+/// Submitted file types worth preferring over ENA's FASTQ.
+const SUBMITTED_SUFFIXES: [&str; 8] = [
+    ".fastq.gz",
+    ".fq.gz",
+    ".fastq",
+    ".fq",
+    ".bam",
+    ".tar.gz",
+    ".tgz",
+    ".tar",
+];
+
+/// Whether `record` comes from a long-read platform.
+pub fn is_long_read(record: &EnaRecord) -> bool {
+    record.instrument_platform.as_deref().is_some_and(|p| {
+        LONG_READ_PLATFORMS
+            .iter()
+            .any(|l| p.eq_ignore_ascii_case(l))
+    })
+}
+
+/// `(ftp, md5, bytes)` lists of the submitted files to download instead
+/// of ENA's FASTQ, for a long-read run whose submitted files qualify.
+pub fn submitted_files(record: &EnaRecord) -> Option<(&str, &str, &str)> {
+    if !is_long_read(record) {
+        return None;
+    }
+    let ftp = record.submitted_ftp.as_deref()?;
+    let md5 = record.submitted_md5.as_deref()?;
+    let urls: Vec<&str> = ftp.split(';').filter(|s| !s.is_empty()).collect();
+    let usable = matches!(urls.len(), 1 | 2)
+        && md5.split(';').filter(|s| !s.is_empty()).count() == urls.len()
+        && urls.iter().all(|url| {
+            let url = url.to_ascii_lowercase();
+            SUBMITTED_SUFFIXES.iter().any(|s| url.ends_with(s))
+        });
+    usable.then(|| {
+        (
+            ftp,
+            md5,
+            record.submitted_bytes.as_deref().unwrap_or_default(),
+        )
+    })
+}
+
+/// `fasterq-dump` layout option: one read per spot for long-read runs.
+pub fn split_arg(long_read: bool) -> &'static str {
+    if long_read {
+        "--split-spot"
+    } else {
+        "--split-3"
+    }
+}
+
+/// Local name of `url` for `run_id`: the remote name, prefixed with the run
+/// accession unless it already starts with it.
+pub fn local_name(run_id: &str, url: &str) -> String {
+    let name = url.rsplit('/').next().unwrap_or_default();
+    if name.starts_with(run_id) {
+        name.to_string()
+    } else {
+        format!("{}_{}", run_id, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_submitted_long_read_files() {
+        let mut record: EnaRecord = serde_json::from_value(serde_json::json!({
+            "run_accession": "ERR1",
+            "instrument_platform": "OXFORD_NANOPORE",
+            "fastq_ftp": "ftp.sra.ebi.ac.uk/vol1/fastq/ERR1/ERR1.fastq.gz",
+            "fastq_md5": "aa",
+            "fastq_bytes": "10",
+            "submitted_ftp": "ftp.sra.ebi.ac.uk/vol1/run/ERR1/reads.bam",
+            "submitted_md5": "bb",
+            "submitted_bytes": "20",
+        }))
+        .unwrap();
+        assert!(is_long_read(&record));
+        let (ftp, md5, bytes) = submitted_files(&record).unwrap();
+        assert_eq!((md5, bytes), ("bb", "20"));
+        assert_eq!(local_name("ERR1", ftp), "ERR1_reads.bam");
+        assert_eq!(local_name("ERR1", "x/ERR1.fastq.gz"), "ERR1.fastq.gz");
+
+        // Three tarballs don't fit one run's two file slots.
+        record.submitted_ftp = Some("x/a.tar.gz;x/b.tar.gz;x/c.tar.gz".into());
+        record.submitted_md5 = Some("1;2;3".into());
+        assert!(submitted_files(&record).is_none());
+
+        record.instrument_platform = Some("ILLUMINA".into());
+        assert!(!is_long_read(&record));
+    }
+}
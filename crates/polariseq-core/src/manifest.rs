@@ -0,0 +1,309 @@
+//! `manifest.json`: provenance of every produced file.
+//!
+//! [`ManifestHook`] records, for each verified run, its files with their
+//! source (ENA URL, S3 URI or SRA accession), the backend that fetched
+//! them, size, MD5 and SHA-256 and modification time. The job-level
+//! header holds the exact invocation and the `--version` lines of the
+//! external tools. The file is rewritten after every run, so an
+//! interrupted job still leaves a valid manifest, and a resumed job keeps
+//! the entries of earlier runs.
+
+use crate::aws_s3::{SraLookup, SraUtils};
+use crate::backend::{BackendContext, RunHook};
+use crate::client::{DownloadJob, JobPlan};
+use crate::{doctor, Config, ProcessedRecord};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::debug;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// One produced file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub run_accession: String,
+    /// Relative to the output directory.
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub bytes: u64,
+    pub md5: String,
+    pub sha256: String,
+    pub modified_at: String,
+    pub recorded_at: String,
+}
+
+/// Contents of `manifest.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// `polariseq <version>`.
+    pub generator: String,
+    pub invocation: Vec<String>,
+    pub started_at: String,
+    pub updated_at: String,
+    /// Tool → first line of its `--version` output.
+    pub tool_versions: BTreeMap<String, String>,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    /// Replace the entries of `run_id` with `files`.
+    fn record_run(&mut self, run_id: &str, files: Vec<ManifestFile>) {
+        self.files.retain(|f| f.run_accession != run_id);
+        self.files.extend(files);
+        self.files
+            .sort_by(|a, b| (&a.run_accession, &a.path).cmp(&(&b.run_accession, &b.path)));
+        self.updated_at = Local::now().to_rfc3339();
+    }
+
+    /// Write atomically through a temporary file.
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// MD5 and SHA-256 of `path` in one read.
+pub fn file_digests(path: &Path) -> Result<(String, String)> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut md5 = md5::Context::new();
+    let mut sha256 = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        md5.consume(&buffer[..read]);
+        sha256.update(&buffer[..read]);
+    }
+    Ok((
+        format!("{:x}", md5.compute()),
+        format!("{:x}", sha256.finalize()),
+    ))
+}
+
+/// ENA URL of `file` of `record`, as fetched by the FTP backend.
+fn ftp_source(record: &ProcessedRecord, file: &str) -> Option<String> {
+    let url = if file == record.fastq_ftp_1_name {
+        &record.fastq_ftp_1_url
+    } else if record.fastq_ftp_2_name.as_deref() == Some(file) {
+        record.fastq_ftp_2_url.as_ref()?
+    } else {
+        return None;
+    };
+    // ENA lists URLs without a scheme; wget then uses HTTP.
+    Some(if url.contains("://") {
+        url.clone()
+    } else {
+        format!("http://{}", url)
+    })
+}
+
+/// Records every run's files into `<output>/manifest.json`.
+pub struct ManifestHook {
+    path: PathBuf,
+    output: PathBuf,
+    job: DownloadJob,
+    records: HashMap<String, ProcessedRecord>,
+    manifest: Mutex<Manifest>,
+}
+
+impl ManifestHook {
+    /// Collect tool versions and start (or continue) the manifest of
+    /// `job`. `invocation` is the command line to record.
+    pub async fn new(
+        config: &Config,
+        job: &DownloadJob,
+        plan: &JobPlan,
+        invocation: Vec<String>,
+    ) -> Result<Self> {
+        let path = job.output().join(MANIFEST_FILE);
+        let mut manifest = if path.exists() {
+            Manifest::load(&path)?
+        } else {
+            Manifest {
+                started_at: Local::now().to_rfc3339(),
+                ..Manifest::default()
+            }
+        };
+        manifest.generator = format!("polariseq {}", env!("CARGO_PKG_VERSION"));
+        manifest.invocation = invocation;
+        let tools = [
+            ("prefetch", config.software.prefetch.clone()),
+            ("fasterq-dump", config.software.fasterq_dump.clone()),
+            ("wget", PathBuf::from("wget")),
+        ];
+        for (name, path) in tools {
+            match doctor::tool_version(&path).await {
+                Ok(version) => {
+                    manifest.tool_versions.insert(name.to_string(), version);
+                }
+                Err(e) => debug!("No version for {}: {:#}", name, e),
+            }
+        }
+        Ok(Self {
+            path,
+            output: job.output().to_path_buf(),
+            job: job.clone(),
+            records: plan
+                .processed
+                .iter()
+                .map(|r| (r.run_accession.clone(), r.clone()))
+                .collect(),
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    async fn source(&self, run_id: &str, backend: Option<&str>, file: &str) -> Option<String> {
+        file_source(
+            &self.output,
+            &self.job,
+            self.records.get(run_id),
+            run_id,
+            backend,
+            file,
+        )
+        .await
+    }
+}
+
+/// Where `file` of `run_id` came from, given the `backend` that fetched
+/// it: the ENA URL, the S3 URI of the `.sra`, or `sra:<run>`.
+pub async fn file_source(
+    output: &Path,
+    job: &DownloadJob,
+    record: Option<&ProcessedRecord>,
+    run_id: &str,
+    backend: Option<&str>,
+    file: &str,
+) -> Option<String> {
+    match backend? {
+        #[cfg(feature = "ftp")]
+        "ftp" => crate::ftp::recorded_source(output, file).or_else(|| ftp_source(record?, file)),
+        #[cfg(not(feature = "ftp"))]
+        "ftp" => {
+            let _ = output;
+            ftp_source(record?, file)
+        }
+        "aws" => match SraUtils::get_metadata_for_format(run_id, job.options.sra_format).await {
+            Ok(SraLookup::Found(metadata)) => Some(metadata.s3_uri),
+            _ => None,
+        },
+        _ => Some(format!("sra:{}", run_id)),
+    }
+}
+
+impl RunHook for ManifestHook {
+    fn name(&self) -> &'static str {
+        "manifest"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut entries = Vec::new();
+            for file in files {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                let metadata = tokio::fs::metadata(file).await?;
+                let (md5, sha256) = {
+                    let file = file.clone();
+                    tokio::task::spawn_blocking(move || file_digests(&file)).await??
+                };
+                entries.push(ManifestFile {
+                    run_accession: run_id.to_string(),
+                    path: file
+                        .strip_prefix(&self.output)
+                        .unwrap_or(file)
+                        .to_path_buf(),
+                    source: self.source(run_id, ctx.backend, &name).await,
+                    backend: ctx.backend.map(str::to_string),
+                    bytes: metadata.len(),
+                    md5,
+                    sha256,
+                    modified_at: metadata
+                        .modified()
+                        .map(|t| DateTime::<Local>::from(t).to_rfc3339())
+                        .unwrap_or_default(),
+                    recorded_at: Local::now().to_rfc3339(),
+                });
+            }
+            let mut manifest = self.manifest.lock().unwrap();
+            manifest.record_run(run_id, entries);
+            manifest.save(&self.path)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_and_run_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("SRR1.fastq.gz");
+        std::fs::write(&path, b"abc").unwrap();
+        let (md5, sha256) = file_digests(&path).unwrap();
+        assert_eq!(md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let entry = |run: &str, file: &str| ManifestFile {
+            run_accession: run.into(),
+            path: file.into(),
+            source: None,
+            backend: None,
+            bytes: 3,
+            md5: md5.clone(),
+            sha256: sha256.clone(),
+            modified_at: String::new(),
+            recorded_at: String::new(),
+        };
+        let mut manifest = Manifest::default();
+        manifest.record_run("SRR2", vec![entry("SRR2", "SRR2.fastq.gz")]);
+        manifest.record_run("SRR1", vec![entry("SRR1", "old.fastq.gz")]);
+        manifest.record_run("SRR1", vec![entry("SRR1", "SRR1.fastq.gz")]);
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("SRR1.fastq.gz"),
+                PathBuf::from("SRR2.fastq.gz")
+            ]
+        );
+
+        manifest.save(&dir.path().join(MANIFEST_FILE)).unwrap();
+        assert_eq!(
+            Manifest::load(&dir.path().join(MANIFEST_FILE))
+                .unwrap()
+                .files
+                .len(),
+            2
+        );
+    }
+}
@@ -3,9 +3,8 @@
 //! Used by the `md5` CLI subcommand to produce `md5sum`-compatible manifests
 //! and to verify files against an existing manifest.
 
-use crate::progress::verify_bar_style;
+use crate::progress::{ProgressHandle, ProgressKind, ProgressSink};
 use anyhow::{anyhow, Context, Result};
-use indicatif::{MultiProgress, ProgressBar};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
@@ -19,8 +18,11 @@ pub fn compute_md5(path: &Path) -> Result<String> {
 }
 
 /// Compute the MD5 hex digest of a single file, reporting bytes read to an
-/// optional progress bar.
-pub fn compute_md5_with_progress(path: &Path, progress: Option<&ProgressBar>) -> Result<String> {
+/// optional progress handle.
+pub fn compute_md5_with_progress(
+    path: &Path,
+    progress: Option<&dyn ProgressHandle>,
+) -> Result<String> {
     let file = File::open(path)
         .with_context(|| format!("Failed to open {}", path.display()))?;
     let mut reader = BufReader::new(file);
@@ -41,19 +43,16 @@ pub fn compute_md5_with_progress(path: &Path, progress: Option<&ProgressBar>) ->
     Ok(format!("{:x}", ctx.compute()))
 }
 
-/// A per-file hashing bar on the shared MultiProgress; matches the style used
-/// for post-download integrity checks in `aws_s3.rs`.
-fn new_hash_bar(mp: &MultiProgress, file: &Path, verb: &str) -> ProgressBar {
+/// A per-file hashing handle; reported like the post-download integrity
+/// checks in `aws_s3.rs`.
+fn new_hash_bar(sink: &Arc<dyn ProgressSink>, file: &Path, verb: &str) -> Arc<dyn ProgressHandle> {
     let size = std::fs::metadata(file).map(|m| m.len()).unwrap_or(0);
-    let pb = mp.add(ProgressBar::new(size));
-    pb.set_style(verify_bar_style());
     let name = file
         .file_name()
         .map(|n| n.to_string_lossy().into_owned())
         .unwrap_or_else(|| file.display().to_string());
-    pb.set_prefix(name);
-    pb.set_message(verb.to_string());
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+    let pb = sink.start(&name, ProgressKind::Verify, size);
+    pb.set_message(verb);
     pb
 }
 
@@ -150,12 +149,12 @@ fn collect_files_recursive(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
 /// verified from any directory containing those files.
 ///
 /// When `progress` is given, each file gets its own hashing bar on the shared
-/// `MultiProgress`.
+/// progress sink.
 pub async fn generate_md5_manifest(
     target: &Path,
     output: &Path,
     threads: usize,
-    progress: Option<Arc<MultiProgress>>,
+    progress: Option<Arc<dyn ProgressSink>>,
 ) -> Result<()> {
     let mut files = if target.is_file() {
         vec![target.to_path_buf()]
@@ -192,17 +191,17 @@ pub async fn generate_md5_manifest(
                 .expect("md5 semaphore closed");
             let pb = progress
                 .as_ref()
-                .map(|mp| new_hash_bar(mp, &file, "Hashing"));
+                .map(|sink| new_hash_bar(sink, &file, "Hashing"));
             let path = file.clone();
             let pb_ref = pb.clone();
             let result = tokio::task::spawn_blocking(move || {
-                compute_md5_with_progress(&path, pb_ref.as_ref())
+                compute_md5_with_progress(&path, pb_ref.as_deref())
             })
             .await
             .context("MD5 compute task panicked")?
             .with_context(|| format!("Failed to compute MD5 for {}", file.display()));
             if let Some(pb) = &pb {
-                pb.finish_and_clear();
+                pb.finish();
             }
             Ok::<_, anyhow::Error>((file, result?))
         }));
@@ -237,12 +236,12 @@ pub async fn generate_md5_manifest(
 /// as failed and logged.
 ///
 /// When `progress` is given, each existing file gets its own verifying bar on
-/// the shared `MultiProgress`.
+/// the shared progress sink.
 pub async fn verify_md5_manifest(
     md5_path: &Path,
     root_dir: &Path,
     threads: usize,
-    progress: Option<Arc<MultiProgress>>,
+    progress: Option<Arc<dyn ProgressSink>>,
 ) -> Result<(usize, usize)> {
     let entries = parse_md5_manifest(md5_path)?;
     if entries.is_empty() {
@@ -306,17 +305,17 @@ pub async fn verify_md5_manifest(
 
             let pb = progress
                 .as_ref()
-                .map(|mp| new_hash_bar(mp, &file_path, "Verifying"));
+                .map(|sink| new_hash_bar(sink, &file_path, "Verifying"));
             let path = file_path.clone();
             let pb_ref = pb.clone();
             let result = tokio::task::spawn_blocking(move || {
-                compute_md5_with_progress(&path, pb_ref.as_ref())
+                compute_md5_with_progress(&path, pb_ref.as_deref())
             })
             .await
             .context("MD5 verify task panicked")?
             .with_context(|| format!("Failed to compute MD5 for {}", file_path.display()));
             if let Some(pb) = &pb {
-                pb.finish_and_clear();
+                pb.finish();
             }
             Ok::<_, anyhow::Error>((filename, expected_md5, Some(result?)))
         }));
@@ -0,0 +1,157 @@
+//! Comparison of a project's ENA metadata with an earlier snapshot
+//! (`metadata --diff old_metadata.tsv`).
+//!
+//! The snapshot is any `ena_metadata*.tsv` written by `download` or
+//! `metadata -o`, or a filereport TSV from the ENA portal. Runs are
+//! matched by accession and reported as added, removed or changed; a
+//! change in file sizes or MD5s means the data was replaced and the run
+//! should be downloaded again, a change in `last_updated` alone does not.
+
+use crate::EnaRecord;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Value of one compared field of a record.
+type Field = fn(&EnaRecord) -> Option<&str>;
+
+/// Compared fields; the last only touches the metadata.
+const FIELDS: [(&str, Field); 7] = [
+    ("fastq_bytes", |r| Some(r.fastq_bytes.as_str())),
+    ("fastq_md5", |r| Some(r.fastq_md5.as_str())),
+    ("submitted_bytes", |r| r.submitted_bytes.as_deref()),
+    ("submitted_md5", |r| r.submitted_md5.as_deref()),
+    ("sra_bytes", |r| r.sra_bytes.as_deref()),
+    ("sra_md5", |r| r.sra_md5.as_deref()),
+    ("last_updated", |r| r.last_updated.as_deref()),
+];
+
+/// One field of a run that differs between the snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// A run present in both snapshots with differing fields.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunChange {
+    pub run_accession: String,
+    pub fields: Vec<FieldChange>,
+}
+
+impl RunChange {
+    /// Whether the run's files changed, not just its `last_updated`.
+    pub fn data_changed(&self) -> bool {
+        self.fields.iter().any(|f| f.field != "last_updated")
+    }
+}
+
+/// Runs added, removed and changed since the old snapshot, each sorted by
+/// accession.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetadataDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<RunChange>,
+}
+
+impl MetadataDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Runs to download again: new ones and those whose files changed.
+    pub fn to_download(&self) -> Vec<&str> {
+        let mut runs: Vec<&str> = self.added.iter().map(String::as_str).collect();
+        runs.extend(
+            self.changed
+                .iter()
+                .filter(|c| c.data_changed())
+                .map(|c| c.run_accession.as_str()),
+        );
+        runs.sort_unstable();
+        runs
+    }
+}
+
+/// Compare the `new` records of a project with the `old` snapshot.
+pub fn diff(old: &[EnaRecord], new: &[EnaRecord]) -> MetadataDiff {
+    let old: BTreeMap<&str, &EnaRecord> =
+        old.iter().map(|r| (r.run_accession.as_str(), r)).collect();
+    let new: BTreeMap<&str, &EnaRecord> =
+        new.iter().map(|r| (r.run_accession.as_str(), r)).collect();
+    let mut result = MetadataDiff::default();
+    for (run, record) in &new {
+        let Some(previous) = old.get(run) else {
+            result.added.push(run.to_string());
+            continue;
+        };
+        let fields: Vec<FieldChange> = FIELDS
+            .iter()
+            .filter_map(|(field, get)| {
+                let (old, new) = (
+                    get(previous).unwrap_or_default(),
+                    get(record).unwrap_or_default(),
+                );
+                (old != new).then(|| FieldChange {
+                    field,
+                    old: old.to_string(),
+                    new: new.to_string(),
+                })
+            })
+            .collect();
+        if !fields.is_empty() {
+            result.changed.push(RunChange {
+                run_accession: run.to_string(),
+                fields,
+            });
+        }
+    }
+    result.removed = old
+        .keys()
+        .filter(|run| !new.contains_key(*run))
+        .map(|run| run.to_string())
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(value: serde_json::Value) -> Vec<EnaRecord> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_runs() {
+        let old = records(serde_json::json!([
+            {"run_accession": "SRR1", "fastq_md5": "a", "last_updated": "2024-01-01"},
+            {"run_accession": "SRR2", "fastq_md5": "b", "last_updated": "2024-01-01"},
+            {"run_accession": "SRR3", "fastq_md5": "c"},
+        ]));
+        let new = records(serde_json::json!([
+            {"run_accession": "SRR4", "fastq_md5": "d"},
+            {"run_accession": "SRR1", "fastq_md5": "a", "last_updated": "2025-06-01"},
+            {"run_accession": "SRR2", "fastq_md5": "B", "last_updated": "2024-01-01"},
+        ]));
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.added, ["SRR4"]);
+        assert_eq!(diff.removed, ["SRR3"]);
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(
+            diff.changed[0].fields,
+            [FieldChange {
+                field: "last_updated",
+                old: "2024-01-01".into(),
+                new: "2025-06-01".into(),
+            }]
+        );
+        assert!(!diff.changed[0].data_changed());
+        assert_eq!(diff.changed[1].fields[0].field, "fastq_md5");
+        assert_eq!(diff.to_download(), ["SRR2", "SRR4"]);
+        assert!(super::diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! Prometheus metrics for `download --metrics-port`.
+//!
+//! [`Metrics`] is filled by the backend loop (active runs, outcomes,
+//! failures per backend, per-run speed) and by a [`ProgressSink`] wrapper
+//! that counts transferred bytes, so every backend reporting progress is
+//! covered. [`Metrics::render`] produces the text exposition format.
+
+use crate::progress::{ProgressHandle, ProgressKind, ProgressSink};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Live counters of one job.
+#[derive(Default)]
+pub struct Metrics {
+    /// Progress handle id → highest byte position seen.
+    transferred: Mutex<HashMap<String, u64>>,
+    active: AtomicUsize,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    backend_failures: Mutex<BTreeMap<String, u64>>,
+    /// Finished run → average bytes/s over its wall time.
+    run_speed: Mutex<BTreeMap<String, f64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Wrap `inner` so transferred bytes are counted before being forwarded.
+    pub fn sink(self: &Arc<Self>, inner: Option<Arc<dyn ProgressSink>>) -> Arc<dyn ProgressSink> {
+        Arc::new(MetricsSink {
+            metrics: self.clone(),
+            inner,
+        })
+    }
+
+    pub(crate) fn run_started(&self) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn run_finished(&self, run_id: &str, ok: bool, elapsed: Duration) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        if !ok {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.succeeded.fetch_add(1, Ordering::Relaxed);
+        // Handles are keyed by run or by file name (`SRR1_1.fastq.gz`).
+        let bytes: u64 = self
+            .transferred
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| id.starts_with(run_id))
+            .map(|(_, bytes)| bytes)
+            .sum();
+        let secs = elapsed.as_secs_f64().max(0.001);
+        self.run_speed
+            .lock()
+            .unwrap()
+            .insert(run_id.to_string(), bytes as f64 / secs);
+    }
+
+    pub(crate) fn backend_failed(&self, backend: &str) {
+        *self
+            .backend_failures
+            .lock()
+            .unwrap()
+            .entry(backend.to_string())
+            .or_default() += 1;
+    }
+
+    fn record(&self, id: &str, position: u64) {
+        let mut transferred = self.transferred.lock().unwrap();
+        let seen = transferred.entry(id.to_string()).or_default();
+        *seen = (*seen).max(position);
+    }
+
+    fn add(&self, id: &str, bytes: u64) {
+        *self
+            .transferred
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default() += bytes;
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let bytes: u64 = self.transferred.lock().unwrap().values().sum();
+        let mut out = String::new();
+        family(
+            &mut out,
+            "polariseq_downloaded_bytes_total",
+            "counter",
+            "Bytes transferred by all backends.",
+            [(String::new(), bytes as f64)],
+        );
+        family(
+            &mut out,
+            "polariseq_active_runs",
+            "gauge",
+            "Runs currently being downloaded.",
+            [(String::new(), self.active.load(Ordering::Relaxed) as f64)],
+        );
+        family(
+            &mut out,
+            "polariseq_runs_total",
+            "counter",
+            "Finished runs by outcome.",
+            [
+                ("status=\"succeeded\"", &self.succeeded),
+                ("status=\"failed\"", &self.failed),
+            ]
+            .map(|(label, count)| (label.to_string(), count.load(Ordering::Relaxed) as f64)),
+        );
+        family(
+            &mut out,
+            "polariseq_backend_failures_total",
+            "counter",
+            "Failed download attempts per backend, before falling back.",
+            self.backend_failures
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(backend, count)| (format!("backend=\"{}\"", backend), *count as f64)),
+        );
+        family(
+            &mut out,
+            "polariseq_run_speed_bytes_per_second",
+            "gauge",
+            "Average speed of each finished run.",
+            self.run_speed
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(run, speed)| (format!("run=\"{}\"", run), speed.round())),
+        );
+        out
+    }
+}
+
+/// Append one metric family; `samples` are (labels, value) pairs.
+fn family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, f64)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+    for (labels, value) in samples {
+        let _ = if labels.is_empty() {
+            writeln!(out, "{} {}", name, value)
+        } else {
+            writeln!(out, "{}{{{}}} {}", name, labels, value)
+        };
+    }
+}
+
+struct MetricsSink {
+    metrics: Arc<Metrics>,
+    inner: Option<Arc<dyn ProgressSink>>,
+}
+
+impl ProgressSink for MetricsSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        let inner = crate::progress::start_progress(self.inner.as_ref(), id, kind, total);
+        if kind != ProgressKind::Transfer {
+            return inner;
+        }
+        Arc::new(MetricsHandle {
+            metrics: self.metrics.clone(),
+            id: id.to_string(),
+            inner,
+        })
+    }
+}
+
+struct MetricsHandle {
+    metrics: Arc<Metrics>,
+    id: String,
+    inner: Arc<dyn ProgressHandle>,
+}
+
+impl ProgressHandle for MetricsHandle {
+    fn set_position(&self, bytes: u64) {
+        self.metrics.record(&self.id, bytes);
+        self.inner.set_position(bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        self.metrics.add(&self.id, bytes);
+        self.inner.inc(bytes);
+    }
+    fn set_message(&self, message: &str) {
+        self.inner.set_message(message);
+    }
+    fn println(&self, line: &str) {
+        self.inner.println(line);
+    }
+    fn finish(&self) {
+        self.inner.finish();
+    }
+    fn fail(&self, reason: &str) {
+        self.inner.fail(reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bytes_and_renders_prometheus_text() {
+        let metrics = Metrics::new();
+        let sink = metrics.sink(None);
+        let handle = sink.start("SRR1_1.fastq.gz", ProgressKind::Transfer, 100);
+        handle.set_position(60);
+        handle.set_position(40);
+        handle.inc(40);
+        sink.start("SRR1", ProgressKind::Verify, 100)
+            .set_position(100);
+
+        metrics.run_started();
+        metrics.backend_failed("aws");
+        metrics.run_finished("SRR1", true, Duration::from_secs(1));
+
+        let text = metrics.render();
+        assert!(text.contains("polariseq_downloaded_bytes_total 100\n"));
+        assert!(text.contains("polariseq_active_runs 0\n"));
+        assert!(text.contains("polariseq_runs_total{status=\"succeeded\"} 1\n"));
+        assert!(text.contains("polariseq_backend_failures_total{backend=\"aws\"} 1\n"));
+        assert!(text.contains("polariseq_run_speed_bytes_per_second{run=\"SRR1\"} 100\n"));
+        assert!(text.contains("# TYPE polariseq_active_runs gauge\n"));
+    }
+}
@@ -0,0 +1,249 @@
+//! MultiQC custom-content report for `download --multiqc`.
+//!
+//! [`MultiqcHook`] tallies every run of the job (backend, size, average
+//! speed, verification status, read counts when `--trim fastp` ran and
+//! read statistics when `--qc` ran)
+//! and rewrites `<output>/polariseq_mqc.json` after each one. MultiQC picks the file up as a table section when it is
+//! run over a directory containing the output.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::qc::{self, QcSummary};
+use crate::trim::{self, FastpStats};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// MultiQC finds custom content by the `_mqc.json` suffix.
+pub const MULTIQC_FILE: &str = "polariseq_mqc.json";
+
+/// One row of the report table.
+#[derive(Debug, Clone, Serialize)]
+struct RunRow {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend: Option<&'static str>,
+    files: usize,
+    size_mb: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed_mb_s: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reads_before: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reads_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q30_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qc: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gc_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q30_reads_pct: Option<f64>,
+}
+
+/// Writes `polariseq_mqc.json` as runs finish.
+pub struct MultiqcHook {
+    path: PathBuf,
+    rows: Mutex<BTreeMap<String, RunRow>>,
+}
+
+impl MultiqcHook {
+    pub fn new(output: &Path) -> Self {
+        Self {
+            path: output.join(MULTIQC_FILE),
+            rows: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, run_id: &str, row: RunRow) -> Result<()> {
+        let mut rows = self.rows.lock().unwrap();
+        rows.insert(run_id.to_string(), row);
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&report(&rows))?)?;
+        Ok(())
+    }
+}
+
+/// Read counts over every `fastp` report among `files`, or `None` if the
+/// run was not trimmed. Q30 is weighted by the reads kept.
+fn fastp_totals(files: &[&PathBuf]) -> Option<FastpStats> {
+    let reports: Vec<FastpStats> = files
+        .iter()
+        .filter(|f| f.to_string_lossy().ends_with(".fastp.json"))
+        .filter_map(|f| trim::fastp_stats(f).ok())
+        .collect();
+    if reports.is_empty() {
+        return None;
+    }
+    let reads_after: u64 = reports.iter().map(|r| r.reads_after).sum();
+    Some(FastpStats {
+        reads_before: reports.iter().map(|r| r.reads_before).sum(),
+        reads_after,
+        q30_after: reports
+            .iter()
+            .map(|r| r.q30_after * r.reads_after as f64)
+            .sum::<f64>()
+            / reads_after.max(1) as f64,
+    })
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// The custom-content document for `rows`.
+fn report(rows: &BTreeMap<String, RunRow>) -> serde_json::Value {
+    let verified = rows.values().filter(|r| r.status == "verified").count();
+    let size_gb: f64 = rows.values().map(|r| r.size_mb).sum::<f64>() / 1024.0;
+    json!({
+        "id": "polariseq",
+        "section_name": "Polariseq downloads",
+        "description": format!(
+            "{} run(s) downloaded and verified, {} failed; {:.2} GB in total.",
+            verified,
+            rows.len() - verified,
+            size_gb
+        ),
+        "plot_type": "table",
+        "pconfig": {
+            "id": "polariseq_downloads",
+            "title": "Polariseq: downloaded runs",
+        },
+        "headers": {
+            "status": { "title": "Status", "description": "verified or failed" },
+            "backend": { "title": "Backend", "description": "Backend that fetched the run" },
+            "files": { "title": "Files", "format": "{:,.0f}" },
+            "size_mb": { "title": "Size", "suffix": " MB", "format": "{:,.2f}" },
+            "speed_mb_s": {
+                "title": "Speed",
+                "suffix": " MB/s",
+                "description": "Average over the run's download, conversion and verification",
+                "format": "{:,.2f}",
+            },
+            "reads_before": { "title": "Reads", "description": "Reads before fastp trimming", "format": "{:,.0f}" },
+            "reads_after": { "title": "Reads kept", "description": "Reads after fastp trimming", "format": "{:,.0f}" },
+            "q30_pct": { "title": "Q30", "suffix": "%", "description": "Bases at Q30 or above after trimming", "format": "{:,.2f}" },
+            "qc": { "title": "QC", "description": "pass, or flagged for no reads or few Q30 reads" },
+            "reads": { "title": "Reads (QC)", "description": "Reads in the final FASTQ files", "format": "{:,.0f}" },
+            "gc_pct": { "title": "GC", "suffix": "%", "format": "{:,.2f}" },
+            "q30_reads_pct": { "title": "Q30 reads", "suffix": "%", "description": "Reads with a mean quality of Q30 or above", "format": "{:,.2f}" },
+        },
+        "data": rows,
+    })
+}
+
+impl RunHook for MultiqcHook {
+    fn name(&self) -> &'static str {
+        "multiqc"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // Size and speed cover the downloaded files, not trimmed copies
+            // or reports.
+            let (derived, downloaded): (Vec<&PathBuf>, Vec<&PathBuf>) = files
+                .iter()
+                .partition(|f| trim::is_trim_output(f) || qc::is_qc_output(f));
+            let mut bytes = 0u64;
+            for file in &downloaded {
+                bytes += tokio::fs::metadata(file).await?.len();
+            }
+            let fastp = fastp_totals(&derived);
+            let qc: Option<QcSummary> = std::fs::read(QcSummary::path(&ctx.options.output, run_id))
+                .ok()
+                .and_then(|json| serde_json::from_slice(&json).ok());
+            let size_mb = bytes as f64 / (1024.0 * 1024.0);
+            let speed_mb_s = ctx
+                .started
+                .map(|s| round2(size_mb / s.elapsed().as_secs_f64().max(0.001)));
+            self.record(
+                run_id,
+                RunRow {
+                    status: "verified",
+                    backend: ctx.backend,
+                    files: downloaded.len(),
+                    size_mb: round2(size_mb),
+                    speed_mb_s,
+                    reads_before: fastp.map(|s| s.reads_before),
+                    reads_after: fastp.map(|s| s.reads_after),
+                    q30_pct: fastp.map(|s| round2(s.q30_after * 100.0)),
+                    qc: qc.map(|q| if q.flagged { "flagged" } else { "pass" }),
+                    reads: qc.map(|q| q.reads),
+                    gc_pct: qc.map(|q| round2(q.gc_pct)),
+                    q30_reads_pct: qc.map(|q| round2(q.q30_reads * 100.0)),
+                },
+            )
+        })
+    }
+
+    fn run_failed<'a>(
+        &'a self,
+        run_id: &'a str,
+        _error: &'a anyhow::Error,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, ()> {
+        let row = RunRow {
+            status: "failed",
+            backend: ctx.backend,
+            files: 0,
+            size_mb: 0.0,
+            speed_mb_s: None,
+            reads_before: None,
+            reads_after: None,
+            q30_pct: None,
+            qc: None,
+            reads: None,
+            gc_pct: None,
+            q30_reads_pct: None,
+        };
+        if let Err(e) = self.record(run_id, row) {
+            tracing::warn!("Failed to write {}: {:#}", MULTIQC_FILE, e);
+        }
+        Box::pin(async {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_counts_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook = MultiqcHook::new(dir.path());
+        let row = |status, size_mb| RunRow {
+            status,
+            backend: Some("ftp"),
+            files: 2,
+            size_mb,
+            speed_mb_s: Some(12.5),
+            reads_before: None,
+            reads_after: None,
+            q30_pct: None,
+            qc: None,
+            reads: None,
+            gc_pct: None,
+            q30_reads_pct: None,
+        };
+        hook.record("SRR1", row("verified", 1024.0)).unwrap();
+        hook.record("SRR2", row("failed", 0.0)).unwrap();
+
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join(MULTIQC_FILE)).unwrap()).unwrap();
+        assert_eq!(
+            report["description"],
+            "1 run(s) downloaded and verified, 1 failed; 1.00 GB in total."
+        );
+        assert_eq!(report["data"]["SRR1"]["backend"], "ftp");
+        assert_eq!(report["data"]["SRR2"]["status"], "failed");
+    }
+}
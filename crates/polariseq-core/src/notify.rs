@@ -0,0 +1,355 @@
+//! Job notifications for `download --notify`.
+//!
+//! A [`Notifier`] is registered as a [`RunHook`]: it counts finished runs,
+//! sends a message as soon as a run fails, and a summary when the job
+//! ends ([`Notifier::finish`]). Targets are Slack incoming webhooks,
+//! generic JSON webhooks and e-mail through the local `sendmail`.
+//! Delivery problems are logged and never fail the download; each delivery
+//! gives up after [`SEND_TIMEOUT`], and failure messages are sent in the
+//! background so a slow target does not hold up the next run.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::error::PolariseqError;
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Failed runs listed in a summary; the rest are only counted.
+const MAX_LISTED_FAILURES: usize = 20;
+
+/// Longest a single delivery may take.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where notifications go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyTarget {
+    /// `slack:https://hooks.slack.com/...`
+    Slack(String),
+    /// `webhook:https://...` or a bare `https://` URL; receives the JSON
+    /// [`JobSummary`] or [`RunFailure`].
+    Webhook(String),
+    /// `mailto:user@lab.org`
+    Email(String),
+}
+
+impl NotifyTarget {
+    /// Target kind for logs; webhook URLs carry secrets.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Slack(_) => "slack",
+            Self::Webhook(_) => "webhook",
+            Self::Email(_) => "e-mail",
+        }
+    }
+}
+
+impl FromStr for NotifyTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        if let Some(url) = spec.strip_prefix("slack:") {
+            return Ok(Self::Slack(url.to_string()));
+        }
+        if let Some(url) = spec.strip_prefix("webhook:") {
+            return Ok(Self::Webhook(url.to_string()));
+        }
+        if spec.starts_with("https://") || spec.starts_with("http://") {
+            return Ok(Self::Webhook(spec.to_string()));
+        }
+        match spec.strip_prefix("mailto:") {
+            Some(address) if address.contains('@') => Ok(Self::Email(address.to_string())),
+            _ => Err(PolariseqError::InvalidInput(format!(
+                "Invalid notification target '{}': expected slack:URL, webhook:URL or mailto:ADDRESS",
+                spec
+            ))
+            .into()),
+        }
+    }
+}
+
+/// A run that failed, sent as soon as it happens.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunFailure {
+    pub job: String,
+    pub run_id: String,
+    pub error: String,
+}
+
+/// End-of-job summary.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSummary {
+    pub job: String,
+    /// `completed`, `failed` or `cancelled`.
+    pub status: &'static str,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub failed_runs: Vec<String>,
+    pub elapsed_secs: u64,
+    pub output: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobSummary {
+    fn subject(&self) -> String {
+        format!("polariseq {}: {}", self.job, self.status)
+    }
+
+    fn text(&self) -> String {
+        let mut text = format!(
+            "{}: {} run(s) succeeded, {} failed in {} (output: {})",
+            self.subject(),
+            self.succeeded,
+            self.failed,
+            format_duration(self.elapsed_secs),
+            self.output.display()
+        );
+        if !self.failed_runs.is_empty() {
+            text.push_str(&format!("\nFailed: {}", self.failed_runs.join(", ")));
+            if self.failed > self.failed_runs.len() {
+                text.push_str(&format!(
+                    " and {} more",
+                    self.failed - self.failed_runs.len()
+                ));
+            }
+        }
+        if let Some(error) = &self.error {
+            text.push_str(&format!("\nError: {}", error));
+        }
+        text
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+#[derive(Default)]
+struct Tally {
+    succeeded: HashSet<String>,
+    failed: Vec<String>,
+}
+
+/// Sends job notifications to one or more [`NotifyTarget`]s.
+pub struct Notifier {
+    targets: Arc<Vec<NotifyTarget>>,
+    job: String,
+    output: PathBuf,
+    http: reqwest::Client,
+    started: Instant,
+    tally: Mutex<Tally>,
+    /// Failure messages still being sent.
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Notifier {
+    /// `job` names the job in messages (usually the accession).
+    pub fn new(targets: Vec<NotifyTarget>, job: &str, output: PathBuf) -> Result<Self> {
+        Ok(Self {
+            targets: Arc::new(targets),
+            job: job.to_string(),
+            output,
+            http: crate::http::client_builder()
+                .timeout(SEND_TIMEOUT)
+                .build()?,
+            started: Instant::now(),
+            tally: Mutex::new(Tally::default()),
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn summary(&self, result: &Result<()>) -> JobSummary {
+        let tally = self.tally.lock().unwrap();
+        let status = match result {
+            Ok(()) => "completed",
+            Err(e) if matches!(e.downcast_ref(), Some(PolariseqError::Cancelled)) => "cancelled",
+            Err(_) => "failed",
+        };
+        JobSummary {
+            job: self.job.clone(),
+            status,
+            succeeded: tally.succeeded.len(),
+            failed: tally.failed.len(),
+            failed_runs: tally
+                .failed
+                .iter()
+                .take(MAX_LISTED_FAILURES)
+                .cloned()
+                .collect(),
+            elapsed_secs: self.started.elapsed().as_secs(),
+            output: self.output.clone(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+
+    /// Wait for failure messages still in flight, then send the
+    /// end-of-job summary for `result`.
+    pub async fn finish(&self, result: &Result<()>) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for handle in pending {
+            let _ = handle.await;
+        }
+        let summary = self.summary(result);
+        send(
+            &self.targets,
+            &self.http,
+            &summary.subject(),
+            &summary.text(),
+            &summary,
+        )
+        .await;
+    }
+}
+
+async fn send(
+    targets: &[NotifyTarget],
+    http: &reqwest::Client,
+    subject: &str,
+    text: &str,
+    payload: &impl Serialize,
+) {
+    for target in targets {
+        let sent = match target {
+            NotifyTarget::Slack(url) => post(http, url, &serde_json::json!({ "text": text })).await,
+            NotifyTarget::Webhook(url) => post(http, url, payload).await,
+            NotifyTarget::Email(address) => {
+                tokio::time::timeout(SEND_TIMEOUT, sendmail(address, subject, text))
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("sendmail timed out")))
+            }
+        };
+        match sent {
+            Ok(()) => info!("Notification sent ({})", target.kind()),
+            Err(e) => warn!("{} notification failed: {:#}", target.kind(), e),
+        }
+    }
+}
+
+async fn post(http: &reqwest::Client, url: &str, body: &impl Serialize) -> Result<()> {
+    http.post(url).json(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+async fn sendmail(address: &str, subject: &str, text: &str) -> Result<()> {
+    let sendmail = crate::deps::locate_tool("sendmail")
+        .or_else(|| Some(PathBuf::from("/usr/sbin/sendmail")).filter(|p| p.exists()))
+        .ok_or_else(|| anyhow!("sendmail not found"))?;
+    let started = Instant::now();
+    let mut command = Command::new(sendmail);
+    command.arg("-t").stdin(Stdio::piped()).kill_on_drop(true);
+    let mut child = command.spawn().context("Failed to run sendmail")?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(format!("To: {}\nSubject: {}\n\n{}\n", address, subject, text).as_bytes())
+        .await?;
+    drop(stdin);
+    let status = child.wait().await?;
+    crate::audit::record(command.as_std(), started, Ok((status, &[])));
+    if !status.success() {
+        return Err(anyhow!("sendmail exited with {}", status));
+    }
+    Ok(())
+}
+
+impl RunHook for Notifier {
+    fn name(&self) -> &'static str {
+        "notify"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        _files: &'a [PathBuf],
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.tally
+            .lock()
+            .unwrap()
+            .succeeded
+            .insert(run_id.to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn run_failed<'a>(
+        &'a self,
+        run_id: &'a str,
+        error: &'a anyhow::Error,
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, ()> {
+        {
+            let mut tally = self.tally.lock().unwrap();
+            // A later hook may fail a run this hook already counted.
+            tally.succeeded.remove(run_id);
+            tally.failed.push(run_id.to_string());
+        }
+        let failure = RunFailure {
+            job: self.job.clone(),
+            run_id: run_id.to_string(),
+            error: format!("{:#}", error),
+        };
+        let subject = format!("polariseq {}: {} failed", self.job, run_id);
+        let text = format!("{}\n{}", subject, failure.error);
+        let targets = self.targets.clone();
+        let http = self.http.clone();
+        let handle = tokio::spawn(async move {
+            send(&targets, &http, &subject, &text, &failure).await;
+        });
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|h| !h.is_finished());
+        pending.push(handle);
+        Box::pin(async {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets_and_summarises_jobs() {
+        assert_eq!(
+            "slack:https://hooks.slack.com/x"
+                .parse::<NotifyTarget>()
+                .unwrap(),
+            NotifyTarget::Slack("https://hooks.slack.com/x".into())
+        );
+        assert_eq!(
+            "https://example.org/hook".parse::<NotifyTarget>().unwrap(),
+            NotifyTarget::Webhook("https://example.org/hook".into())
+        );
+        assert_eq!(
+            "mailto:me@lab.org".parse::<NotifyTarget>().unwrap(),
+            NotifyTarget::Email("me@lab.org".into())
+        );
+        assert!("mailto:nobody".parse::<NotifyTarget>().is_err());
+
+        let notifier = Notifier::new(Vec::new(), "PRJNA1", PathBuf::from("out")).unwrap();
+        {
+            let mut tally = notifier.tally.lock().unwrap();
+            tally
+                .succeeded
+                .extend(["SRR1", "SRR2", "SRR3", "SRR4"].map(String::from));
+            tally.succeeded.remove("SRR2");
+            tally.failed.push("SRR2".into());
+        }
+        let summary = notifier.summary(&Err(anyhow!("1 of 4 download task(s) failed")));
+        assert_eq!(summary.status, "failed");
+        assert!(summary.text().starts_with(
+            "polariseq PRJNA1: failed: 3 run(s) succeeded, 1 failed in 0s (output: out)\nFailed: SRR2"
+        ));
+    }
+}
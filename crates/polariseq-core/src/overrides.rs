@@ -0,0 +1,127 @@
+//! Per-run overrides read from optional `--tsv` columns.
+//!
+//! A TSV may carry any of these columns next to the ENA fields; empty cells
+//! keep the job-wide setting:
+//!
+//! - `preferred_backend`: backend chain for the run (`aws`, `ftp,aws`, ...),
+//!   taking precedence over `--download` and every `--route` rule,
+//! - `output_subdir`: directory under `--output` the run is written to,
+//! - `sample_name`: replaces the sample title in MD5 tables and filters,
+//! - `priority`: runs with a higher value are queued first (default 0).
+
+use crate::backend::RunItem;
+use crate::error::PolariseqError;
+use crate::route::Route;
+use crate::EnaRecord;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Check the override columns and apply `sample_name`.
+pub fn apply(records: &mut [EnaRecord]) -> Result<()> {
+    for record in records {
+        if let Some(subdir) = &record.output_subdir {
+            let relative = Path::new(subdir)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !relative {
+                return Err(PolariseqError::InvalidInput(format!(
+                    "output_subdir of {} must be a relative path inside --output: {}",
+                    record.run_accession, subdir
+                ))
+                .into());
+            }
+        }
+        if let Some(name) = &record.sample_name {
+            record.sample_title = name.clone();
+        }
+    }
+    Ok(())
+}
+
+/// One route per run with a `preferred_backend`, to go before `--route`.
+pub fn routes(records: &[EnaRecord]) -> Result<Vec<Route>> {
+    records
+        .iter()
+        .filter_map(|r| {
+            let backend = r.preferred_backend.as_deref()?;
+            Some(
+                format!("{}={}", r.run_accession, backend)
+                    .parse::<Route>()
+                    .map_err(|_| {
+                        PolariseqError::InvalidInput(format!(
+                            "Invalid preferred_backend for {}: '{}'",
+                            r.run_accession, backend
+                        ))
+                        .into()
+                    }),
+            )
+        })
+        .collect()
+}
+
+/// `output_subdir` of each run that has one.
+pub fn output_subdirs(records: &[EnaRecord]) -> HashMap<String, PathBuf> {
+    records
+        .iter()
+        .filter_map(|r| Some((r.run_accession.clone(), r.output_subdir.as_ref()?.into())))
+        .collect()
+}
+
+/// Directory `run_id` is written to.
+pub fn output_for(output: &Path, subdirs: &HashMap<String, PathBuf>, run_id: &str) -> PathBuf {
+    match subdirs.get(run_id) {
+        Some(subdir) => output.join(subdir),
+        None => output.to_path_buf(),
+    }
+}
+
+/// Order `items` by descending `priority`, keeping ties in plan order.
+pub fn sort_by_priority(items: &mut [RunItem], records: &[EnaRecord]) {
+    let priorities: HashMap<&str, i64> = records
+        .iter()
+        .filter_map(|r| Some((r.run_accession.as_str(), r.priority?)))
+        .collect();
+    items.sort_by_key(|item| Reverse(priorities.get(item.run_id()).copied().unwrap_or(0)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tsv_columns_override_per_run() {
+        let tsv = "run_accession\tsample_title\tpreferred_backend\toutput_subdir\tsample_name\tpriority\n\
+                   SRR1\tliver\t\t\t\t\n\
+                   SRR2\tbrain\taws,ftp\tbatch2\tB-01\t5\n";
+        let mut records: Vec<EnaRecord> = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(tsv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        apply(&mut records).unwrap();
+        assert_eq!(records[0].sample_title, "liver");
+        assert_eq!(records[1].sample_title, "B-01");
+
+        let routes = routes(&records).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].backends, ["aws", "ftp"]);
+
+        let subdirs = output_subdirs(&records);
+        let output = Path::new("/data");
+        assert_eq!(output_for(output, &subdirs, "SRR1"), output);
+        assert_eq!(output_for(output, &subdirs, "SRR2"), output.join("batch2"));
+
+        let mut items = vec![
+            RunItem::Protected("SRR1".into()),
+            RunItem::Protected("SRR2".into()),
+        ];
+        sort_by_priority(&mut items, &records);
+        assert_eq!(items[0].run_id(), "SRR2");
+
+        records[0].output_subdir = Some("../elsewhere".into());
+        assert!(apply(&mut records).is_err());
+    }
+}
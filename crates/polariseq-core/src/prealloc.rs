@@ -0,0 +1,147 @@
+//! Preallocation of `.part` files (`--preallocate`).
+//!
+//! `File::set_len` only records the size: the blocks are allocated as chunks
+//! arrive, so a full disk shows up as `ENOSPC` hours into a download.
+//! [`Preallocation::Full`] reserves the blocks up front with `fallocate`, or
+//! by writing zeros where `fallocate` is missing or unsupported, so a
+//! download that cannot fit fails before the first byte is fetched.
+//!
+//! Chunk workers write at arbitrary offsets of the file. Some FUSE mounts
+//! only accept sequential writes, so [`supports_random_writes`] probes the
+//! directory first and a sparse file is then filled with zeros instead.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::{debug, warn};
+
+/// How a `.part` file gets its full size before chunks are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Preallocation {
+    /// Set the length only; blocks are allocated as chunks are written.
+    #[default]
+    Sparse,
+    /// Reserve every block up front (`fallocate`, else zero-fill).
+    Full,
+}
+
+/// Offset the probe writes at, past the end of its file.
+const PROBE_OFFSET: u64 = 1 << 20;
+
+/// Give the new file `path` a length of `size` as `mode` asks. Sparse files
+/// in a directory without random-write support are zero-filled instead.
+pub fn preallocate(path: &Path, size: u64, mode: Preallocation) -> Result<()> {
+    let mut file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    match mode {
+        Preallocation::Sparse if supports_random_writes(dir) => file.set_len(size)?,
+        Preallocation::Sparse => {
+            warn!(
+                "{} does not support writes past the end of a file; filling {} with zeros",
+                dir.display(),
+                path.display()
+            );
+            zero_fill(&mut file, size)?;
+        }
+        Preallocation::Full => {
+            drop(file);
+            if !fallocate(path, size) {
+                let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+                zero_fill(&mut file, size)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `fallocate -l` through util-linux; `false` when the tool is missing or
+/// the filesystem does not support it. On a full disk the zero-fill that
+/// follows fails too and reports it.
+fn fallocate(path: &Path, size: u64) -> bool {
+    match crate::audit::output_blocking(
+        std::process::Command::new("fallocate")
+            .arg("-l")
+            .arg(size.to_string())
+            .arg(path),
+    ) {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            debug!(
+                "fallocate {} failed: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            debug!("fallocate is not available: {}", e);
+            false
+        }
+    }
+}
+
+/// Write zeros up to `size`, so every block is allocated.
+fn zero_fill(file: &mut File, size: u64) -> Result<()> {
+    let block = vec![0u8; 1 << 20];
+    file.seek(SeekFrom::Start(0))?;
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(block.len() as u64) as usize;
+        file.write_all(&block[..n])
+            .context("Failed to preallocate the download")?;
+        left -= n as u64;
+    }
+    file.set_len(size)?;
+    Ok(())
+}
+
+/// Whether files in `dir` take writes past their end and read back what
+/// was written there.
+pub fn supports_random_writes(dir: &Path) -> bool {
+    let probe = || -> std::io::Result<bool> {
+        let mut file = tempfile::tempfile_in(dir)?;
+        file.seek(SeekFrom::Start(PROBE_OFFSET))?;
+        file.write_all(b"x")?;
+        file.flush()?;
+        let mut byte = [0u8; 1];
+        file.seek(SeekFrom::Start(PROBE_OFFSET))?;
+        file.read_exact(&mut byte)?;
+        Ok(byte == *b"x" && file.metadata()?.len() == PROBE_OFFSET + 1)
+    };
+    probe().unwrap_or_else(|e| {
+        debug!("Random-write probe in {} failed: {}", dir.display(), e);
+        false
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preallocates_sparse_and_full_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(supports_random_writes(dir.path()));
+        for (name, mode) in [
+            ("sparse.part", Preallocation::Sparse),
+            ("full.part", Preallocation::Full),
+        ] {
+            let path = dir.path().join(name);
+            preallocate(&path, 3 << 20, mode).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), 3 << 20);
+        }
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"stale").unwrap();
+        zero_fill(&mut file, 3).unwrap();
+        let mut content = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(content, [0, 0, 0]);
+    }
+}
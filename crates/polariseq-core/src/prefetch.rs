@@ -55,21 +55,22 @@ pub async fn download_all(
             } else {
                 info!("[{}] Step 1: Prefetching...", run_id);
                 // Direct execution
-                let output = Command::new(&prefetch)
-                    .arg(&run_id)
-                    .arg("-O")
-                    .arg(".")
-                    .arg("--max-size")
-                    .arg(&max_size_arg)
-                    .arg("--verify")
-                    .arg("yes")
-                    .arg("--force")
-                    .arg("no")
-                    .current_dir(&output_dir)
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::piped())
-                    .output()
-                    .await?;
+                let output = crate::audit::output(
+                    Command::new(&prefetch)
+                        .arg(&run_id)
+                        .arg("-O")
+                        .arg(".")
+                        .arg("--max-size")
+                        .arg(&max_size_arg)
+                        .arg("--verify")
+                        .arg("yes")
+                        .arg("--force")
+                        .arg("no")
+                        .current_dir(&output_dir)
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::piped()),
+                )
+                .await?;
 
                 if !output.status.success() {
                     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -115,21 +116,22 @@ pub async fn download_all(
                     })?;
 
                 // Direct execution
-                let output = Command::new(&fasterq_dump)
-                    .arg("--split-3")
-                    .arg("-e")
-                    .arg(threads.to_string())
-                    .arg("-O")
-                    .arg(&fasterq_output_dir)
-                    .arg("-t")
-                    .arg(&fasterq_tmp_dir)
-                    .arg("-f")
-                    .arg(&relative_sra_path)
-                    .current_dir(&output_dir)
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::piped())
-                    .output()
-                    .await;
+                let output = crate::audit::output(
+                    Command::new(&fasterq_dump)
+                        .arg("--split-3")
+                        .arg("-e")
+                        .arg(threads.to_string())
+                        .arg("-O")
+                        .arg(&fasterq_output_dir)
+                        .arg("-t")
+                        .arg(&fasterq_tmp_dir)
+                        .arg("-f")
+                        .arg(&relative_sra_path)
+                        .current_dir(&output_dir)
+                        .stdout(Stdio::null())
+                        .stderr(Stdio::piped()),
+                )
+                .await;
 
                 match output {
                     Ok(out) if !out.status.success() => {
@@ -0,0 +1,155 @@
+//! Pre-scan of the output directory before runs are queued.
+//!
+//! [`verified_runs`] hashes the files already present for each planned run
+//! in one parallel pass. A file counts as done when its size and MD5 match
+//! the ENA record, or, for FASTQ produced locally (e.g. by the AWS
+//! backend), when it matches its line in the job's `md5.txt`. Runs whose
+//! files are all done are skipped, so rerunning a job over the same project
+//! only fetches what is missing or broken.
+
+use crate::md5::{compute_md5, parse_md5_manifest};
+use crate::ProcessedRecord;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Files hashed at once.
+const PARALLEL_HASHES: usize = 4;
+
+/// A file a run should have produced.
+struct Expected {
+    path: PathBuf,
+    md5: String,
+    bytes: u64,
+}
+
+fn expected_files(output: &Path, record: &ProcessedRecord) -> Vec<Expected> {
+    let mut files = vec![Expected {
+        path: output.join(&record.fastq_ftp_1_name),
+        md5: record.fastq_md5_1.clone(),
+        bytes: record.fastq_bytes_1,
+    }];
+    if let (Some(name), Some(md5), Some(bytes)) = (
+        &record.fastq_ftp_2_name,
+        &record.fastq_md5_2,
+        record.fastq_bytes_2,
+    ) {
+        files.push(Expected {
+            path: output.join(name),
+            md5: md5.clone(),
+            bytes,
+        });
+    }
+    files
+}
+
+/// Runs among `records` whose output files in `output` are all present and
+/// verified.
+pub async fn verified_runs(output: &Path, records: &[ProcessedRecord]) -> HashSet<String> {
+    // A missing or unreadable md5.txt just means no local checksums.
+    let local: HashMap<String, String> = parse_md5_manifest(&output.join("md5.txt"))
+        .map(|entries| entries.into_iter().map(|(md5, name)| (name, md5)).collect())
+        .unwrap_or_default();
+
+    // Only runs with every file present are worth hashing.
+    let candidates: Vec<(&str, Vec<Expected>)> = records
+        .iter()
+        .map(|r| (r.run_accession.as_str(), expected_files(output, r)))
+        .filter(|(_, files)| files.iter().all(|f| f.path.is_file()))
+        .collect();
+
+    let local = &local;
+    let checks = candidates.iter().flat_map(|(run, files)| {
+        let run = *run;
+        files.iter().map(move |file| {
+            let name = file
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let size = file.path.metadata().map(|m| m.len()).unwrap_or(0);
+            let expected = if size == file.bytes && size > 0 {
+                Some(file.md5.clone())
+            } else {
+                local.get(&name).cloned()
+            };
+            let path = file.path.clone();
+            async move {
+                let ok = match expected {
+                    Some(expected) => tokio::task::spawn_blocking(move || compute_md5(&path))
+                        .await
+                        .ok()
+                        .and_then(|md5| md5.ok())
+                        .is_some_and(|md5| md5.eq_ignore_ascii_case(&expected)),
+                    None => false,
+                };
+                (run, ok)
+            }
+        })
+    });
+    let results: Vec<(&str, bool)> = stream::iter(checks)
+        .buffer_unordered(PARALLEL_HASHES)
+        .collect()
+        .await;
+
+    let failed: HashSet<&str> = results
+        .iter()
+        .filter(|(_, ok)| !ok)
+        .map(|(run, _)| *run)
+        .collect();
+    candidates
+        .iter()
+        .map(|(run, _)| *run)
+        .filter(|run| !failed.contains(run))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(run: &str, md5: &str, bytes: u64) -> ProcessedRecord {
+        ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: String::new(),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: format!("{}.fastq.gz", run),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: md5.into(),
+            fastq_md5_2: None,
+            fastq_bytes_1: bytes,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_only_verified_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let abc = "900150983cd24fb0d6963f7d28e17f72";
+        for run in ["SRR1", "SRR2", "SRR3"] {
+            std::fs::write(dir.path().join(format!("{}.fastq.gz", run)), b"abc").unwrap();
+        }
+        // SRR3 was converted locally: ENA's size differs, md5.txt vouches.
+        std::fs::write(
+            dir.path().join("md5.txt"),
+            format!("{}  SRR3.fastq.gz\n", abc),
+        )
+        .unwrap();
+        let records = [
+            record("SRR1", abc, 3),
+            record("SRR2", "00000000000000000000000000000000", 3),
+            record("SRR3", "ffffffffffffffffffffffffffffffff", 99),
+            record("SRR4", abc, 3),
+        ];
+
+        let mut done: Vec<String> = verified_runs(dir.path(), &records)
+            .await
+            .into_iter()
+            .collect();
+        done.sort();
+        assert_eq!(done, ["SRR1", "SRR3"]);
+    }
+}
@@ -0,0 +1,231 @@
+//! Scheduling priority of conversion subprocesses.
+//!
+//! On shared login and data-transfer nodes a job's `fasterq-dump` and
+//! `samtools import` should yield to interactive users. The `priority:`
+//! section of `polariseq.yaml` runs them through `nice`, `ionice` and
+//! `taskset`; the `download` flags of the same names override it.
+//!
+//! ```yaml
+//! priority:
+//!   nice: 10
+//!   ionice: idle        # or best-effort, best-effort:7
+//!   taskset: 0-7
+//! ```
+//!
+//! The gzip compression of the AWS and `prefetch` backends runs inside
+//! polariseq and is not affected; generated scripts wrap `pigz` as well.
+
+use crate::error::PolariseqError;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fmt;
+use std::str::FromStr;
+use tokio::process::Command;
+
+/// `ionice` scheduling class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum IoNice {
+    /// Disk time only when no one else wants it.
+    Idle,
+    /// Best effort at a level from 0 (highest) to 7 (lowest).
+    BestEffort(Option<u8>),
+}
+
+impl IoNice {
+    fn args(self) -> Vec<String> {
+        match self {
+            Self::Idle => vec!["-c".into(), "3".into()],
+            Self::BestEffort(None) => vec!["-c".into(), "2".into()],
+            Self::BestEffort(Some(level)) => {
+                vec!["-c".into(), "2".into(), "-n".into(), level.to_string()]
+            }
+        }
+    }
+}
+
+impl FromStr for IoNice {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (class, level) = match text.trim().split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (text.trim(), None),
+        };
+        match (class, level) {
+            ("idle", None) => Ok(Self::Idle),
+            ("best-effort", None) => Ok(Self::BestEffort(None)),
+            ("best-effort", Some(level)) => match level.parse::<u8>() {
+                Ok(level) if level <= 7 => Ok(Self::BestEffort(Some(level))),
+                _ => Err(anyhow!("ionice level must be 0-7, got '{}'", level)),
+            },
+            _ => Err(anyhow!(
+                "unknown ionice class '{}' (use idle, best-effort or best-effort:N)",
+                text
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IoNice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Idle => write!(f, "idle"),
+            Self::BestEffort(None) => write!(f, "best-effort"),
+            Self::BestEffort(Some(level)) => write!(f, "best-effort:{}", level),
+        }
+    }
+}
+
+impl TryFrom<String> for IoNice {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<IoNice> for String {
+    fn from(ionice: IoNice) -> Self {
+        ionice.to_string()
+    }
+}
+
+/// `taskset -c` CPU list such as `0-7` or `0,2,4-6`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CpuList(String);
+
+impl FromStr for CpuList {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let valid = text.split(',').all(|range| {
+            let mut bounds = range.split('-');
+            let ok = |b: Option<&str>| b.is_some_and(|b| b.parse::<u32>().is_ok());
+            match (bounds.next(), bounds.next(), bounds.next()) {
+                (first, None, None) => ok(first),
+                (first, last, None) => ok(first) && ok(last),
+                _ => false,
+            }
+        });
+        if !valid {
+            return Err(anyhow!("invalid CPU list '{}' (e.g. 0-7 or 0,2,4)", text));
+        }
+        Ok(Self(text.to_string()))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for CpuList {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<CpuList> for String {
+    fn from(cpus: CpuList) -> Self {
+        cpus.0
+    }
+}
+
+/// `priority:` section of `polariseq.yaml`; see the module docs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriorityConfig {
+    /// `nice -n`, from -20 (highest) to 19 (lowest).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice: Option<IoNice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taskset: Option<CpuList>,
+}
+
+impl PriorityConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Wrapper put in front of a tool, e.g.
+    /// `nice -n 10 ionice -c 3 taskset -c 0-7`; empty when nothing is set.
+    pub fn prefix(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(nice) = self.nice {
+            args.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        if let Some(ionice) = self.ionice {
+            args.push("ionice".to_string());
+            args.extend(ionice.args());
+        }
+        if let Some(cpus) = &self.taskset {
+            args.extend(["taskset".to_string(), "-c".to_string(), cpus.to_string()]);
+        }
+        args
+    }
+
+    /// `program` run through [`prefix`](Self::prefix).
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let prefix = self.prefix();
+        match prefix.split_first() {
+            Some((wrapper, rest)) => {
+                let mut command = Command::new(wrapper);
+                command.args(rest).arg(program);
+                command
+            }
+            None => Command::new(program),
+        }
+    }
+
+    /// Make sure the wrapper tools are on `PATH` (`ionice` and `taskset`
+    /// are Linux-only).
+    pub fn validate_tools(&self) -> Result<()> {
+        let tools = [
+            ("nice", self.nice.is_some()),
+            ("ionice", self.ionice.is_some()),
+            ("taskset", self.taskset.is_some()),
+        ];
+        for (tool, used) in tools {
+            if used && which::which(tool).is_err() {
+                return Err(PolariseqError::DependencyMissing {
+                    tool: tool.to_string(),
+                    path: tool.into(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_tools_from_yaml() {
+        let config: PriorityConfig =
+            serde_yaml::from_str("nice: 10\nionice: best-effort:7\ntaskset: 0-3,8\n").unwrap();
+        assert_eq!(
+            config.prefix().join(" "),
+            "nice -n 10 ionice -c 2 -n 7 taskset -c 0-3,8"
+        );
+        let command = config.command("fasterq-dump");
+        assert_eq!(command.as_std().get_program(), "nice");
+        assert_eq!(command.as_std().get_args().last().unwrap(), "fasterq-dump");
+
+        assert!(PriorityConfig::default().prefix().is_empty());
+        assert!("best-effort:8".parse::<IoNice>().is_err());
+        assert!("realtime".parse::<IoNice>().is_err());
+        assert!("0-".parse::<CpuList>().is_err());
+        assert!(serde_yaml::from_str::<PriorityConfig>("ionice: fast\n").is_err());
+    }
+}
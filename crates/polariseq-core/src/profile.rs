@@ -0,0 +1,89 @@
+//! Named download settings (`profiles:` in `polariseq.yaml`).
+//!
+//! A team can keep tuned settings for each place it downloads from and pick
+//! one with `download --profile NAME`:
+//!
+//! ```yaml
+//! profiles:
+//!   cluster:
+//!     download: aws
+//!     multithreads: 16
+//!     aws_threads: 16
+//!     chunk_size: 500     # MB
+//!     bandwidth: 200M
+//!   laptop:
+//!     download: ftp
+//!     multithreads: 2
+//!     bandwidth: 10M
+//! ```
+//!
+//! Flags given on the command line take precedence over the profile.
+
+use crate::error::PolariseqError;
+use crate::{Config, DownloadMethod};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// One entry of `profiles:`; unset fields keep the flag defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Backend, as `--download`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadMethod>,
+    /// File-level concurrency, as `-p`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multithreads: Option<usize>,
+    /// Threads per file, as `-t`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aws_threads: Option<usize>,
+    /// Chunk size in MB, as `--chunk-size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    /// Bandwidth limit such as `50M`, as `--bandwidth`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<String>,
+}
+
+impl Config {
+    /// The profile called `name`, with its bandwidth checked.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            PolariseqError::Config(if known.is_empty() {
+                format!("Unknown profile '{}': no profiles are defined", name)
+            } else {
+                format!("Unknown profile '{}' (defined: {})", name, known.join(", "))
+            })
+        })?;
+        if let Some(rate) = &profile.bandwidth {
+            crate::control::parse_rate(rate)
+                .with_context(|| format!("Invalid bandwidth in profile '{}'", name))?;
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_profiles() {
+        let config: Config = serde_yaml::from_str(
+            "software:\n  prefetch: p\n  fasterq_dump: f\n\
+             profiles:\n  cluster:\n    download: aws\n    multithreads: 16\n    bandwidth: 200M\n\
+             \x20 slow:\n    bandwidth: fast\n",
+        )
+        .unwrap();
+        let cluster = config.profile("cluster").unwrap();
+        assert_eq!(cluster.download, Some(DownloadMethod::Aws));
+        assert_eq!(cluster.multithreads, Some(16));
+        assert_eq!(cluster.chunk_size, None);
+        assert!(config.profile("slow").is_err());
+        let err = config.profile("laptop").unwrap_err().to_string();
+        assert!(err.contains("defined: cluster, slow"), "{}", err);
+
+        assert!(serde_yaml::from_str::<Profile>("threads: 4\n").is_err());
+    }
+}
@@ -1,4 +1,17 @@
-use indicatif::ProgressStyle;
+//! Progress reporting.
+//!
+//! Download code reports through [`ProgressSink`] instead of drawing
+//! `indicatif` bars directly: the CLI plugs in [`IndicatifSink`] on its
+//! shared `MultiProgress`, library users and non-TTY runs can use
+//! [`NoopSink`], the periodic log lines of [`LogSink`] or the
+//! line-delimited [`JsonSink`].
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
 pub fn transfer_bar_style() -> ProgressStyle {
     ProgressStyle::with_template(
@@ -24,6 +37,361 @@ pub fn spinner_style() -> ProgressStyle {
         .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ")
 }
 
+/// What a progress handle tracks; selects the bar style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressKind {
+    /// Bytes downloaded out of a known total.
+    Transfer,
+    /// Bytes hashed during an integrity check.
+    Verify,
+    /// Work without a known size.
+    Spinner,
+}
+
+/// Live updates for one file or run, created by [`ProgressSink::start`].
+pub trait ProgressHandle: Send + Sync {
+    fn set_position(&self, bytes: u64);
+    fn inc(&self, bytes: u64);
+    /// Stage transition or status text ("Downloading", "Verifying MD5", …).
+    fn set_message(&self, message: &str);
+    /// A line to print without corrupting the progress display.
+    fn println(&self, line: &str);
+    /// Successful completion; the handle is removed from the display.
+    fn finish(&self);
+    /// Failed completion with a short reason.
+    fn fail(&self, reason: &str);
+}
+
+/// Destination for progress events from the download engine.
+pub trait ProgressSink: Send + Sync {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle>;
+}
+
+/// Discards all progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl ProgressHandle for NoopSink {
+    fn set_position(&self, _bytes: u64) {}
+    fn inc(&self, _bytes: u64) {}
+    fn set_message(&self, _message: &str) {}
+    fn println(&self, _line: &str) {}
+    fn finish(&self) {}
+    fn fail(&self, _reason: &str) {}
+}
+
+impl ProgressSink for NoopSink {
+    fn start(&self, _id: &str, _kind: ProgressKind, _total: u64) -> Arc<dyn ProgressHandle> {
+        Arc::new(NoopSink)
+    }
+}
+
+/// Start a handle on `sink`, or a no-op handle when there is none.
+pub fn start_progress(
+    sink: Option<&Arc<dyn ProgressSink>>,
+    id: &str,
+    kind: ProgressKind,
+    total: u64,
+) -> Arc<dyn ProgressHandle> {
+    match sink {
+        Some(sink) => sink.start(id, kind, total),
+        None => Arc::new(NoopSink),
+    }
+}
+
+/// Terminal bars on a shared `MultiProgress`.
+#[derive(Debug, Clone)]
+pub struct IndicatifSink {
+    mp: MultiProgress,
+}
+
+impl IndicatifSink {
+    pub fn new(mp: MultiProgress) -> Self {
+        Self { mp }
+    }
+}
+
+impl ProgressSink for IndicatifSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        // insert_from_back(1) places the bar just above the pinned global
+        // status bar (which lives at the very back of the MultiProgress),
+        // so transient per-file bars never sink below it.
+        let pb = match kind {
+            ProgressKind::Spinner => self.mp.insert_from_back(1, ProgressBar::new_spinner()),
+            _ => self.mp.insert_from_back(1, ProgressBar::new(total)),
+        };
+        pb.set_style(match kind {
+            ProgressKind::Transfer => transfer_bar_style(),
+            ProgressKind::Verify => verify_bar_style(),
+            ProgressKind::Spinner => spinner_style(),
+        });
+        pb.set_prefix(id.to_string());
+        pb.enable_steady_tick(Duration::from_millis(100));
+        Arc::new(pb)
+    }
+}
+
+impl ProgressHandle for ProgressBar {
+    fn set_position(&self, bytes: u64) {
+        ProgressBar::set_position(self, bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        ProgressBar::inc(self, bytes);
+    }
+    fn set_message(&self, message: &str) {
+        ProgressBar::set_message(self, message.to_string());
+    }
+    fn println(&self, line: &str) {
+        ProgressBar::println(self, line);
+    }
+    fn finish(&self) {
+        self.finish_and_clear();
+    }
+    fn fail(&self, reason: &str) {
+        self.finish_with_message(reason.to_string());
+    }
+}
+
+/// Byte updates are written at most this often per handle.
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+struct JsonEvent<'a> {
+    id: &'a str,
+    event: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<ProgressKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// One JSON object per line (`start`, `progress`, `message`, `log`,
+/// `finish`, `fail`) for scripts and non-interactive logs.
+#[derive(Clone)]
+pub struct JsonSink {
+    out: SharedWriter,
+}
+
+impl JsonSink {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Arc::new(Mutex::new(out)),
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(Box::new(std::io::stderr()))
+    }
+}
+
+impl ProgressSink for JsonSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        let handle = JsonHandle {
+            id: id.to_string(),
+            total,
+            out: self.out.clone(),
+            state: Mutex::new((0, None)),
+        };
+        handle.emit("start", Some(kind), None, None);
+        Arc::new(handle)
+    }
+}
+
+struct JsonHandle {
+    id: String,
+    total: u64,
+    out: SharedWriter,
+    /// Current position and when it was last written.
+    state: Mutex<(u64, Option<Instant>)>,
+}
+
+impl JsonHandle {
+    fn emit(
+        &self,
+        event: &str,
+        kind: Option<ProgressKind>,
+        bytes: Option<u64>,
+        message: Option<&str>,
+    ) {
+        let line = JsonEvent {
+            id: &self.id,
+            event,
+            kind,
+            bytes,
+            total: (event == "start" || bytes.is_some()).then_some(self.total),
+            message,
+        };
+        if let (Ok(json), Ok(mut out)) = (serde_json::to_string(&line), self.out.lock()) {
+            let _ = writeln!(out, "{}", json);
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(u64) -> u64) {
+        let bytes = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.0 = f(state.0);
+            if state
+                .1
+                .is_some_and(|t| t.elapsed() < JSON_PROGRESS_INTERVAL)
+            {
+                return;
+            }
+            state.1 = Some(Instant::now());
+            state.0
+        };
+        self.emit("progress", None, Some(bytes), None);
+    }
+
+    fn position(&self) -> u64 {
+        self.state.lock().map(|s| s.0).unwrap_or_default()
+    }
+}
+
+impl ProgressHandle for JsonHandle {
+    fn set_position(&self, bytes: u64) {
+        self.update(|_| bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        self.update(|done| done + bytes);
+    }
+    fn set_message(&self, message: &str) {
+        self.emit("message", None, None, Some(message));
+    }
+    fn println(&self, line: &str) {
+        self.emit("log", None, None, Some(line));
+    }
+    fn finish(&self) {
+        self.emit("finish", None, Some(self.position()), None);
+    }
+    fn fail(&self, reason: &str) {
+        self.emit("fail", None, Some(self.position()), Some(reason));
+    }
+}
+
+/// A transfer is logged each time it crosses another step of this many
+/// percent, and at least this often otherwise.
+const LOG_PROGRESS_STEP: u64 = 10;
+const LOG_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodic `info!` percent lines instead of bars, for nohup/SLURM output
+/// files and other non-interactive logs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl ProgressSink for LogSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        Arc::new(LogHandle {
+            id: id.to_string(),
+            kind,
+            total,
+            state: Mutex::new(LogState {
+                position: 0,
+                logged_percent: 0,
+                logged_at: Instant::now(),
+            }),
+        })
+    }
+}
+
+struct LogState {
+    position: u64,
+    logged_percent: u64,
+    logged_at: Instant,
+}
+
+struct LogHandle {
+    id: String,
+    kind: ProgressKind,
+    total: u64,
+    state: Mutex<LogState>,
+}
+
+impl LogHandle {
+    fn verb(&self) -> &'static str {
+        match self.kind {
+            ProgressKind::Transfer => "downloaded",
+            ProgressKind::Verify => "verified",
+            ProgressKind::Spinner => "processed",
+        }
+    }
+
+    /// The line to log for `position`, if one is due.
+    fn due(&self, state: &mut LogState) -> Option<String> {
+        let percent = (self.total > 0).then(|| state.position.min(self.total) * 100 / self.total);
+        let step = percent.is_some_and(|p| p >= state.logged_percent + LOG_PROGRESS_STEP);
+        if !step && state.logged_at.elapsed() < LOG_PROGRESS_INTERVAL {
+            return None;
+        }
+        state.logged_at = Instant::now();
+        Some(match percent {
+            Some(percent) => {
+                state.logged_percent = percent - percent % LOG_PROGRESS_STEP;
+                format!(
+                    "[{}] {:>3}% {} ({} / {})",
+                    self.id,
+                    percent,
+                    self.verb(),
+                    HumanBytes(state.position),
+                    HumanBytes(self.total)
+                )
+            }
+            None => format!(
+                "[{}] {} {}",
+                self.id,
+                HumanBytes(state.position),
+                self.verb()
+            ),
+        })
+    }
+
+    fn update(&self, f: impl FnOnce(u64) -> u64) {
+        let line = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.position = f(state.position);
+            self.due(&mut state)
+        };
+        if let Some(line) = line {
+            info!("{}", line);
+        }
+    }
+}
+
+impl ProgressHandle for LogHandle {
+    fn set_position(&self, bytes: u64) {
+        self.update(|_| bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        self.update(|done| done + bytes);
+    }
+    fn set_message(&self, message: &str) {
+        debug!("[{}] {}", self.id, message);
+    }
+    fn println(&self, line: &str) {
+        info!("{}", line);
+    }
+    fn finish(&self) {
+        if self.kind != ProgressKind::Spinner {
+            let position = self.state.lock().map(|s| s.position).unwrap_or_default();
+            info!(
+                "[{}] 100% {} ({})",
+                self.id,
+                self.verb(),
+                HumanBytes(position.max(self.total))
+            );
+        }
+    }
+    fn fail(&self, reason: &str) {
+        warn!("[{}] {}", self.id, reason);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -34,4 +402,70 @@ mod tests {
         let _ = verify_bar_style();
         let _ = spinner_style();
     }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_sink_writes_throttled_events() {
+        let buffer = Buffer::default();
+        let sink = JsonSink::new(Box::new(buffer.clone()));
+        let handle = sink.start("SRR1", ProgressKind::Transfer, 100);
+        handle.set_position(10);
+        handle.inc(5); // within the throttle window
+        handle.set_message("Verifying");
+        handle.finish();
+
+        let out = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let names: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["start", "progress", "message", "finish"]);
+        assert_eq!(events[0]["kind"], "transfer");
+        assert_eq!(events[1]["bytes"], 10);
+        assert_eq!(events[3]["bytes"], 15);
+    }
+
+    #[test]
+    fn log_sink_logs_each_step() {
+        let handle = LogHandle {
+            id: "SRR1".into(),
+            kind: ProgressKind::Transfer,
+            total: 1000,
+            state: Mutex::new(LogState {
+                position: 0,
+                logged_percent: 0,
+                logged_at: Instant::now(),
+            }),
+        };
+        let mut logged = Vec::new();
+        for position in [50, 120, 150, 350, 1000] {
+            let mut state = handle.state.lock().unwrap();
+            state.position = position;
+            logged.extend(handle.due(&mut state));
+        }
+        assert_eq!(
+            logged,
+            [
+                "[SRR1]  12% downloaded (120 B / 1000 B)",
+                "[SRR1]  35% downloaded (350 B / 1000 B)",
+                "[SRR1] 100% downloaded (1000 B / 1000 B)",
+            ]
+        );
+    }
 }
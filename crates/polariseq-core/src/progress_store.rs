@@ -42,6 +42,10 @@ pub enum RunStage {
     Compressing,
     Completed,
     Failed,
+    /// Deliberately not downloaded (e.g. SRA Lite only with `--sra-format full`).
+    Skipped,
+    /// Stopped through a cancellation token; partial files are kept for resume.
+    Cancelled,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,179 @@
+//! `README_PROVENANCE.md` in the output directory.
+//!
+//! Written with the metadata of every `download`: the exact command line,
+//! the polariseq and tool versions, the effective configuration (secrets
+//! redacted), the query and how many records the filters kept, and the
+//! date. A rerun replaces it, so it describes the latest invocation.
+
+use crate::client::{DownloadJob, JobPlan};
+use crate::{doctor, shell, Config};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use tracing::debug;
+
+pub const PROVENANCE_FILE: &str = "README_PROVENANCE.md";
+
+const REDACTED: &str = "<redacted>";
+
+/// `config` without header values and the EGA client secret.
+fn redacted(config: &Config) -> Config {
+    let mut config = config.clone();
+    for value in config.http.headers.values_mut() {
+        *value = REDACTED.to_string();
+    }
+    if let Some(secret) = config
+        .ega
+        .as_mut()
+        .and_then(|ega| ega.client_secret.as_mut())
+    {
+        *secret = REDACTED.to_string();
+    }
+    config
+}
+
+/// Filter flags of `job` as they would be given on the command line.
+fn filters(job: &DownloadJob) -> Vec<String> {
+    let options = &job.options;
+    let mut filters = Vec::new();
+    let patterns = [
+        ("--filter-sample", &options.filter_sample),
+        ("--filter-run", &options.filter_run),
+        ("--exclude-sample", &options.exclude_sample),
+        ("--exclude-run", &options.exclude_run),
+    ];
+    for (flag, values) in patterns {
+        for value in values {
+            filters.push(format!("{} {}", flag, shell::quote(value)));
+        }
+    }
+    if options.pe_only {
+        filters.push("--pe-only".to_string());
+    }
+    filters
+}
+
+/// The document for `plan`; `tools` are `(tool, version)` pairs.
+pub fn render(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    invocation: &[String],
+    tools: &[(String, String)],
+) -> Result<String> {
+    let options = &job.options;
+    let version = env!("CARGO_PKG_VERSION");
+    let mut text = String::from("# Provenance\n\n");
+    writeln!(
+        text,
+        "Written by polariseq {} on {}.\n",
+        version,
+        Local::now().to_rfc3339()
+    )?;
+
+    writeln!(
+        text,
+        "## Command\n\n```\n{}\n```\n",
+        shell::join(invocation)
+    )?;
+
+    text.push_str("## Query\n\n");
+    if let Some(accession) = &options.accession {
+        writeln!(text, "- Accession: {}", accession)?;
+    }
+    if let Some(tsv) = &options.tsv {
+        writeln!(text, "- Run list: {}", tsv.display())?;
+    }
+    writeln!(text, "- Backend: {:?}", options.download_method)?;
+    writeln!(text, "- Output format: {:?}", options.output_format)?;
+    writeln!(text, "- Records fetched: {}", plan.fetched)?;
+    writeln!(text, "- Records after filtering: {}", plan.records.len())?;
+    writeln!(text, "- Runs with public files: {}", plan.processed.len())?;
+    writeln!(
+        text,
+        "- Runs without public files: {}",
+        plan.protected_runs.len()
+    )?;
+    let filters = filters(job);
+    if filters.is_empty() {
+        text.push_str("- Filters: none\n\n");
+    } else {
+        writeln!(text, "- Filters: `{}`\n", filters.join(" "))?;
+    }
+
+    text.push_str("## Tools\n\n| Tool | Version |\n|------|---------|\n");
+    writeln!(text, "| polariseq | {} |", version)?;
+    for (tool, version) in tools {
+        writeln!(text, "| {} | {} |", tool, version)?;
+    }
+
+    writeln!(
+        text,
+        "\n## Configuration\n\n```yaml\n{}```",
+        serde_yaml::to_string(&redacted(config))?
+    )?;
+    Ok(text)
+}
+
+/// Look up the tool versions and write [`PROVENANCE_FILE`] for `plan`.
+pub async fn write(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    invocation: &[String],
+) -> Result<PathBuf> {
+    let mut tools = Vec::new();
+    let paths = [
+        ("prefetch", config.software.prefetch.clone()),
+        ("fasterq-dump", config.software.fasterq_dump.clone()),
+        ("wget", PathBuf::from("wget")),
+    ];
+    for (name, path) in paths {
+        match doctor::tool_version(&path).await {
+            Ok(version) => tools.push((name.to_string(), version)),
+            Err(e) => debug!("No version for {}: {:#}", name, e),
+        }
+    }
+    let path = job.output().join(PROVENANCE_FILE);
+    std::fs::write(&path, render(config, job, plan, invocation, &tools)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadMethod;
+
+    #[test]
+    fn renders_query_counts_and_redacted_config() {
+        let config: Config = serde_yaml::from_str(
+            "software:\n  prefetch: p\n  fasterq_dump: f\n\
+             http:\n  headers:\n    Authorization: Bearer s3cret\n",
+        )
+        .unwrap();
+        let mut job = DownloadJob::accession("PRJNA1", "out").method(DownloadMethod::Ftp);
+        job.options.filter_run = vec!["SRR1.*".into()];
+        job.options.pe_only = true;
+        let plan = JobPlan {
+            fetched: 12,
+            protected_runs: vec!["SRR9".into()],
+            ..JobPlan::default()
+        };
+        let invocation: Vec<String> = ["polariseq", "download", "-A", "PRJNA1", "-o", "my out"]
+            .map(String::from)
+            .into();
+        let tools = [("wget".to_string(), "GNU Wget 1.21".to_string())];
+
+        let text = render(&config, &job, &plan, &invocation, &tools).unwrap();
+        assert!(text.contains("polariseq download -A PRJNA1 -o 'my out'\n"));
+        assert!(text.contains("- Accession: PRJNA1\n- Backend: Ftp\n"));
+        assert!(text.contains("- Records fetched: 12\n- Records after filtering: 0\n"));
+        assert!(text.contains("- Runs without public files: 1\n"));
+        assert!(text.contains("- Filters: `--filter-run 'SRR1.*' --pe-only`\n"));
+        assert!(text.contains("| wget | GNU Wget 1.21 |\n"));
+        assert!(text.contains("Authorization: <redacted>"));
+        assert!(!text.contains("s3cret"));
+    }
+}
@@ -2,10 +2,11 @@ use super::{parse_s3_url, s3_url_to_https, should_download_key, DatabaseType, Pu
 use crate::aws_s3::{ResumableDownloader, SraMetadata};
 use crate::generate_md5sum_file_at;
 use crate::observer::{CompletedInfo, DownloadObserver};
+use crate::progress::{NoopSink, ProgressSink};
 use crate::SoftwarePaths;
 use anyhow::{anyhow, Context, Result};
 use aws_sdk_s3::Client;
-use indicatif::{HumanBytes, MultiProgress};
+use indicatif::HumanBytes;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
@@ -86,7 +87,7 @@ pub struct PublicDataDownloader {
     file_workers: usize,
     inner_workers: usize,
     chunk_size_mb: u64,
-    progress: Arc<MultiProgress>,
+    progress: Arc<dyn ProgressSink>,
     observer: Option<Arc<dyn DownloadObserver>>,
 }
 
@@ -104,7 +105,7 @@ impl PublicDataDownloader {
             file_workers: DEFAULT_FILE_WORKERS,
             inner_workers: DEFAULT_INNER_WORKERS,
             chunk_size_mb: DEFAULT_CHUNK_SIZE_MB,
-            progress: Arc::new(MultiProgress::new()),
+            progress: Arc::new(NoopSink),
             observer: None,
         })
     }
@@ -122,8 +123,8 @@ impl PublicDataDownloader {
         self
     }
 
-    /// Use a caller-owned progress renderer for all concurrent file downloads.
-    pub fn with_progress(mut self, progress: Arc<MultiProgress>) -> Self {
+    /// Report per-file progress for all concurrent downloads to `progress`.
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
         self.progress = progress;
         self
     }
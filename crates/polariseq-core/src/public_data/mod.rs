@@ -1,10 +1,12 @@
 //! Download support for publicly available reference databases stored in S3.
 
 mod config;
+#[cfg(feature = "aws")]
 mod downloader;
 mod s3;
 pub mod validator;
 
 pub use config::{DatabaseType, PublicDatabase, ValidateConfig};
+#[cfg(feature = "aws")]
 pub use downloader::PublicDataDownloader;
 pub use s3::{parse_s3_url, s3_url_to_https, should_download_key, S3Location};
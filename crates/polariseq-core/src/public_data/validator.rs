@@ -30,20 +30,21 @@ pub async fn validate_blast_volume(
         .to_str()
         .ok_or_else(|| anyhow!("Invalid volume prefix path: {}", volume_prefix.display()))?;
 
-    let output = Command::new(tool_path)
-        .arg("-db")
-        .arg(prefix_str)
-        .arg("-dbtype")
-        .arg(dbtype)
-        .arg("-info")
-        .output()
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to run blastdbcmd for volume {}",
-                volume_prefix.display()
-            )
-        })?;
+    let output = crate::audit::output(
+        Command::new(tool_path)
+            .arg("-db")
+            .arg(prefix_str)
+            .arg("-dbtype")
+            .arg(dbtype)
+            .arg("-info"),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to run blastdbcmd for volume {}",
+            volume_prefix.display()
+        )
+    })?;
 
     Ok(output.status.success())
 }
@@ -57,13 +58,18 @@ pub async fn delete_volume_files(volume_prefix: &Path) -> Result<()> {
                 .await
                 .with_context(|| format!("Failed to remove {}", path.display()))?;
         }
-        // Also remove the resumable download progress metadata so the next
-        // download does not skip chunks based on a stale record.
-        let meta_path = volume_prefix.with_extension(format!("{ext}.meta.json"));
-        if meta_path.exists() {
-            tokio::fs::remove_file(&meta_path)
-                .await
-                .with_context(|| format!("Failed to remove {}", meta_path.display()))?;
+        // Also remove the resumable download progress metadata and partial
+        // file so the next download does not skip chunks based on a stale
+        // record.
+        for stale in [
+            volume_prefix.with_extension(format!("{ext}.meta.json")),
+            volume_prefix.with_extension(format!("{ext}.part")),
+        ] {
+            if stale.exists() {
+                tokio::fs::remove_file(&stale)
+                    .await
+                    .with_context(|| format!("Failed to remove {}", stale.display()))?;
+            }
         }
     }
     Ok(())
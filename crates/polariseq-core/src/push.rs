@@ -0,0 +1,247 @@
+//! SSH/rsync destinations for `download --dest`.
+//!
+//! [`RsyncHook`] pushes each verified run's files with `rsync` to
+//! `sftp://[user@]host[:port]/path`, `[user@]host:/path` or an rsync
+//! daemon (`rsync://host/module/path`). Interrupted transfers keep their
+//! partial file in `.rsync-partial` and are resumed on the next attempt.
+//! Files are compared by checksum, so a stale or corrupt copy already at
+//! the destination is replaced before `--dest-remove-local` deletes ours.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::error::PolariseqError;
+use crate::run_cancellable;
+use crate::shell;
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Where `rsync` pushes files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RsyncTarget {
+    /// Remote shell transfer to `[user@]host:dir`.
+    Ssh {
+        host: String,
+        port: Option<u16>,
+        dir: String,
+    },
+    /// rsync daemon URL, passed through as is.
+    Daemon(String),
+}
+
+impl RsyncTarget {
+    /// Whether `url` names an SSH or rsync destination rather than a
+    /// bucket.
+    pub fn matches(url: &str) -> bool {
+        url.starts_with("sftp://")
+            || url.starts_with("rsync://")
+            || (!url.contains("://") && url.contains(":/"))
+    }
+
+    fn display(&self) -> String {
+        match self {
+            Self::Ssh { host, dir, .. } => format!("{}:{}", host, dir),
+            Self::Daemon(url) => url.clone(),
+        }
+    }
+
+    /// Full `rsync` argument list for `files`.
+    fn rsync_args(&self, files: &[PathBuf], remove_local: bool) -> Vec<OsString> {
+        let mut args: Vec<OsString> = [
+            "-a",
+            "--partial",
+            "--partial-dir=.rsync-partial",
+            "--checksum",
+            "--timeout=300",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        if remove_local {
+            args.push("--remove-source-files".into());
+        }
+        if let Self::Ssh { port, dir, .. } = self {
+            let mut ssh = String::from("ssh -o BatchMode=yes");
+            if let Some(port) = port {
+                ssh.push_str(&format!(" -p {}", port));
+            }
+            args.push("-e".into());
+            args.push(ssh.into());
+            // Create the target directory on the remote side first.
+            // The remote shell runs this, so `dir` must be quoted for it.
+            args.push(format!("--rsync-path=mkdir -p -- {} && rsync", shell::quote(dir)).into());
+        }
+        args.push("--".into());
+        args.extend(files.iter().map(|f| f.as_os_str().to_owned()));
+        args.push(format!("{}/", self.display().trim_end_matches('/')).into());
+        args
+    }
+}
+
+impl FromStr for RsyncTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = || {
+            PolariseqError::InvalidInput(format!(
+                "Invalid destination '{}': expected sftp://[user@]host[:port]/path, \
+                 [user@]host:/path or rsync://host/module/path",
+                url
+            ))
+        };
+        if url.starts_with("rsync://") {
+            return Ok(Self::Daemon(url.to_string()));
+        }
+        if let Some(rest) = url.strip_prefix("sftp://") {
+            let (authority, path) = rest.split_once('/').ok_or_else(invalid)?;
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.parse::<u16>().map_err(|_| invalid())?)),
+                None => (authority, None),
+            };
+            if host.is_empty() || host.starts_with('-') || path.is_empty() {
+                return Err(invalid().into());
+            }
+            return Ok(Self::Ssh {
+                host: host.to_string(),
+                port,
+                dir: format!("/{}", path.trim_end_matches('/')),
+            });
+        }
+        match url.split_once(':') {
+            Some((host, dir))
+                if !host.is_empty() && !host.starts_with('-') && dir.starts_with('/') =>
+            {
+                Ok(Self::Ssh {
+                    host: host.to_string(),
+                    port: None,
+                    dir: dir.trim_end_matches('/').to_string(),
+                })
+            }
+            _ => Err(invalid().into()),
+        }
+    }
+}
+
+/// Pushes finished runs to an [`RsyncTarget`].
+pub struct RsyncHook {
+    target: RsyncTarget,
+    remove_local: bool,
+}
+
+impl RsyncHook {
+    pub fn new(target: RsyncTarget, remove_local: bool) -> Result<Self> {
+        crate::deps::locate_tool("rsync").ok_or_else(|| PolariseqError::DependencyMissing {
+            tool: "rsync".to_string(),
+            path: PathBuf::from("rsync"),
+        })?;
+        Ok(Self {
+            target,
+            remove_local,
+        })
+    }
+}
+
+impl RunHook for RsyncHook {
+    fn name(&self) -> &'static str {
+        "rsync"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if files.is_empty() {
+                return Err(anyhow!("no output files found for {}", run_id));
+            }
+            let args = self.target.rsync_args(files, self.remove_local);
+            let mut attempt = 1;
+            loop {
+                let output =
+                    run_cancellable(Command::new("rsync").args(&args), &ctx.cancel).await?;
+                if output.status.success() {
+                    info!(
+                        "[{}] Pushed {} file(s) to {}",
+                        run_id,
+                        files.len(),
+                        self.target.display()
+                    );
+                    return Ok(());
+                }
+                let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                if attempt == ATTEMPTS {
+                    return Err(anyhow!(
+                        "rsync failed after {} attempts: {}",
+                        ATTEMPTS,
+                        stderr
+                    ));
+                }
+                warn!(
+                    "[{}] rsync attempt {}/{} failed ({}); resuming in {}s",
+                    run_id,
+                    attempt,
+                    ATTEMPTS,
+                    stderr,
+                    RETRY_DELAY.as_secs()
+                );
+                tokio::time::sleep(RETRY_DELAY).await;
+                attempt += 1;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ssh_and_rsync_targets() {
+        let target: RsyncTarget = "sftp://alice@nas:2222/data/seq/".parse().unwrap();
+        assert_eq!(
+            target,
+            RsyncTarget::Ssh {
+                host: "alice@nas".into(),
+                port: Some(2222),
+                dir: "/data/seq".into()
+            }
+        );
+        let args = target.rsync_args(&[PathBuf::from("SRR1_1.fastq.gz")], false);
+        assert_eq!(
+            args[args.len() - 3..],
+            ["--", "SRR1_1.fastq.gz", "alice@nas:/data/seq/"]
+        );
+        assert!(args.contains(&OsString::from("--checksum")));
+        assert!(args.contains(&OsString::from("ssh -o BatchMode=yes -p 2222")));
+        assert!(args.contains(&OsString::from(
+            "--rsync-path=mkdir -p -- /data/seq && rsync"
+        )));
+
+        let target: RsyncTarget = "nas:/data/it's; rm -rf ~".parse().unwrap();
+        let args = target.rsync_args(&[], false);
+        assert!(args.contains(&OsString::from(
+            r"--rsync-path=mkdir -p -- '/data/it'\''s; rm -rf ~' && rsync"
+        )));
+
+        assert!(RsyncTarget::matches("nas:/data"));
+        assert!(!RsyncTarget::matches("s3://bucket/x"));
+        assert_eq!(
+            "rsync://nas/mod/x".parse::<RsyncTarget>().unwrap(),
+            RsyncTarget::Daemon("rsync://nas/mod/x".into())
+        );
+        assert!("sftp://nas".parse::<RsyncTarget>().is_err());
+        assert!("sftp://-oProxyCommand=x/data"
+            .parse::<RsyncTarget>()
+            .is_err());
+        assert!("-e sh:/data".parse::<RsyncTarget>().is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Read QC after download (`download --qc fastqc|falco`).
+//!
+//! [`QcHook`] runs FastQC or falco on each run's final FASTQ files (the
+//! trimmed ones when `--trim` ran) into `<output>/qc/<name>_fastqc/`, then
+//! condenses the reports into `<run>.qc.json`: reads, GC content and the
+//! share of reads with a mean quality of Q30 or more. Runs with no reads or
+//! a Q30 share below [`MIN_Q30_READS`] are flagged in the log right away
+//! and in the `--multiqc` table.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::error::PolariseqError;
+use crate::run_cancellable;
+use crate::trim;
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Directory under the output that holds the tool reports.
+pub const QC_DIR: &str = "qc";
+
+/// Runs whose share of Q30 reads falls below this are flagged.
+pub const MIN_Q30_READS: f64 = 0.5;
+
+/// QC tool for `--qc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum QcTool {
+    Fastqc,
+    /// Drop-in FastQC replacement, much faster on large files.
+    Falco,
+}
+
+impl QcTool {
+    fn binary(self) -> &'static str {
+        match self {
+            Self::Fastqc => "fastqc",
+            Self::Falco => "falco",
+        }
+    }
+}
+
+/// Condensed QC of one run, written to `<run>.qc.json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct QcSummary {
+    pub reads: u64,
+    /// GC content in percent, averaged over the run's files by reads.
+    pub gc_pct: f64,
+    /// Fraction of reads with a mean quality of Q30 or more.
+    pub q30_reads: f64,
+    pub flagged: bool,
+}
+
+impl QcSummary {
+    /// `<run>.qc.json` of `run_id` in `output`.
+    pub fn path(output: &Path, run_id: &str) -> PathBuf {
+        output.join(format!("{}.qc.json", run_id))
+    }
+
+    fn combine(files: &[QcSummary]) -> Self {
+        let reads: u64 = files.iter().map(|f| f.reads).sum();
+        let weighted = |value: fn(&QcSummary) -> f64| {
+            files.iter().map(|f| value(f) * f.reads as f64).sum::<f64>() / reads.max(1) as f64
+        };
+        let q30_reads = weighted(|f| f.q30_reads);
+        Self {
+            reads,
+            gc_pct: weighted(|f| f.gc_pct),
+            q30_reads,
+            flagged: reads == 0 || q30_reads < MIN_Q30_READS,
+        }
+    }
+}
+
+/// Basic statistics from a FastQC `fastqc_data.txt`.
+fn parse_fastqc_data(text: &str) -> Result<QcSummary> {
+    let mut summary = QcSummary::default();
+    let mut module = "";
+    let (mut q30, mut total) = (0.0, 0.0);
+    for line in text.lines() {
+        if let Some(name) = line.strip_prefix(">>") {
+            module = name.split('\t').next().unwrap_or_default();
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(key), Some(value)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        match module {
+            "Basic Statistics" if key == "Total Sequences" => summary.reads = value.parse()?,
+            "Basic Statistics" if key == "%GC" => summary.gc_pct = value.parse()?,
+            "Per sequence quality scores" => {
+                let quality: f64 = key.parse()?;
+                let count: f64 = value.parse()?;
+                total += count;
+                if quality >= 30.0 {
+                    q30 += count;
+                }
+            }
+            _ => {}
+        }
+    }
+    if total > 0.0 {
+        summary.q30_reads = q30 / total;
+    }
+    Ok(summary)
+}
+
+/// Whether `path` is a QC summary rather than a downloaded file.
+pub fn is_qc_output(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".qc.json")
+}
+
+/// FASTQ files QC should look at: the trimmed ones if any, else the
+/// downloaded ones.
+fn final_fastqs(files: &[PathBuf]) -> Vec<&PathBuf> {
+    let fastqs: Vec<&PathBuf> = files.iter().filter(|f| trim::is_fastq(f)).collect();
+    let trimmed: Vec<&PathBuf> = fastqs
+        .iter()
+        .copied()
+        .filter(|f| trim::is_trim_output(f))
+        .collect();
+    if trimmed.is_empty() {
+        fastqs
+    } else {
+        trimmed
+    }
+}
+
+/// Report directory of `fastq` under `qc_dir`, as FastQC names it.
+fn report_dir(qc_dir: &Path, fastq: &Path) -> PathBuf {
+    let name = fastq.file_name().unwrap_or_default().to_string_lossy();
+    let stem = trim::fastq_stem(&name).unwrap_or(&name);
+    qc_dir.join(format!("{}_fastqc", stem))
+}
+
+/// Runs FastQC or falco over each finished run.
+pub struct QcHook {
+    tool: QcTool,
+    path: PathBuf,
+    threads: usize,
+}
+
+impl QcHook {
+    pub fn new(tool: QcTool, threads: usize) -> Result<Self> {
+        let path = crate::deps::locate_tool(tool.binary()).ok_or_else(|| {
+            PolariseqError::DependencyMissing {
+                tool: tool.binary().to_string(),
+                path: PathBuf::from(tool.binary()),
+            }
+        })?;
+        Ok(Self {
+            tool,
+            path,
+            threads: threads.max(1),
+        })
+    }
+
+    async fn run_tool(&self, fastq: &Path, qc_dir: &Path, ctx: &BackendContext) -> Result<()> {
+        let mut command = Command::new(&self.path);
+        command.arg("-t").arg(self.threads.to_string());
+        match self.tool {
+            // FastQC names its output after the input; --extract keeps the
+            // unzipped fastqc_data.txt next to the zip.
+            QcTool::Fastqc => command.arg("--extract").arg("-o").arg(qc_dir),
+            // falco writes fixed names into the directory it is given.
+            QcTool::Falco => command.arg("-o").arg(report_dir(qc_dir, fastq)),
+        };
+        let output = run_cancellable(command.arg(fastq), &ctx.cancel).await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                self.tool.binary(),
+                output.status,
+                stderr.lines().last().unwrap_or_default().trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RunHook for QcHook {
+    fn name(&self) -> &'static str {
+        self.tool.binary()
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let fastqs = final_fastqs(files);
+            if fastqs.is_empty() {
+                info!("[{}] No FASTQ files to check", run_id);
+                return Ok(());
+            }
+            let qc_dir = ctx.options.output.join(QC_DIR);
+            tokio::fs::create_dir_all(&qc_dir).await?;
+            let mut per_file = Vec::new();
+            for fastq in fastqs {
+                self.run_tool(fastq, &qc_dir, ctx).await?;
+                let data = report_dir(&qc_dir, fastq).join("fastqc_data.txt");
+                per_file.push(parse_fastqc_data(&tokio::fs::read_to_string(&data).await?)?);
+            }
+            let summary = QcSummary::combine(&per_file);
+            tokio::fs::write(
+                QcSummary::path(&ctx.options.output, run_id),
+                serde_json::to_vec_pretty(&summary)?,
+            )
+            .await?;
+            let stats = format!(
+                "{} reads, {:.1}% GC, {:.1}% of reads at Q30+",
+                summary.reads,
+                summary.gc_pct,
+                summary.q30_reads * 100.0
+            );
+            if summary.flagged {
+                warn!("[{}] QC flagged: {}", run_id, stats);
+            } else {
+                info!("[{}] QC: {}", run_id, stats);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_fastqc_reports() {
+        let data = "##FastQC\t0.12.1\n\
+                    >>Basic Statistics\tpass\n\
+                    #Measure\tValue\n\
+                    Filename\tSRR1_1.fastq.gz\n\
+                    Total Sequences\t1000\n\
+                    %GC\t40\n\
+                    >>END_MODULE\n\
+                    >>Per sequence quality scores\tpass\n\
+                    #Quality\tCount\n\
+                    20\t100.0\n\
+                    30\t600.0\n\
+                    37\t300.0\n\
+                    >>END_MODULE\n";
+        let r1 = parse_fastqc_data(data).unwrap();
+        assert_eq!((r1.reads, r1.gc_pct), (1000, 40.0));
+        assert!((r1.q30_reads - 0.9).abs() < 1e-9);
+
+        let poor = QcSummary {
+            reads: 3000,
+            gc_pct: 60.0,
+            q30_reads: 0.1,
+            flagged: false,
+        };
+        let run = QcSummary::combine(&[r1, poor]);
+        assert_eq!(run.reads, 4000);
+        assert!((run.gc_pct - 55.0).abs() < 1e-9);
+        assert!(run.flagged);
+
+        let dir = Path::new("/data");
+        let files = [
+            dir.join("SRR1_1.fastq.gz"),
+            dir.join("SRR1_1.trimmed.fastq.gz"),
+        ];
+        assert_eq!(final_fastqs(&files), [&files[1]]);
+        assert_eq!(
+            report_dir(&dir.join(QC_DIR), &files[1]),
+            dir.join("qc/SRR1_1.trimmed_fastqc")
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Quarantine for files that failed verification.
+//!
+//! A file whose MD5 or size does not match is moved to
+//! `<dir>/quarantine/<name>` next to `<name>.reason.txt`, instead of being
+//! left where a rerun's existing-file check could pick it up or resume onto
+//! it. The next attempt starts from scratch; the quarantined copy stays for
+//! inspection until it is deleted by hand.
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Move `file` (a `.part` keeps its final name) into the quarantine
+/// directory beside it and record `reason`. Returns the new path.
+pub fn quarantine(file: &Path, reason: &str) -> Result<PathBuf> {
+    let dir = file
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(QUARANTINE_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let name = name.strip_suffix(".part").unwrap_or(&name);
+    let target = dir.join(name);
+    // An older copy of the same file is superseded.
+    let _ = fs::remove_file(&target);
+    fs::rename(file, &target)
+        .with_context(|| format!("Failed to move {} to {}", file.display(), target.display()))?;
+    fs::write(
+        dir.join(format!("{}.reason.txt", name)),
+        format!(
+            "file: {}\nreason: {}\nquarantined: {}\n",
+            file.display(),
+            reason,
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        ),
+    )?;
+    warn!("Quarantined {} ({}): {}", name, reason, target.display());
+    Ok(target)
+}
+
+/// [`quarantine`] `file` if it exists, falling back to deleting it, so a
+/// failed move never leaves the bad file in place.
+pub fn quarantine_or_remove(file: &Path, reason: &str) {
+    if !file.exists() {
+        return;
+    }
+    if let Err(e) = quarantine(file, reason) {
+        warn!("{:#}; removing it instead", e);
+        if let Err(e) = fs::remove_file(file) {
+            warn!("Failed to remove {}: {}", file.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_file_and_records_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("SRR1_1.fastq.gz.part");
+        fs::write(&file, b"bad").unwrap();
+
+        let target = quarantine(&file, "MD5 mismatch").unwrap();
+        assert_eq!(target, dir.path().join("quarantine/SRR1_1.fastq.gz"));
+        assert!(!file.exists());
+        let reason =
+            fs::read_to_string(dir.path().join("quarantine/SRR1_1.fastq.gz.reason.txt")).unwrap();
+        assert!(reason.contains("reason: MD5 mismatch\n"));
+
+        // A second bad copy replaces the first.
+        fs::write(&file, b"worse").unwrap();
+        quarantine_or_remove(&file, "MD5 mismatch");
+        assert_eq!(fs::read(&target).unwrap(), b"worse");
+    }
+}
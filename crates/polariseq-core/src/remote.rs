@@ -0,0 +1,187 @@
+//! Remote execution for `download --remote user@host:/dir`.
+//!
+//! The per-run bash scripts of [`crate::scripts`] are piped to
+//! `ssh <host> bash -s`, so the data lands on a machine with external
+//! connectivity (e.g. a data-transfer node) while the metadata step and
+//! the logs stay local. Tools are called by bare name on the remote side.
+
+use crate::client::{DownloadJob, JobPlan};
+use crate::error::PolariseqError;
+use crate::{scripts, Config, SoftwarePaths};
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// `[user@]host:/absolute/dir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteTarget {
+    /// Anything `ssh` accepts as destination, including `~/.ssh/config`
+    /// aliases, but not starting with `-`, which ssh would read as an option.
+    pub host: String,
+    pub dir: PathBuf,
+}
+
+impl FromStr for RemoteTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (host, dir) = spec
+            .split_once(':')
+            .filter(|(host, dir)| {
+                !host.is_empty() && !host.starts_with('-') && dir.starts_with('/')
+            })
+            .ok_or_else(|| {
+                PolariseqError::InvalidInput(format!(
+                    "Invalid remote '{}': expected [user@]host:/absolute/dir",
+                    spec
+                ))
+            })?;
+        Ok(Self {
+            host: host.to_string(),
+            dir: PathBuf::from(dir),
+        })
+    }
+}
+
+/// Log each line of a remote stream, prefixed with the run.
+async fn forward_lines(run_id: String, stream: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!(target: "download_detail", "[{}@remote] {}", run_id, line);
+    }
+}
+
+/// Run one script over SSH, streaming its output back.
+async fn run_one(
+    target: &RemoteTarget,
+    run_id: &str,
+    script: &str,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let mut command = Command::new("ssh");
+    command
+        .args(["-o", "BatchMode=yes", "--", &target.host, "bash", "-s"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn().context("Failed to run ssh")?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin.write_all(script.as_bytes()).await?;
+    drop(stdin);
+    let stdout = tokio::spawn(forward_lines(
+        run_id.to_string(),
+        child.stdout.take().expect("stdout is piped"),
+    ));
+    let stderr = tokio::spawn(forward_lines(
+        run_id.to_string(),
+        child.stderr.take().expect("stderr is piped"),
+    ));
+
+    let status = tokio::select! {
+        status = child.wait() => status?,
+        _ = cancel.cancelled() => {
+            crate::audit::record(command.as_std(), started, Err("cancelled"));
+            return Err(PolariseqError::Cancelled.into());
+        }
+    };
+    // stderr went to the run's log as it arrived.
+    crate::audit::record(command.as_std(), started, Ok((status, &[])));
+    let _ = tokio::join!(stdout, stderr);
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("remote script exited with {}", status))
+    }
+}
+
+/// Run every planned run on `target`, `job.options.multithreads` at a time.
+/// Fails when any run fails, after all runs were attempted.
+pub async fn run_remote(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    target: &RemoteTarget,
+    cancel: CancellationToken,
+) -> Result<()> {
+    if job.credential.is_some() && !plan.protected_runs.is_empty() {
+        warn!(
+            "Controlled-access runs use the credential path as given; it must exist on {}",
+            target.host
+        );
+    }
+    // Local tool paths mean nothing on the remote host.
+    let remote_config = Config {
+        software: SoftwarePaths {
+            prefetch: "prefetch".into(),
+            fasterq_dump: "fasterq-dump".into(),
+            blastdbcmd: None,
+            samtools: None,
+        },
+        ..config.clone()
+    };
+    let scripts = scripts::bash_scripts(&remote_config, job, plan, &target.dir).await?;
+    let total = scripts.len();
+    info!(
+        "Running {} run(s) on {}:{}",
+        total,
+        target.host,
+        target.dir.display()
+    );
+
+    let semaphore = Arc::new(Semaphore::new(job.options.multithreads.max(1)));
+    let mut tasks = Vec::new();
+    for (run_id, script) in scripts {
+        let semaphore = semaphore.clone();
+        let target = target.clone();
+        let cancel = cancel.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            let result = run_one(&target, &run_id, &script, &cancel).await;
+            match &result {
+                Ok(()) => info!("[{}] Finished on {}", run_id, target.host),
+                Err(e) => warn!("[{}] Failed on {}: {:#}", run_id, target.host, e),
+            }
+            result
+        }));
+    }
+
+    let mut failed = 0;
+    for task in tasks {
+        if !matches!(task.await, Ok(Ok(()))) {
+            failed += 1;
+        }
+    }
+    if cancel.is_cancelled() {
+        return Err(PolariseqError::Cancelled.into());
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} remote run(s) failed", failed, total));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_remote_targets() {
+        let target: RemoteTarget = "alice@dtn.example.org:/data/out".parse().unwrap();
+        assert_eq!(target.host, "alice@dtn.example.org");
+        assert_eq!(target.dir, PathBuf::from("/data/out"));
+        assert_eq!("dtn:/x".parse::<RemoteTarget>().unwrap().host, "dtn");
+        assert!("dtn:relative".parse::<RemoteTarget>().is_err());
+        assert!("/data/out".parse::<RemoteTarget>().is_err());
+        assert!("-oProxyCommand=x:/data".parse::<RemoteTarget>().is_err());
+    }
+}
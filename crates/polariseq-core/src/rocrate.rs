@@ -0,0 +1,242 @@
+//! RO-Crate metadata for `download --ro-crate`.
+//!
+//! [`make_crate`] writes `ro-crate-metadata.json` (RO-Crate 1.1) into the
+//! output directory once the job has finished. The root dataset lists the
+//! files of every planned run with size, MD5 and SHA-256; each file points
+//! at its ENA run, and a `CreateAction` ties the runs to the files and to
+//! the polariseq version that retrieved them. With `--bagit` it is written
+//! after the bag, so files are found under `data/`.
+
+use crate::backend::run_output_files;
+use crate::bagit::PAYLOAD_DIR;
+use crate::client::JobPlan;
+use crate::manifest::file_digests;
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+pub const CRATE_FILE: &str = "ro-crate-metadata.json";
+
+const ENA_VIEW: &str = "https://www.ebi.ac.uk/ena/browser/view/";
+const SOFTWARE_ID: &str = "#polariseq";
+
+fn id(value: &str) -> Value {
+    json!({ "@id": value })
+}
+
+/// `/`-separated path of `file` relative to `output`.
+fn crate_path(output: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(output).unwrap_or(file);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Files of `run_id` in `output` or, after `--bagit`, in its payload.
+fn run_files(output: &Path, run_id: &str) -> Vec<PathBuf> {
+    let mut files = run_output_files(output, run_id);
+    files.extend(run_output_files(&output.join(PAYLOAD_DIR), run_id));
+    files
+}
+
+fn encoding_format(name: &str) -> Option<&'static str> {
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    if name.ends_with(".fastq") || name.ends_with(".fq") {
+        Some("http://edamontology.org/format_1930")
+    } else if name.ends_with(".bam") {
+        Some("http://edamontology.org/format_2572")
+    } else if name.ends_with(".cram") {
+        Some("http://edamontology.org/format_3462")
+    } else {
+        None
+    }
+}
+
+/// The crate of `plan` in `output`; `accession` identifies the dataset.
+pub fn render(output: &Path, plan: &JobPlan, accession: Option<&str>) -> Result<Value> {
+    let now = Local::now().to_rfc3339();
+    let mut graph = vec![json!({
+        "@id": CRATE_FILE,
+        "@type": "CreativeWork",
+        "conformsTo": id("https://w3id.org/ro/crate/1.1"),
+        "about": id("./"),
+    })];
+
+    let mut parts = Vec::new();
+    let mut runs = Vec::new();
+    let mut entities = Vec::new();
+    for record in &plan.processed {
+        let run_id = &record.run_accession;
+        let run_url = format!("{}{}", ENA_VIEW, run_id);
+        let mut run = json!({
+            "@id": run_url,
+            "@type": "CreativeWork",
+            "identifier": run_id,
+        });
+        if !record.sample_title.is_empty() {
+            run["name"] = json!(record.sample_title);
+        }
+        entities.push(run);
+        runs.push(id(&run_url));
+
+        for file in run_files(output, run_id) {
+            let path = crate_path(output, &file);
+            let bytes = std::fs::metadata(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?
+                .len();
+            let (md5, sha256) = file_digests(&file)?;
+            let mut entity = json!({
+                "@id": path,
+                "@type": "File",
+                "name": file.file_name().unwrap_or_default().to_string_lossy(),
+                "contentSize": bytes.to_string(),
+                "md5": md5,
+                "sha256": sha256,
+                "isBasedOn": id(&run_url),
+            });
+            if let Some(format) = encoding_format(&path) {
+                entity["encodingFormat"] = id(format);
+            }
+            entities.push(entity);
+            parts.push(id(&path));
+        }
+    }
+
+    let titles: BTreeSet<&str> = plan
+        .records
+        .iter()
+        .filter_map(|r| r.study_title.as_deref())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+    let studies: BTreeSet<&str> = plan
+        .records
+        .iter()
+        .filter_map(|r| r.study_accession.as_deref())
+        .collect();
+    let name = match (titles.iter().next(), accession) {
+        (Some(title), _) if titles.len() == 1 => title.to_string(),
+        (_, Some(accession)) => format!("ENA {}", accession),
+        _ => "ENA sequencing runs".to_string(),
+    };
+    let mut sources: Vec<Value> = accession
+        .into_iter()
+        .chain(studies.iter().copied().filter(|s| Some(*s) != accession))
+        .map(|a| id(&format!("{}{}", ENA_VIEW, a)))
+        .collect();
+    sources.extend(runs.iter().cloned());
+
+    let mut root = json!({
+        "@id": "./",
+        "@type": "Dataset",
+        "name": name,
+        "datePublished": now,
+        "hasPart": parts,
+        "isBasedOn": sources,
+    });
+    if let Some(accession) = accession {
+        root["identifier"] = json!(accession);
+    }
+    if titles.len() > 1 {
+        root["description"] = json!(titles.into_iter().collect::<Vec<_>>().join("; "));
+    }
+    graph.push(root);
+    graph.push(json!({
+        "@id": SOFTWARE_ID,
+        "@type": "SoftwareApplication",
+        "name": "polariseq",
+        "version": env!("CARGO_PKG_VERSION"),
+    }));
+    graph.push(json!({
+        "@id": "#retrieval",
+        "@type": "CreateAction",
+        "name": "Download from ENA",
+        "instrument": id(SOFTWARE_ID),
+        "object": runs,
+        "result": parts,
+        "endTime": now,
+    }));
+    graph.extend(entities);
+
+    Ok(json!({
+        "@context": "https://w3id.org/ro/crate/1.1/context",
+        "@graph": graph,
+    }))
+}
+
+/// Write [`CRATE_FILE`] for `plan` into `output`.
+pub fn make_crate(output: &Path, plan: &JobPlan, accession: Option<&str>) -> Result<PathBuf> {
+    let metadata = render(output, plan, accession)?;
+    let path = output.join(CRATE_FILE);
+    std::fs::write(&path, serde_json::to_vec_pretty(&metadata)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("RO-Crate metadata written to {}", path.display());
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnaRecord, ProcessedRecord};
+
+    #[test]
+    fn describes_runs_files_and_software() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path();
+        std::fs::write(output.join("SRR1_1.fastq.gz"), b"abc").unwrap();
+        std::fs::create_dir(output.join(PAYLOAD_DIR)).unwrap();
+        std::fs::write(output.join("data/SRR1_2.fastq.gz"), b"de").unwrap();
+        let record: EnaRecord = serde_json::from_value(json!({
+            "run_accession": "SRR1",
+            "study_accession": "PRJNA1",
+            "study_title": "A study",
+        }))
+        .unwrap();
+        let plan = JobPlan {
+            records: vec![record],
+            processed: vec![ProcessedRecord {
+                run_accession: "SRR1".into(),
+                fastq_ftp_1_url: String::new(),
+                fastq_ftp_2_url: None,
+                fastq_ftp_1_name: "SRR1_1.fastq.gz".into(),
+                fastq_ftp_2_name: None,
+                fastq_md5_1: String::new(),
+                fastq_md5_2: None,
+                fastq_bytes_1: 3,
+                fastq_bytes_2: None,
+                sample_title: "liver".into(),
+            }],
+            ..JobPlan::default()
+        };
+
+        let path = make_crate(output, &plan, Some("PRJNA1")).unwrap();
+        let metadata: Value = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        let graph = metadata["@graph"].as_array().unwrap();
+        let entity = |id: &str| graph.iter().find(|e| e["@id"] == id).unwrap();
+
+        let root = entity("./");
+        assert_eq!(root["name"], "A study");
+        assert_eq!(root["identifier"], "PRJNA1");
+        assert_eq!(
+            root["hasPart"],
+            json!([{ "@id": "SRR1_1.fastq.gz" }, { "@id": "data/SRR1_2.fastq.gz" }])
+        );
+        let file = entity("SRR1_1.fastq.gz");
+        assert_eq!(file["md5"], "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(file["contentSize"], "3");
+        assert_eq!(
+            file["isBasedOn"]["@id"],
+            "https://www.ebi.ac.uk/ena/browser/view/SRR1"
+        );
+        assert_eq!(
+            entity("https://www.ebi.ac.uk/ena/browser/view/SRR1")["name"],
+            "liver"
+        );
+        assert_eq!(entity(SOFTWARE_ID)["version"], env!("CARGO_PKG_VERSION"));
+    }
+}
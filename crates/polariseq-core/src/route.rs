@@ -0,0 +1,163 @@
+//! Per-run backend routing (`--route`).
+//!
+//! By default every run of a job goes through the same backend chain
+//! (`--download`). Routes pick the chain per run instead, so one job can
+//! fetch some runs over FTP and others from S3 at the same time. A rule is
+//! `<match>=<backend>[,<backend>...]`, where `<match>` is one of
+//!
+//! - `fastq`: runs with public ENA FASTQ files,
+//! - `paired` / `single`: public runs by layout,
+//! - `protected`: controlled-access runs, only available as SRA,
+//! - an accession glob such as `SRR*` or `ERR1234*` (`*` matches any run).
+//!
+//! The first matching rule wins and its backends are tried in the order
+//! given. Runs no rule matches use the whole chain, `--download` first.
+
+use crate::backend::{DownloadBackend, RunItem};
+use crate::error::PolariseqError;
+use anyhow::Result;
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub enum RouteMatch {
+    Fastq,
+    Paired,
+    Single,
+    Protected,
+    Accession(Regex),
+}
+
+impl RouteMatch {
+    pub(crate) fn matches(&self, item: &RunItem) -> bool {
+        match (self, item) {
+            (Self::Fastq, RunItem::Public(_)) => true,
+            (Self::Paired, RunItem::Public(record)) => record.fastq_ftp_2_name.is_some(),
+            (Self::Single, RunItem::Public(record)) => record.fastq_ftp_2_name.is_none(),
+            (Self::Protected, RunItem::Protected(_)) => true,
+            (Self::Accession(glob), item) => glob.is_match(item.run_id()),
+            _ => false,
+        }
+    }
+}
+
+/// One `--route` rule.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub matches: RouteMatch,
+    /// Backend names (`aws`, `ftp`, `prefetch`, ...), in the order tried.
+    pub backends: Vec<String>,
+}
+
+impl FromStr for Route {
+    type Err = PolariseqError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| {
+            PolariseqError::InvalidInput(format!(
+                "Invalid route '{}': {} (expected <match>=<backend>[,<backend>...], e.g. fastq=ftp)",
+                rule, why
+            ))
+        };
+        let (pattern, backends) = rule.split_once('=').ok_or_else(|| invalid("missing '='"))?;
+        let matches = match pattern.trim() {
+            "fastq" => RouteMatch::Fastq,
+            "paired" => RouteMatch::Paired,
+            "single" => RouteMatch::Single,
+            "protected" => RouteMatch::Protected,
+            "" => return Err(invalid("empty match")),
+            glob => {
+                let pattern = format!("^{}$", regex::escape(glob).replace(r"\*", ".*"));
+                RouteMatch::Accession(Regex::new(&pattern).map_err(|e| invalid(&e.to_string()))?)
+            }
+        };
+        let backends: Vec<String> = backends
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if backends.is_empty() {
+            return Err(invalid("no backend given"));
+        }
+        Ok(Self { matches, backends })
+    }
+}
+
+/// Parse the `--route` rules of a job.
+pub fn parse_routes(rules: &[String]) -> Result<Vec<Route>> {
+    Ok(rules
+        .iter()
+        .map(|rule| rule.parse())
+        .collect::<Result<_, _>>()?)
+}
+
+/// Backends `item` may use, in the order to try them: those named by the
+/// first route matching it, or every backend in `chain` if none does.
+/// Backends that do not support `item` are left out.
+pub fn chain_for<'a>(
+    item: &RunItem,
+    routes: &[Route],
+    chain: &'a [Arc<dyn DownloadBackend>],
+) -> Vec<&'a Arc<dyn DownloadBackend>> {
+    let selected: Vec<&Arc<dyn DownloadBackend>> =
+        match routes.iter().find(|route| route.matches.matches(item)) {
+            Some(route) => route
+                .backends
+                .iter()
+                .filter_map(|name| chain.iter().find(|b| b.name() == name))
+                .collect(),
+            None => chain.iter().collect(),
+        };
+    selected
+        .into_iter()
+        .filter(|backend| backend.supports(item))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessedRecord;
+
+    fn public(run: &str, paired: bool) -> RunItem {
+        RunItem::Public(ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: String::new(),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: format!("{}_1.fastq.gz", run),
+            fastq_ftp_2_name: paired.then(|| format!("{}_2.fastq.gz", run)),
+            fastq_md5_1: String::new(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 0,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        })
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let routes = parse_routes(&[
+            "ERR12*=aws,ftp".to_string(),
+            "paired=ftp".to_string(),
+            "protected = prefetch".to_string(),
+        ])
+        .unwrap();
+        let pick = |item: &RunItem| {
+            routes
+                .iter()
+                .find(|r| r.matches.matches(item))
+                .map(|r| r.backends.join(","))
+        };
+        assert_eq!(pick(&public("ERR123", true)).as_deref(), Some("aws,ftp"));
+        assert_eq!(pick(&public("SRR1", true)).as_deref(), Some("ftp"));
+        assert_eq!(pick(&public("SRR1", false)), None);
+        assert_eq!(
+            pick(&RunItem::Protected("SRR2".into())).as_deref(),
+            Some("prefetch")
+        );
+
+        assert!("fastq".parse::<Route>().is_err());
+        assert!("fastq=".parse::<Route>().is_err());
+    }
+}
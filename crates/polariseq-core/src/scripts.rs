@@ -0,0 +1,800 @@
+//! Per-run shell scripts for `download --only-scripts`.
+//!
+//! Instead of downloading, every planned run gets `scripts/<run>.sh` with
+//! the commands that fetch, check and (for SRA) convert it. With a
+//! scheduler [`ScriptFlavor`] the script starts with the matching
+//! `#SBATCH` / `#PBS` / `#BSUB` / `#$` header, sized from the run's bytes,
+//! so it can be submitted as is.
+//!
+//! A script that finishes touches `scripts/done/<run>.done` and exits early
+//! when that sentinel already exists, so rerunning the scripts (or a
+//! runner) after a partial failure only redoes the unfinished runs.
+
+use crate::aws_s3::{SraLookup, SraMetadata, SraUtils};
+use crate::client::{DownloadJob, JobPlan};
+use crate::shell::{self, quote};
+use crate::{Config, DownloadMethod, ProcessedRecord};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Directory under the output directory that receives the scripts.
+pub const SCRIPTS_DIR: &str = "scripts";
+
+/// Directory under [`SCRIPTS_DIR`] holding the `<run>.done` sentinels.
+pub const DONE_DIR: &str = "done";
+
+/// AWS Open Data location of a run's `.sra`.
+const SRA_ODP_URL: &str = "https://sra-pub-run-odp.s3.amazonaws.com/sra";
+
+/// Job-array script and its run index, under [`SCRIPTS_DIR`].
+pub const ARRAY_SCRIPT: &str = "array.sh";
+pub const ARRAY_INDEX: &str = "array_index.txt";
+
+/// Single script of every run, under [`SCRIPTS_DIR`], written instead of
+/// per-run scripts where file counts are limited.
+pub const COMBINED_SCRIPT: &str = "all_runs.sh";
+
+/// Runners of the per-run scripts, under [`SCRIPTS_DIR`].
+pub const RUN_ALL_SCRIPT: &str = "run_all.sh";
+pub const MAKEFILE: &str = "Makefile";
+
+/// Runner written next to the per-run scripts (`--script-runners`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptRunner {
+    /// [`RUN_ALL_SCRIPT`]: GNU parallel (or `xargs -P`) over all runs.
+    Parallel,
+    /// [`MAKEFILE`]: one target per run, for `make -jN`.
+    Make,
+}
+
+/// Header style of generated scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptFlavor {
+    /// Plain bash.
+    #[default]
+    Bash,
+    Slurm,
+    Pbs,
+    Lsf,
+    Sge,
+}
+
+/// Scheduler request for one run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resources {
+    pub cpus: usize,
+    pub mem_mb: u64,
+    pub minutes: u64,
+}
+
+impl Resources {
+    /// Rough request for a run of `bytes`: 30 min plus 10 MB/s of transfer,
+    /// doubled when the run is converted with `fasterq-dump`, whose memory
+    /// use also needs more than a plain `wget`.
+    pub fn estimate(bytes: u64, cpus: usize, converts: bool) -> Self {
+        const MB: u64 = 1024 * 1024;
+        let transfer_minutes = bytes / (10 * MB) / 60;
+        let (mem_mb, minutes) = if converts {
+            (8192, 30 + transfer_minutes * 2)
+        } else {
+            (2048, 30 + transfer_minutes)
+        };
+        Self {
+            cpus: cpus.max(1),
+            mem_mb,
+            minutes,
+        }
+    }
+
+    fn walltime(&self) -> String {
+        format!("{:02}:{:02}:00", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// Scheduler directives for `flavor`; empty for plain bash.
+pub fn scheduler_header(flavor: ScriptFlavor, job_name: &str, res: &Resources) -> Vec<String> {
+    let gb = res.mem_mb.div_ceil(1024);
+    match flavor {
+        ScriptFlavor::Bash => Vec::new(),
+        ScriptFlavor::Slurm => vec![
+            format!("#SBATCH --job-name={}", job_name),
+            format!("#SBATCH --cpus-per-task={}", res.cpus),
+            format!("#SBATCH --mem={}G", gb),
+            format!("#SBATCH --time={}", res.walltime()),
+            format!("#SBATCH --output={}.%j.log", job_name),
+        ],
+        ScriptFlavor::Pbs => vec![
+            format!("#PBS -N {}", job_name),
+            format!("#PBS -l nodes=1:ppn={}", res.cpus),
+            format!("#PBS -l mem={}gb", gb),
+            format!("#PBS -l walltime={}", res.walltime()),
+            "#PBS -j oe".to_string(),
+        ],
+        ScriptFlavor::Lsf => vec![
+            format!("#BSUB -J {}", job_name),
+            format!("#BSUB -n {}", res.cpus),
+            format!("#BSUB -R \"rusage[mem={}]\"", res.mem_mb),
+            format!("#BSUB -W {:02}:{:02}", res.minutes / 60, res.minutes % 60),
+            format!("#BSUB -o {}.%J.log", job_name),
+        ],
+        ScriptFlavor::Sge => vec![
+            format!("#$ -N {}", job_name),
+            format!("#$ -pe smp {}", res.cpus),
+            // h_vmem is per slot.
+            format!("#$ -l h_vmem={}M", res.mem_mb.div_ceil(res.cpus as u64)),
+            format!("#$ -l h_rt={}", res.walltime()),
+            "#$ -cwd".to_string(),
+            "#$ -j y".to_string(),
+        ],
+    }
+}
+
+/// Bash script that runs `command` inside `output_path`.
+fn bash_script(output_path: &Path, header: &[String], command: &str) -> Result<String> {
+    let dir = shell::quote_path(output_path)?;
+    let mut script = String::from("#!/usr/bin/env bash\n");
+    for line in header {
+        script.push_str(line);
+        script.push('\n');
+    }
+    script.push_str("set -euo pipefail\n");
+    script.push_str(&format!("mkdir -p {}\n", dir));
+    script.push_str(&format!("cd {}\n", dir));
+    script.push_str(command);
+    script.push('\n');
+    Ok(script)
+}
+
+/// Write `scripts/<id>.sh` (bash, with `header` after the shebang). The
+/// fragments and runners are bash too, so there is no PowerShell variant;
+/// on Windows the scripts run under Git Bash or WSL.
+pub fn create_script(
+    output_path: &Path,
+    id: &str,
+    header: &[String],
+    command: &str,
+) -> Result<PathBuf> {
+    let scripts_dir = output_path.join(SCRIPTS_DIR);
+    fs::create_dir_all(&scripts_dir)?;
+    let script_path = scripts_dir.join(format!("{}.sh", id));
+    let text = bash_script(output_path, header, command)?;
+    // Keep an unchanged script's mtime, which make compares with the sentinel.
+    if fs::read_to_string(&script_path).ok().as_deref() != Some(text.as_str()) {
+        fs::write(&script_path, text)?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&script_path)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&script_path, perms)?;
+    }
+    Ok(script_path)
+}
+
+/// `md5sum -c` of `file` against `md5`.
+fn md5_check(md5: &str, file: &str) -> String {
+    format!(
+        "echo {} | md5sum -c -",
+        quote(&format!("{}  {}", md5, file))
+    )
+}
+
+/// `wget` + MD5 check of a run's ENA FASTQ files.
+fn ftp_commands(record: &ProcessedRecord) -> Vec<String> {
+    let mut files = vec![(
+        &record.fastq_ftp_1_url,
+        &record.fastq_ftp_1_name,
+        &record.fastq_md5_1,
+    )];
+    if let (Some(url), Some(name), Some(md5)) = (
+        &record.fastq_ftp_2_url,
+        &record.fastq_ftp_2_name,
+        &record.fastq_md5_2,
+    ) {
+        files.push((url, name, md5));
+    }
+    files
+        .into_iter()
+        .flat_map(|(url, name, md5)| [format!("wget -c {}", quote(url)), md5_check(md5, name)])
+        .collect()
+}
+
+/// Convert `source` (a run accession or `.sra` path) and gzip the FASTQ.
+fn convert_commands(
+    config: &Config,
+    run_id: &str,
+    source: &str,
+    extra_args: &str,
+    cpus: usize,
+) -> Result<Vec<String>> {
+    let mut wrapper = shell::join(&config.priority.prefix());
+    if !wrapper.is_empty() {
+        wrapper.push(' ');
+    }
+    Ok(vec![
+        format!(
+            "{}{} --split-3 -e {}{} -O . -t {} -f {}",
+            wrapper,
+            shell::quote_path(&config.software.fasterq_dump)?,
+            cpus,
+            extra_args,
+            quote(&format!(".fasterq_tmp/{}", run_id)),
+            quote(source)
+        ),
+        format!(
+            "{}\"$(command -v pigz || echo gzip)\" -f {}*.fastq",
+            wrapper,
+            quote(run_id)
+        ),
+    ])
+}
+
+/// Fetch the `.sra` with a resumable `curl` and convert it. With resolved
+/// metadata the exact S3 URL is used and the file is checked against its
+/// MD5; otherwise the AWS Open Data path of the run is tried unchecked.
+fn aws_commands(
+    config: &Config,
+    run_id: &str,
+    metadata: Option<&SraMetadata>,
+    cpus: usize,
+) -> Result<Vec<String>> {
+    let sra = format!("{}/{}.sra", run_id, run_id);
+    let url = metadata
+        .map(|m| m.http_url.clone())
+        .unwrap_or_else(|| format!("{}/{}/{}", SRA_ODP_URL, run_id, run_id));
+    let mut commands = vec![
+        format!("mkdir -p {}", quote(run_id)),
+        format!("curl -fL --retry 5 -C - -o {} {}", quote(&sra), quote(&url)),
+    ];
+    match metadata.and_then(|m| m.md5.as_deref()) {
+        Some(md5) => commands.push(md5_check(md5, &sra)),
+        None => commands.push(format!("# no MD5 published for {}; not verified", run_id)),
+    }
+    commands.extend(convert_commands(config, run_id, &sra, "", cpus)?);
+    Ok(commands)
+}
+
+/// `prefetch` (with the job's credential) and convert.
+fn prefetch_commands(
+    config: &Config,
+    job: &DownloadJob,
+    run_id: &str,
+    cpus: usize,
+) -> Result<Vec<String>> {
+    let mut credential = String::new();
+    if let Some(c) = &job.credential {
+        for arg in c.tool_args() {
+            credential.push(' ');
+            credential.push_str(&shell::quote_os(&arg)?);
+        }
+    }
+    let mut commands = vec![format!(
+        "{} {}{} -O . {}",
+        shell::quote_path(&config.software.prefetch)?,
+        quote(run_id),
+        credential,
+        shell::join(&config.prefetch.args(&job.options.prefetch_max_size))
+    )];
+    commands.extend(convert_commands(config, run_id, run_id, &credential, cpus)?);
+    Ok(commands)
+}
+
+/// `commands` skipped when the run's sentinel exists, and touching it
+/// once they succeeded. Paths are relative to the output directory.
+fn with_sentinel(run_id: &str, commands: Vec<String>) -> Vec<String> {
+    let done_dir = format!("{}/{}", SCRIPTS_DIR, DONE_DIR);
+    let sentinel = quote(&format!("{}/{}.done", done_dir, run_id)).into_owned();
+    let mut wrapped = vec![
+        format!("if [ -e {} ]; then", sentinel),
+        format!(
+            "  echo {}",
+            quote(&format!("{} already done; skipping", run_id))
+        ),
+        "  exit 0".to_string(),
+        "fi".to_string(),
+    ];
+    wrapped.extend(commands);
+    wrapped.push(format!("mkdir -p {}", done_dir));
+    wrapped.push(format!("touch {}", sentinel));
+    wrapped
+}
+
+/// Commands and scheduler request of one run's script.
+struct RunScript {
+    run_id: String,
+    resources: Resources,
+    commands: Vec<String>,
+}
+
+/// Scripts for every planned run. Public runs follow the job's download
+/// method; protected runs use `prefetch` when the job carries a credential.
+/// AWS runs are resolved through NCBI here, so their scripts carry the S3
+/// URL and MD5 the downloader would use.
+async fn plan_run_scripts(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+) -> Result<Vec<RunScript>> {
+    let cpus = job.options.aws_threads.max(1);
+    let mut scripts = Vec::new();
+
+    for record in &plan.processed {
+        let run_id = &record.run_accession;
+        let bytes = record.fastq_bytes_1 + record.fastq_bytes_2.unwrap_or(0);
+        let (commands, bytes, converts) =
+            match job.options.download_method {
+                DownloadMethod::Ftp => (ftp_commands(record), bytes, false),
+                DownloadMethod::Aws => {
+                    let metadata =
+                        match SraUtils::get_metadata_for_format(run_id, job.options.sra_format)
+                            .await?
+                        {
+                            SraLookup::Found(metadata) => Some(metadata),
+                            SraLookup::LiteOnly => {
+                                warn!("[{}] Only SRA Lite is available; no script written", run_id);
+                                continue;
+                            }
+                            SraLookup::NotFound => None,
+                        };
+                    let commands = aws_commands(config, run_id, metadata.as_ref(), cpus)?;
+                    (commands, metadata.map_or(bytes, |m| m.size), true)
+                }
+                DownloadMethod::Ega => {
+                    return Err(anyhow!("--only-scripts does not support EGA downloads"));
+                }
+                DownloadMethod::Mock => {
+                    return Err(anyhow!("--only-scripts does not support mock downloads"));
+                }
+            };
+        scripts.push(RunScript {
+            run_id: run_id.clone(),
+            resources: Resources::estimate(bytes, cpus, converts),
+            commands: with_sentinel(run_id, commands),
+        });
+    }
+
+    if job.credential.is_some() {
+        for run_id in &plan.protected_runs {
+            // Protected runs have no published size; request a day.
+            scripts.push(RunScript {
+                run_id: run_id.clone(),
+                resources: Resources {
+                    minutes: 24 * 60,
+                    ..Resources::estimate(0, cpus, true)
+                },
+                commands: with_sentinel(run_id, prefetch_commands(config, job, run_id, cpus)?),
+            });
+        }
+    }
+    Ok(scripts)
+}
+
+/// Write one script per planned run and return their paths.
+pub async fn write_scripts(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    flavor: ScriptFlavor,
+) -> Result<Vec<PathBuf>> {
+    plan_run_scripts(config, job, plan)
+        .await?
+        .iter()
+        .map(|script| {
+            let header = scheduler_header(flavor, &script.run_id, &script.resources);
+            create_script(
+                job.output(),
+                &script.run_id,
+                &header,
+                &script.commands.join("\n"),
+            )
+        })
+        .collect()
+}
+
+/// Write all runs into one [`COMBINED_SCRIPT`], each run's commands in
+/// its own `bash -euo pipefail` so a failed run does not stop the rest.
+/// The script exits non-zero when any run failed; its scheduler header
+/// requests the sum of the runs' walltimes.
+pub async fn write_combined_script(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    flavor: ScriptFlavor,
+) -> Result<PathBuf> {
+    let runs = plan_run_scripts(config, job, plan).await?;
+    let resources = Resources {
+        cpus: runs.first().map_or(1, |s| s.resources.cpus),
+        mem_mb: runs.iter().map(|s| s.resources.mem_mb).max().unwrap_or(0),
+        minutes: runs.iter().map(|s| s.resources.minutes).sum(),
+    };
+    let name = job.options.accession.as_deref().unwrap_or("polariseq");
+    let mut body = vec!["failed=0".to_string()];
+    for script in &runs {
+        body.push(format!("echo \"== {}\"", script.run_id));
+        body.push("if ! bash -euo pipefail <<'POLARISEQ_RUN'".to_string());
+        body.extend(script.commands.iter().cloned());
+        body.push("POLARISEQ_RUN".to_string());
+        body.push("then".to_string());
+        body.push(format!("  echo \"{} failed\" >&2", script.run_id));
+        body.push("  failed=$((failed + 1))".to_string());
+        body.push("fi".to_string());
+    }
+    body.push("if [ \"$failed\" -gt 0 ]; then".to_string());
+    body.push(format!(
+        "  echo \"$failed of {} run(s) failed\" >&2",
+        runs.len()
+    ));
+    body.push("  exit 1".to_string());
+    body.push("fi".to_string());
+
+    let scripts_dir = job.output().join(SCRIPTS_DIR);
+    fs::create_dir_all(&scripts_dir)?;
+    let script = scripts_dir.join(COMBINED_SCRIPT);
+    let header = scheduler_header(flavor, name, &resources);
+    fs::write(
+        &script,
+        bash_script(job.output(), &header, &body.join("\n"))?,
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(script)
+}
+
+/// Write `runner` for the scripts of `runs` under `output`. Both resume,
+/// as finished scripts exit early on their sentinel: [`RUN_ALL_SCRIPT`]
+/// also keeps a GNU parallel job log, and the [`MAKEFILE`] has the
+/// sentinels as targets, so make does not even start finished runs.
+pub fn write_runner(
+    output: &Path,
+    runner: ScriptRunner,
+    runs: &[String],
+    jobs: usize,
+) -> Result<PathBuf> {
+    let scripts_dir = output.join(SCRIPTS_DIR);
+    fs::create_dir_all(&scripts_dir)?;
+    let jobs = jobs.max(1);
+    let (path, text) = match runner {
+        ScriptRunner::Parallel => {
+            let text = [
+                "#!/usr/bin/env bash".to_string(),
+                "set -euo pipefail".to_string(),
+                "cd \"$(dirname \"$0\")\"".to_string(),
+                format!("RUNS=({})", shell::join(runs)),
+                "if command -v parallel >/dev/null; then".to_string(),
+                format!(
+                    "  printf '%s\\n' \"${{RUNS[@]}}\" | parallel --jobs {} --joblog run_all.joblog --resume-failed bash {{}}.sh",
+                    jobs
+                ),
+                "else".to_string(),
+                "  echo 'GNU parallel not found; using xargs' >&2".to_string(),
+                format!(
+                    "  printf '%s\\n' \"${{RUNS[@]}}\" | xargs -P {} -I{{}} bash {{}}.sh",
+                    jobs
+                ),
+                "fi".to_string(),
+            ];
+            (scripts_dir.join(RUN_ALL_SCRIPT), text.join("\n"))
+        }
+        ScriptRunner::Make => {
+            let text = [
+                format!("# Run with `make -j{}`; finished runs are skipped.", jobs),
+                "SHELL := bash".to_string(),
+                format!("RUNS := {}", runs.join(" ")),
+                String::new(),
+                "all: $(RUNS)".to_string(),
+                format!("$(RUNS): %: {}/%.done", DONE_DIR),
+                format!("{}/%.done: %.sh", DONE_DIR),
+                "\tbash $<".to_string(),
+                // A script that skipped on an older sentinel leaves it as is.
+                format!("\t@mkdir -p {} && touch $@", DONE_DIR),
+                String::new(),
+                ".PHONY: all $(RUNS)".to_string(),
+            ];
+            (scripts_dir.join(MAKEFILE), text.join("\n"))
+        }
+    };
+    fs::write(&path, format!("{}\n", text))?;
+    #[cfg(unix)]
+    if runner == ScriptRunner::Parallel {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+/// Bash script text per planned run, as `(run, script)`, working in
+/// `output_path` instead of the job's output directory. Used to run the
+/// same commands on another machine.
+pub async fn bash_scripts(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    output_path: &Path,
+) -> Result<Vec<(String, String)>> {
+    plan_run_scripts(config, job, plan)
+        .await?
+        .into_iter()
+        .map(|script| {
+            let text = bash_script(output_path, &[], &script.commands.join("\n"))?;
+            Ok((script.run_id, text))
+        })
+        .collect()
+}
+
+/// Scheduler that `download --submit` targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SubmitTarget {
+    Slurm,
+}
+
+/// Files of a written SLURM job array.
+#[derive(Debug, Clone)]
+pub struct SlurmArray {
+    /// Parameterised array script (`scripts/array.sh`).
+    pub script: PathBuf,
+    /// One run accession per line; task `i` runs line `i`.
+    pub index: PathBuf,
+    pub tasks: usize,
+    pub throttle: usize,
+}
+
+impl SlurmArray {
+    /// The `sbatch` invocation that submits the array.
+    pub fn sbatch_command(&self) -> String {
+        format!("sbatch {}", quote(&self.script.to_string_lossy()))
+    }
+
+    /// Submit with `sbatch --parsable` and return the job id.
+    pub fn submit(&self) -> Result<String> {
+        let output = crate::audit::output_blocking(
+            std::process::Command::new("sbatch")
+                .arg("--parsable")
+                .arg(&self.script),
+        )
+        .context("Failed to run sbatch")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "sbatch failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        // --parsable prints `jobid[;cluster]`.
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout
+            .trim()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .to_string())
+    }
+}
+
+/// Write the per-run scripts, an index of run accessions and one SLURM
+/// array script whose task `i` runs the script of index line `i`, with
+/// at most `throttle` tasks at once. The array requests the largest
+/// memory and walltime of any run.
+pub async fn write_slurm_array(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    throttle: usize,
+) -> Result<SlurmArray> {
+    let runs = plan_run_scripts(config, job, plan).await?;
+    if runs.is_empty() {
+        return Err(anyhow!("No runs to submit"));
+    }
+    let output = job.output();
+    for script in &runs {
+        create_script(output, &script.run_id, &[], &script.commands.join("\n"))?;
+    }
+
+    let scripts_dir = output.join(SCRIPTS_DIR);
+    let index = scripts_dir.join(ARRAY_INDEX);
+    let ids: Vec<&str> = runs.iter().map(|s| s.run_id.as_str()).collect();
+    fs::write(&index, format!("{}\n", ids.join("\n")))?;
+
+    let resources = Resources {
+        cpus: runs[0].resources.cpus,
+        mem_mb: runs.iter().map(|s| s.resources.mem_mb).max().unwrap_or(0),
+        minutes: runs.iter().map(|s| s.resources.minutes).max().unwrap_or(0),
+    };
+    let name = job.options.accession.as_deref().unwrap_or("polariseq");
+    let throttle = throttle.max(1);
+    let mut header = scheduler_header(ScriptFlavor::Slurm, name, &resources);
+    header.retain(|line| !line.starts_with("#SBATCH --output="));
+    header.push(format!("#SBATCH --array=1-{}%{}", runs.len(), throttle));
+    header.push(format!(
+        "#SBATCH --output={}",
+        shell::quote_path(&scripts_dir.join("%x.%A_%a.log"))?
+    ));
+
+    let script = scripts_dir.join(ARRAY_SCRIPT);
+    let mut file = File::create(&script)?;
+    writeln!(file, "#!/usr/bin/env bash")?;
+    for line in &header {
+        writeln!(file, "{}", line)?;
+    }
+    writeln!(file, "set -euo pipefail")?;
+    writeln!(
+        file,
+        "RUN=$(sed -n \"${{SLURM_ARRAY_TASK_ID}}p\" {})",
+        shell::quote_path(&index)?
+    )?;
+    writeln!(
+        file,
+        "exec bash {}/\"$RUN\".sh",
+        shell::quote_path(&scripts_dir)?
+    )?;
+
+    Ok(SlurmArray {
+        script,
+        index,
+        tasks: runs.len(),
+        throttle,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_and_renders_scheduler_headers() {
+        let res = Resources::estimate(12 * 1024 * 1024 * 1024, 8, true);
+        assert_eq!(res.mem_mb, 8192);
+        // 12 GiB at 10 MB/s ≈ 20 min, doubled for conversion, plus 30.
+        assert_eq!(res.minutes, 30 + 20 * 2);
+
+        let slurm = scheduler_header(ScriptFlavor::Slurm, "SRR1", &res);
+        assert!(slurm.contains(&"#SBATCH --job-name=SRR1".to_string()));
+        assert!(slurm.contains(&"#SBATCH --cpus-per-task=8".to_string()));
+        assert!(slurm.contains(&"#SBATCH --mem=8G".to_string()));
+        assert!(slurm.contains(&"#SBATCH --time=01:10:00".to_string()));
+
+        let sge = scheduler_header(ScriptFlavor::Sge, "SRR1", &res);
+        assert!(sge.contains(&"#$ -l h_vmem=1024M".to_string()));
+        assert!(scheduler_header(ScriptFlavor::Bash, "SRR1", &res).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn writes_ftp_scripts_with_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: f\n").unwrap();
+        let job = DownloadJob::accession("PRJNA1", dir.path()).method(DownloadMethod::Ftp);
+        let plan = JobPlan {
+            processed: vec![ProcessedRecord {
+                run_accession: "SRR1".into(),
+                fastq_ftp_1_url: "ftp://x/SRR1_1.fastq.gz".into(),
+                fastq_ftp_1_name: "SRR1_1.fastq.gz".into(),
+                fastq_md5_1: "abc".into(),
+                fastq_ftp_2_url: None,
+                fastq_ftp_2_name: None,
+                fastq_md5_2: None,
+                fastq_bytes_1: 10,
+                fastq_bytes_2: None,
+                sample_title: String::new(),
+            }],
+            ..JobPlan::default()
+        };
+
+        let scripts = write_scripts(&config, &job, &plan, ScriptFlavor::Pbs)
+            .await
+            .unwrap();
+        let text = fs::read_to_string(&scripts[0]).unwrap();
+        assert!(scripts[0].ends_with("scripts/SRR1.sh"));
+        assert!(text.starts_with("#!/usr/bin/env bash\n#PBS -N SRR1\n"));
+        assert!(text.contains(
+            "wget -c ftp://x/SRR1_1.fastq.gz\necho 'abc  SRR1_1.fastq.gz' | md5sum -c -"
+        ));
+        assert!(text.ends_with("mkdir -p scripts/done\ntouch scripts/done/SRR1.done\n"));
+        // A finished run is skipped without touching the network.
+        fs::create_dir_all(dir.path().join("scripts/done")).unwrap();
+        fs::write(dir.path().join("scripts/done/SRR1.done"), b"").unwrap();
+        let status = std::process::Command::new("bash")
+            .arg(&scripts[0])
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        // Rewriting an unchanged script keeps it as is.
+        let modified = fs::metadata(&scripts[0]).unwrap().modified().unwrap();
+        write_scripts(&config, &job, &plan, ScriptFlavor::Pbs)
+            .await
+            .unwrap();
+        let again = fs::metadata(&scripts[0]).unwrap().modified().unwrap();
+        assert_eq!(modified, again);
+
+        let combined = write_combined_script(&config, &job, &plan, ScriptFlavor::Bash)
+            .await
+            .unwrap();
+        let text = fs::read_to_string(&combined).unwrap();
+        assert!(combined.ends_with("scripts/all_runs.sh"));
+        assert!(text.contains(
+            "if ! bash -euo pipefail <<'POLARISEQ_RUN'\nif [ -e scripts/done/SRR1.done ]; then\n"
+        ));
+        assert!(text.contains("POLARISEQ_RUN\nthen\n  echo \"SRR1 failed\" >&2\n"));
+
+        let runs = vec!["SRR1".to_string(), "SRR2".to_string()];
+        let run_all = write_runner(dir.path(), ScriptRunner::Parallel, &runs, 8).unwrap();
+        let text = fs::read_to_string(&run_all).unwrap();
+        assert!(text.contains("RUNS=(SRR1 SRR2)\n"));
+        assert!(text.contains("parallel --jobs 8 --joblog run_all.joblog --resume-failed"));
+        let makefile = write_runner(dir.path(), ScriptRunner::Make, &runs, 8).unwrap();
+        let text = fs::read_to_string(&makefile).unwrap();
+        assert!(makefile.ends_with("scripts/Makefile"));
+        assert!(text.contains("done/%.done: %.sh\n\tbash $<\n"));
+    }
+
+    #[tokio::test]
+    async fn writes_throttled_slurm_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: f\n").unwrap();
+        let job = DownloadJob::accession("PRJNA1", dir.path()).method(DownloadMethod::Ftp);
+        let record = |run: &str| ProcessedRecord {
+            run_accession: run.into(),
+            fastq_ftp_1_url: format!("ftp://x/{}.fastq.gz", run),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: format!("{}.fastq.gz", run),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: "abc".into(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 10,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        };
+        let plan = JobPlan {
+            processed: vec![record("SRR1"), record("SRR2")],
+            ..JobPlan::default()
+        };
+
+        let array = write_slurm_array(&config, &job, &plan, 20).await.unwrap();
+        assert_eq!(array.tasks, 2);
+        assert_eq!(fs::read_to_string(&array.index).unwrap(), "SRR1\nSRR2\n");
+        let text = fs::read_to_string(&array.script).unwrap();
+        assert!(text.contains("#SBATCH --job-name=PRJNA1\n"));
+        assert!(text.contains("#SBATCH --array=1-2%20\n"));
+        assert!(dir.path().join("scripts/SRR2.sh").exists());
+    }
+
+    #[test]
+    fn aws_scripts_check_the_resolved_md5() {
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: f\n").unwrap();
+        let metadata = SraMetadata {
+            s3_uri: "s3://bucket/SRR1".into(),
+            http_url: "https://bucket.s3.amazonaws.com/SRR1".into(),
+            md5: Some("abc".into()),
+            size: 1,
+        };
+        let commands = aws_commands(&config, "SRR1", Some(&metadata), 4).unwrap();
+        assert_eq!(
+            commands[1],
+            "curl -fL --retry 5 -C - -o SRR1/SRR1.sra https://bucket.s3.amazonaws.com/SRR1"
+        );
+        assert_eq!(commands[2], "echo 'abc  SRR1/SRR1.sra' | md5sum -c -");
+        assert!(commands[3].starts_with("f --split-3 -e 4 "));
+
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: /opt/sra tools/f\n")
+                .unwrap();
+        let commands = aws_commands(&config, "SRR1", None, 4).unwrap();
+        assert!(commands[3].starts_with("'/opt/sra tools/f' --split-3 "));
+    }
+}
@@ -0,0 +1,77 @@
+//! Quoting of words in generated shell scripts.
+//!
+//! Output directories, tool paths and file names end up in the scripts of
+//! `--only-scripts`, `--submit` and `--remote`. Any of them may contain
+//! spaces, parentheses or quotes, so every word goes through [`quote`];
+//! paths that no quoting can carry through a line-based script (newlines,
+//! other control characters) or that are not UTF-8 are rejected.
+
+use anyhow::{anyhow, Result};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::Path;
+
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c)
+}
+
+/// `word` as one POSIX shell word: as is when it only has safe characters,
+/// else in single quotes with each `'` written as `'\''`.
+pub fn quote(word: &str) -> Cow<'_, str> {
+    if !word.is_empty() && word.chars().all(is_safe) {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+    }
+}
+
+/// [`quote`] of a path or tool argument, which must be UTF-8 without
+/// control characters.
+pub fn quote_os(word: &OsStr) -> Result<String> {
+    let text = word
+        .to_str()
+        .ok_or_else(|| anyhow!("{:?} is not valid UTF-8", word))?;
+    if text.chars().any(char::is_control) {
+        return Err(anyhow!("{:?} contains a control character", text));
+    }
+    Ok(quote(text).into_owned())
+}
+
+/// [`quote_os`] of a path.
+pub fn quote_path(path: &Path) -> Result<String> {
+    quote_os(path.as_os_str())
+}
+
+/// `words` quoted and joined with spaces.
+pub fn join<S: AsRef<str>>(words: &[S]) -> String {
+    words
+        .iter()
+        .map(|word| quote(word.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_unsafe_words() {
+        assert_eq!(quote("SRR1/SRR1.sra"), "SRR1/SRR1.sra");
+        assert_eq!(
+            quote("https://x.org/a?b=1&c=2"),
+            "'https://x.org/a?b=1&c=2'"
+        );
+        assert_eq!(quote("/data/my run (2)"), "'/data/my run (2)'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(join(&["nice", "-n", "10"]), "nice -n 10");
+
+        assert_eq!(
+            quote_path(Path::new("/opt/sra tools/fasterq-dump")).unwrap(),
+            "'/opt/sra tools/fasterq-dump'"
+        );
+        assert!(quote_path(Path::new("/data/a\nb")).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! User commands run before each download (`--on-start`).
+//!
+//! Each command runs through `sh -c` once the run has a download slot and
+//! before any backend touches it, e.g. to stage scratch space or register
+//! the run in a tracking database. The run's ENA metadata is exported as
+//! `POLARISEQ_<FIELD>` variables (`POLARISEQ_RUN_ACCESSION`,
+//! `POLARISEQ_SAMPLE_ACCESSION`, `POLARISEQ_FASTQ_FTP`, ...) next to
+//! `POLARISEQ_RUN` and `POLARISEQ_OUTPUT`. A command exiting non-zero fails
+//! the run before anything is downloaded.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::{run_cancellable, EnaRecord};
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Runs `--on-start` commands for every run of a plan.
+pub struct ShellHook {
+    on_start: Vec<String>,
+    /// Environment of each run, by run accession.
+    metadata: HashMap<String, Vec<(String, String)>>,
+}
+
+impl ShellHook {
+    pub fn new(on_start: Vec<String>, records: &[EnaRecord]) -> Self {
+        Self {
+            on_start,
+            metadata: records
+                .iter()
+                .map(|r| (r.run_accession.clone(), metadata_env(r)))
+                .collect(),
+        }
+    }
+}
+
+/// `record` as `POLARISEQ_<FIELD>` variables; empty fields are exported
+/// as empty strings so commands can test them with `-n`.
+pub fn metadata_env(record: &EnaRecord) -> Vec<(String, String)> {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(record) else {
+        return Vec::new();
+    };
+    fields
+        .into_iter()
+        .map(|(field, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (format!("POLARISEQ_{}", field.to_ascii_uppercase()), value)
+        })
+        .collect()
+}
+
+/// Run `command` with `env` added to the environment.
+async fn run_command(
+    command: &str,
+    env: &[(String, String)],
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let output = run_cancellable(
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().map(|(k, v)| (k, v))),
+        cancel,
+    )
+    .await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "'{}' exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl RunHook for ShellHook {
+    fn name(&self) -> &'static str {
+        "on-start"
+    }
+
+    fn before_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut env = self.metadata.get(run_id).cloned().unwrap_or_default();
+            env.push(("POLARISEQ_RUN".to_string(), run_id.to_string()));
+            env.push((
+                "POLARISEQ_OUTPUT".to_string(),
+                ctx.options.output.display().to_string(),
+            ));
+            for command in &self.on_start {
+                run_command(command, &env, &ctx.cancel).await?;
+            }
+            if !self.on_start.is_empty() {
+                info!(
+                    "[{}] Ran {} --on-start command(s)",
+                    run_id,
+                    self.on_start.len()
+                );
+            }
+            Ok(())
+        })
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        _run_id: &'a str,
+        _files: &'a [PathBuf],
+        _ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_see_the_run_metadata() {
+        let record: EnaRecord = serde_json::from_value(serde_json::json!({
+            "run_accession": "SRR1",
+            "sample_accession": "SAMN1",
+        }))
+        .unwrap();
+        let env = metadata_env(&record);
+        assert!(env.contains(&("POLARISEQ_SAMPLE_ACCESSION".into(), "SAMN1".into())));
+        assert!(env.contains(&("POLARISEQ_STUDY_ACCESSION".into(), String::new())));
+
+        let cancel = CancellationToken::new();
+        run_command(r#"test "$POLARISEQ_RUN_ACCESSION" = SRR1"#, &env, &cancel)
+            .await
+            .unwrap();
+        let err = run_command("echo scratch full >&2; exit 3", &env, &cancel)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("scratch full"), "{}", err);
+    }
+}
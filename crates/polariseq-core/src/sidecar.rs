@@ -0,0 +1,201 @@
+//! `<run>.info.json` next to each downloaded run (`download --info-json`).
+//!
+//! The sidecar holds the run's full ENA record and how its files were
+//! fetched (backend, source, time, polariseq version), so files copied
+//! away from the output directory, e.g. by `--dest`, keep their metadata.
+//! It is written before the `--dest` hook runs and travels with the run.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::client::{DownloadJob, JobPlan};
+use crate::manifest::file_source;
+use crate::{overrides, EnaRecord, ProcessedRecord};
+use anyhow::{Context, Result};
+use chrono::Local;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the sidecar after the run accession.
+pub const INFO_SUFFIX: &str = ".info.json";
+
+/// One downloaded file of the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfoFile {
+    pub name: String,
+    pub bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// Contents of `<run>.info.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    /// `polariseq <version>`.
+    pub generator: String,
+    pub run_accession: String,
+    /// Absent for controlled-access runs, which have no ENA record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<EnaRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub files: Vec<InfoFile>,
+    pub downloaded_at: String,
+}
+
+impl RunInfo {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("Invalid {}", path.display()))
+    }
+}
+
+/// Sidecar of `run_id` in `dir`.
+pub fn info_path(dir: &Path, run_id: &str) -> PathBuf {
+    dir.join(format!("{}{}", run_id, INFO_SUFFIX))
+}
+
+/// Writes `<run>.info.json` into the directory of every finished run.
+pub struct InfoJsonHook {
+    job: DownloadJob,
+    records: HashMap<String, EnaRecord>,
+    processed: HashMap<String, ProcessedRecord>,
+}
+
+impl InfoJsonHook {
+    pub fn new(job: &DownloadJob, plan: &JobPlan) -> Self {
+        Self {
+            job: job.clone(),
+            records: plan
+                .records
+                .iter()
+                .map(|r| (r.run_accession.clone(), r.clone()))
+                .collect(),
+            processed: plan
+                .processed
+                .iter()
+                .map(|r| (r.run_accession.clone(), r.clone()))
+                .collect(),
+        }
+    }
+
+    /// Sidecar contents for `files` of `run_id`, fetched by `backend`.
+    pub async fn run_info(
+        &self,
+        run_id: &str,
+        files: &[PathBuf],
+        backend: Option<&str>,
+    ) -> Result<RunInfo> {
+        let mut entries = Vec::new();
+        for file in files {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            if name.ends_with(INFO_SUFFIX) {
+                continue;
+            }
+            let source = file_source(
+                self.job.output(),
+                &self.job,
+                self.processed.get(run_id),
+                run_id,
+                backend,
+                &name,
+            )
+            .await;
+            entries.push(InfoFile {
+                bytes: tokio::fs::metadata(file).await?.len(),
+                name: name.into_owned(),
+                source,
+            });
+        }
+        Ok(RunInfo {
+            generator: format!("polariseq {}", env!("CARGO_PKG_VERSION")),
+            run_accession: run_id.to_string(),
+            record: self.records.get(run_id).cloned(),
+            backend: backend.map(str::to_string),
+            files: entries,
+            downloaded_at: Local::now().to_rfc3339(),
+        })
+    }
+}
+
+impl RunHook for InfoJsonHook {
+    fn name(&self) -> &'static str {
+        "info-json"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let info = self.run_info(run_id, files, ctx.backend).await?;
+            let dir = overrides::output_for(&ctx.options.output, &ctx.output_subdirs, run_id);
+            let path = info_path(&dir, run_id);
+            let tmp = path.with_extension("json.tmp");
+            tokio::fs::write(&tmp, serde_json::to_vec_pretty(&info)?).await?;
+            tokio::fs::rename(&tmp, &path)
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadMethod;
+
+    #[tokio::test]
+    async fn describes_a_run_with_its_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let record: EnaRecord = serde_json::from_value(serde_json::json!({
+            "run_accession": "SRR1",
+            "library_layout": "SINGLE",
+        }))
+        .unwrap();
+        let plan = JobPlan {
+            records: vec![record],
+            processed: vec![ProcessedRecord {
+                run_accession: "SRR1".into(),
+                fastq_ftp_1_url: "ftp.sra.ebi.ac.uk/vol1/SRR1.fastq.gz".into(),
+                fastq_ftp_2_url: None,
+                fastq_ftp_1_name: "SRR1.fastq.gz".into(),
+                fastq_ftp_2_name: None,
+                fastq_md5_1: "abc".into(),
+                fastq_md5_2: None,
+                fastq_bytes_1: 4,
+                fastq_bytes_2: None,
+                sample_title: String::new(),
+            }],
+            ..JobPlan::default()
+        };
+        let job = DownloadJob::accession("PRJNA1", dir.path()).method(DownloadMethod::Ftp);
+        let fastq = dir.path().join("SRR1.fastq.gz");
+        std::fs::write(&fastq, b"data").unwrap();
+        let sidecar = info_path(dir.path(), "SRR1");
+        std::fs::write(&sidecar, b"{}").unwrap();
+
+        let hook = InfoJsonHook::new(&job, &plan);
+        let info = hook
+            .run_info("SRR1", &[fastq, sidecar.clone()], Some("ftp"))
+            .await
+            .unwrap();
+        assert_eq!(info.files.len(), 1);
+        assert_eq!(info.files[0].bytes, 4);
+        assert_eq!(
+            info.files[0].source.as_deref(),
+            Some("http://ftp.sra.ebi.ac.uk/vol1/SRR1.fastq.gz")
+        );
+        let record = info.record.as_ref().unwrap();
+        assert_eq!(record.library_layout.as_deref(), Some("SINGLE"));
+
+        std::fs::write(&sidecar, serde_json::to_vec(&info).unwrap()).unwrap();
+        assert_eq!(
+            RunInfo::load(&sidecar).unwrap().backend.as_deref(),
+            Some("ftp")
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Speed floor per download attempt (`--min-speed`, `--min-speed-window`).
+//!
+//! A congested route can keep a transfer crawling for days. With a floor
+//! set, each backend attempt of a run counts the bytes its transfers report
+//! to the progress sink; once transfers have run for a whole window below
+//! the floor on average, the attempt is cancelled and the run falls back to
+//! the next backend of its chain like after any other failure. Time without
+//! a running transfer (conversion, compression, hooks) does not count.
+
+use crate::progress::{start_progress, ProgressHandle, ProgressKind, ProgressSink};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// The floor: at least `bytes_per_sec` on average over `window_secs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MinSpeed {
+    pub bytes_per_sec: u64,
+    pub window_secs: u64,
+}
+
+/// Parse `90`, `90s`, `5m` or `1h` into seconds.
+pub fn parse_window(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor = match &text[digits.len()..] {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        unit => return Err(anyhow!("unknown window unit '{}' (use s, m or h)", unit)),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * factor),
+        _ => Err(anyhow!("invalid window '{}'", text)),
+    }
+}
+
+/// Bytes transferred during one attempt, fed by [`SpeedWatch::sink`].
+pub(crate) struct SpeedWatch {
+    floor: MinSpeed,
+    bytes: AtomicU64,
+    /// Transfers started and not yet finished.
+    active: AtomicUsize,
+    slow: AtomicBool,
+}
+
+impl SpeedWatch {
+    pub(crate) fn new(floor: MinSpeed) -> Arc<Self> {
+        Arc::new(Self {
+            floor,
+            bytes: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+            slow: AtomicBool::new(false),
+        })
+    }
+
+    /// `inner`, with transfer handles also counted here.
+    pub(crate) fn sink(
+        self: &Arc<Self>,
+        inner: Option<Arc<dyn ProgressSink>>,
+    ) -> Arc<dyn ProgressSink> {
+        Arc::new(SpeedSink {
+            watch: self.clone(),
+            inner,
+        })
+    }
+
+    /// Whether the watch cancelled its attempt for being too slow.
+    pub(crate) fn is_slow(&self) -> bool {
+        self.slow.load(Ordering::Relaxed)
+    }
+
+    /// Cancel `attempt` once transfers stay below the floor for a window.
+    pub(crate) async fn watch(self: Arc<Self>, attempt: CancellationToken) {
+        let mut window = Window::default();
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = attempt.cancelled() => return,
+                _ = tick.tick() => {}
+            }
+            let active = self.active.load(Ordering::Relaxed) > 0;
+            let bytes = self.bytes.load(Ordering::Relaxed);
+            if window.too_slow(self.floor, Instant::now(), bytes, active) {
+                self.slow.store(true, Ordering::Relaxed);
+                attempt.cancel();
+                return;
+            }
+        }
+    }
+}
+
+/// Start of the current measuring window and the byte count then.
+#[derive(Default)]
+struct Window {
+    start: Option<(Instant, u64)>,
+}
+
+impl Window {
+    /// Record `bytes` at `now`; true once a full window stayed below the
+    /// floor. A window restarts after each pass and whenever no transfer
+    /// is running.
+    fn too_slow(&mut self, floor: MinSpeed, now: Instant, bytes: u64, active: bool) -> bool {
+        if !active {
+            self.start = None;
+            return false;
+        }
+        let (start, start_bytes) = *self.start.get_or_insert((now, bytes));
+        let elapsed = now.duration_since(start);
+        if elapsed < Duration::from_secs(floor.window_secs) {
+            return false;
+        }
+        let rate = bytes.saturating_sub(start_bytes) as f64 / elapsed.as_secs_f64().max(1.0);
+        self.start = Some((now, bytes));
+        rate < floor.bytes_per_sec as f64
+    }
+}
+
+struct SpeedSink {
+    watch: Arc<SpeedWatch>,
+    inner: Option<Arc<dyn ProgressSink>>,
+}
+
+impl ProgressSink for SpeedSink {
+    fn start(&self, id: &str, kind: ProgressKind, total: u64) -> Arc<dyn ProgressHandle> {
+        let inner = start_progress(self.inner.as_ref(), id, kind, total);
+        if kind != ProgressKind::Transfer {
+            return inner;
+        }
+        self.watch.active.fetch_add(1, Ordering::Relaxed);
+        Arc::new(SpeedHandle {
+            watch: self.watch.clone(),
+            // The first position is where a resumed transfer starts.
+            last: AtomicU64::new(u64::MAX),
+            done: AtomicBool::new(false),
+            inner,
+        })
+    }
+}
+
+struct SpeedHandle {
+    watch: Arc<SpeedWatch>,
+    last: AtomicU64,
+    done: AtomicBool,
+    inner: Arc<dyn ProgressHandle>,
+}
+
+impl SpeedHandle {
+    fn end(&self) {
+        if !self.done.swap(true, Ordering::Relaxed) {
+            self.watch.active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for SpeedHandle {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+impl ProgressHandle for SpeedHandle {
+    fn set_position(&self, bytes: u64) {
+        let last = self.last.swap(bytes, Ordering::Relaxed);
+        if last != u64::MAX {
+            let delta = bytes.saturating_sub(last);
+            self.watch.bytes.fetch_add(delta, Ordering::Relaxed);
+        }
+        self.inner.set_position(bytes);
+    }
+    fn inc(&self, bytes: u64) {
+        self.watch.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.inc(bytes);
+    }
+    fn set_message(&self, message: &str) {
+        self.inner.set_message(message);
+    }
+    fn println(&self, line: &str) {
+        self.inner.println(line);
+    }
+    fn finish(&self) {
+        self.end();
+        self.inner.finish();
+    }
+    fn fail(&self, reason: &str) {
+        self.end();
+        self.inner.fail(reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_windows_trip_the_floor() {
+        assert_eq!(parse_window("60s").unwrap(), 60);
+        assert_eq!(parse_window("5m").unwrap(), 300);
+        assert!(parse_window("5d").is_err());
+
+        let floor = MinSpeed {
+            bytes_per_sec: 1000,
+            window_secs: 10,
+        };
+        let watch = SpeedWatch::new(floor);
+        let sink = watch.sink(None);
+        let handle = sink.start("SRR1_1.fastq.gz", ProgressKind::Transfer, 0);
+        handle.set_position(50_000);
+        handle.set_position(55_000);
+        assert_eq!(watch.bytes.load(Ordering::Relaxed), 5_000);
+        assert_eq!(watch.active.load(Ordering::Relaxed), 1);
+        drop(handle);
+        assert_eq!(watch.active.load(Ordering::Relaxed), 0);
+
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
+        let mut window = Window::default();
+        assert!(!window.too_slow(floor, at(0), 0, true));
+        // 12 kB in 10 s passes; the next window starts there.
+        assert!(!window.too_slow(floor, at(10), 12_000, true));
+        // Idle time between transfers resets the window.
+        assert!(!window.too_slow(floor, at(15), 12_000, false));
+        assert!(!window.too_slow(floor, at(16), 12_000, true));
+        assert!(window.too_slow(floor, at(26), 15_000, true));
+    }
+}
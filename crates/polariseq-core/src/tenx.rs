@@ -0,0 +1,247 @@
+//! CellRanger names for 10x Genomics runs (`download --tenx-names`).
+//!
+//! Each file's role comes from the length of its first read: reads of at
+//! most [`MAX_INDEX_LEN`] bases are sample indexes (`I1`, `I2` in file
+//! order), and of the two remaining files the shorter (barcode + UMI) is
+//! `R1` and the longer `R2`. A run counts as 10x when it has three or four
+//! FASTQ files including an index, or just the two reads with an `R1` of
+//! [`BARCODE_UMI_LEN`] bases.
+//!
+//! Recognised runs are hard-linked as
+//! `cellranger/<sample>/<sample>_S1_L00<lane>_<role>_001.fastq.gz`, the
+//! lanes numbering the sample's runs in plan order, so `cellranger count
+//! --fastqs cellranger/<sample>` works while the downloaded files stay in
+//! place for verification and resume. ENA and `fasterq-dump` usually ship
+//! only the two biological reads; index reads are named when present.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::trim;
+use crate::EnaRecord;
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Longest read treated as a sample index.
+pub const MAX_INDEX_LEN: usize = 12;
+
+/// `R1` length of 10x 3' and 5' kits: 16 bp barcode plus 10 or 12 bp UMI.
+pub const BARCODE_UMI_LEN: RangeInclusive<usize> = 26..=28;
+
+/// Directory under the output that holds the renamed links.
+pub const TENX_DIR: &str = "cellranger";
+
+/// Length of the first read in `path`, gzipped or not.
+fn first_read_len(path: &Path) -> Result<usize> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut lines = BufReader::new(reader).lines();
+    lines.next().transpose()?;
+    Ok(lines
+        .next()
+        .transpose()?
+        .unwrap_or_default()
+        .trim_end()
+        .len())
+}
+
+/// CellRanger role of each file from its read length, or `None` if the
+/// lengths don't look like a 10x run.
+fn assign_roles(lengths: &[usize]) -> Option<Vec<&'static str>> {
+    let indexes = lengths.iter().filter(|&&l| l <= MAX_INDEX_LEN).count();
+    if lengths.len() - indexes != 2 || indexes > 2 {
+        return None;
+    }
+    let reads: Vec<usize> = lengths
+        .iter()
+        .copied()
+        .filter(|&l| l > MAX_INDEX_LEN)
+        .collect();
+    // Shorter read is R1; on a tie, file order decides.
+    let r1_first = reads[0] <= reads[1];
+    if indexes == 0 && !BARCODE_UMI_LEN.contains(&reads[0].min(reads[1])) {
+        return None;
+    }
+    let (mut index, mut read) = (0, 0);
+    let roles = lengths
+        .iter()
+        .map(|&l| {
+            if l <= MAX_INDEX_LEN {
+                index += 1;
+                if index == 1 {
+                    "I1"
+                } else {
+                    "I2"
+                }
+            } else {
+                read += 1;
+                if (read == 1) == r1_first {
+                    "R1"
+                } else {
+                    "R2"
+                }
+            }
+        })
+        .collect();
+    Some(roles)
+}
+
+/// Sample name CellRanger accepts: letters, digits, `-` and `_`.
+fn cellranger_sample(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn tenx_name(sample: &str, lane: usize, role: &str) -> String {
+    format!("{}_S1_L{:03}_{}_001.fastq.gz", sample, lane, role)
+}
+
+/// `<run>_<n>` FASTQ files of `run_id` in read order.
+fn numbered_fastqs(run_id: &str, files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut numbered: Vec<(u32, PathBuf)> = files
+        .iter()
+        .filter(|f| !trim::is_trim_output(f))
+        .filter_map(|f| {
+            let name = f.file_name()?.to_string_lossy();
+            let read = trim::fastq_stem(&name)?
+                .strip_prefix(run_id)?
+                .strip_prefix('_')?
+                .parse()
+                .ok()?;
+            Some((read, f.clone()))
+        })
+        .collect();
+    numbered.sort();
+    numbered.into_iter().map(|(_, f)| f).collect()
+}
+
+/// Links the FASTQ files of 10x runs under CellRanger names.
+pub struct TenxHook {
+    /// Sample name and lane of each run, by run accession.
+    lanes: HashMap<String, (String, usize)>,
+}
+
+impl TenxHook {
+    /// Samples are named after `sample_name` or the sample accession.
+    pub fn new(records: &[EnaRecord]) -> Self {
+        let mut next_lane: HashMap<String, usize> = HashMap::new();
+        let lanes = records
+            .iter()
+            .map(|r| {
+                let sample = r
+                    .sample_name
+                    .as_deref()
+                    .or(r.sample_accession.as_deref())
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or(&r.run_accession);
+                let sample = cellranger_sample(sample);
+                let lane = next_lane.entry(sample.clone()).or_default();
+                *lane += 1;
+                (r.run_accession.clone(), (sample, *lane))
+            })
+            .collect();
+        Self { lanes }
+    }
+}
+
+impl RunHook for TenxHook {
+    fn name(&self) -> &'static str {
+        "tenx-names"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let fastqs = numbered_fastqs(run_id, files);
+            let lengths = fastqs
+                .iter()
+                .map(|f| first_read_len(f))
+                .collect::<Result<Vec<_>>>()?;
+            let Some(roles) = assign_roles(&lengths) else {
+                info!("[{}] Not a 10x layout, keeping file names", run_id);
+                return Ok(());
+            };
+            let (sample, lane) = self
+                .lanes
+                .get(run_id)
+                .cloned()
+                .unwrap_or_else(|| (cellranger_sample(run_id), 1));
+            let dir = ctx.options.output.join(TENX_DIR).join(&sample);
+            tokio::fs::create_dir_all(&dir).await?;
+            for (fastq, role) in fastqs.iter().zip(&roles) {
+                let link = dir.join(tenx_name(&sample, lane, role));
+                // A rerun links the new download.
+                let _ = tokio::fs::remove_file(&link).await;
+                tokio::fs::hard_link(fastq, &link).await.with_context(|| {
+                    format!("Failed to link {} to {}", fastq.display(), link.display())
+                })?;
+            }
+            info!(
+                "[{}] Linked as 10x {} ({}) in {}",
+                run_id,
+                sample,
+                roles.join("/"),
+                dir.display()
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn names_10x_reads_by_length() {
+        assert_eq!(assign_roles(&[8, 28, 91]).unwrap(), ["I1", "R1", "R2"]);
+        assert_eq!(
+            assign_roles(&[10, 10, 98, 26]).unwrap(),
+            ["I1", "I2", "R2", "R1"]
+        );
+        assert_eq!(assign_roles(&[28, 90]).unwrap(), ["R1", "R2"]);
+        assert!(assign_roles(&[150, 150]).is_none());
+        assert!(assign_roles(&[8, 150]).is_none());
+        assert_eq!(
+            tenx_name("PBMC-1", 2, "R1"),
+            "PBMC-1_S1_L002_R1_001.fastq.gz"
+        );
+        assert_eq!(cellranger_sample("liver 3/a"), "liver-3-a");
+
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["SRR1_3.fastq.gz", "SRR1_1.fastq.gz", "SRR1_2.fastq.gz"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        let mut gz = GzEncoder::new(File::create(&files[1]).unwrap(), Default::default());
+        gz.write_all(b"@r1\nACGTACGT\n+\nIIIIIIII\n").unwrap();
+        gz.finish().unwrap();
+        assert_eq!(first_read_len(&files[1]).unwrap(), 8);
+        assert_eq!(
+            numbered_fastqs("SRR1", &files),
+            [files[1].clone(), files[2].clone(), files[0].clone()]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Read trimming after download (`download --trim fastp`).
+//!
+//! [`FastpHook`] runs `fastp` on each verified run's FASTQ files, treating
+//! `<run>_1`/`<run>_2` as a pair and any other FASTQ as single-end, except
+//! the unpaired `<run>.fastq.gz` ENA ships next to some pairs. Trimmed
+//! reads land next to the originals as `<name>.trimmed.fastq.gz`, with
+//! `<run>.fastp.json` and `<run>.fastp.html` reports. MultiQC reads the JSON
+//! reports natively, and `--multiqc` adds their read counts to its table.
+
+use crate::backend::{BackendContext, RunHook};
+use crate::error::PolariseqError;
+use crate::run_cancellable;
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::info;
+
+/// `fastp` uses at most this many worker threads.
+const MAX_FASTP_THREADS: usize = 16;
+
+const FASTQ_SUFFIXES: [&str; 4] = [".fastq.gz", ".fq.gz", ".fastq", ".fq"];
+
+/// Trimming tool for `--trim`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Trimmer {
+    Fastp,
+}
+
+/// Split `name` into its stem and FASTQ suffix.
+fn split_fastq(name: &str) -> Option<(&str, &str)> {
+    FASTQ_SUFFIXES
+        .iter()
+        .find_map(|suffix| Some((name.strip_suffix(suffix)?, *suffix)))
+}
+
+/// `name` without its FASTQ suffix, if it has one.
+pub fn fastq_stem(name: &str) -> Option<&str> {
+    split_fastq(name).map(|(stem, _)| stem)
+}
+
+/// Whether `path` is a FASTQ file, by its suffix.
+pub fn is_fastq(path: &Path) -> bool {
+    split_fastq(&path.file_name().unwrap_or_default().to_string_lossy()).is_some()
+}
+
+/// Whether `path` was written by a trimmer rather than downloaded.
+pub fn is_trim_output(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.contains(".trimmed.") || name.contains(".fastp.")
+}
+
+/// One `fastp` invocation: one or two inputs and the report stem.
+#[derive(Debug, PartialEq, Eq)]
+struct FastpJob {
+    inputs: Vec<PathBuf>,
+    report: String,
+}
+
+fn trimmed_path(input: &Path) -> PathBuf {
+    let name = input.file_name().unwrap_or_default().to_string_lossy();
+    let trimmed = match split_fastq(&name) {
+        Some((stem, _)) => format!("{}.trimmed.fastq.gz", stem),
+        None => format!("{}.trimmed.fastq.gz", name),
+    };
+    input.with_file_name(trimmed)
+}
+
+/// Group the downloaded FASTQs of `run_id` into `fastp` jobs.
+fn plan_jobs(run_id: &str, files: &[PathBuf]) -> Vec<FastpJob> {
+    let fastqs: Vec<&PathBuf> = files
+        .iter()
+        .filter(|f| !is_trim_output(f) && is_fastq(f))
+        .collect();
+    let mate = |read: &str| {
+        fastqs.iter().find(|f| {
+            let name = f.file_name().unwrap_or_default().to_string_lossy();
+            split_fastq(&name).is_some_and(|(stem, _)| stem == format!("{}_{}", run_id, read))
+        })
+    };
+    let mut jobs = Vec::new();
+    let mut paired: Vec<&PathBuf> = Vec::new();
+    if let (Some(r1), Some(r2)) = (mate("1"), mate("2")) {
+        paired = vec![r1, r2];
+        jobs.push(FastpJob {
+            inputs: vec![(*r1).clone(), (*r2).clone()],
+            report: run_id.to_string(),
+        });
+    }
+    for fastq in fastqs.iter().filter(|f| !paired.contains(f)) {
+        let name = fastq.file_name().unwrap_or_default().to_string_lossy();
+        let stem = split_fastq(&name).map(|(stem, _)| stem).unwrap_or(&name);
+        // Orphan reads of a pair; the pair's report already has that name.
+        if !paired.is_empty() && stem == run_id {
+            continue;
+        }
+        jobs.push(FastpJob {
+            inputs: vec![(*fastq).clone()],
+            report: stem.to_string(),
+        });
+    }
+    jobs
+}
+
+/// Runs `fastp` over each finished run.
+pub struct FastpHook {
+    tool: PathBuf,
+    /// Extra arguments appended to every `fastp` call.
+    args: Vec<String>,
+}
+
+impl FastpHook {
+    /// `args` is split on whitespace (`--fastp-args "--cut_front -l 30"`).
+    pub fn new(args: &str) -> Result<Self> {
+        let tool =
+            crate::deps::locate_tool("fastp").ok_or_else(|| PolariseqError::DependencyMissing {
+                tool: "fastp".to_string(),
+                path: PathBuf::from("fastp"),
+            })?;
+        Ok(Self {
+            tool,
+            args: args.split_whitespace().map(String::from).collect(),
+        })
+    }
+
+    fn command_args(&self, job: &FastpJob, output: &Path, threads: usize) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        for (i, input) in job.inputs.iter().enumerate() {
+            let (input_flag, output_flag) = if i == 0 { ("-i", "-o") } else { ("-I", "-O") };
+            args.push(input_flag.into());
+            args.push(input.into());
+            args.push(output_flag.into());
+            args.push(trimmed_path(input).into());
+        }
+        args.push("-j".into());
+        args.push(output.join(format!("{}.fastp.json", job.report)).into());
+        args.push("-h".into());
+        args.push(output.join(format!("{}.fastp.html", job.report)).into());
+        args.push("-w".into());
+        args.push(threads.min(MAX_FASTP_THREADS).to_string().into());
+        args.extend(self.args.iter().map(OsString::from));
+        args
+    }
+}
+
+impl RunHook for FastpHook {
+    fn name(&self) -> &'static str {
+        "fastp"
+    }
+
+    fn after_run<'a>(
+        &'a self,
+        run_id: &'a str,
+        files: &'a [PathBuf],
+        ctx: &'a BackendContext,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let jobs = plan_jobs(run_id, files);
+            if jobs.is_empty() {
+                info!("[{}] No FASTQ files to trim", run_id);
+                return Ok(());
+            }
+            for job in &jobs {
+                let args = self.command_args(job, &ctx.options.output, ctx.process_threads());
+                let output =
+                    run_cancellable(Command::new(&self.tool).args(&args), &ctx.cancel).await?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    return Err(anyhow!(
+                        "fastp exited with {}: {}",
+                        output.status,
+                        stderr.lines().last().unwrap_or_default().trim()
+                    ));
+                }
+            }
+            info!(
+                "[{}] Trimmed {} FASTQ set(s) with fastp",
+                run_id,
+                jobs.len()
+            );
+            Ok(())
+        })
+    }
+}
+
+/// Read counts from a `fastp` JSON report.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FastpStats {
+    pub reads_before: u64,
+    pub reads_after: u64,
+    /// Fraction of bases at Q30 or above after filtering.
+    pub q30_after: f64,
+}
+
+#[derive(Deserialize)]
+struct FastpReport {
+    summary: FastpSummary,
+}
+
+#[derive(Deserialize)]
+struct FastpSummary {
+    before_filtering: FastpCounts,
+    after_filtering: FastpCounts,
+}
+
+#[derive(Deserialize)]
+struct FastpCounts {
+    total_reads: u64,
+    #[serde(default)]
+    q30_rate: f64,
+}
+
+/// Parse the `summary` section of a `fastp` JSON report.
+pub fn fastp_stats(path: &Path) -> Result<FastpStats> {
+    let report: FastpReport = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(FastpStats {
+        reads_before: report.summary.before_filtering.total_reads,
+        reads_after: report.summary.after_filtering.total_reads,
+        q30_after: report.summary.after_filtering.q30_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_mates_and_reads_reports() {
+        let dir = Path::new("/data");
+        let files: Vec<PathBuf> = [
+            "SRR1.fastp.json",
+            "SRR1.fastq.gz",
+            "SRR1_1.fastq.gz",
+            "SRR1_1.trimmed.fastq.gz",
+            "SRR1_2.fastq.gz",
+            "SRR1_3.fastq.gz",
+        ]
+        .iter()
+        .map(|name| dir.join(name))
+        .collect();
+        let jobs = plan_jobs("SRR1", &files);
+        assert_eq!(
+            jobs,
+            [
+                FastpJob {
+                    inputs: vec![dir.join("SRR1_1.fastq.gz"), dir.join("SRR1_2.fastq.gz")],
+                    report: "SRR1".into(),
+                },
+                FastpJob {
+                    inputs: vec![dir.join("SRR1_3.fastq.gz")],
+                    report: "SRR1_3".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            trimmed_path(&dir.join("SRR1_2.fq")),
+            dir.join("SRR1_2.trimmed.fastq.gz")
+        );
+
+        let report = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            report.path(),
+            r#"{"summary": {"before_filtering": {"total_reads": 1000, "q30_rate": 0.9},
+                            "after_filtering": {"total_reads": 950, "q30_rate": 0.95}}}"#,
+        )
+        .unwrap();
+        let stats = fastp_stats(report.path()).unwrap();
+        assert_eq!((stats.reads_before, stats.reads_after), (1000, 950));
+        assert_eq!(stats.q30_after, 0.95);
+    }
+}
@@ -0,0 +1,159 @@
+//! Unaligned BAM/CRAM output (`download --output-format ubam|cram`).
+//!
+//! Runs converted locally from SRA (the `aws` and `prefetch` backends)
+//! normally end as gzipped FASTQ. With an unaligned format, `samtools
+//! import` packs the `fasterq-dump` output into `<run>.bam` or `<run>.cram`
+//! instead, with mates flagged as pairs and the run accession as read
+//! group, and the FASTQ files are removed. Runs fetched from ENA over FTP
+//! arrive as FASTQ and stay that way.
+
+use crate::error::PolariseqError;
+use crate::{part_path, run_cancellable, Config, OutputFormat};
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+impl OutputFormat {
+    /// Extension of the converted file; `None` for FASTQ.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::Fastq => None,
+            Self::Ubam => Some("bam"),
+            Self::Cram => Some("cram"),
+        }
+    }
+}
+
+/// `samtools` from the YAML config, else from `PATH`.
+pub fn samtools(config: &Config) -> PathBuf {
+    config
+        .software
+        .samtools
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("samtools"))
+}
+
+/// Check that `samtools` exists and runs when `format` needs it.
+pub fn validate(config: &Config, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Fastq {
+        return Ok(());
+    }
+    crate::check_executable(&samtools(config), "samtools")
+}
+
+fn non_empty(path: &Path) -> bool {
+    path.metadata().map(|m| m.len() > 0).unwrap_or(false)
+}
+
+/// `samtools import` arguments for the FASTQ of `run_id` in `output_dir`,
+/// and the FASTQ files they read; `None` if there is no FASTQ.
+fn import_args(
+    output_dir: &Path,
+    run_id: &str,
+    format: &str,
+    output: &Path,
+    threads: usize,
+) -> Option<(Vec<OsString>, Vec<PathBuf>)> {
+    let inputs: Vec<(&str, PathBuf)> = [
+        ("-1", format!("{}_1.fastq", run_id)),
+        ("-2", format!("{}_2.fastq", run_id)),
+        ("-0", format!("{}.fastq", run_id)),
+    ]
+    .into_iter()
+    .map(|(flag, name)| (flag, output_dir.join(name)))
+    .filter(|(_, path)| non_empty(path))
+    .collect();
+    if inputs.is_empty() {
+        return None;
+    }
+    let mut args: Vec<OsString> = vec![
+        "import".into(),
+        "-@".into(),
+        threads.max(1).to_string().into(),
+        "-R".into(),
+        run_id.into(),
+        "-O".into(),
+        format.into(),
+        "-o".into(),
+        output.into(),
+    ];
+    for (flag, path) in &inputs {
+        args.push((*flag).into());
+        args.push(path.into());
+    }
+    Some((args, inputs.into_iter().map(|(_, path)| path).collect()))
+}
+
+/// Convert the FASTQ of `run_id` in `output_dir` to `format` and remove it.
+/// Returns the BAM/CRAM path.
+pub async fn import_fastq(
+    config: &Config,
+    output_dir: &Path,
+    run_id: &str,
+    format: OutputFormat,
+    threads: usize,
+    cancel: &CancellationToken,
+) -> Result<PathBuf> {
+    let failed = |reason: String| PolariseqError::ConversionFailed {
+        run: run_id.to_string(),
+        reason,
+    };
+    let extension = format
+        .extension()
+        .ok_or_else(|| failed("FASTQ output needs no import".to_string()))?;
+    let target = output_dir.join(format!("{}.{}", run_id, extension));
+    let partial = part_path(&target);
+    let (args, inputs) = import_args(output_dir, run_id, extension, &partial, threads)
+        .ok_or_else(|| failed("no FASTQ output found".to_string()))?;
+
+    let samtools = samtools(config);
+    info!("[{}] Importing FASTQ into {}", run_id, target.display());
+    let output = run_cancellable(config.priority.command(&samtools).args(&args), cancel)
+        .await
+        .with_context(|| format!("Failed to run {}", samtools.display()))?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&partial).await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(failed(format!(
+            "samtools import exited with {}: {}",
+            output.status,
+            stderr.lines().last().unwrap_or_default().trim()
+        ))
+        .into());
+    }
+    tokio::fs::rename(&partial, &target).await?;
+    for input in inputs {
+        tokio::fs::remove_file(&input).await?;
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_pairs_and_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path();
+        assert!(import_args(out, "SRR1", "bam", &out.join("x"), 4).is_none());
+
+        std::fs::write(out.join("SRR1_1.fastq"), "@r\nA\n+\nI\n").unwrap();
+        std::fs::write(out.join("SRR1_2.fastq"), "@r\nC\n+\nI\n").unwrap();
+        std::fs::write(out.join("SRR1.fastq"), "").unwrap();
+        let (args, inputs) =
+            import_args(out, "SRR1", "cram", &out.join("SRR1.cram.part"), 0).unwrap();
+        let args: Vec<String> = args
+            .iter()
+            .map(|a| a.to_string_lossy().replace(&*out.to_string_lossy(), "."))
+            .collect();
+        assert_eq!(
+            args.join(" "),
+            "import -@ 1 -R SRR1 -O cram -o ./SRR1.cram.part -1 ./SRR1_1.fastq -2 ./SRR1_2.fastq"
+        );
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(OutputFormat::Ubam.extension(), Some("bam"));
+    }
+}
@@ -0,0 +1,214 @@
+//! URL lists of a planned job (`download --print-urls`, `--export-aria2`).
+//!
+//! Instead of downloading, every file the job would fetch is listed with
+//! its expected MD5, size and local path, so another download manager or
+//! a mirroring service can do the transfer after Polariseq's metadata and
+//! filter steps. FTP runs list their ENA files with the other ENA
+//! endpoints as mirrors; AWS runs list the `.sra` object from S3 in its
+//! staging directory (its conversion is left to the user). Protected runs have no public URL and
+//! are left out.
+
+use crate::aws_s3::{self, AwsStaging, SraLookup, SraUtils};
+use crate::client::{DownloadJob, JobPlan};
+use crate::{overrides, DownloadMethod, ProcessedRecord};
+use anyhow::{anyhow, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// One file of the job and where it should end up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    pub run: String,
+    /// The primary URL first, then mirrors of the same file.
+    pub urls: Vec<String>,
+    pub dir: PathBuf,
+    pub name: String,
+    pub md5: Option<String>,
+    /// 0 when unknown.
+    pub bytes: u64,
+}
+
+/// `url` with a scheme; ENA lists FTP paths without one.
+fn with_scheme(url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("ftp://{}", url)
+    }
+}
+
+/// `url` and the other ENA endpoints serving the same file.
+fn mirrors(url: &str) -> Vec<String> {
+    #[cfg(feature = "ftp")]
+    return crate::ftp::mirror_urls(url);
+    #[cfg(not(feature = "ftp"))]
+    vec![url.to_string()]
+}
+
+fn ftp_files(record: &ProcessedRecord, dir: &Path) -> Vec<PlannedFile> {
+    let mut files = vec![(
+        &record.fastq_ftp_1_url,
+        &record.fastq_ftp_1_name,
+        &record.fastq_md5_1,
+        record.fastq_bytes_1,
+    )];
+    if let (Some(url), Some(name), Some(md5)) = (
+        &record.fastq_ftp_2_url,
+        &record.fastq_ftp_2_name,
+        &record.fastq_md5_2,
+    ) {
+        files.push((url, name, md5, record.fastq_bytes_2.unwrap_or(0)));
+    }
+    files
+        .into_iter()
+        .map(|(url, name, md5, bytes)| PlannedFile {
+            run: record.run_accession.clone(),
+            urls: mirrors(&with_scheme(url)),
+            dir: dir.to_path_buf(),
+            name: name.clone(),
+            md5: Some(md5.clone()).filter(|m| !m.is_empty()),
+            bytes,
+        })
+        .collect()
+}
+
+/// Every file `plan` would download. AWS runs are resolved like the AWS
+/// backend does: from ENA's `sra_md5`/`sra_bytes` when S3 agrees, else
+/// through NCBI.
+pub async fn planned_files(job: &DownloadJob, plan: &JobPlan) -> Result<Vec<PlannedFile>> {
+    let subdirs = overrides::output_subdirs(&plan.records);
+    let mut files = Vec::new();
+    for record in &plan.processed {
+        let run_id = &record.run_accession;
+        let dir = overrides::output_for(job.output(), &subdirs, run_id);
+        let ena = plan.records.iter().find(|r| &r.run_accession == run_id);
+        let ftp_only = ena.and_then(|r| r.preferred_backend.as_deref()) == Some("ftp");
+        match job.options.download_method {
+            DownloadMethod::Ftp => files.extend(ftp_files(record, &dir)),
+            DownloadMethod::Aws if ftp_only => files.extend(ftp_files(record, &dir)),
+            DownloadMethod::Aws => {
+                let format = job.options.sra_format;
+                let from_ena = match ena.and_then(aws_s3::ena_sra_md5) {
+                    Some((md5, bytes)) => {
+                        SraUtils::metadata_from_ena(run_id, &md5, bytes, format).await
+                    }
+                    None => None,
+                };
+                let lookup = match from_ena {
+                    Some(metadata) => SraLookup::Found(metadata),
+                    None => SraUtils::get_metadata_for_format(run_id, format).await?,
+                };
+                let SraLookup::Found(metadata) = lookup else {
+                    warn!(
+                        "[{}] No AWS copy in the requested format; not listed",
+                        run_id
+                    );
+                    continue;
+                };
+                let name = metadata
+                    .s3_uri
+                    .rsplit('/')
+                    .next()
+                    .filter(|n| !n.is_empty())
+                    .unwrap_or(run_id)
+                    .to_string();
+                let dir = match job.options.aws_staging {
+                    AwsStaging::Run => dir.join(run_id),
+                    AwsStaging::Flat => dir,
+                };
+                files.push(PlannedFile {
+                    run: run_id.clone(),
+                    urls: vec![metadata.http_url],
+                    dir,
+                    name,
+                    md5: metadata.md5,
+                    bytes: metadata.size,
+                });
+            }
+            DownloadMethod::Ega => {
+                return Err(anyhow!("URL lists do not support EGA downloads"));
+            }
+            DownloadMethod::Mock => {
+                return Err(anyhow!("URL lists do not support mock downloads"));
+            }
+        }
+    }
+    if !plan.protected_runs.is_empty() {
+        warn!(
+            "{} protected run(s) have no public URL and are not listed",
+            plan.protected_runs.len()
+        );
+    }
+    Ok(files)
+}
+
+/// Tab-separated `run`, `url`, `md5`, `bytes` and local path, one file per
+/// line after a header.
+pub fn url_list(files: &[PlannedFile]) -> String {
+    let mut out = String::from("run\turl\tmd5\tbytes\tpath\n");
+    for file in files {
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            file.run,
+            file.urls[0],
+            file.md5.as_deref().unwrap_or_default(),
+            file.bytes,
+            file.dir.join(&file.name).display()
+        );
+    }
+    out
+}
+
+/// aria2 input file: the URLs of each file (mirrors tab-separated) with
+/// its `dir`, `out` and MD5 `checksum` options.
+pub fn aria2_input(files: &[PlannedFile]) -> String {
+    let mut out = String::new();
+    for file in files {
+        let _ = writeln!(out, "{}", file.urls.join("\t"));
+        let _ = writeln!(out, "  dir={}", file.dir.display());
+        let _ = writeln!(out, "  out={}", file.name);
+        if let Some(md5) = &file.md5 {
+            let _ = writeln!(out, "  checksum=md5={}", md5);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_ftp_files_for_aria2() {
+        let record = ProcessedRecord {
+            run_accession: "SRR1".into(),
+            fastq_ftp_1_url: "ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz".into(),
+            fastq_ftp_2_url: None,
+            fastq_ftp_1_name: "SRR1_1.fastq.gz".into(),
+            fastq_ftp_2_name: None,
+            fastq_md5_1: "abc".into(),
+            fastq_md5_2: None,
+            fastq_bytes_1: 42,
+            fastq_bytes_2: None,
+            sample_title: String::new(),
+        };
+        let files = ftp_files(&record, Path::new("out"));
+        assert_eq!(
+            url_list(&files).lines().nth(1).unwrap(),
+            "SRR1\tftp://ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz\tabc\t42\tout/SRR1_1.fastq.gz"
+        );
+        let aria2 = aria2_input(&files);
+        let mut lines = aria2.lines();
+        let urls: Vec<&str> = lines.next().unwrap().split('\t').collect();
+        assert_eq!(
+            urls[1],
+            "https://ftp.sra.ebi.ac.uk/vol1/fastq/SRR1/SRR1_1.fastq.gz"
+        );
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            ["  dir=out", "  out=SRR1_1.fastq.gz", "  checksum=md5=abc"]
+        );
+    }
+}
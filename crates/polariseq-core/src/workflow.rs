@@ -0,0 +1,367 @@
+//! Workflow export for `download --export-workflow`.
+//!
+//! Writes `workflow/runs.tsv` (one `run`/`url`/`md5` row per file to fetch)
+//! next to a small Nextflow or Snakemake pipeline that reads it, so the
+//! download runs under the workflow manager's own retry and resume.
+//! FTP runs are fetched as published FASTQ; AWS runs go through separate
+//! download, convert (`fasterq-dump`) and compress steps.
+
+use crate::aws_s3::{SraLookup, SraUtils};
+use crate::client::{DownloadJob, JobPlan};
+use crate::{Config, DownloadMethod};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Directory under the output directory that receives the pipeline.
+pub const WORKFLOW_DIR: &str = "workflow";
+/// Input sheet of the exported pipeline.
+pub const RUNS_TSV: &str = "runs.tsv";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum WorkflowEngine {
+    Nextflow,
+    Snakemake,
+}
+
+impl WorkflowEngine {
+    fn file_name(self) -> &'static str {
+        match self {
+            WorkflowEngine::Nextflow => "main.nf",
+            WorkflowEngine::Snakemake => "Snakefile",
+        }
+    }
+}
+
+/// One file to fetch; AWS rows point at the run's `.sra`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WorkflowRow {
+    run: String,
+    url: String,
+    md5: String,
+}
+
+/// Rows for the plan. AWS runs are resolved through NCBI, as the
+/// downloader does; runs without a usable copy are left out with a warning.
+async fn workflow_rows(job: &DownloadJob, plan: &JobPlan) -> Result<Vec<WorkflowRow>> {
+    let mut rows = Vec::new();
+    for record in &plan.processed {
+        let run = record.run_accession.clone();
+        match job.options.download_method {
+            DownloadMethod::Ftp => {
+                rows.push(WorkflowRow {
+                    run: run.clone(),
+                    url: record.fastq_ftp_1_url.clone(),
+                    md5: record.fastq_md5_1.clone(),
+                });
+                if let (Some(url), Some(md5)) = (&record.fastq_ftp_2_url, &record.fastq_md5_2) {
+                    rows.push(WorkflowRow {
+                        run,
+                        url: url.clone(),
+                        md5: md5.clone(),
+                    });
+                }
+            }
+            DownloadMethod::Aws => {
+                match SraUtils::get_metadata_for_format(&run, job.options.sra_format).await? {
+                    SraLookup::Found(metadata) => rows.push(WorkflowRow {
+                        run,
+                        url: metadata.http_url,
+                        md5: metadata.md5.unwrap_or_default(),
+                    }),
+                    SraLookup::LiteOnly => {
+                        warn!(
+                            "[{}] Only SRA Lite is available; left out of the workflow",
+                            run
+                        )
+                    }
+                    SraLookup::NotFound => warn!("[{}] No AWS copy; left out of the workflow", run),
+                }
+            }
+            DownloadMethod::Ega => {
+                return Err(anyhow!("--export-workflow does not support EGA downloads"));
+            }
+            DownloadMethod::Mock => {
+                return Err(anyhow!("--export-workflow does not support mock downloads"));
+            }
+        }
+    }
+    if !plan.protected_runs.is_empty() {
+        warn!(
+            "{} controlled-access run(s) are not exported; use --only-scripts with --ngc for them",
+            plan.protected_runs.len()
+        );
+    }
+    Ok(rows)
+}
+
+fn runs_tsv(rows: &[WorkflowRow]) -> String {
+    let mut tsv = String::from("run\turl\tmd5\n");
+    for row in rows {
+        tsv.push_str(&format!("{}\t{}\t{}\n", row.run, row.url, row.md5));
+    }
+    tsv
+}
+
+const NEXTFLOW_HEADER: &str = r#"// Generated by `polariseq download --export-workflow nextflow`.
+// Run with: nextflow run main.nf -resume
+nextflow.enable.dsl = 2
+
+params.runs = "${projectDir}/runs.tsv"
+params.outdir = "@OUTDIR@"
+params.fasterq_dump = "@FASTERQ_DUMP@"
+params.threads = @THREADS@
+
+runs = Channel
+    .fromPath(params.runs)
+    .splitCsv(header: true, sep: '\t')
+    .map { row -> tuple(row.run, row.url, row.md5) }
+"#;
+
+const NEXTFLOW_FTP: &str = r#"
+process DOWNLOAD {
+    tag "$run"
+    publishDir params.outdir, mode: 'copy'
+    errorStrategy 'retry'
+    maxRetries 3
+
+    input:
+    tuple val(run), val(url), val(md5)
+
+    output:
+    path "*.fastq.gz"
+
+    script:
+    def name = url.tokenize('/')[-1]
+    """
+    wget -c -O ${name} '${url}'
+    echo '${md5}  ${name}' | md5sum -c -
+    """
+}
+
+workflow {
+    DOWNLOAD(runs)
+}
+"#;
+
+const NEXTFLOW_AWS: &str = r#"
+process DOWNLOAD {
+    tag "$run"
+    errorStrategy 'retry'
+    maxRetries 3
+
+    input:
+    tuple val(run), val(url), val(md5)
+
+    output:
+    tuple val(run), path("${run}.sra")
+
+    script:
+    """
+    curl -fL --retry 5 -C - -o ${run}.sra '${url}'
+    if [ -n '${md5}' ]; then echo '${md5}  ${run}.sra' | md5sum -c -; fi
+    """
+}
+
+process CONVERT {
+    tag "$run"
+    cpus params.threads
+
+    input:
+    tuple val(run), path(sra)
+
+    output:
+    tuple val(run), path("${run}*.fastq")
+
+    script:
+    """
+    ${params.fasterq_dump} --split-3 -e ${task.cpus} -O . -f ${sra}
+    """
+}
+
+process COMPRESS {
+    tag "$run"
+    cpus params.threads
+    publishDir params.outdir, mode: 'copy'
+
+    input:
+    tuple val(run), path(fastq)
+
+    output:
+    path "*.fastq.gz"
+
+    script:
+    """
+    if command -v pigz >/dev/null; then pigz -p ${task.cpus} -f ${fastq}; else gzip -f ${fastq}; fi
+    """
+}
+
+workflow {
+    runs | DOWNLOAD | CONVERT | COMPRESS
+}
+"#;
+
+const SNAKEMAKE_HEADER: &str = r#"# Generated by `polariseq download --export-workflow snakemake`.
+# Run with: snakemake -s Snakefile --cores @THREADS@
+import csv
+import os
+
+OUTDIR = "@OUTDIR@"
+FASTERQ_DUMP = "@FASTERQ_DUMP@"
+THREADS = @THREADS@
+
+with open(os.path.join(workflow.basedir, "runs.tsv")) as handle:
+    ROWS = list(csv.DictReader(handle, delimiter="\t"))
+"#;
+
+const SNAKEMAKE_FTP: &str = r#"
+FILES = {os.path.basename(row["url"]): row for row in ROWS}
+
+rule all:
+    input: expand(os.path.join(OUTDIR, "{name}"), name=FILES)
+
+rule download:
+    output: os.path.join(OUTDIR, "{name}")
+    params:
+        url=lambda w: FILES[w.name]["url"],
+        md5=lambda w: FILES[w.name]["md5"],
+    retries: 3
+    shell:
+        "wget -c -O {output} '{params.url}' && "
+        "echo '{params.md5}  {output}' | md5sum -c -"
+"#;
+
+const SNAKEMAKE_AWS: &str = r#"
+RUNS = {row["run"]: row for row in ROWS}
+
+rule all:
+    input: expand(os.path.join(OUTDIR, "{run}.done"), run=RUNS)
+
+rule download:
+    output: temp("sra/{run}.sra")
+    params:
+        url=lambda w: RUNS[w.run]["url"],
+        md5=lambda w: RUNS[w.run]["md5"],
+    retries: 3
+    shell:
+        "curl -fL --retry 5 -C - -o {output} '{params.url}' && "
+        "if [ -n '{params.md5}' ]; then echo '{params.md5}  {output}' | md5sum -c -; fi"
+
+rule convert:
+    input: "sra/{run}.sra"
+    output: temp(directory("fastq/{run}"))
+    threads: THREADS
+    shell:
+        "{FASTERQ_DUMP} --split-3 -e {threads} -O {output} -t {output}.tmp -f {input}"
+
+rule compress:
+    input: "fastq/{run}"
+    output: touch(os.path.join(OUTDIR, "{run}.done"))
+    threads: THREADS
+    shell:
+        "if command -v pigz >/dev/null; then pigz -p {threads} -f {input}/*.fastq; "
+        "else gzip -f {input}/*.fastq; fi && "
+        "mv {input}/*.fastq.gz " + OUTDIR
+"#;
+
+fn render(
+    engine: WorkflowEngine,
+    method: DownloadMethod,
+    config: &Config,
+    job: &DownloadJob,
+) -> String {
+    let (header, body) = match (engine, method) {
+        (WorkflowEngine::Nextflow, DownloadMethod::Ftp) => (NEXTFLOW_HEADER, NEXTFLOW_FTP),
+        (WorkflowEngine::Nextflow, _) => (NEXTFLOW_HEADER, NEXTFLOW_AWS),
+        (WorkflowEngine::Snakemake, DownloadMethod::Ftp) => (SNAKEMAKE_HEADER, SNAKEMAKE_FTP),
+        (WorkflowEngine::Snakemake, _) => (SNAKEMAKE_HEADER, SNAKEMAKE_AWS),
+    };
+    let output = fs::canonicalize(job.output()).unwrap_or_else(|_| job.output().to_path_buf());
+    format!("{}{}", header, body)
+        .replace("@OUTDIR@", &output.display().to_string())
+        .replace(
+            "@FASTERQ_DUMP@",
+            &config.software.fasterq_dump.display().to_string(),
+        )
+        .replace("@THREADS@", &job.options.aws_threads.max(1).to_string())
+}
+
+/// Write `workflow/runs.tsv` and the pipeline for `engine` under the job's
+/// output directory and return the pipeline's path.
+pub async fn export_workflow(
+    config: &Config,
+    job: &DownloadJob,
+    plan: &JobPlan,
+    engine: WorkflowEngine,
+) -> Result<PathBuf> {
+    let rows = workflow_rows(job, plan).await?;
+    if rows.is_empty() {
+        return Err(anyhow!("No runs to export"));
+    }
+    let dir = job.output().join(WORKFLOW_DIR);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(RUNS_TSV), runs_tsv(&rows))?;
+    let pipeline = dir.join(engine.file_name());
+    fs::write(
+        &pipeline,
+        render(engine, job.options.download_method, config, job),
+    )?;
+    Ok(pipeline)
+}
+
+/// Path of the runs sheet next to an exported pipeline.
+pub fn runs_sheet(pipeline: &Path) -> PathBuf {
+    pipeline.with_file_name(RUNS_TSV)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProcessedRecord;
+
+    #[tokio::test]
+    async fn exports_ftp_runs_for_both_engines() {
+        let dir = tempfile::tempdir().unwrap();
+        let config: Config =
+            serde_yaml::from_str("software:\n  prefetch: p\n  fasterq_dump: f\n").unwrap();
+        let job = DownloadJob::accession("PRJNA1", dir.path()).method(DownloadMethod::Ftp);
+        let plan = JobPlan {
+            processed: vec![ProcessedRecord {
+                run_accession: "SRR1".into(),
+                fastq_ftp_1_url: "ftp.x/SRR1_1.fastq.gz".into(),
+                fastq_ftp_2_url: Some("ftp.x/SRR1_2.fastq.gz".into()),
+                fastq_ftp_1_name: "SRR1_1.fastq.gz".into(),
+                fastq_ftp_2_name: Some("SRR1_2.fastq.gz".into()),
+                fastq_md5_1: "a".into(),
+                fastq_md5_2: Some("b".into()),
+                fastq_bytes_1: 1,
+                fastq_bytes_2: Some(1),
+                sample_title: String::new(),
+            }],
+            ..JobPlan::default()
+        };
+
+        let nf = export_workflow(&config, &job, &plan, WorkflowEngine::Nextflow)
+            .await
+            .unwrap();
+        assert!(nf.ends_with("workflow/main.nf"));
+        assert_eq!(
+            fs::read_to_string(runs_sheet(&nf)).unwrap(),
+            "run\turl\tmd5\nSRR1\tftp.x/SRR1_1.fastq.gz\ta\nSRR1\tftp.x/SRR1_2.fastq.gz\tb\n"
+        );
+        let text = fs::read_to_string(&nf).unwrap();
+        assert!(text.contains("process DOWNLOAD"));
+        assert!(!text.contains('@'));
+
+        let smk = export_workflow(&config, &job, &plan, WorkflowEngine::Snakemake)
+            .await
+            .unwrap();
+        let text = fs::read_to_string(smk).unwrap();
+        assert!(text.contains("rule download:"));
+        assert!(text.contains("THREADS = "));
+    }
+}
@@ -0,0 +1,198 @@
+//! Bounded write queue between the AWS chunk workers and a `.part` file.
+//!
+//! Workers hand each received buffer to one writer thread instead of
+//! writing from the async runtime. Queued bytes are capped at
+//! [`WRITE_BUDGET`]: when the disk is slower than the network, workers
+//! wait for room and stop reading their responses, so TCP flow control
+//! slows the transfer down instead of memory filling up. [`WriteStats`]
+//! tells when the disk, not the network, held a download back.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+/// Bytes queued for one file at most.
+pub const WRITE_BUDGET: usize = 64 << 20;
+
+/// Share of a period the writer must be busy for the disk to count as the
+/// bottleneck.
+const DISK_BOUND_SHARE: f64 = 0.8;
+
+enum Message {
+    Write {
+        chunk: usize,
+        offset: u64,
+        bytes: Bytes,
+        /// Returns the bytes to the budget once written.
+        _permit: OwnedSemaphorePermit,
+    },
+    /// Reply once every earlier write of `chunk` is done.
+    Flush {
+        chunk: usize,
+        done: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    /// Time workers waited for room in the queue, summed over workers.
+    waited_ns: AtomicU64,
+    /// Time the writer spent in writes.
+    writing_ns: AtomicU64,
+}
+
+/// Queue and writer time of a file so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStats {
+    pub waited: Duration,
+    pub writing: Duration,
+}
+
+impl WriteStats {
+    /// Whether the writer was busy for most of `period`, the time since
+    /// `earlier` was taken.
+    pub fn disk_bound(&self, earlier: &WriteStats, period: Duration) -> bool {
+        let writing = self.writing.saturating_sub(earlier.writing);
+        !period.is_zero() && writing.as_secs_f64() >= period.as_secs_f64() * DISK_BOUND_SHARE
+    }
+}
+
+/// Handle to the writer of one file; clones share the queue.
+#[derive(Clone)]
+pub struct WriteQueue {
+    tx: mpsc::UnboundedSender<Message>,
+    budget: Arc<Semaphore>,
+    counters: Arc<Counters>,
+}
+
+impl WriteQueue {
+    /// Open `path` for writing; the writer thread ends with the last handle.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let counters = Arc::new(Counters::default());
+        let writer_counters = counters.clone();
+        tokio::task::spawn_blocking(move || write_loop(file, rx, &writer_counters));
+        Ok(Self {
+            tx,
+            budget: Arc::new(Semaphore::new(WRITE_BUDGET)),
+            counters,
+        })
+    }
+
+    /// Queue `bytes` of `chunk` for `offset`, waiting while the queue is full.
+    pub async fn write(&self, chunk: usize, offset: u64, bytes: Bytes) -> Result<()> {
+        let permits = bytes.len().clamp(1, WRITE_BUDGET) as u32;
+        let permit = match self.budget.clone().try_acquire_many_owned(permits) {
+            Ok(permit) => permit,
+            Err(_) => {
+                let started = Instant::now();
+                let permit = self.budget.clone().acquire_many_owned(permits).await?;
+                self.counters
+                    .waited_ns
+                    .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                permit
+            }
+        };
+        self.tx
+            .send(Message::Write {
+                chunk,
+                offset,
+                bytes,
+                _permit: permit,
+            })
+            .map_err(|_| anyhow!("The writer of the file stopped"))
+    }
+
+    /// Wait until the queued bytes of `chunk` are written; fails when any
+    /// of them could not be.
+    pub async fn flush(&self, chunk: usize) -> Result<()> {
+        let (done, written) = oneshot::channel();
+        self.tx
+            .send(Message::Flush { chunk, done })
+            .map_err(|_| anyhow!("The writer of the file stopped"))?;
+        written
+            .await
+            .map_err(|_| anyhow!("The writer of the file stopped"))?
+            .context("Failed to write the downloaded data")
+    }
+
+    pub fn stats(&self) -> WriteStats {
+        let load = |counter: &AtomicU64| Duration::from_nanos(counter.load(Ordering::Relaxed));
+        WriteStats {
+            waited: load(&self.counters.waited_ns),
+            writing: load(&self.counters.writing_ns),
+        }
+    }
+}
+
+fn write_loop(mut file: File, mut rx: mpsc::UnboundedReceiver<Message>, counters: &Counters) {
+    // First failed write of each chunk, reported at its flush.
+    let mut failed: HashMap<usize, io::Error> = HashMap::new();
+    while let Some(message) = rx.blocking_recv() {
+        match message {
+            Message::Write {
+                chunk,
+                offset,
+                bytes,
+                _permit,
+            } => {
+                if failed.contains_key(&chunk) {
+                    continue;
+                }
+                let started = Instant::now();
+                let result = file
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| file.write_all(&bytes));
+                counters
+                    .writing_ns
+                    .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                if let Err(e) = result {
+                    failed.insert(chunk, e);
+                }
+            }
+            Message::Flush { chunk, done } => {
+                let _ = done.send(failed.remove(&chunk).map_or(Ok(()), Err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_chunks_at_their_offsets_and_flags_a_busy_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("SRR1.part");
+        std::fs::write(&path, vec![b'.'; 8]).unwrap();
+        let queue = WriteQueue::open(&path).unwrap();
+        queue.write(1, 4, Bytes::from_static(b"EF")).await.unwrap();
+        queue.write(0, 0, Bytes::from_static(b"AB")).await.unwrap();
+        queue.write(1, 6, Bytes::from_static(b"GH")).await.unwrap();
+        queue.flush(0).await.unwrap();
+        queue.flush(1).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"AB..EFGH");
+        assert_eq!(queue.budget.available_permits(), WRITE_BUDGET);
+
+        let idle = WriteStats::default();
+        let busy = WriteStats {
+            waited: Duration::from_secs(3),
+            writing: Duration::from_secs(9),
+        };
+        assert!(busy.disk_bound(&idle, Duration::from_secs(10)));
+        assert!(!busy.disk_bound(&idle, Duration::from_secs(20)));
+        assert!(!idle.disk_bound(&idle, Duration::ZERO));
+    }
+}
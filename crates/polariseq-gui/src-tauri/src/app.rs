@@ -327,6 +327,12 @@ async fn run_download_async(
     app_handle: ::tauri::AppHandle,
     pause_token: Option<crate::aws_s3::PauseToken>,
 ) -> Result<()> {
+    if options.download_method == DownloadMethod::Ega {
+        return Err(anyhow!(
+            "EGA downloads are only available from the polariseq CLI (--download ega)"
+        ));
+    }
+
     let records = if let Some(accession) = &options.accession {
         fetch_ena_data(accession).await?
     } else if let Some(tsv_path) = &options.tsv {
@@ -388,6 +394,7 @@ async fn run_download_async(
             download_aws(processed, config, options, app_handle, pause_token).await
         }
         DownloadMethod::Ftp => download_ftp(processed, config, options, app_handle).await,
+        DownloadMethod::Ega => unreachable!("EGA is rejected before fetching metadata"),
     }
 }
 
@@ -413,6 +420,7 @@ async fn download_aws(
     let output_dir = options.output;
     let fasterq_dump = config.software.fasterq_dump.display().to_string();
     let cleanup_sra = options.cleanup_sra;
+    let sra_format = options.sra_format;
 
     let semaphore = Arc::new(tokio::sync::Semaphore::new(file_concurrency));
     let mut handles = Vec::new();
@@ -440,7 +448,26 @@ async fn download_aws(
                 },
             )?;
 
-            let metadata = crate::aws_s3::SraUtils::get_metadata(&run_id, None).await?;
+            let metadata = match crate::aws_s3::SraUtils::get_metadata_for_format(
+                &run_id, sra_format,
+            )
+            .await?
+            {
+                crate::aws_s3::SraLookup::Found(metadata) => Some(metadata),
+                crate::aws_s3::SraLookup::LiteOnly => {
+                    app_handle.emit(
+                        "download-event",
+                        DownloadEvent::Progress {
+                            run_id: run_id.clone(),
+                            percent: 100.0,
+                            status: "Skipped (SRA Lite only)".to_string(),
+                            speed_mbps: 0.0,
+                        },
+                    )?;
+                    return Ok(());
+                }
+                crate::aws_s3::SraLookup::NotFound => None,
+            };
             if let Some(sra_metadata) = metadata {
                 let total_size = sra_metadata.size;
                 let progress_bytes = Arc::new(AtomicU64::new(0));
@@ -702,6 +729,9 @@ async fn download_ftp(
         &options.output,
         crate::ftp::Protocol::Ftp,
         options.multithreads,
+        None,
+        Default::default(),
+        Default::default(),
     )
     .await?;
 